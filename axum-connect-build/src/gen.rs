@@ -1,109 +1,488 @@
+use convert_case::{Case, Casing};
 use proc_macro2::TokenStream;
 use prost_build::{Method, Service, ServiceGenerator};
 use quote::{format_ident, quote};
 use syn::parse_str;
 
 #[derive(Default)]
-pub struct AxumConnectServiceGenerator {}
+pub struct AxumConnectServiceGenerator {
+    // Other generators (like tonic-build's) that are run over the same services, so they share the
+    // prost message types emitted for axum-connect.
+    extra_generators: Vec<Box<dyn ServiceGenerator>>,
+    clients: bool,
+}
 
 impl AxumConnectServiceGenerator {
-    pub fn new() -> Self {
-        Default::default()
+    pub fn with_extra_generators(extra_generators: Vec<Box<dyn ServiceGenerator>>) -> Self {
+        Self {
+            extra_generators,
+            clients: false,
+        }
+    }
+
+    pub fn with_clients(mut self, clients: bool) -> Self {
+        self.clients = clients;
+        self
     }
 
     fn generate_service(&mut self, service: Service, buf: &mut String) {
         // Service struct
         let service_name = format_ident!("{}", service.name);
-        let methods =
-            service.methods.into_iter().filter_map(|m| {
-                // Don't currently support client streaming. Will-do soon.
-                if m.client_streaming {
-                    return None;
-                }
+        let handler_trait_name = format_ident!("{}Handler", service.name);
+        let path_root = format!("{}.{}", service.package, service.proto_name);
+        let from_handler_doc = format!(
+            " Registers every method of the service, implemented as `&self` methods of `{}`. \
+             Calls of any other method get an `Unimplemented` error.",
+            handler_trait_name
+        );
+        let handler_trait_doc = format!(
+            " The service as a trait, for implementing all of its methods on one (usually \
+             stateful) struct. Register it with `{}::from_handler` or a `{}Router`.",
+            service_name, service_name
+        );
+        let router_name = format_ident!("{}Router", service.name);
+        let router_doc = format!(
+            " An implementation of `{}` with all of its methods, mounted with \
+             `.rpc({}::new(handler).routes())`.",
+            handler_trait_name, router_name
+        );
+
+        // Don't currently support bidi streaming, which needs HTTP/2 end to end.
+        let methods: Vec<Method> = service
+            .methods
+            .into_iter()
+            .filter(|m| !(m.client_streaming && m.server_streaming))
+            .collect();
 
-                Some(self.generate_service_method(
-                    m,
-                    &format!("{}.{}", service.package, service.proto_name),
-                ))
-            });
+        let trait_methods: Vec<_> = methods
+            .iter()
+            .map(|m| self.generate_handler_trait_method(m))
+            .collect();
+        let registrations: Vec<_> = methods
+            .iter()
+            .map(|m| self.generate_handler_registration(m))
+            .collect();
+        let descriptors: Vec<_> = methods
+            .iter()
+            .map(|m| format_ident!("{}_DESCRIPTOR", m.name.to_uppercase()))
+            .collect();
+        let method_types_mod = format_ident!("{}", service.name.to_case(Case::Snake));
+        let method_types_doc = format!(
+            " The methods of `{}` as `RpcMethod` types, for `#[rpc_handler]`.",
+            service_name
+        );
+        let method_types: Vec<_> = methods
+            .iter()
+            .map(|m| self.generate_method_type(m, &service_name))
+            .collect();
+        let client = if self.clients {
+            self.generate_client(&service_name, &methods)
+        } else {
+            quote!()
+        };
+        let methods = methods
+            .into_iter()
+            .map(|m| self.generate_service_method(m, &path_root));
+        let package = &service.package;
 
         buf.push_str(
             quote! {
                 pub struct #service_name;
 
                 impl #service_name {
+                    #[allow(dead_code)]
+                    pub const DESCRIPTOR: axum_connect::descriptor::ServiceDescriptor =
+                        axum_connect::descriptor::ServiceDescriptor {
+                            name: #path_root,
+                            package: #package,
+                            methods: &[#(Self::#descriptors),*],
+                        };
+
                     #(#methods)*
+
+                    #[doc = #from_handler_doc]
+                    #[allow(dead_code)]
+                    pub fn from_handler<T, S, R>(
+                        handler: std::sync::Arc<T>
+                    ) -> impl FnOnce(R) -> R
+                    where
+                        T: #handler_trait_name + ?Sized,
+                        S: Clone + Send + Sync + 'static,
+                        R: axum_connect::router::RpcRouterExt<S>,
+                    {
+                        move |router: R| {
+                            #(#registrations)*
+                            axum_connect::router::RpcRouterExt::rpc_unimplemented(
+                                router,
+                                &Self::DESCRIPTOR,
+                            )
+                        }
+                    }
+                }
+
+                #[doc = #method_types_doc]
+                #[allow(dead_code)]
+                pub mod #method_types_mod {
+                    #(#method_types)*
+                }
+
+                #[doc = #handler_trait_doc]
+                #[allow(dead_code)]
+                #[axum_connect::async_trait::async_trait]
+                pub trait #handler_trait_name: Send + Sync + 'static {
+                    #(#trait_methods)*
+                }
+
+                #[doc = #router_doc]
+                #[allow(dead_code)]
+                pub struct #router_name<T: ?Sized> {
+                    handler: std::sync::Arc<T>,
+                }
+
+                #[allow(dead_code)]
+                impl<T> #router_name<T>
+                where
+                    T: #handler_trait_name,
+                {
+                    pub fn new(handler: T) -> Self {
+                        Self { handler: std::sync::Arc::new(handler) }
+                    }
+                }
+
+                #[allow(dead_code)]
+                impl<T> #router_name<T>
+                where
+                    T: #handler_trait_name + ?Sized,
+                {
+                    /// For a handler that's shared, or a trait object.
+                    pub fn from_arc(handler: std::sync::Arc<T>) -> Self {
+                        Self { handler }
+                    }
+
+                    /// Registers every method of the service, use it with `RpcRouterExt::rpc`.
+                    pub fn routes<S, R>(self) -> impl FnOnce(R) -> R
+                    where
+                        S: Clone + Send + Sync + 'static,
+                        R: axum_connect::router::RpcRouterExt<S>,
+                    {
+                        #service_name::from_handler(self.handler)
+                    }
                 }
+
+                #client
             }
             .to_string()
             .as_str(),
         );
     }
 
-    fn generate_service_method(&mut self, method: Method, path_root: &str) -> TokenStream {
+    fn generate_handler_trait_method(&self, method: &Method) -> TokenStream {
         let method_name = format_ident!("{}", method.name);
         let input_type: syn::Type = parse_str(&method.input_type).unwrap();
         let output_type: syn::Type = parse_str(&method.output_type).unwrap();
-        let path = format!("/{}/{}", path_root, method.proto_name);
 
         if method.server_streaming {
             quote! {
-                pub fn #method_name<T, H, S, B>(
-                    handler: H
-                ) -> impl FnOnce(axum::Router<S, B>) -> axum_connect::router::RpcRouter<S, B>
-                where
-                    H: axum_connect::handler::RpcHandlerStream<#input_type, #output_type, T, S, B>,
-                    T: 'static,
-                    S: Clone + Send + Sync + 'static,
-                    B: axum::body::HttpBody + Send + 'static,
-                    B::Data: Send,
-                    B::Error: Into<axum::BoxError>,
-                {
-                    move |router: axum::Router<S, B>| {
-                        router.route(
-                            #path,
-                            axum::routing::post(|
-                                axum::extract::State(state): axum::extract::State<S>,
-                                request: axum::http::Request<B>
-                            | async move {
-                                handler.call(request, state).await
-                            }),
-                        )
+                async fn #method_name(
+                    &self,
+                    request: #input_type,
+                ) -> axum_connect::futures::stream::BoxStream<
+                    'static,
+                    axum_connect::response::RpcResult<#output_type>,
+                >;
+            }
+        } else if method.client_streaming {
+            quote! {
+                async fn #method_name(
+                    &self,
+                    request: axum_connect::request::RpcRequestStream<#input_type>,
+                ) -> axum_connect::response::RpcResult<#output_type>;
+            }
+        } else {
+            quote! {
+                async fn #method_name(
+                    &self,
+                    request: #input_type,
+                ) -> axum_connect::response::RpcResult<#output_type>;
+            }
+        }
+    }
+
+    fn generate_client(&self, service_name: &syn::Ident, methods: &[Method]) -> TokenStream {
+        let client_name = format_ident!("{}Client", service_name);
+        let client_doc = format!(
+            " A typed client of `{}`, calling it over any `RpcTransport`.",
+            service_name
+        );
+        let methods = methods.iter().map(|method| {
+            let method_name = format_ident!("{}", method.name);
+            let input_type: syn::Type = parse_str(&method.input_type).unwrap();
+            let output_type: syn::Type = parse_str(&method.output_type).unwrap();
+            let path_const = format_ident!("{}_PATH", method.name.to_uppercase());
+
+            if method.server_streaming {
+                quote! {
+                    pub async fn #method_name(
+                        &self,
+                        request: #input_type,
+                    ) -> axum_connect::futures::stream::BoxStream<
+                        'static,
+                        axum_connect::response::RpcResult<#output_type>,
+                    > {
+                        self.transport
+                            .server_stream(#service_name::#path_const, request)
+                            .await
+                    }
+                }
+            } else if method.client_streaming {
+                quote! {
+                    pub async fn #method_name<R>(
+                        &self,
+                        requests: R,
+                    ) -> axum_connect::response::RpcResult<#output_type>
+                    where
+                        R: axum_connect::futures::Stream<Item = #input_type> + Send + 'static,
+                    {
+                        let requests = axum_connect::futures::StreamExt::boxed(requests);
+                        self.transport
+                            .client_stream(#service_name::#path_const, requests)
+                            .await
+                    }
+                }
+            } else {
+                // `NO_SIDE_EFFECTS` (1) and `IDEMPOTENT` (2) methods may be retried.
+                let call = match method.options.idempotency_level {
+                    Some(1 | 2) => quote!(idempotent_unary),
+                    _ => quote!(unary),
+                };
+                quote! {
+                    pub async fn #method_name(
+                        &self,
+                        request: #input_type,
+                    ) -> axum_connect::response::RpcResult<#output_type> {
+                        self.transport.#call(#service_name::#path_const, request).await
                     }
                 }
             }
+        });
+
+        quote! {
+            #[doc = #client_doc]
+            #[allow(dead_code)]
+            #[derive(Clone, Debug)]
+            pub struct #client_name<T> {
+                transport: T,
+            }
+
+            #[allow(dead_code)]
+            impl<T> #client_name<T>
+            where
+                T: axum_connect::transport::RpcTransport,
+            {
+                pub fn new(transport: T) -> Self {
+                    Self { transport }
+                }
+
+                pub fn transport(&self) -> &T {
+                    &self.transport
+                }
+
+                #(#methods)*
+            }
+        }
+    }
+
+    fn generate_method_type(&self, method: &Method, service_name: &syn::Ident) -> TokenStream {
+        let type_name = format_ident!("{}", method.name.to_case(Case::Pascal));
+        let descriptor_const = format_ident!("{}_DESCRIPTOR", method.name.to_uppercase());
+        // One module down from where prost put the messages.
+        let in_module = |path: &str| -> syn::Type {
+            if path.starts_with("::") || path.starts_with("crate::") {
+                parse_str(path).unwrap()
+            } else {
+                parse_str(&format!("super::{}", path)).unwrap()
+            }
+        };
+        let input_type = in_module(&method.input_type);
+        let output_type = in_module(&method.output_type);
+
+        quote! {
+            pub struct #type_name;
+
+            impl axum_connect::descriptor::RpcMethod for #type_name {
+                type Request = #input_type;
+                type Response = #output_type;
+                const DESCRIPTOR: &'static axum_connect::descriptor::MethodDescriptor =
+                    &super::#service_name::#descriptor_const;
+            }
+        }
+    }
+
+    fn generate_handler_registration(&self, method: &Method) -> TokenStream {
+        let method_name = format_ident!("{}", method.name);
+        let input_type: syn::Type = parse_str(&method.input_type).unwrap();
+        let request_type = if method.client_streaming {
+            quote!(axum_connect::request::RpcRequestStream<#input_type>)
         } else {
+            quote!(#input_type)
+        };
+
+        quote! {
+            let handler_c = handler.clone();
+            let router = Self::#method_name(move |request: #request_type| async move {
+                handler_c.#method_name(request).await
+            })(router);
+        }
+    }
+
+    fn generate_method_descriptor(
+        &self,
+        method: &Method,
+        path_root: &str,
+        path: &str,
+    ) -> TokenStream {
+        let descriptor_const = format_ident!("{}_DESCRIPTOR", method.name.to_uppercase());
+        let name = &method.proto_name;
+        let input_type = method.input_proto_type.trim_start_matches('.');
+        let output_type = method.output_proto_type.trim_start_matches('.');
+        let kind = match (method.client_streaming, method.server_streaming) {
+            (false, false) => quote!(Unary),
+            (false, true) => quote!(ServerStreaming),
+            (true, false) => quote!(ClientStreaming),
+            (true, true) => quote!(BidiStreaming),
+        };
+        let idempotency = match method.options.idempotency_level {
+            Some(1) => quote!(NoSideEffects),
+            Some(2) => quote!(Idempotent),
+            _ => quote!(Unknown),
+        };
+        let deprecated = method.options.deprecated.unwrap_or(false);
+
+        quote! {
+            #[allow(dead_code)]
+            pub const #descriptor_const: axum_connect::descriptor::MethodDescriptor =
+                axum_connect::descriptor::MethodDescriptor {
+                    name: #name,
+                    service: #path_root,
+                    path: #path,
+                    input_type: #input_type,
+                    output_type: #output_type,
+                    kind: axum_connect::descriptor::MethodKind::#kind,
+                    idempotency: axum_connect::descriptor::IdempotencyLevel::#idempotency,
+                    deprecated: #deprecated,
+                };
+        }
+    }
+
+    fn generate_service_method(&self, method: Method, path_root: &str) -> TokenStream {
+        let method_name = format_ident!("{}", method.name);
+        let input_type: syn::Type = parse_str(&method.input_type).unwrap();
+        let output_type: syn::Type = parse_str(&method.output_type).unwrap();
+        let path = format!("/{}/{}", path_root, method.proto_name);
+        let path_const = format_ident!("{}_PATH", method.name.to_uppercase());
+        let service_fn = format_ident!("{}_service", method.name);
+        let descriptor = self.generate_method_descriptor(&method, path_root, &path);
+        let descriptor_const = format_ident!("{}_DESCRIPTOR", method.name.to_uppercase());
+
+        let (handler_trait, service_ctor) = if method.server_streaming {
+            (quote!(RpcHandlerStream), quote!(server_stream))
+        } else if method.client_streaming {
+            (quote!(RpcHandlerClientStream), quote!(client_stream))
+        } else {
+            (quote!(RpcHandlerUnary), quote!(unary))
+        };
+        // Only some callers want these, don't warn in binaries that don't.
+        let service = quote! {
+            #[allow(dead_code)]
+            pub const #path_const: &str = #path;
+
+            #descriptor
+
+            #[allow(dead_code)]
+            pub fn #service_fn<T, H, S>(
+                handler: H,
+                state: S,
+            ) -> axum_connect::handler::RpcService<H, S>
+            where
+                H: axum_connect::handler::#handler_trait<#input_type, #output_type, T, S>,
+            {
+                axum_connect::handler::RpcService::#service_ctor(handler, state)
+            }
+        };
+
+        // Unary methods without side effects are also served for Connect GET requests, which
+        // browsers and CDNs can cache.
+        let allows_get = !method.client_streaming
+            && !method.server_streaming
+            && method.options.idempotency_level == Some(1);
+        let get_route = if allows_get {
             quote! {
-                pub fn #method_name<T, H, S, B>(
-                    handler: H
-                ) -> impl FnOnce(axum::Router<S, B>) -> axum_connect::router::RpcRouter<S, B>
-                where
-                    H: axum_connect::handler::RpcHandlerUnary<#input_type, #output_type, T, S, B>,
-                    T: 'static,
-                    S: Clone + Send + Sync + 'static,
-                    B: axum::body::HttpBody + Send + 'static,
-                    B::Data: Send,
-                    B::Error: Into<axum::BoxError>,
-                {
-                    move |router: axum::Router<S, B>| {
-                        router.route(
-                            #path,
-                            axum::routing::post(|
-                                axum::extract::State(state): axum::extract::State<S>,
-                                request: axum::http::Request<B>
-                            | async move {
-                                handler.call(request, state).await
-                            }),
-                        )
+                .get({
+                    let handler = handler.clone();
+                    move |
+                        axum::extract::State(state): axum::extract::State<S>,
+                        request: axum::extract::Request
+                    | async move {
+                        axum_connect::handler::call_unary_get(handler, request, state).await
                     }
+                })
+            }
+        } else {
+            quote!()
+        };
+
+        let register = quote! {
+            pub fn #method_name<T, H, S, R>(
+                handler: H
+            ) -> impl FnOnce(R) -> R
+            where
+                H: axum_connect::handler::#handler_trait<#input_type, #output_type, T, S>,
+                T: 'static,
+                S: Clone + Send + Sync + 'static,
+                R: axum_connect::router::RpcRouterExt<S>,
+            {
+                move |router: R| {
+                    router.rpc_route(
+                        &Self::#descriptor_const,
+                        axum::routing::MethodRouter::new()
+                        #get_route
+                        .post(|
+                            axum::extract::State(state): axum::extract::State<S>,
+                            request: axum::extract::Request
+                        | async move {
+                            handler.call(request, state).await
+                        })
+                        .fallback(axum_connect::handler::method_not_allowed),
+                    )
                 }
             }
+        };
+
+        quote! {
+            #register
+            #service
         }
     }
 }
 
 impl ServiceGenerator for AxumConnectServiceGenerator {
     fn generate(&mut self, service: Service, buf: &mut String) {
+        for generator in &mut self.extra_generators {
+            generator.generate(service.clone(), buf);
+        }
+
         self.generate_service(service, buf);
     }
+
+    fn finalize(&mut self, buf: &mut String) {
+        for generator in &mut self.extra_generators {
+            generator.finalize(buf);
+        }
+    }
+
+    fn finalize_package(&mut self, package: &str, buf: &mut String) {
+        for generator in &mut self.extra_generators {
+            generator.finalize_package(package, buf);
+        }
+    }
 }
@@ -8,8 +8,68 @@ use std::{
 };
 
 use gen::AxumConnectServiceGenerator;
+use prost_build::ServiceGenerator;
+
+pub use lint::{LintLevel, LintRule, LintSettings};
 
 mod gen;
+mod lint;
+
+// See the `prost-*` features, the same selection as in `axum-connect`: when more than one is
+// enabled, the newest wins.
+#[cfg(feature = "prost-0-13")]
+extern crate pbjson_build_0_7 as pbjson_build;
+#[cfg(feature = "prost-0-13")]
+extern crate prost_0_13 as prost;
+#[cfg(feature = "prost-0-13")]
+pub extern crate prost_build_0_13 as prost_build;
+#[cfg(feature = "prost-0-13")]
+extern crate prost_types_0_13 as prost_types;
+
+#[cfg(all(feature = "prost-0-12", not(feature = "prost-0-13")))]
+extern crate pbjson_build_0_6 as pbjson_build;
+#[cfg(all(feature = "prost-0-12", not(feature = "prost-0-13")))]
+extern crate prost_0_12 as prost;
+#[cfg(all(feature = "prost-0-12", not(feature = "prost-0-13")))]
+pub extern crate prost_build_0_12 as prost_build;
+#[cfg(all(feature = "prost-0-12", not(feature = "prost-0-13")))]
+extern crate prost_types_0_12 as prost_types;
+
+#[cfg(all(
+    feature = "prost-0-11",
+    not(any(feature = "prost-0-12", feature = "prost-0-13"))
+))]
+extern crate pbjson_build_0_5 as pbjson_build;
+#[cfg(all(
+    feature = "prost-0-11",
+    not(any(feature = "prost-0-12", feature = "prost-0-13"))
+))]
+extern crate prost_0_11 as prost;
+#[cfg(all(
+    feature = "prost-0-11",
+    not(any(feature = "prost-0-12", feature = "prost-0-13"))
+))]
+pub extern crate prost_build_0_11 as prost_build;
+#[cfg(all(
+    feature = "prost-0-11",
+    not(any(feature = "prost-0-12", feature = "prost-0-13"))
+))]
+extern crate prost_types_0_11 as prost_types;
+
+#[cfg(not(any(feature = "prost-0-11", feature = "prost-0-12", feature = "prost-0-13")))]
+compile_error!(
+    "axum-connect-build needs one of the `prost-0-11`, `prost-0-12` or `prost-0-13` features"
+);
+
+/// Messages from Google's common protos that `axum-connect` provides, so protos importing them
+/// (like an RPC returning a `google.longrunning.Operation`) work with its helpers.
+const EXTERN_MESSAGES: &[(&str, &str)] = &[
+    (
+        ".google.longrunning.Operation",
+        "::axum_connect::operations::Operation",
+    ),
+    (".google.rpc.Status", "::axum_connect::operations::Status"),
+];
 
 #[derive(Clone, Debug)]
 pub struct AxumConnectGenSettings {
@@ -17,6 +77,15 @@ pub struct AxumConnectGenSettings {
     pub inputs: Vec<PathBuf>,
     pub protoc_args: Vec<String>,
     pub protoc_version: Option<String>,
+    /// Checks run over the input protos before generating code, see `LintRule`.
+    pub lints: LintSettings,
+    /// Also generates a typed `{Service}Client` per service, calling its RPCs over an
+    /// `axum_connect::transport::RpcTransport`.
+    pub clients: bool,
+    /// Also adds a `FILE_DESCRIPTOR_SET` constant to each generated package: the encoded
+    /// `google.protobuf.FileDescriptorSet` of the input protos and their imports, for
+    /// `axum_connect::reflection::Reflection`.
+    pub file_descriptor_set: bool,
 }
 
 impl Default for AxumConnectGenSettings {
@@ -26,6 +95,9 @@ impl Default for AxumConnectGenSettings {
             inputs: Default::default(),
             protoc_args: Default::default(),
             protoc_version: Some("22.3".to_string()),
+            lints: Default::default(),
+            clients: false,
+            file_descriptor_set: false,
         }
     }
 }
@@ -58,6 +130,16 @@ impl AxumConnectGenSettings {
 }
 
 pub fn axum_connect_codegen(settings: AxumConnectGenSettings) -> anyhow::Result<()> {
+    axum_connect_codegen_with_generators(settings, vec![])
+}
+
+/// Same as `axum_connect_codegen`, but also runs each of the given `ServiceGenerator`s over every
+/// service. This is how you generate a tonic gRPC server (via `tonic_build::configure()
+/// .service_generator()`) from the same protos without duplicating the prost message types.
+pub fn axum_connect_codegen_with_generators(
+    settings: AxumConnectGenSettings,
+    extra_generators: Vec<Box<dyn ServiceGenerator>>,
+) -> anyhow::Result<()> {
     // Fetch protoc
     if let Some(version) = &settings.protoc_version {
         let out_dir = env::var("OUT_DIR").unwrap();
@@ -78,7 +160,13 @@ pub fn axum_connect_codegen(settings: AxumConnectGenSettings) -> anyhow::Result<
     conf.compile_well_known_types();
     conf.file_descriptor_set_path(&descriptor_path);
     conf.extern_path(".google.protobuf", "::axum_connect::pbjson_types");
-    conf.service_generator(Box::new(AxumConnectServiceGenerator::new()));
+    for (proto_path, rust_path) in EXTERN_MESSAGES {
+        conf.extern_path(*proto_path, *rust_path);
+    }
+    conf.service_generator(Box::new(
+        AxumConnectServiceGenerator::with_extra_generators(extra_generators)
+            .with_clients(settings.clients),
+    ));
 
     // Arg configuration
     for arg in settings.protoc_args {
@@ -90,7 +178,9 @@ pub fn axum_connect_codegen(settings: AxumConnectGenSettings) -> anyhow::Result<
         .unwrap();
 
     // Use pbjson to generate the Serde impls, and inline them with the Prost files.
-    let descriptor_set = std::fs::read(descriptor_path)?;
+    let descriptor_set = std::fs::read(&descriptor_path)?;
+    lint::lint(&descriptor_set, &settings.inputs, &settings.lints)?;
+
     let mut output: PathBuf = PathBuf::from(env::var("OUT_DIR").unwrap());
     output.push("FILENAME");
 
@@ -98,17 +188,24 @@ pub fn axum_connect_codegen(settings: AxumConnectGenSettings) -> anyhow::Result<
     let files = Rc::new(RefCell::new(vec![]));
 
     let files_c = files.clone();
-    let writers = pbjson_build::Builder::new()
+    let mut builder = pbjson_build::Builder::new();
+    builder
         .register_descriptors(&descriptor_set)?
-        .extern_path(".google.protobuf", "::axum_connect::pbjson_types")
-        .generate(&["."], move |package| {
-            output.set_file_name(format!("{}.rs", package));
-            files_c.deref().borrow_mut().push(output.clone());
+        .extern_path(".google.protobuf", "::axum_connect::pbjson_types");
+    for (proto_path, rust_path) in EXTERN_MESSAGES {
+        builder.extern_path(*proto_path, *rust_path);
+    }
+    let writers = builder.generate(&["."], move |package| {
+        output.set_file_name(format!("{}.rs", package));
+        files_c.deref().borrow_mut().push(output.clone());
 
-            let file = std::fs::OpenOptions::new().append(true).create(true).open(&output)?;
+        let file = std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&output)?;
 
-            Ok(BufWriter::new(file))
-        })?;
+        Ok(BufWriter::new(file))
+    })?;
 
     for (_, mut writer) in writers {
         writer.flush()?;
@@ -119,7 +216,14 @@ pub fn axum_connect_codegen(settings: AxumConnectGenSettings) -> anyhow::Result<
         let contents = std::fs::read_to_string(&file)?;
         let contents = contents.replace("pbjson::", "axum_connect::pbjson::");
         let contents = contents.replace("prost::", "axum_connect::prost::");
-        let contents = contents.replace("serde::", "axum_connect::serde::");
+        let mut contents = contents.replace("serde::", "axum_connect::serde::");
+        if settings.file_descriptor_set {
+            contents.push_str(&format!(
+                "/// The encoded `FileDescriptorSet` of the protos this was generated from, with \
+                 their imports.\npub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!({:?});\n",
+                descriptor_path
+            ));
+        }
         std::fs::write(&file, contents)?;
     }
 
@@ -0,0 +1,244 @@
+use std::{collections::HashMap, path::Path};
+
+use prost::Message;
+use prost_types::{DescriptorProto, EnumDescriptorProto, FileDescriptorProto, FileDescriptorSet};
+
+/// How a lint failure is reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LintLevel {
+    Allow,
+    /// A cargo warning, the build goes on.
+    Warn,
+    /// Fails the build.
+    Deny,
+}
+
+/// The schema hygiene checks run over the input protos (not their imports) before generating code.
+/// They follow the protobuf style guide, as `buf lint` does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LintRule {
+    /// Every file has a package, made of lower_snake_case segments like `acme.billing.v1`.
+    PackageName,
+    /// Services are UpperCamelCase and end in `Service`.
+    ServiceName,
+    /// Methods are UpperCamelCase.
+    MethodName,
+    /// The zero value of every enum is its first, and is named like `STATUS_UNSPECIFIED`.
+    EnumZeroValue,
+    /// Services and methods have a leading comment, which also becomes their Rust docs.
+    Comments,
+}
+
+/// The level of each `LintRule`. By default every rule is allowed, so existing protos build as
+/// they did; turn on the ones you want:
+///
+/// ```ignore
+/// let mut settings = AxumConnectGenSettings::from_directory_recursive("proto")?;
+/// settings.lints = LintSettings::all(LintLevel::Warn)
+///     .level(LintRule::EnumZeroValue, LintLevel::Deny)
+///     .level(LintRule::Comments, LintLevel::Allow);
+/// ```
+#[derive(Clone, Debug)]
+pub struct LintSettings {
+    levels: HashMap<LintRule, LintLevel>,
+}
+
+impl Default for LintSettings {
+    fn default() -> Self {
+        Self::all(LintLevel::Allow)
+    }
+}
+
+impl LintSettings {
+    /// Every rule at `level`, like `LintSettings::all(LintLevel::Warn)` to warn of everything.
+    pub fn all(level: LintLevel) -> Self {
+        let rules = [
+            LintRule::PackageName,
+            LintRule::ServiceName,
+            LintRule::MethodName,
+            LintRule::EnumZeroValue,
+            LintRule::Comments,
+        ];
+        Self {
+            levels: rules.into_iter().map(|rule| (rule, level)).collect(),
+        }
+    }
+
+    pub fn level(mut self, rule: LintRule, level: LintLevel) -> Self {
+        self.levels.insert(rule, level);
+        self
+    }
+
+    fn get(&self, rule: LintRule) -> LintLevel {
+        self.levels.get(&rule).copied().unwrap_or(LintLevel::Allow)
+    }
+}
+
+/// Lints the files of the encoded `descriptor_set` that are among `inputs`, printing warnings for
+/// cargo and failing if any denied rule was broken.
+pub(crate) fn lint(
+    descriptor_set: &[u8],
+    inputs: &[impl AsRef<Path>],
+    settings: &LintSettings,
+) -> anyhow::Result<()> {
+    let descriptor_set = FileDescriptorSet::decode(descriptor_set)?;
+    let mut linter = Linter {
+        settings,
+        denied: vec![],
+    };
+
+    for file in &descriptor_set.file {
+        let is_input = inputs
+            .iter()
+            .any(|input| input.as_ref().ends_with(file.name()));
+        if is_input {
+            linter.lint_file(file);
+        }
+    }
+
+    if linter.denied.is_empty() {
+        Ok(())
+    } else {
+        anyhow::bail!("proto lints failed:\n{}", linter.denied.join("\n"))
+    }
+}
+
+struct Linter<'a> {
+    settings: &'a LintSettings,
+    denied: Vec<String>,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, rule: LintRule, file: &FileDescriptorProto, message: String) {
+        let message = format!("{}: {} ({:?})", file.name(), message, rule);
+        match self.settings.get(rule) {
+            LintLevel::Allow => {}
+            LintLevel::Warn => println!("cargo:warning={}", message),
+            LintLevel::Deny => self.denied.push(message),
+        }
+    }
+
+    fn lint_file(&mut self, file: &FileDescriptorProto) {
+        let package = file.package();
+        if package.is_empty() {
+            self.report(LintRule::PackageName, file, "no package".to_string());
+        } else if !package.split('.').all(is_lower_snake_case) {
+            self.report(
+                LintRule::PackageName,
+                file,
+                format!("package `{}` isn't lower_snake_case", package),
+            );
+        }
+
+        // Leading comments by their location path: services are field 6 of the file, methods
+        // field 2 of the service.
+        let comments: HashMap<&[i32], &str> = file
+            .source_code_info
+            .iter()
+            .flat_map(|info| &info.location)
+            .filter(|location| !location.leading_comments().trim().is_empty())
+            .map(|location| (location.path.as_slice(), location.leading_comments()))
+            .collect();
+
+        for (service_index, service) in file.service.iter().enumerate() {
+            let name = service.name();
+            if !is_upper_camel_case(name) || !name.ends_with("Service") {
+                self.report(
+                    LintRule::ServiceName,
+                    file,
+                    format!(
+                        "service `{}` should be UpperCamelCase ending in `Service`",
+                        name
+                    ),
+                );
+            }
+            if !comments.contains_key([6, service_index as i32].as_slice()) {
+                self.report(
+                    LintRule::Comments,
+                    file,
+                    format!("service `{}` has no comment", name),
+                );
+            }
+
+            for (method_index, method) in service.method.iter().enumerate() {
+                if !is_upper_camel_case(method.name()) {
+                    self.report(
+                        LintRule::MethodName,
+                        file,
+                        format!("method `{}.{}` isn't UpperCamelCase", name, method.name()),
+                    );
+                }
+                let path = [6, service_index as i32, 2, method_index as i32];
+                if !comments.contains_key(path.as_slice()) {
+                    self.report(
+                        LintRule::Comments,
+                        file,
+                        format!("method `{}.{}` has no comment", name, method.name()),
+                    );
+                }
+            }
+        }
+
+        for enumeration in &file.enum_type {
+            self.lint_enum(file, enumeration, enumeration.name());
+        }
+        for message in &file.message_type {
+            self.lint_message_enums(file, message, message.name());
+        }
+    }
+
+    fn lint_message_enums(
+        &mut self,
+        file: &FileDescriptorProto,
+        message: &DescriptorProto,
+        scope: &str,
+    ) {
+        for enumeration in &message.enum_type {
+            self.lint_enum(
+                file,
+                enumeration,
+                &format!("{}.{}", scope, enumeration.name()),
+            );
+        }
+        for nested in &message.nested_type {
+            self.lint_message_enums(file, nested, &format!("{}.{}", scope, nested.name()));
+        }
+    }
+
+    fn lint_enum(
+        &mut self,
+        file: &FileDescriptorProto,
+        enumeration: &EnumDescriptorProto,
+        name: &str,
+    ) {
+        match enumeration.value.first() {
+            Some(zero) if zero.number() == 0 && zero.name().ends_with("_UNSPECIFIED") => {}
+            Some(zero) if zero.number() == 0 => self.report(
+                LintRule::EnumZeroValue,
+                file,
+                format!(
+                    "zero value `{}` of enum `{}` should end in `_UNSPECIFIED`",
+                    zero.name(),
+                    name
+                ),
+            ),
+            _ => self.report(
+                LintRule::EnumZeroValue,
+                file,
+                format!("enum `{}` should start with its zero value", name),
+            ),
+        }
+    }
+}
+
+fn is_lower_snake_case(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+fn is_upper_camel_case(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+        && name.chars().all(|c| c.is_ascii_alphanumeric())
+}
@@ -0,0 +1,321 @@
+//! A server for the Connect conformance suite (https://github.com/connectrpc/conformance), started
+//! by its runner:
+//!
+//! ```sh
+//! cargo build --bin conformance
+//! connectconformance --mode server --conf conformance.yaml -- target/debug/conformance
+//! ```
+//!
+//! The runner writes the configuration to test (a `ServerCompatRequest`) to stdin, and reads where
+//! the server is listening (a `ServerCompatResponse`) back from stdout, both size-delimited: a
+//! big-endian u32 length, then the message. `conformance.yaml` lists the features it's tested
+//! for. Bidi streaming, TLS and HTTP/2 aren't served.
+
+use std::{
+    io::{self, Read, Write},
+    time::Duration,
+};
+
+use async_stream::stream;
+use axum::{extract::Query, http::HeaderMap, Extension, Router};
+use axum_connect::{
+    futures::{Stream, StreamExt},
+    pbjson_types::Any,
+    prelude::*,
+    router::RpcRouterOptions,
+};
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use prost::Message;
+use proto::{
+    conformance_payload::{ConnectGetInfo, RequestInfo},
+    unary_response_definition::Response,
+    *,
+};
+
+// Generated, and the bidi messages go unused as bidi streaming isn't served.
+#[allow(dead_code, clippy::all)]
+mod proto {
+    include!(concat!(env!("OUT_DIR"), "/connectrpc.conformance.v1.rs"));
+}
+
+const TYPE_URL_PREFIX: &str = "type.googleapis.com/connectrpc.conformance.v1.";
+
+#[tokio::main]
+async fn main() {
+    let mut len = [0; 4];
+    let mut stdin = io::stdin();
+    stdin
+        .read_exact(&mut len)
+        .expect("a size-delimited request");
+    let mut buf = vec![0; u32::from_be_bytes(len) as usize];
+    stdin
+        .read_exact(&mut buf)
+        .expect("a size-delimited request");
+    let request = ServerCompatRequest::decode(buf.as_slice()).expect("a ServerCompatRequest");
+    if request.use_tls {
+        eprintln!("TLS isn't supported");
+        std::process::exit(1);
+    }
+
+    let mut config = RpcServiceConfig::new();
+    if request.message_receive_limit > 0 {
+        config = config.max_request_message_size(request.message_receive_limit as usize);
+    }
+    let app = Router::new()
+        .rpc(ConformanceService::unary(unary))
+        .rpc(ConformanceService::idempotent_unary(idempotent_unary))
+        .rpc(ConformanceService::server_stream(server_stream))
+        .rpc(ConformanceService::client_stream(client_stream))
+        .rpc(ConformanceService::unimplemented(unimplemented))
+        .layer(Extension(config))
+        .layer(
+            RpcRouterOptions::new()
+                .grpc(true)
+                .grpc_web(true)
+                .layer([&ConformanceService::DESCRIPTOR]),
+        );
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let response = ServerCompatResponse {
+        host: addr.ip().to_string(),
+        port: addr.port().into(),
+        pem_cert: vec![],
+    };
+    let mut stdout = io::stdout();
+    let response = response.encode_to_vec();
+    stdout
+        .write_all(&(response.len() as u32).to_be_bytes())
+        .and_then(|()| stdout.write_all(&response))
+        .and_then(|()| stdout.flush())
+        .expect("writing the ServerCompatResponse");
+
+    axum::serve(listener, app).await.unwrap();
+}
+
+async fn unary(
+    headers: HeaderMap,
+    deadline: RpcDeadline,
+    request: UnaryRequest,
+) -> RpcResponse<RpcResult<UnaryResponse>> {
+    let info = request_info(&headers, deadline, [any("UnaryRequest", &request)], None);
+    respond(request.response_definition, info, |payload| UnaryResponse {
+        payload: Some(payload),
+    })
+    .await
+}
+
+async fn idempotent_unary(
+    headers: HeaderMap,
+    deadline: RpcDeadline,
+    Query(query): Query<Vec<(String, String)>>,
+    request: IdempotentUnaryRequest,
+) -> RpcResponse<RpcResult<IdempotentUnaryResponse>> {
+    // Only GET requests have the message in the query.
+    let get_info = (!query.is_empty()).then(|| ConnectGetInfo {
+        query_params: query
+            .into_iter()
+            .map(|(name, value)| Header {
+                name,
+                value: vec![value],
+            })
+            .collect(),
+    });
+    let requests = [any("IdempotentUnaryRequest", &request)];
+    let info = request_info(&headers, deadline, requests, get_info);
+    respond(request.response_definition, info, |payload| {
+        IdempotentUnaryResponse {
+            payload: Some(payload),
+        }
+    })
+    .await
+}
+
+async fn client_stream(
+    headers: HeaderMap,
+    deadline: RpcDeadline,
+    mut requests: RpcRequestStream<ClientStreamRequest>,
+) -> RpcResponse<RpcResult<ClientStreamResponse>> {
+    let mut definition = None;
+    let mut received = vec![];
+    while let Some(request) = requests.next().await {
+        let request = match request {
+            Ok(request) => request,
+            Err(e) => return RpcResponse::new(Err(e)),
+        };
+        if received.is_empty() {
+            definition = request.response_definition.clone();
+        }
+        received.push(any("ClientStreamRequest", &request));
+    }
+
+    let info = request_info(&headers, deadline, received, None);
+    respond(definition, info, |payload| ClientStreamResponse {
+        payload: Some(payload),
+    })
+    .await
+}
+
+async fn server_stream(
+    headers: HeaderMap,
+    deadline: RpcDeadline,
+    request: ServerStreamRequest,
+) -> impl Stream<Item = RpcResponse<RpcResult<ServerStreamResponse>>> {
+    let info = request_info(
+        &headers,
+        deadline,
+        [any("ServerStreamRequest", &request)],
+        None,
+    );
+    let definition = request.response_definition.unwrap_or_default();
+    let delay = Duration::from_millis(definition.response_delay_ms.into());
+
+    stream! {
+        // The first message carries the response headers, the last the trailers.
+        let mut headers = Some(definition.response_headers);
+        let mut info = Some(info);
+        let count = definition.response_data.len();
+        for (i, data) in definition.response_data.into_iter().enumerate() {
+            tokio::time::sleep(delay).await;
+            let payload = ConformancePayload {
+                data,
+                request_info: info.take(),
+            };
+            let mut response = RpcResponse::new(Ok(ServerStreamResponse {
+                payload: Some(payload),
+            }));
+            add_headers(response.headers_mut(), headers.take().unwrap_or_default());
+            if i + 1 == count && definition.error.is_none() {
+                add_headers(response.trailers_mut(), definition.response_trailers.clone());
+            }
+            yield response;
+        }
+
+        if let Some(error) = definition.error {
+            tokio::time::sleep(delay).await;
+            // The request info goes in the error when no message could carry it.
+            let mut response = RpcResponse::new(Err(rpc_error(error, info.as_ref())));
+            add_headers(response.headers_mut(), headers.take().unwrap_or_default());
+            add_headers(response.trailers_mut(), definition.response_trailers);
+            yield response;
+        }
+    }
+}
+
+async fn unimplemented(_request: UnimplementedRequest) -> RpcResult<UnimplementedResponse> {
+    Err(RpcError::new(
+        RpcErrorCode::Unimplemented,
+        "connectrpc.conformance.v1.ConformanceService.Unimplemented is not implemented".to_string(),
+    ))
+}
+
+/// Answers as `definition` says, after its delay, with `info` in the payload or the error.
+async fn respond<T>(
+    definition: Option<UnaryResponseDefinition>,
+    info: RequestInfo,
+    response: impl FnOnce(ConformancePayload) -> T,
+) -> RpcResponse<RpcResult<T>> {
+    let definition = definition.unwrap_or_default();
+    tokio::time::sleep(Duration::from_millis(definition.response_delay_ms.into())).await;
+
+    let result = match definition.response {
+        Some(Response::Error(error)) => Err(rpc_error(error, Some(&info))),
+        Some(Response::ResponseData(data)) => Ok(response(ConformancePayload {
+            data,
+            request_info: Some(info),
+        })),
+        None => Ok(response(ConformancePayload {
+            data: vec![],
+            request_info: Some(info),
+        })),
+    };
+    let mut res = RpcResponse::new(result);
+    add_headers(res.headers_mut(), definition.response_headers);
+    add_headers(res.trailers_mut(), definition.response_trailers);
+    res
+}
+
+fn request_info(
+    headers: &HeaderMap,
+    deadline: RpcDeadline,
+    requests: impl IntoIterator<Item = Any>,
+    connect_get_info: Option<ConnectGetInfo>,
+) -> RequestInfo {
+    let mut request_headers: Vec<Header> = vec![];
+    for (name, value) in headers {
+        let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+        match request_headers.iter_mut().find(|h| h.name == name.as_str()) {
+            Some(header) => header.value.push(value),
+            None => request_headers.push(Header {
+                name: name.to_string(),
+                value: vec![value],
+            }),
+        }
+    }
+
+    RequestInfo {
+        request_headers,
+        timeout_ms: deadline
+            .remaining()
+            .map(|remaining| remaining.as_millis() as i64),
+        requests: requests.into_iter().collect(),
+        connect_get_info,
+    }
+}
+
+/// Adds conformance headers to response metadata. Binary (`-bin`) values are given raw.
+fn add_headers(metadata: &mut RpcMetadata, headers: Vec<Header>) {
+    for header in headers {
+        let name = header.name.to_ascii_lowercase();
+        for value in header.value {
+            if name.ends_with("-bin") {
+                metadata.append_bin(&name, value.into_bytes());
+            } else {
+                metadata.append(&name, value);
+            }
+        }
+    }
+}
+
+fn rpc_error(error: Error, info: Option<&RequestInfo>) -> RpcError {
+    let code = match error.code() {
+        Code::Canceled => RpcErrorCode::Canceled,
+        Code::Unspecified | Code::Unknown => RpcErrorCode::Unknown,
+        Code::InvalidArgument => RpcErrorCode::InvalidArgument,
+        Code::DeadlineExceeded => RpcErrorCode::DeadlineExceeded,
+        Code::NotFound => RpcErrorCode::NotFound,
+        Code::AlreadyExists => RpcErrorCode::AlreadyExists,
+        Code::PermissionDenied => RpcErrorCode::PermissionDenied,
+        Code::ResourceExhausted => RpcErrorCode::ResourceExhausted,
+        Code::FailedPrecondition => RpcErrorCode::FailedPrecondition,
+        Code::Aborted => RpcErrorCode::Aborted,
+        Code::OutOfRange => RpcErrorCode::OutOfRange,
+        Code::Unimplemented => RpcErrorCode::Unimplemented,
+        Code::Internal => RpcErrorCode::Internal,
+        Code::Unavailable => RpcErrorCode::Unavailable,
+        Code::DataLoss => RpcErrorCode::DataLoss,
+        Code::Unauthenticated => RpcErrorCode::Unauthenticated,
+    };
+
+    let mut e = RpcError::new(code, error.message.unwrap_or_default());
+    for detail in error.details {
+        let proto_type = detail.type_url.rsplit('/').next().unwrap_or_default();
+        e = e.with_detail(RpcErrorDetail {
+            proto_type: proto_type.to_string(),
+            proto_b62_value: STANDARD_NO_PAD.encode(&detail.value),
+            debug: None,
+        });
+    }
+    if let Some(info) = info {
+        let name = "connectrpc.conformance.v1.ConformancePayload.RequestInfo";
+        e = e.with_detail(RpcErrorDetail::new(name, info));
+    }
+    e
+}
+
+fn any<M: Message>(name: &str, message: &M) -> Any {
+    Any {
+        type_url: format!("{}{}", TYPE_URL_PREFIX, name),
+        value: message.encode_to_vec().into(),
+    }
+}
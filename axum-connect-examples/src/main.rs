@@ -1,8 +1,13 @@
 use std::net::SocketAddr;
 
 use async_stream::stream;
-use axum::{extract::Host, Router};
-use axum_connect::{futures::Stream, prelude::*};
+use axum::Router;
+use axum_connect::{
+    futures::{Stream, StreamExt},
+    prelude::*,
+    rpc_handler,
+};
+use axum_extra::extract::Host;
 use proto::hello::*;
 
 mod proto {
@@ -18,17 +23,19 @@ async fn main() {
     // just a normal Rust function. Just like Axum, it also supports extractors!
     let app = Router::new()
         .rpc(HelloWorldService::say_hello(say_hello_success))
-        .rpc(HelloWorldService::say_hello_stream(say_hello_stream));
+        .rpc(HelloWorldService::say_hello_stream(say_hello_stream))
+        .rpc(HelloWorldService::say_hello_to_all(say_hello_to_all));
 
     // Axum boilerplate to start the server.
     let addr = SocketAddr::from(([127, 0, 0, 1], 3030));
     println!("listening on http://{}", addr);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    axum::serve(listener, app).await.unwrap();
 }
 
+// `#[rpc_handler]` (from the `macros` feature) is optional. It checks the handler against the RPC
+// method it's for, and reports any mismatch on the offending argument.
+#[rpc_handler(hello_world_service::SayHello)]
 async fn say_hello_success(Host(host): Host, request: HelloRequest) -> HelloResponse {
     HelloResponse {
         message: format!(
@@ -39,6 +46,7 @@ async fn say_hello_success(Host(host): Host, request: HelloRequest) -> HelloResp
     }
 }
 
+#[rpc_handler(hello_world_service::SayHelloStream)]
 async fn say_hello_stream(
     Host(host): Host,
     request: HelloRequest,
@@ -52,3 +60,17 @@ async fn say_hello_stream(
         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
     }
 }
+
+#[rpc_handler(hello_world_service::SayHelloToAll)]
+async fn say_hello_to_all(
+    mut requests: RpcRequestStream<HelloRequest>,
+) -> RpcResult<HelloResponse> {
+    let mut names = vec![];
+    while let Some(request) = requests.next().await {
+        names.push(request?.name.unwrap_or_else(|| "unnamed".to_string()));
+    }
+
+    Ok(HelloResponse {
+        message: format!("Hello {}!", names.join(", ")),
+    })
+}
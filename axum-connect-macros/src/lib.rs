@@ -0,0 +1,420 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote, quote_spanned};
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input, parse_quote,
+    spanned::Spanned,
+    Attribute, Data, DeriveInput, Fields, FnArg, GenericArgument, Ident, ItemFn, LitStr,
+    PathArguments, ReturnType, Token, Type, TypeImplTrait, TypeParamBound,
+};
+
+/// Checks an async fn against the RPC method it handles, one of the `RpcMethod` types generated
+/// next to each service (`hello_world_service::SayHello` for `HelloWorldService.SayHello`):
+///
+/// ```ignore
+/// #[rpc_handler(hello_world_service::SayHello, state = AppState)]
+/// async fn say_hello(
+///     Host(host): Host,
+///     State(state): State<AppState>,
+///     request: HelloRequest,
+/// ) -> Result<HelloResponse, RpcError> {
+///     ...
+/// }
+///
+/// let app = Router::new()
+///     .rpc(HelloWorldService::say_hello(say_hello))
+///     .with_state(state);
+/// ```
+///
+/// A request, response or extractor type that doesn't fit is reported on that type, and a handler
+/// returning a stream for a unary method (or the other way around, or likewise taking an
+/// `RpcRequestStream` of requests) is reported as such, instead of as a missing `RpcHandlerUnary`
+/// impl where the handler is registered. Extractors can only be checked here against a known
+/// router state, given with `state = ...`; without it they're checked on registration.
+///
+/// The function itself is left as written; the checks go in a `const _: () = ...` item next to it.
+#[proc_macro_attribute]
+pub fn rpc_handler(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as Args);
+    let function = parse_macro_input!(item as ItemFn);
+
+    expand(args, function)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct Args {
+    method: syn::Path,
+    state: Option<Type>,
+}
+
+impl Parse for Args {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let method = input.parse()?;
+        let mut state = None;
+
+        if input.parse::<Option<Token![,]>>()?.is_some() && !input.is_empty() {
+            let key: Ident = input.parse()?;
+            if key != "state" {
+                return Err(syn::Error::new(key.span(), "expected `state = <type>`"));
+            }
+            input.parse::<Token![=]>()?;
+            state = Some(input.parse()?);
+            input.parse::<Option<Token![,]>>()?;
+        }
+
+        Ok(Self { method, state })
+    }
+}
+
+fn expand(args: Args, function: ItemFn) -> syn::Result<TokenStream2> {
+    let sig = &function.sig;
+    if sig.asyncness.is_none() {
+        return Err(syn::Error::new_spanned(
+            sig.fn_token,
+            "`#[rpc_handler]` functions must be `async`",
+        ));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &sig.generics,
+            "`#[rpc_handler]` functions can't be generic",
+        ));
+    }
+
+    let mut inputs = vec![];
+    for input in &sig.inputs {
+        match input {
+            FnArg::Typed(input) => inputs.push(input.clone()),
+            FnArg::Receiver(receiver) => {
+                return Err(syn::Error::new_spanned(
+                    receiver,
+                    "`#[rpc_handler]` functions can't take `self`",
+                ))
+            }
+        }
+    }
+    let request = inputs.pop().ok_or_else(|| {
+        syn::Error::new_spanned(
+            &sig.ident,
+            "`#[rpc_handler]` functions take the request message as their last argument",
+        )
+    })?;
+
+    let method = &args.method;
+    let name = &sig.ident;
+    let mut checks = vec![];
+
+    // Client-streaming handlers take an `RpcRequestStream<...>` of request messages instead.
+    let request_type = &request.ty;
+    let client_streaming = is_request_stream(request_type);
+    if client_streaming {
+        checks.push(quote_spanned! {request_type.span()=>
+            ::axum_connect::handler::rpc_handler::request_stream::<#method>(
+                ::core::marker::PhantomData::<#request_type>,
+            );
+        });
+    } else {
+        checks.push(quote_spanned! {request_type.span()=>
+            ::axum_connect::handler::rpc_handler::request::<#method, #request_type>();
+        });
+    }
+
+    if let Some(state) = &args.state {
+        for input in &inputs {
+            let ty = &input.ty;
+            checks.push(quote_spanned! {ty.span()=>
+                ::axum_connect::handler::rpc_handler::extractor::<#method, #ty, #state>();
+            });
+        }
+    }
+
+    // Streaming handlers are async fns returning an `impl Stream<Item = ...>`.
+    let (streaming, response_type) = match &sig.output {
+        ReturnType::Default => (false, Some(parse_quote!(()))),
+        ReturnType::Type(_, ty) => match &**ty {
+            Type::ImplTrait(ty) => (true, stream_item(ty)),
+            ty => (false, Some(ty.clone())),
+        },
+    };
+    if let Some(response_type) = response_type {
+        let span = match &sig.output {
+            ReturnType::Default => name.span(),
+            ReturnType::Type(..) => response_type.span(),
+        };
+        checks.push(quote_spanned! {span=>
+            ::axum_connect::handler::rpc_handler::response::<#method, #response_type>();
+        });
+    }
+
+    let response_check = if streaming {
+        let message = format!(
+            "`{}` returns a stream, but the RPC method it handles has a single response",
+            name
+        );
+        quote!(assert!(::axum_connect::handler::rpc_handler::is_server_streaming::<#method>(), #message);)
+    } else {
+        let message = format!(
+            "`{}` returns a single response, but the RPC method it handles is server streaming",
+            name
+        );
+        quote!(assert!(!::axum_connect::handler::rpc_handler::is_server_streaming::<#method>(), #message);)
+    };
+    let request_check = if client_streaming {
+        let message = format!(
+            "`{}` takes an `RpcRequestStream`, but the RPC method it handles has a single request",
+            name
+        );
+        quote!(assert!(::axum_connect::handler::rpc_handler::is_client_streaming::<#method>(), #message);)
+    } else {
+        let message = format!(
+            "`{}` takes a single request, but the RPC method it handles is client streaming",
+            name
+        );
+        quote!(assert!(!::axum_connect::handler::rpc_handler::is_client_streaming::<#method>(), #message);)
+    };
+
+    Ok(quote! {
+        #function
+
+        const _: () = {
+            #request_check
+            #response_check
+
+            #[allow(dead_code)]
+            fn checks() {
+                #(#checks)*
+            }
+        };
+    })
+}
+
+/// Whether the request argument is an `RpcRequestStream<...>`, going by its name like the
+/// `impl Stream` return type of streaming handlers.
+fn is_request_stream(ty: &Type) -> bool {
+    match ty {
+        Type::Path(ty) => ty
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "RpcRequestStream"),
+        _ => false,
+    }
+}
+
+/// `X` of an `impl Stream<Item = X>`.
+fn stream_item(ty: &TypeImplTrait) -> Option<Type> {
+    ty.bounds.iter().find_map(|bound| {
+        let TypeParamBound::Trait(bound) = bound else {
+            return None;
+        };
+        let segment = bound.path.segments.last()?;
+        if segment.ident != "Stream" {
+            return None;
+        }
+        let PathArguments::AngleBracketed(arguments) = &segment.arguments else {
+            return None;
+        };
+        arguments.args.iter().find_map(|argument| match argument {
+            GenericArgument::AssocType(assoc) if assoc.ident == "Item" => Some(assoc.ty.clone()),
+            _ => None,
+        })
+    })
+}
+
+/// Implements `RpcIntoError` (and `From` into `RpcError`) for an error enum (or struct), so
+/// handlers can return it or `?` it into an `RpcResult`. Each variant picks its code with `#[rpc(code = ...)]`, falling back to
+/// the one on the type and then to `Internal`. The message is its `Display` output, or a
+/// `message = "..."` format string over the variant's fields (`{0}` for tuple fields). Variants
+/// wrapping another `RpcIntoError`, like an `RpcError`, can pass it on with `#[rpc(transparent)]`:
+///
+/// ```ignore
+/// #[derive(Debug, thiserror::Error, RpcIntoError)]
+/// enum ShopError {
+///     #[error("no item {0}")]
+///     #[rpc(code = NotFound)]
+///     NoSuchItem(String),
+///     #[error("out of stock")]
+///     #[rpc(code = FailedPrecondition, message = "only {left} of {sku} left")]
+///     OutOfStock { sku: String, left: u32 },
+///     #[error(transparent)]
+///     #[rpc(transparent)]
+///     Rpc(#[from] RpcError),
+///     #[error("database error")]
+///     Database(#[from] sqlx::Error),
+/// }
+/// ```
+#[proc_macro_derive(RpcIntoError, attributes(rpc))]
+pub fn derive_rpc_into_error(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+
+    expand_into_error(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct ErrorArgs {
+    code: Option<Ident>,
+    message: Option<LitStr>,
+    transparent: bool,
+}
+
+impl ErrorArgs {
+    fn from_attributes(attributes: &[Attribute]) -> syn::Result<Self> {
+        let mut args = Self::default();
+        for attribute in attributes.iter().filter(|a| a.path().is_ident("rpc")) {
+            attribute.parse_nested_meta(|meta| {
+                if meta.path.is_ident("code") {
+                    args.code = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("message") {
+                    args.message = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("transparent") {
+                    args.transparent = true;
+                } else {
+                    return Err(meta.error("expected `code`, `message` or `transparent`"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(args)
+    }
+}
+
+fn expand_into_error(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let type_args = ErrorArgs::from_attributes(&input.attrs)?;
+    if type_args.transparent {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "`#[rpc(transparent)]` goes on variants",
+        ));
+    }
+    let default_code = type_args
+        .code
+        .unwrap_or_else(|| Ident::new("Internal", proc_macro2::Span::call_site()));
+
+    let arms = match &input.data {
+        Data::Enum(data) => data
+            .variants
+            .iter()
+            .map(|variant| {
+                let ident = &variant.ident;
+                let args = ErrorArgs::from_attributes(&variant.attrs)?;
+                error_arm(quote!(Self::#ident), &variant.fields, args, &default_code)
+            })
+            .collect::<syn::Result<Vec<_>>>()?,
+        Data::Struct(data) => {
+            let args = ErrorArgs {
+                code: Some(default_code.clone()),
+                message: type_args.message,
+                transparent: false,
+            };
+            vec![error_arm(quote!(Self), &data.fields, args, &default_code)?]
+        }
+        Data::Union(data) => {
+            return Err(syn::Error::new_spanned(
+                data.union_token,
+                "`RpcIntoError` can't be derived for unions",
+            ))
+        }
+    };
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::axum_connect::error::RpcIntoError for #name #type_generics
+        #where_clause
+        {
+            #[allow(unused_variables)]
+            fn rpc_into_error(self) -> ::axum_connect::error::RpcError {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+
+        impl #impl_generics ::core::convert::From<#name #type_generics>
+            for ::axum_connect::error::RpcError
+        #where_clause
+        {
+            fn from(error: #name #type_generics) -> Self {
+                ::axum_connect::error::RpcIntoError::rpc_into_error(error)
+            }
+        }
+    })
+}
+
+/// The match arm turning one variant (or the struct) into an `RpcError`.
+fn error_arm(
+    path: TokenStream2,
+    fields: &Fields,
+    args: ErrorArgs,
+    default_code: &Ident,
+) -> syn::Result<TokenStream2> {
+    // Tuple fields are bound as `_0`, `_1`..., for `{0}` in messages to refer to.
+    let bindings: Vec<Ident> = fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match &field.ident {
+            Some(ident) => ident.clone(),
+            None => format_ident!("_{}", i),
+        })
+        .collect();
+
+    if args.transparent {
+        if bindings.len() != 1 {
+            return Err(syn::Error::new_spanned(
+                fields,
+                "`#[rpc(transparent)]` variants have exactly one field",
+            ));
+        }
+        let pattern = fields_pattern(&path, fields, quote!(inner));
+        return Ok(quote! {
+            #pattern => ::axum_connect::error::RpcIntoError::rpc_into_error(inner),
+        });
+    }
+
+    let code = args.code.as_ref().unwrap_or(default_code);
+    let message = match &args.message {
+        Some(message) => {
+            let message = LitStr::new(&positional_to_named(&message.value()), message.span());
+            quote!(::std::format!(#message))
+        }
+        None => quote!(::std::string::ToString::to_string(&self)),
+    };
+    let pattern = fields_pattern(&path, fields, quote!(#(ref #bindings),*));
+
+    Ok(quote! {
+        #pattern => ::axum_connect::error::RpcError::new(
+            ::axum_connect::error::RpcErrorCode::#code,
+            #message,
+        ),
+    })
+}
+
+fn fields_pattern(path: &TokenStream2, fields: &Fields, bindings: TokenStream2) -> TokenStream2 {
+    match fields {
+        Fields::Named(_) => quote!(#path { #bindings }),
+        Fields::Unnamed(_) => quote!(#path(#bindings)),
+        Fields::Unit => quote!(#path),
+    }
+}
+
+/// `{0}` as `{_0}` (and `{0:?}` as `{_0:?}`), the names tuple fields are bound to.
+fn positional_to_named(message: &str) -> String {
+    let mut named = String::with_capacity(message.len());
+    let mut chars = message.chars().peekable();
+    while let Some(c) = chars.next() {
+        named.push(c);
+        if c == '{' {
+            if chars.peek() == Some(&'{') {
+                named.push(chars.next().unwrap());
+            } else if chars.peek().is_some_and(char::is_ascii_digit) {
+                named.push('_');
+            }
+        }
+    }
+    named
+}
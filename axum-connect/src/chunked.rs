@@ -0,0 +1,279 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use async_stream::stream;
+use axum::BoxError;
+use bytes::{Bytes, BytesMut};
+use futures::{channel::mpsc, Sink, Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::error::{RpcError, RpcErrorCode};
+
+/// One message of a chunked byte stream, see `ByteChunk`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Chunk {
+    Data(Bytes),
+    Trailer(ChunkTrailer),
+}
+
+/// Sent as the last message of a chunked byte stream, so the receiver can tell a complete transfer
+/// from a truncated or corrupted one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChunkTrailer {
+    /// Total size of the data, in bytes.
+    pub size: u64,
+    /// CRC-32C (Castagnoli) of the data.
+    pub crc32c: u32,
+}
+
+/// A message type used to move a byte stream (a file, a blob) as a stream of chunks. The convention
+/// is a `oneof` of a data chunk and a trailer:
+///
+/// ```protobuf
+/// message FileChunk {
+///   oneof kind {
+///     bytes data = 1;
+///     ChunkTrailer trailer = 2;
+///   }
+/// }
+///
+/// message ChunkTrailer {
+///   uint64 size = 1;
+///   fixed32 crc32c = 2;
+/// }
+/// ```
+///
+/// Messages that are just `bytes data = 1;` work too, they just don't get integrity checking.
+pub trait ByteChunk: Sized {
+    fn data_chunk(data: Bytes) -> Self;
+
+    /// The trailer message, or `None` if this message type can't carry one.
+    fn trailer_chunk(trailer: ChunkTrailer) -> Option<Self> {
+        let _ = trailer;
+        None
+    }
+
+    fn into_chunk(self) -> Chunk;
+}
+
+/// Reads `reader` to the end as a stream of `chunk_size` (at most) chunks, followed by a trailer.
+/// Return it straight from a server streaming handler to serve a download:
+///
+/// ```ignore
+/// async fn download(request: DownloadRequest) -> impl Stream<Item = Result<FileChunk, RpcError>> {
+///     let file = tokio::fs::File::open(&request.path).await.unwrap();
+///     read_chunks(file, 64 * 1024)
+/// }
+/// ```
+pub fn read_chunks<R, M>(reader: R, chunk_size: usize) -> impl Stream<Item = Result<M, RpcError>>
+where
+    R: AsyncRead + Send + 'static,
+    M: ByteChunk + Send,
+{
+    stream! {
+        let mut data = Box::pin(ReaderStream::with_capacity(reader, chunk_size));
+        let mut trailer = ChunkTrailer::default();
+
+        while let Some(chunk) = data.next().await {
+            match chunk {
+                Ok(chunk) => {
+                    trailer.size += chunk.len() as u64;
+                    trailer.crc32c = crc32c::crc32c_append(trailer.crc32c, &chunk);
+                    yield Ok(M::data_chunk(chunk));
+                }
+                Err(e) => {
+                    yield Err(RpcError::new(
+                        RpcErrorCode::Internal,
+                        format!("Failed to read data. {}", e),
+                    ));
+                    return;
+                }
+            }
+        }
+
+        if let Some(trailer) = M::trailer_chunk(trailer) {
+            yield Ok(trailer);
+        }
+    }
+}
+
+/// The reverse of `read_chunks`: reads a stream of chunk messages as one `AsyncRead`, checking the
+/// size and checksum when the trailer arrives. A mismatch, or data after the trailer, fails the read
+/// with `InvalidData`.
+pub fn chunks_reader<S, M, E>(chunks: S) -> impl AsyncRead + Send
+where
+    S: Stream<Item = Result<M, E>> + Send + 'static,
+    M: ByteChunk + Send,
+    E: Into<BoxError> + Send,
+{
+    let data = stream! {
+        let mut chunks = Box::pin(chunks);
+        let mut received = ChunkTrailer::default();
+        let mut trailer = None;
+
+        while let Some(chunk) = chunks.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk.into_chunk(),
+                Err(e) => {
+                    yield Err(io::Error::other(e.into()));
+                    return;
+                }
+            };
+
+            match (chunk, trailer) {
+                (_, Some(_)) => {
+                    yield Err(invalid_data("Chunk received after the trailer".to_string()));
+                    return;
+                }
+                (Chunk::Data(data), None) => {
+                    received.size += data.len() as u64;
+                    received.crc32c = crc32c::crc32c_append(received.crc32c, &data);
+                    yield Ok(data);
+                }
+                (Chunk::Trailer(expected), None) => {
+                    if expected != received {
+                        yield Err(invalid_data(format!(
+                            "Chunked data doesn't match its trailer, expected {:?} but got {:?}",
+                            expected, received
+                        )));
+                        return;
+                    }
+                    trailer = Some(expected);
+                }
+            }
+        }
+    };
+
+    StreamReader::new(Box::pin(data))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// An `AsyncWrite` whose writes come out of the paired stream as chunk messages, for producing a
+/// download from code that wants to write (an encoder, an archiver). Bytes are sent in chunks of
+/// `chunk_size`, and `shutdown` sends the trailer and ends the stream:
+///
+/// ```ignore
+/// async fn export(request: ExportRequest) -> impl Stream<Item = Result<FileChunk, RpcError>> {
+///     let (mut writer, chunks) = chunk_writer(64 * 1024);
+///     tokio::spawn(async move {
+///         if let Err(e) = write_export(&mut writer, request).await {
+///             return writer.abort(e.into());
+///         }
+///         let _ = writer.shutdown().await;
+///     });
+///     chunks
+/// }
+/// ```
+///
+/// Dropping the writer without a `shutdown` ends the stream without a trailer.
+pub fn chunk_writer<M>(
+    chunk_size: usize,
+) -> (ChunkWriter<M>, impl Stream<Item = Result<M, RpcError>>)
+where
+    M: ByteChunk,
+{
+    let (sender, receiver) = mpsc::channel(1);
+    let writer = ChunkWriter {
+        sender,
+        buffer: BytesMut::with_capacity(chunk_size),
+        chunk_size: chunk_size.max(1),
+        trailer: ChunkTrailer::default(),
+        trailer_sent: false,
+    };
+
+    (writer, receiver)
+}
+
+/// See `chunk_writer`.
+pub struct ChunkWriter<M> {
+    sender: mpsc::Sender<Result<M, RpcError>>,
+    buffer: BytesMut,
+    chunk_size: usize,
+    trailer: ChunkTrailer,
+    trailer_sent: bool,
+}
+
+impl<M> ChunkWriter<M>
+where
+    M: ByteChunk,
+{
+    /// Ends the stream with `error` instead of a trailer.
+    pub fn abort(mut self, error: RpcError) {
+        // If the stream is already full the receiver sees the stream end without a trailer, which
+        // is still distinguishable from success.
+        let _ = self.sender.try_send(Err(error));
+    }
+
+    fn poll_send_buffer(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(Pin::new(&mut self.sender).poll_ready(cx)).map_err(broken_pipe)?;
+
+        let data = self.buffer.split().freeze();
+        self.trailer.size += data.len() as u64;
+        self.trailer.crc32c = crc32c::crc32c_append(self.trailer.crc32c, &data);
+        Pin::new(&mut self.sender)
+            .start_send(Ok(M::data_chunk(data)))
+            .map_err(broken_pipe)?;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn broken_pipe(e: mpsc::SendError) -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, e)
+}
+
+impl<M> AsyncWrite for ChunkWriter<M>
+where
+    M: ByteChunk,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.buffer.len() >= this.chunk_size {
+            ready!(this.poll_send_buffer(cx))?;
+        }
+
+        let len = buf.len().min(this.chunk_size - this.buffer.len());
+        this.buffer.extend_from_slice(&buf[..len]);
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.buffer.is_empty() {
+            ready!(this.poll_send_buffer(cx))?;
+        }
+        Pin::new(&mut this.sender)
+            .poll_flush(cx)
+            .map_err(broken_pipe)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+
+        let this = self.get_mut();
+        if !this.trailer_sent {
+            ready!(Pin::new(&mut this.sender).poll_ready(cx)).map_err(broken_pipe)?;
+            if let Some(trailer) = M::trailer_chunk(this.trailer) {
+                Pin::new(&mut this.sender)
+                    .start_send(Ok(trailer))
+                    .map_err(broken_pipe)?;
+            }
+            this.trailer_sent = true;
+        }
+
+        Pin::new(&mut this.sender)
+            .poll_close(cx)
+            .map_err(broken_pipe)
+    }
+}
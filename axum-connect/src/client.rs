@@ -0,0 +1,779 @@
+//! A Connect client over HTTP, with the `client` feature.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_stream::stream;
+use async_trait::async_trait;
+use axum::{
+    body::Bytes,
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    BoxError,
+};
+use bytes::BytesMut;
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use prost::Message;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    compression::{compress_response, decompress, Compression, RpcCompressions},
+    error::{RpcError, RpcErrorCode},
+    error_details::RetryInfo,
+    handler::body::{envelope, Envelope, FLAG_COMPRESSED, FLAG_END_STREAM},
+    metadata::RpcMetadata,
+    prelude::RpcResult,
+    transport::{end_stream_error, error_response, RpcTransport},
+};
+
+/// Calls the RPCs of a Connect server over HTTP (with reqwest), as the `RpcTransport` of the
+/// generated `{Service}Client`s:
+///
+/// ```ignore
+/// let transport = RpcClient::new("https://hello.example.com")
+///     .timeout(Duration::from_secs(5))
+///     .compress_requests(Gzip, 1024);
+/// let client = HelloWorldServiceClient::new(transport);
+/// let res = client.say_hello(HelloRequest { name: Some("Alec".into()) }).await?;
+/// ```
+///
+/// Requests are binary proto unless switched to JSON with `json()`. Responses may be compressed
+/// with any of `compressions` (gzip, and `br` and `zstd` with their features, by default). Errors
+/// come back as the `RpcError` the server failed with, with the response headers (of a unary call)
+/// or the EndStreamResponse metadata (of a stream) as its metadata. Failures to reach the server
+/// are `Unavailable`, and running out of `timeout` is `DeadlineExceeded`. Calls of idempotent
+/// unary methods that fail with a retryable code are tried again when there's a `retry` policy.
+///
+/// Clones share their connection pool, so per call headers are a `clone().header(..)` away.
+#[derive(Clone)]
+pub struct RpcClient {
+    base_url: String,
+    http: reqwest::Client,
+    json: bool,
+    headers: HeaderMap,
+    timeout: Option<Duration>,
+    compressions: RpcCompressions,
+    request_compression: Option<(Arc<dyn Compression>, usize)>,
+    max_message_size: usize,
+    retry: Option<RetryPolicy>,
+}
+
+impl RpcClient {
+    /// A client of the server at `base_url`, like `https://hello.example.com`, which RPC paths
+    /// are appended to. It may end in the prefix the services are mounted under, like
+    /// `https://hello.example.com/api` (see `RpcRouterExt::rpc_nest`).
+    pub fn new(base_url: impl Into<String>) -> Self {
+        let mut base_url = base_url.into();
+        while base_url.ends_with('/') {
+            base_url.pop();
+        }
+        Self {
+            base_url,
+            http: reqwest::Client::new(),
+            json: false,
+            headers: HeaderMap::new(),
+            timeout: None,
+            compressions: RpcCompressions::default(),
+            request_compression: None,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            retry: None,
+        }
+    }
+
+    /// Uses `client` for the calls, for custom TLS roots, proxies or connection pooling.
+    pub fn http_client(mut self, client: reqwest::Client) -> Self {
+        self.http = client;
+        self
+    }
+
+    /// Sends and asks for JSON instead of binary proto.
+    pub fn json(mut self) -> Self {
+        self.json = true;
+        self
+    }
+
+    /// Adds a header to every request, like `authorization` or other custom metadata.
+    ///
+    /// # Panics
+    ///
+    /// If `name` or `value` aren't a valid header name or value.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.append(
+            HeaderName::from_bytes(name.as_bytes()).expect("a valid header name"),
+            HeaderValue::from_str(value).expect("a valid header value"),
+        );
+        self
+    }
+
+    /// Gives every call `timeout` to finish, streams and retries included. The server is told
+    /// (what's left of it) with `connect-timeout-ms`, so it gives up at the same time.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Compresses request messages of at least `min_size` bytes (as encoded) with `compression`,
+    /// which the server has to support.
+    pub fn compress_requests<C>(mut self, compression: C, min_size: usize) -> Self
+    where
+        C: Compression,
+    {
+        self.request_compression = Some((Arc::new(compression), min_size));
+        self
+    }
+
+    /// The compressions responses may use, offered in `Accept-Encoding`.
+    pub fn compressions(mut self, compressions: RpcCompressions) -> Self {
+        self.compressions = compressions;
+        self
+    }
+
+    /// Fails response messages bigger than `max` bytes (decompressed) with `ResourceExhausted`.
+    /// 4 MiB by default.
+    pub fn max_message_size(mut self, max: usize) -> Self {
+        self.max_message_size = max;
+        self
+    }
+
+    /// Retries failed calls of idempotent unary methods (see `RpcTransport::idempotent_unary`) as
+    /// `policy` says. Streams are never retried, as their requests are gone once sent.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    async fn send(
+        &self,
+        path: &str,
+        streaming: bool,
+        encoding: Option<&'static str>,
+        deadline: Option<Instant>,
+        body: reqwest::Body,
+    ) -> RpcResult<reqwest::Response> {
+        let (content_type, content_encoding, accept_encoding) = match streaming {
+            false => (
+                "application/",
+                header::CONTENT_ENCODING,
+                header::ACCEPT_ENCODING,
+            ),
+            true => (
+                "application/connect+",
+                HeaderName::from_static("connect-content-encoding"),
+                HeaderName::from_static("connect-accept-encoding"),
+            ),
+        };
+        let codec = if self.json { "json" } else { "proto" };
+
+        let mut req = self
+            .http
+            .post(format!("{}{}", self.base_url, path))
+            .headers(self.headers.clone())
+            .header(header::CONTENT_TYPE, format!("{}{}", content_type, codec))
+            .header("connect-protocol-version", "1");
+        if let Some(encoding) = encoding {
+            req = req.header(content_encoding, encoding);
+        }
+        let accepted = self.compressions.accepted();
+        if !accepted.is_empty() {
+            req = req.header(accept_encoding, accepted);
+        }
+        if let Some(deadline) = deadline {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return Err(RpcError::new(
+                    RpcErrorCode::DeadlineExceeded,
+                    "Out of time before calling the server".to_string(),
+                ));
+            }
+            req = req
+                .header("connect-timeout-ms", timeout.as_millis().to_string())
+                .timeout(timeout);
+        }
+
+        req.body(body)
+            .send()
+            .await
+            .map_err(|e| http_error("Failed to call the server", e))
+    }
+
+    /// The body of a failed (non-200) response as an error, with the response headers as its
+    /// metadata.
+    async fn error(&self, res: reqwest::Response) -> RpcError {
+        let status = res.status();
+        let metadata = RpcMetadata::from_headers(res.headers()).unwrap_or_default();
+        let mut e = match self.read(res).await {
+            Ok(body) => error_response(status, &body),
+            Err(_) => error_response(status, &[]),
+        };
+        *e.metadata_mut() = metadata;
+        e
+    }
+
+    /// The whole (decompressed) body of a response.
+    async fn read(&self, res: reqwest::Response) -> RpcResult<Bytes> {
+        let compression = self.response_compression(res.headers(), header::CONTENT_ENCODING)?;
+        let body = res
+            .bytes()
+            .await
+            .map_err(|e| http_error("Failed to read response", e))?;
+        match compression {
+            Some(compression) => decompress(&*compression, &body, self.max_message_size),
+            None if body.len() > self.max_message_size => Err(too_large(self.max_message_size)),
+            None => Ok(body),
+        }
+    }
+
+    fn response_compression(
+        &self,
+        headers: &HeaderMap,
+        name: impl header::AsHeaderName,
+    ) -> RpcResult<Option<Arc<dyn Compression>>> {
+        self.compressions
+            .for_header(headers.get(name))
+            .map_err(|e| RpcError::new(RpcErrorCode::Internal, e.message))
+    }
+
+    /// `message` encoded, then compressed if it's large enough, along with the encoding name.
+    fn encode<Req>(&self, message: &Req) -> RpcResult<(Vec<u8>, Option<&'static str>)>
+    where
+        Req: Message + Serialize,
+    {
+        let encoded = encode(self.json, message)?;
+        let (compression, min_size) = match &self.request_compression {
+            Some((compression, min_size)) => (Some(&**compression), *min_size),
+            None => (None, 0),
+        };
+        Ok(compress_response(compression, min_size, encoded))
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.timeout.map(|timeout| Instant::now() + timeout)
+    }
+
+    async fn unary_attempt(
+        &self,
+        path: &str,
+        encoding: Option<&'static str>,
+        deadline: Option<Instant>,
+        body: Bytes,
+    ) -> RpcResult<Bytes> {
+        let res = self
+            .send(path, false, encoding, deadline, body.into())
+            .await?;
+        if res.status() != StatusCode::OK {
+            return Err(self.error(res).await);
+        }
+        self.read(res).await
+    }
+
+    /// Opens a stream, sending `requests` as they come.
+    async fn stream<Req, Res>(
+        &self,
+        path: &str,
+        requests: BoxStream<'static, Req>,
+    ) -> RpcResult<BoxStream<'static, RpcResult<Res>>>
+    where
+        Req: Message + Serialize + Send + 'static,
+        Res: Message + DeserializeOwned + Default + Send + 'static,
+    {
+        let client = self.clone();
+        let frames = requests.map(move |request| {
+            let (message, encoding) = client.encode(&request).map_err(|e| e.message)?;
+            let flags = if encoding.is_some() {
+                FLAG_COMPRESSED
+            } else {
+                0
+            };
+            envelope(flags, |buf| {
+                buf.extend_from_slice(&message);
+                Ok::<_, BoxError>(())
+            })
+        });
+        let encoding = self
+            .request_compression
+            .as_ref()
+            .map(|(compression, _)| compression.name());
+        let res = self
+            .send(
+                path,
+                true,
+                encoding,
+                self.deadline(),
+                reqwest::Body::wrap_stream(frames),
+            )
+            .await?;
+        if res.status() != StatusCode::OK {
+            return Err(self.error(res).await);
+        }
+
+        let compression = self.response_compression(res.headers(), "connect-content-encoding")?;
+        let mut reader = ResponseReader {
+            res,
+            buf: BytesMut::new(),
+            max_message_size: self.max_message_size,
+        };
+        let (json, max) = (self.json, self.max_message_size);
+        let responses = stream! {
+            loop {
+                let message = match reader.next().await {
+                    Ok(Some(message)) => message,
+                    Ok(None) => {
+                        yield Err(RpcError::new(
+                            RpcErrorCode::Internal,
+                            "Stream ended without an EndStreamResponse".to_string(),
+                        ));
+                        return;
+                    }
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                };
+                let payload = match (message.flags & FLAG_COMPRESSED != 0, &compression) {
+                    (false, _) => Ok(message.payload),
+                    (true, Some(compression)) => decompress(&**compression, &message.payload, max),
+                    (true, None) => Err(RpcError::new(
+                        RpcErrorCode::Internal,
+                        "Compressed message, but no compression was negotiated".to_string(),
+                    )),
+                };
+                let payload = match payload {
+                    Ok(payload) => payload,
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                };
+                if message.flags & FLAG_END_STREAM == 0 {
+                    yield decode(json, payload);
+                    continue;
+                }
+
+                match end_stream_error(&payload) {
+                    Ok(None) => {}
+                    Ok(Some(e)) | Err(e) => yield Err(e),
+                }
+                return;
+            }
+        };
+        Ok(responses.boxed())
+    }
+
+    /// A unary call, retried as the `retry` policy says if the method is `idempotent`.
+    async fn call_unary<Req, Res>(
+        &self,
+        path: &str,
+        request: Req,
+        idempotent: bool,
+    ) -> RpcResult<Res>
+    where
+        Req: Message + Serialize,
+        Res: Message + DeserializeOwned + Default,
+    {
+        let (body, encoding) = self.encode(&request)?;
+        let body = Bytes::from(body);
+        let deadline = self.deadline();
+
+        let mut attempt = 1;
+        loop {
+            let e = match self
+                .unary_attempt(path, encoding, deadline, body.clone())
+                .await
+            {
+                Ok(res) => return decode(self.json, res),
+                Err(e) => e,
+            };
+            let delay = self
+                .retry
+                .as_ref()
+                .and_then(|retry| retry.delay(attempt, idempotent, &e));
+            let Some(delay) = delay else {
+                return Err(e);
+            };
+            // Not worth waiting for if the call would be out of time by then.
+            if deadline.is_some_and(|deadline| Instant::now() + delay >= deadline) {
+                return Err(e);
+            }
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[async_trait]
+impl RpcTransport for RpcClient {
+    async fn unary<Req, Res>(&self, path: &str, request: Req) -> RpcResult<Res>
+    where
+        Req: Message + Serialize + Send + 'static,
+        Res: Message + DeserializeOwned + Default + Send + 'static,
+    {
+        self.call_unary(path, request, false).await
+    }
+
+    async fn idempotent_unary<Req, Res>(&self, path: &str, request: Req) -> RpcResult<Res>
+    where
+        Req: Message + Serialize + Send + 'static,
+        Res: Message + DeserializeOwned + Default + Send + 'static,
+    {
+        self.call_unary(path, request, true).await
+    }
+
+    async fn server_stream<Req, Res>(
+        &self,
+        path: &str,
+        request: Req,
+    ) -> BoxStream<'static, RpcResult<Res>>
+    where
+        Req: Message + Serialize + Send + 'static,
+        Res: Message + DeserializeOwned + Default + Send + 'static,
+    {
+        match self.stream(path, stream::iter([request]).boxed()).await {
+            Ok(responses) => responses,
+            Err(e) => stream::iter([Err(e)]).boxed(),
+        }
+    }
+
+    async fn client_stream<Req, Res>(
+        &self,
+        path: &str,
+        requests: BoxStream<'static, Req>,
+    ) -> RpcResult<Res>
+    where
+        Req: Message + Serialize + Send + 'static,
+        Res: Message + DeserializeOwned + Default + Send + 'static,
+    {
+        let mut responses = self.stream(path, requests).await?;
+        match (responses.next().await, responses.next().await) {
+            (Some(response), None) => response,
+            (Some(Err(e)), _) | (_, Some(Err(e))) => Err(e),
+            _ => Err(RpcError::new(
+                RpcErrorCode::Unimplemented,
+                "Expected exactly one response message".to_string(),
+            )),
+        }
+    }
+}
+
+/// How failed unary calls are retried, see `RpcClient::retry`:
+///
+/// ```ignore
+/// let transport = RpcClient::new("https://hello.example.com").retry(
+///     RetryPolicy::new(4)
+///         .backoff(Duration::from_millis(50), Duration::from_secs(2))
+///         .retry_on([RpcErrorCode::Unavailable, RpcErrorCode::ResourceExhausted]),
+/// );
+/// ```
+///
+/// Only `Unavailable` is retried by default, which servers (and `RpcClient` itself, when it
+/// can't reach them) use for failures the call can safely be tried again after, and only for
+/// methods marked idempotent, unless `any_method` says otherwise. The wait doubles
+/// from `initial` up to `max`, randomized so clients that failed together don't retry together,
+/// unless the error has a `google.rpc.RetryInfo` detail: then it's the delay the server asked for.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    retryable: Vec<RpcErrorCode>,
+    any_method: bool,
+}
+
+impl RetryPolicy {
+    /// Up to `max_attempts` attempts per call, the first one included.
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            retryable: vec![RpcErrorCode::Unavailable],
+            any_method: false,
+        }
+    }
+
+    /// Waits about `initial` before the first retry, doubling for each one after up to `max`.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// The codes to retry, instead of only `Unavailable`.
+    pub fn retry_on(mut self, codes: impl IntoIterator<Item = RpcErrorCode>) -> Self {
+        self.retryable = codes.into_iter().collect();
+        self
+    }
+
+    /// Retries the calls of methods without an `idempotency_level` too, for servers whose
+    /// methods are all safe to repeat though they don't say so.
+    pub fn any_method(mut self) -> Self {
+        self.any_method = true;
+        self
+    }
+
+    /// How long to wait before retrying the call that failed `attempt` with `e`, `None` if it
+    /// shouldn't be.
+    fn delay(&self, attempt: u32, idempotent: bool, e: &RpcError) -> Option<Duration> {
+        if attempt >= self.max_attempts
+            || !self.retryable.contains(&e.code)
+            || !(idempotent || self.any_method)
+        {
+            return None;
+        }
+        let asked = e
+            .detail::<RetryInfo>()
+            .and_then(|info| info.retry_delay)
+            .and_then(|delay| {
+                let seconds = u64::try_from(delay.seconds).ok()?;
+                let nanos = u32::try_from(delay.nanos).ok()?;
+                Some(Duration::new(seconds, nanos))
+            });
+        if let Some(asked) = asked {
+            return Some(asked);
+        }
+
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1 << (attempt - 1).min(31))
+            .min(self.max_backoff);
+        // Between half and all of it.
+        let jitter = RandomState::new().hash_one(attempt) as f64 / u64::MAX as f64;
+        Some(backoff.mul_f64(0.5 + jitter / 2.0))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(3)
+    }
+}
+
+/// Response messages are allowed more room than requests are by default, as they're usually the
+/// bigger ones.
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Reads enveloped messages off a streaming response as its chunks come in.
+struct ResponseReader {
+    res: reqwest::Response,
+    buf: BytesMut,
+    max_message_size: usize,
+}
+
+impl ResponseReader {
+    /// The next message, or `None` once the body has ended cleanly.
+    async fn next(&mut self) -> RpcResult<Option<Envelope>> {
+        loop {
+            if self.buf.len() >= 5 {
+                let size = u32::from_be_bytes([self.buf[1], self.buf[2], self.buf[3], self.buf[4]]);
+                if size as usize > self.max_message_size {
+                    return Err(too_large(self.max_message_size));
+                }
+                let end = 5 + size as usize;
+                if self.buf.len() >= end {
+                    let mut message = self.buf.split_to(end);
+                    let flags = message[0];
+                    let payload = message.split_off(5).freeze();
+                    return Ok(Some(Envelope { flags, payload }));
+                }
+            }
+
+            let chunk = self
+                .res
+                .chunk()
+                .await
+                .map_err(|e| http_error("Failed to read response", e))?;
+            match chunk {
+                Some(chunk) => self.buf.extend_from_slice(&chunk),
+                None if self.buf.is_empty() => return Ok(None),
+                None => {
+                    return Err(RpcError::new(
+                        RpcErrorCode::Internal,
+                        "Response ended in the middle of a message".to_string(),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+fn encode<Req>(json: bool, message: &Req) -> RpcResult<Vec<u8>>
+where
+    Req: Message + Serialize,
+{
+    if !json {
+        return Ok(message.encode_to_vec());
+    }
+    serde_json::to_vec(message).map_err(|e| {
+        RpcError::new(
+            RpcErrorCode::InvalidArgument,
+            format!("Failed to encode request. {}", e),
+        )
+    })
+}
+
+fn decode<Res>(json: bool, bytes: Bytes) -> RpcResult<Res>
+where
+    Res: Message + DeserializeOwned + Default,
+{
+    let decoded = if json {
+        serde_json::from_slice(&bytes).map_err(|e| e.to_string())
+    } else {
+        Res::decode(bytes).map_err(|e| e.to_string())
+    };
+    decoded.map_err(|e| {
+        RpcError::new(
+            RpcErrorCode::Internal,
+            format!("Failed to decode response. {}", e),
+        )
+    })
+}
+
+/// A failed HTTP exchange: `DeadlineExceeded` once out of time, else `Unavailable`.
+fn http_error(context: &str, e: reqwest::Error) -> RpcError {
+    let code = if e.is_timeout() {
+        RpcErrorCode::DeadlineExceeded
+    } else {
+        RpcErrorCode::Unavailable
+    };
+    RpcError::new(code, format!("{}. {}", context, e))
+}
+
+fn too_large(max: usize) -> RpcError {
+    RpcError::new(
+        RpcErrorCode::ResourceExhausted,
+        format!("Response message is larger than the {} byte limit", max),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::Router;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{
+        handler::RpcService,
+        health::{HealthCheckRequest, HealthCheckResponse, ServingStatus},
+    };
+
+    const CHECK: &str = "/grpc.health.v1.Health/Check";
+
+    /// Serves a health check that fails with `e` the first `failures` times, counting its calls.
+    async fn server(failures: usize, e: RpcError) -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let check = {
+            let calls = calls.clone();
+            move |_: HealthCheckRequest| {
+                let call = calls.fetch_add(1, Ordering::SeqCst);
+                let e = e.clone();
+                async move {
+                    match call < failures {
+                        true => Err(e),
+                        false => Ok(HealthCheckResponse::new(ServingStatus::Serving)),
+                    }
+                }
+            }
+        };
+        let app = Router::new().route_service(CHECK, RpcService::unary(check, ()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, calls)
+    }
+
+    fn retrying(url: &str) -> RpcClient {
+        RpcClient::new(url)
+            .retry(RetryPolicy::new(3).backoff(Duration::from_millis(1), Duration::from_millis(1)))
+    }
+
+    fn unavailable() -> RpcError {
+        RpcError::new(RpcErrorCode::Unavailable, "Try again".to_string())
+    }
+
+    #[tokio::test]
+    async fn retries_idempotent_calls_with_retryable_codes() {
+        let (url, calls) = server(2, unavailable()).await;
+        let res: HealthCheckResponse = retrying(&url)
+            .idempotent_unary(CHECK, HealthCheckRequest::default())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), ServingStatus::Serving);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn stops_retrying_after_max_attempts() {
+        let (url, calls) = server(usize::MAX, unavailable()).await;
+        let e = retrying(&url)
+            .idempotent_unary::<_, HealthCheckResponse>(CHECK, HealthCheckRequest::default())
+            .await
+            .unwrap_err();
+        assert_eq!(e.code, RpcErrorCode::Unavailable);
+        assert_eq!(e.message, "Try again");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_other_codes() {
+        let e = RpcError::new(RpcErrorCode::InvalidArgument, "No".to_string());
+        let (url, calls) = server(1, e).await;
+        let e = retrying(&url)
+            .idempotent_unary::<_, HealthCheckResponse>(CHECK, HealthCheckRequest::default())
+            .await
+            .unwrap_err();
+        assert_eq!(e.code, RpcErrorCode::InvalidArgument);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_other_methods_only_when_asked_to() {
+        let (url, calls) = server(1, unavailable()).await;
+        let e = retrying(&url)
+            .unary::<_, HealthCheckResponse>(CHECK, HealthCheckRequest::default())
+            .await
+            .unwrap_err();
+        assert_eq!(e.code, RpcErrorCode::Unavailable);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let client = RpcClient::new(&url).retry(
+            RetryPolicy::new(3)
+                .backoff(Duration::from_millis(1), Duration::from_millis(1))
+                .any_method(),
+        );
+        let res: HealthCheckResponse = client
+            .unary(CHECK, HealthCheckRequest::default())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), ServingStatus::Serving);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_the_max() {
+        let policy =
+            RetryPolicy::new(10).backoff(Duration::from_millis(100), Duration::from_secs(1));
+        let e = unavailable();
+        for (attempt, full) in [(1, 100), (2, 200), (3, 400), (4, 800), (5, 1000), (9, 1000)] {
+            let delay = policy.delay(attempt, true, &e).unwrap();
+            let full = Duration::from_millis(full);
+            assert!(delay >= full / 2 && delay <= full, "{attempt}: {delay:?}");
+        }
+        assert_eq!(policy.delay(10, true, &e), None);
+
+        // The delay the server asked for wins.
+        let e = unavailable().with_detail(RetryInfo {
+            retry_delay: Some(pbjson_types::Duration {
+                seconds: 3,
+                nanos: 0,
+            }),
+        });
+        assert_eq!(policy.delay(1, true, &e), Some(Duration::from_secs(3)));
+    }
+}
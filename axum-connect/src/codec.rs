@@ -0,0 +1,218 @@
+use std::sync::{Arc, OnceLock};
+
+use axum::{body::Bytes, BoxError};
+use prost::Message;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// A message encoding, negotiated from the request `Content-Type`. A codec named `"json"` handles
+/// `application/json` for unary RPCs and `application/connect+json` for streaming RPCs.
+///
+/// Codecs only ever see messages through the object safe `EncodeMessage` and `DecodeMessage`
+/// views, so they can be registered at runtime (see `RpcCodecs`). Serde based formats should go
+/// through `erased_serialize` / `erased_deserialize`; those are the same (pbjson) impls used for
+/// JSON.
+pub trait Codec: Send + Sync + 'static {
+    /// The content-type sub-type, like `"json"` or `"proto"`.
+    fn name(&self) -> &'static str;
+
+    /// Appends the encoded message to `buf`.
+    fn encode(&self, message: &dyn EncodeMessage, buf: &mut Vec<u8>) -> Result<(), BoxError>;
+
+    /// Decodes `bytes` into the (default initialized) `message`.
+    fn decode(&self, bytes: Bytes, message: &mut dyn DecodeMessage) -> Result<(), BoxError>;
+}
+
+/// Object safe view of an outgoing message. Implemented for all generated message types.
+pub trait EncodeMessage {
+    fn encode_proto(&self, buf: &mut Vec<u8>) -> Result<(), prost::EncodeError>;
+
+    fn erased_serialize(&self) -> &dyn erased_serde::Serialize;
+}
+
+impl<M> EncodeMessage for M
+where
+    M: Message + Serialize,
+{
+    fn encode_proto(&self, buf: &mut Vec<u8>) -> Result<(), prost::EncodeError> {
+        self.encode(buf)
+    }
+
+    fn erased_serialize(&self) -> &dyn erased_serde::Serialize {
+        self
+    }
+}
+
+/// Object safe view of an incoming message. Implemented for all generated message types.
+pub trait DecodeMessage {
+    fn merge_proto(&mut self, bytes: Bytes) -> Result<(), prost::DecodeError>;
+
+    fn erased_deserialize(
+        &mut self,
+        deserializer: &mut dyn erased_serde::Deserializer<'_>,
+    ) -> Result<(), erased_serde::Error>;
+}
+
+impl<M> DecodeMessage for M
+where
+    M: Message + DeserializeOwned,
+{
+    fn merge_proto(&mut self, bytes: Bytes) -> Result<(), prost::DecodeError> {
+        self.merge(bytes)
+    }
+
+    fn erased_deserialize(
+        &mut self,
+        deserializer: &mut dyn erased_serde::Deserializer<'_>,
+    ) -> Result<(), erased_serde::Error> {
+        *self = erased_serde::deserialize(deserializer)?;
+        Ok(())
+    }
+}
+
+/// The canonical protobuf JSON mapping, `application/json`.
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn encode(&self, message: &dyn EncodeMessage, buf: &mut Vec<u8>) -> Result<(), BoxError> {
+        Ok(serde_json::to_writer(buf, message.erased_serialize())?)
+    }
+
+    fn decode(&self, bytes: Bytes, message: &mut dyn DecodeMessage) -> Result<(), BoxError> {
+        let mut deserializer = serde_json::Deserializer::from_slice(&bytes);
+        message.erased_deserialize(&mut <dyn erased_serde::Deserializer>::erase(
+            &mut deserializer,
+        ))?;
+        deserializer.end()?;
+        Ok(())
+    }
+}
+
+/// Binary protobuf, `application/proto`.
+pub struct ProtoCodec;
+
+impl Codec for ProtoCodec {
+    fn name(&self) -> &'static str {
+        "proto"
+    }
+
+    fn encode(&self, message: &dyn EncodeMessage, buf: &mut Vec<u8>) -> Result<(), BoxError> {
+        Ok(message.encode_proto(buf)?)
+    }
+
+    fn decode(&self, bytes: Bytes, message: &mut dyn DecodeMessage) -> Result<(), BoxError> {
+        Ok(message.merge_proto(bytes)?)
+    }
+}
+
+/// Non-standard! MessagePack (via the same serde impls as JSON), for Rust <-> Rust services that
+/// want smaller-than-JSON payloads without proto tooling on one side.
+#[cfg(feature = "msgpack")]
+pub struct MsgPackCodec;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MsgPackCodec {
+    fn name(&self) -> &'static str {
+        "msgpack"
+    }
+
+    fn encode(&self, message: &dyn EncodeMessage, buf: &mut Vec<u8>) -> Result<(), BoxError> {
+        // Named (map) encoding, the pbjson deserializers expect field names.
+        let mut serializer = rmp_serde::Serializer::new(buf).with_struct_map();
+        Ok(erased_serde::serialize(
+            message.erased_serialize(),
+            &mut serializer,
+        )?)
+    }
+
+    fn decode(&self, bytes: Bytes, message: &mut dyn DecodeMessage) -> Result<(), BoxError> {
+        let mut deserializer = rmp_serde::Deserializer::from_read_ref(&bytes[..]);
+        let mut deserializer = <dyn erased_serde::Deserializer>::erase(&mut deserializer);
+        Ok(message.erased_deserialize(&mut deserializer)?)
+    }
+}
+
+/// Non-standard! CBOR (via the same serde impls as JSON).
+#[cfg(feature = "cbor")]
+pub struct CborCodec;
+
+#[cfg(feature = "cbor")]
+impl Codec for CborCodec {
+    fn name(&self) -> &'static str {
+        "cbor"
+    }
+
+    fn encode(&self, message: &dyn EncodeMessage, buf: &mut Vec<u8>) -> Result<(), BoxError> {
+        Ok(cbor4ii::serde::to_writer(buf, &message.erased_serialize())?)
+    }
+
+    fn decode(&self, bytes: Bytes, message: &mut dyn DecodeMessage) -> Result<(), BoxError> {
+        let reader = cbor4ii::core::utils::SliceReader::new(&bytes);
+        let mut deserializer = cbor4ii::serde::Deserializer::new(reader);
+        let mut deserializer = <dyn erased_serde::Deserializer>::erase(&mut deserializer);
+        Ok(message.erased_deserialize(&mut deserializer)?)
+    }
+}
+
+/// The set of codecs a request can pick from. Handlers read it from the request extensions, so
+/// registering your own codec is just a layer:
+///
+/// ```ignore
+/// let app = Router::new()
+///     .rpc(HelloWorldService::say_hello(say_hello))
+///     .layer(Extension(RpcCodecs::default().with(MyCodec)));
+/// ```
+///
+/// Without that extension the default set is used: JSON, proto and any codec enabled by a feature.
+#[derive(Clone)]
+pub struct RpcCodecs {
+    codecs: Vec<Arc<dyn Codec>>,
+}
+
+impl RpcCodecs {
+    /// An empty set, not even JSON or proto.
+    pub fn empty() -> Self {
+        Self { codecs: vec![] }
+    }
+
+    /// Adds a codec, replacing any existing codec with the same name.
+    pub fn with<C>(mut self, codec: C) -> Self
+    where
+        C: Codec,
+    {
+        self.codecs.retain(|c| c.name() != codec.name());
+        self.codecs.push(Arc::new(codec));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Codec>> {
+        self.codecs.iter().find(|c| c.name() == name).cloned()
+    }
+
+    /// The registered codec names, in registration order.
+    pub(crate) fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.codecs.iter().map(|c| c.name())
+    }
+
+    pub(crate) fn default_ref() -> &'static Self {
+        static DEFAULT: OnceLock<RpcCodecs> = OnceLock::new();
+        DEFAULT.get_or_init(Self::default)
+    }
+}
+
+impl Default for RpcCodecs {
+    fn default() -> Self {
+        let codecs = Self::empty().with(JsonCodec).with(ProtoCodec);
+
+        #[cfg(feature = "msgpack")]
+        let codecs = codecs.with(MsgPackCodec);
+
+        #[cfg(feature = "cbor")]
+        let codecs = codecs.with(CborCodec);
+
+        codecs
+    }
+}
@@ -0,0 +1,362 @@
+use std::{
+    io::{self, Read, Write},
+    sync::{Arc, OnceLock},
+};
+
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, HeaderValue},
+};
+use flate2::{read::GzDecoder, write::GzEncoder};
+
+use crate::error::{RpcError, RpcErrorCode};
+
+/// A compression algorithm for messages, by its `Content-Encoding` name. Requests name theirs in
+/// `Content-Encoding` (unary) or `Connect-Content-Encoding` (streaming, where each compressed
+/// message is flagged as such).
+pub trait Compression: Send + Sync + 'static {
+    /// The encoding name, like `"gzip"`.
+    fn name(&self) -> &'static str;
+
+    /// A reader of the decompressed `bytes`, which only decompresses as it's read.
+    fn decoder<'a>(&self, bytes: &'a [u8]) -> io::Result<Box<dyn Read + 'a>>;
+
+    fn compress(&self, bytes: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+/// `gzip`, the one encoding every Connect implementation supports, always registered by default.
+pub struct Gzip;
+
+impl Compression for Gzip {
+    fn name(&self) -> &'static str {
+        "gzip"
+    }
+
+    fn decoder<'a>(&self, bytes: &'a [u8]) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(GzDecoder::new(bytes)))
+    }
+
+    fn compress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(bytes)?;
+        encoder.finish()
+    }
+}
+
+/// `br`, enabled by the `brotli` feature. Browsers accept it, and it compresses JSON better than
+/// gzip.
+#[cfg(feature = "brotli")]
+pub struct Brotli;
+
+#[cfg(feature = "brotli")]
+impl Compression for Brotli {
+    fn name(&self) -> &'static str {
+        "br"
+    }
+
+    fn decoder<'a>(&self, bytes: &'a [u8]) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(brotli::Decompressor::new(bytes, 4096)))
+    }
+
+    fn compress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        // A middling quality, as responses are compressed on the fly.
+        let mut encoder = brotli::CompressorWriter::new(vec![], 4096, 5, 22);
+        encoder.write_all(bytes)?;
+        Ok(encoder.into_inner())
+    }
+}
+
+/// `zstd`, enabled by the `zstd` feature. Fast to compress, for service to service traffic.
+#[cfg(feature = "zstd")]
+pub struct Zstd;
+
+#[cfg(feature = "zstd")]
+impl Compression for Zstd {
+    fn name(&self) -> &'static str {
+        "zstd"
+    }
+
+    fn decoder<'a>(&self, bytes: &'a [u8]) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(zstd::stream::read::Decoder::with_buffer(bytes)?))
+    }
+
+    fn compress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        zstd::stream::encode_all(bytes, zstd::DEFAULT_COMPRESSION_LEVEL)
+    }
+}
+
+/// The set of compressions requests and responses can use, read from the request extensions like
+/// `RpcCodecs`. Registering your own is a layer:
+///
+/// ```ignore
+/// let app = Router::new()
+///     .rpc(HelloWorldService::say_hello(say_hello))
+///     .layer(Extension(RpcCompressions::default().with(MyCompression)));
+/// ```
+///
+/// Without that extension the default set is used: gzip, and `br` and `zstd` when their features
+/// are enabled. Registration order breaks ties when negotiating the response compression.
+#[derive(Clone)]
+pub struct RpcCompressions {
+    compressions: Vec<Arc<dyn Compression>>,
+}
+
+impl RpcCompressions {
+    /// An empty set, which only allows uncompressed (`identity`) messages.
+    pub fn empty() -> Self {
+        Self {
+            compressions: vec![],
+        }
+    }
+
+    /// Adds a compression, replacing any existing one with the same name.
+    pub fn with<C>(mut self, compression: C) -> Self
+    where
+        C: Compression,
+    {
+        self.compressions.retain(|c| c.name() != compression.name());
+        self.compressions.push(Arc::new(compression));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Compression>> {
+        self.compressions.iter().find(|c| c.name() == name).cloned()
+    }
+
+    pub(crate) fn default_ref() -> &'static Self {
+        static DEFAULT: OnceLock<RpcCompressions> = OnceLock::new();
+        DEFAULT.get_or_init(Self::default)
+    }
+
+    /// The compression named by a request header, `None` for `identity` (or no header). Unknown
+    /// encodings fail with `Unimplemented`, as the protocol asks.
+    pub(crate) fn for_header(
+        &self,
+        value: Option<&HeaderValue>,
+    ) -> Result<Option<Arc<dyn Compression>>, RpcError> {
+        let Some(value) = value else {
+            return Ok(None);
+        };
+        let name = String::from_utf8_lossy(value.as_bytes());
+        match name.trim() {
+            "identity" => Ok(None),
+            name => self.get(name).map(Some).ok_or_else(|| {
+                RpcError::new(
+                    RpcErrorCode::Unimplemented,
+                    format!("Unsupported compression: {}", name),
+                )
+            }),
+        }
+    }
+
+    /// The compression to answer with, from an `Accept-Encoding` header like `gzip;q=0.8, br`: the
+    /// highest weighted registered encoding, or `None` if there's none (or only `identity`).
+    pub(crate) fn negotiate(
+        &self,
+        headers: &HeaderMap,
+        name: &str,
+    ) -> Option<Arc<dyn Compression>> {
+        let mut best: Option<(f32, Arc<dyn Compression>)> = None;
+
+        for accept in headers.get_all(name) {
+            for coding in accept.to_str().unwrap_or_default().split(',') {
+                let mut params = coding.split(';');
+                let coding = params.next().unwrap_or_default().trim();
+                let q = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                if q <= 0.0 || best.as_ref().is_some_and(|(best, _)| q <= *best) {
+                    continue;
+                }
+                // A wildcard takes any encoding, so the first registered.
+                let compression = match coding {
+                    "*" => self.compressions.first().cloned(),
+                    coding => self.get(coding),
+                };
+                if let Some(compression) = compression {
+                    best = Some((q, compression));
+                }
+            }
+        }
+
+        best.map(|(_, compression)| compression)
+    }
+
+    /// The registered encoding names, for the `Accept-Encoding` header of an `Unimplemented` error.
+    pub(crate) fn accepted(&self) -> String {
+        let names: Vec<_> = self.compressions.iter().map(|c| c.name()).collect();
+        names.join(", ")
+    }
+}
+
+impl Default for RpcCompressions {
+    fn default() -> Self {
+        let compressions = Self::empty().with(Gzip);
+
+        #[cfg(feature = "brotli")]
+        let compressions = compressions.with(Brotli);
+
+        #[cfg(feature = "zstd")]
+        let compressions = compressions.with(Zstd);
+
+        compressions
+    }
+}
+
+/// How large a decompressed request message may get without a configured
+/// `max_request_message_size`: axum's default body limit, which uncompressed ones are held to.
+pub(crate) const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 2 * 1024 * 1024;
+
+/// `body` compressed with `compression` if it's at least `min_size` bytes, along with the encoding
+/// name for the `Content-Encoding` header. Bodies that fail to compress are sent as they are.
+pub(crate) fn compress_response(
+    compression: Option<&dyn Compression>,
+    min_size: usize,
+    body: Vec<u8>,
+) -> (Vec<u8>, Option<&'static str>) {
+    match compression {
+        Some(compression) if body.len() >= min_size => match compression.compress(&body) {
+            Ok(compressed) => (compressed, Some(compression.name())),
+            Err(_) => (body, None),
+        },
+        _ => (body, None),
+    }
+}
+
+/// Decompresses a message, failing with `ResourceExhausted` once it grows over `max`
+/// bytes rather than inflating a compression bomb.
+pub(crate) fn decompress(
+    compression: &dyn Compression,
+    bytes: &[u8],
+    max: usize,
+) -> Result<Bytes, RpcError> {
+    let mut decompressed = vec![];
+    compression
+        .decoder(bytes)
+        .and_then(|decoder| decoder.take(max as u64 + 1).read_to_end(&mut decompressed))
+        .map_err(|e: io::Error| {
+            RpcError::new(
+                RpcErrorCode::InvalidArgument,
+                format!("Failed to decompress {} message. {}", compression.name(), e),
+            )
+        })?;
+
+    if decompressed.len() > max {
+        return Err(RpcError::new(
+            RpcErrorCode::ResourceExhausted,
+            format!("Decompressed message is larger than the {} byte limit", max),
+        ));
+    }
+    Ok(decompressed.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{body::Body, http::Request, Router};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{health::Health, router::RpcRouterExt};
+
+    /// Counts the decompressed bytes read from the decoders of a compression.
+    struct Counting(Arc<dyn Compression>, Arc<AtomicUsize>);
+
+    struct CountingReader<'a>(Box<dyn Read + 'a>, Arc<AtomicUsize>);
+
+    impl Read for CountingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.read(buf)?;
+            self.1.fetch_add(n, Ordering::SeqCst);
+            Ok(n)
+        }
+    }
+
+    impl Compression for Counting {
+        fn name(&self) -> &'static str {
+            self.0.name()
+        }
+
+        fn decoder<'a>(&self, bytes: &'a [u8]) -> io::Result<Box<dyn Read + 'a>> {
+            Ok(Box::new(CountingReader(
+                self.0.decoder(bytes)?,
+                self.1.clone(),
+            )))
+        }
+
+        fn compress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+            self.0.compress(bytes)
+        }
+    }
+
+    fn compressions() -> Vec<Arc<dyn Compression>> {
+        RpcCompressions::default().compressions
+    }
+
+    /// A message of `{}` padded with whitespace to `size` bytes, compressing to next to nothing.
+    fn bomb(size: usize) -> Vec<u8> {
+        let mut message = b"{}".to_vec();
+        message.resize(size, b' ');
+        message
+    }
+
+    #[test]
+    fn round_trips_messages() {
+        for compression in compressions() {
+            let compressed = compression.compress(b"{}").unwrap();
+            let decompressed = decompress(compression.as_ref(), &compressed, 2).unwrap();
+            assert_eq!(&decompressed[..], b"{}", "{}", compression.name());
+        }
+    }
+
+    #[test]
+    fn stops_decompressing_at_the_limit() {
+        let message = bomb(4 * DEFAULT_MAX_DECOMPRESSED_SIZE);
+        for compression in compressions() {
+            let read = Arc::new(AtomicUsize::new(0));
+            let counting = Counting(compression.clone(), read.clone());
+            let compressed = compression.compress(&message).unwrap();
+            assert!(compressed.len() < 64 * 1024, "{}", compression.name());
+
+            let e = decompress(&counting, &compressed, DEFAULT_MAX_DECOMPRESSED_SIZE).unwrap_err();
+            assert_eq!(
+                e.code,
+                RpcErrorCode::ResourceExhausted,
+                "{}",
+                compression.name()
+            );
+            assert_eq!(
+                read.load(Ordering::SeqCst),
+                DEFAULT_MAX_DECOMPRESSED_SIZE + 1,
+                "{}",
+                compression.name()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_decompressing_past_the_limit() {
+        let app = Router::new().rpc(Health::new().routes());
+        let message = bomb(DEFAULT_MAX_DECOMPRESSED_SIZE + 1);
+        for compression in compressions() {
+            let req = Request::post("/grpc.health.v1.Health/Check")
+                .header("content-type", "application/json")
+                .header("content-encoding", compression.name())
+                .body(Body::from(compression.compress(&message).unwrap()))
+                .unwrap();
+
+            let res = app.clone().oneshot(req).await.unwrap();
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(
+                error["code"],
+                "resource_exhausted",
+                "{}",
+                compression.name()
+            );
+        }
+    }
+}
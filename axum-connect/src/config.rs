@@ -0,0 +1,310 @@
+use std::{fmt, future::Future, sync::Arc, time::Duration};
+
+use axum::{extract::Request, http::HeaderValue};
+use tokio::time::Instant;
+
+use crate::{
+    error::{RpcError, RpcErrorCode},
+    instrument::CallProtocol,
+    interceptor::{CallState, RpcInterceptor},
+    middleware::request_id::X_REQUEST_ID,
+    parts::RpcRequestId,
+};
+
+/// Runtime settings for the RPCs of one service (or a whole router), read by the handlers from the
+/// request extensions. Attach it when mounting the services it's for:
+///
+/// ```ignore
+/// let app = Router::new()
+///     .rpc(HelloWorldService::say_hello(say_hello))
+///     .rpc_with_config(
+///         RpcServiceConfig::new()
+///             .max_request_message_size(64 * 1024)
+///             .max_response_message_size(16 * 1024 * 1024)
+///             .timeout(Duration::from_secs(5))
+///             .require_protocol_version(true),
+///         Router::new().rpc(UploadService::upload(upload)),
+///     );
+/// ```
+///
+/// or for everything on a router, with `.layer(Extension(config))`. Without one, the defaults
+/// apply: axum's body limit, no response limit or compression, only the client's timeout, the
+/// lenient protocol checks, no debug error details and no error hook or interceptors.
+#[derive(Clone, Debug, Default)]
+pub struct RpcServiceConfig {
+    pub(crate) max_request_message_size: Option<usize>,
+    pub(crate) max_response_message_size: Option<usize>,
+    pub(crate) compress_min_size: Option<usize>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) stream_buffer: Option<usize>,
+    pub(crate) cache_control: Option<HeaderValue>,
+    pub(crate) require_protocol_version: bool,
+    pub(crate) error_debug_details: bool,
+    pub(crate) on_error: Option<ErrorHook>,
+    pub(crate) interceptors: Interceptors,
+    /// Set on the copy a call runs with, for the error hook and the interceptors.
+    pub(crate) call: Option<Arc<CallState>>,
+    /// Set on the copy a call runs with, from `RequestIdLayer`, to add to its errors.
+    pub(crate) request_id: Option<RpcRequestId>,
+}
+
+type ErrorHookFn = dyn Fn(&mut RpcError, &RpcErrorContext) + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) struct ErrorHook(Arc<ErrorHookFn>);
+
+impl fmt::Debug for ErrorHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ErrorHook")
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Interceptors(Arc<[Arc<dyn RpcInterceptor>]>);
+
+impl fmt::Debug for Interceptors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Interceptors({})", self.0.len())
+    }
+}
+
+/// The call an error is sent for, see `RpcServiceConfig::on_error`.
+#[derive(Clone, Debug)]
+pub struct RpcErrorContext {
+    /// The RPC method, as `package.Service/Method`.
+    pub method: String,
+    /// How long the call took, up to the error.
+    pub latency: Duration,
+}
+
+impl RpcServiceConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The largest request message accepted, in bytes, rejecting bigger ones with
+    /// `ResourceExhausted`. This replaces axum's `DefaultBodyLimit` for these RPCs.
+    pub fn max_request_message_size(mut self, max: usize) -> Self {
+        self.max_request_message_size = Some(max);
+        self
+    }
+
+    /// The largest response message sent, in bytes as encoded, failing the call (or stream) with
+    /// `ResourceExhausted` instead of sending a bigger one. A guard against handlers that build
+    /// runaway responses; unlimited by default.
+    pub fn max_response_message_size(mut self, max: usize) -> Self {
+        self.max_response_message_size = Some(max);
+        self
+    }
+
+    /// Compresses unary responses and stream messages of at least `min_size` bytes (as encoded)
+    /// when the client's `Accept-Encoding` (`Connect-Accept-Encoding` for streams) allows, which
+    /// pays off most for large JSON responses to browsers. Off by default; small responses are
+    /// rarely worth the CPU.
+    pub fn compress_responses(mut self, min_size: usize) -> Self {
+        self.compress_min_size = Some(min_size);
+        self
+    }
+
+    /// How long a handler may run (for a stream, until its last message) before the call fails
+    /// with `DeadlineExceeded`. Clients can ask for less with `connect-timeout-ms`, not more.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Lets server stream handlers run up to `messages` ahead of the client. By default a handler's
+    /// stream is only polled for its next message once the previous one has been written out, so
+    /// a slow client holds up a fast producer but nothing piles up in memory. With a buffer, the
+    /// producer (say, a database cursor holding a connection) can finish sooner, at the cost of up
+    /// to `messages` responses held per stream.
+    pub fn stream_buffer(mut self, messages: usize) -> Self {
+        self.stream_buffer = Some(messages);
+        self
+    }
+
+    /// The `Cache-Control` header of successful responses to Connect GET requests, for handlers
+    /// that don't set one with `RpcResponse::cache_control`, like
+    /// `HeaderValue::from_static("public, max-age=60")`. Only methods with
+    /// `idempotency_level = NO_SIDE_EFFECTS` are served over GET; POST responses are never cached.
+    pub fn cache_control(mut self, value: HeaderValue) -> Self {
+        self.cache_control = Some(value);
+        self
+    }
+
+    /// Rejects requests without a `connect-protocol-version: 1` header with `InvalidArgument`,
+    /// rather than only ones with a wrong version, as the protocol allows servers to. This keeps
+    /// out plain HTTP clients (like browser form POSTs) that happen to hit an RPC path. For a
+    /// whole router:
+    ///
+    /// ```ignore
+    /// let app = Router::new()
+    ///     .rpc(HelloWorldService::say_hello(say_hello))
+    ///     .layer(Extension(RpcServiceConfig::new().require_protocol_version(true)));
+    /// ```
+    pub fn require_protocol_version(mut self, require: bool) -> Self {
+        self.require_protocol_version = require;
+        self
+    }
+
+    /// Sends the `debug` field of error details (see `RpcErrorDetail::with_debug`), and the
+    /// messages of handler panics as a `DebugInfo` detail, which are left out by default as they
+    /// can leak internals. Turn it on for internal or development services.
+    pub fn error_debug_details(mut self, include: bool) -> Self {
+        self.error_debug_details = include;
+        self
+    }
+
+    /// Calls `hook` with every error sent for an RPC (including the ones of undecodable requests,
+    /// timeouts and panics) before it's encoded, with the method and the latency. It can log and
+    /// count them, or change them, like scrubbing the messages of `Internal` errors:
+    ///
+    /// ```ignore
+    /// let config = RpcServiceConfig::new().on_error(|e, call| {
+    ///     log::warn!("{} failed after {:?}: {}", call.method, call.latency, e.message);
+    ///     if e.code == RpcErrorCode::Internal {
+    ///         e.message = "Internal error".to_string();
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// Debug details are still in the errors it sees, and left out after unless they're enabled.
+    pub fn on_error<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&mut RpcError, &RpcErrorContext) + Send + Sync + 'static,
+    {
+        self.on_error = Some(ErrorHook(Arc::new(hook)));
+        self
+    }
+
+    /// Adds an `RpcInterceptor`, running after the ones added before it.
+    pub fn interceptor(mut self, interceptor: impl RpcInterceptor) -> Self {
+        let interceptor: Arc<dyn RpcInterceptor> = Arc::new(interceptor);
+        self.interceptors = Interceptors(
+            self.interceptors
+                .0
+                .iter()
+                .cloned()
+                .chain([interceptor])
+                .collect(),
+        );
+        self
+    }
+
+    /// The config of the call `req` starts, from its extensions or the defaults. With hooks that
+    /// follow the call or a request ID, this copy (with its `CallState`) replaces the one in the
+    /// extensions, for the handler to read.
+    pub(crate) fn for_call(req: &mut Request) -> Self {
+        let mut config = req.extensions().get::<Self>().cloned().unwrap_or_default();
+        let mut changed = false;
+        if config.request_id.is_none() {
+            config.request_id = req.extensions().get::<RpcRequestId>().cloned();
+            changed = config.request_id.is_some();
+        }
+        let hooked = config.on_error.is_some()
+            || !config.interceptors.0.is_empty()
+            || cfg!(any(feature = "tracing", feature = "metrics"));
+        if hooked && config.call.is_none() {
+            let protocol = req
+                .extensions()
+                .get::<CallProtocol>()
+                .map_or("connect", |p| p.0);
+            let call = CallState::new(req.uri().path(), protocol, config.interceptors.0.clone());
+            #[cfg(feature = "opentelemetry")]
+            crate::telemetry::continue_trace(&call.span, req.headers());
+            #[cfg(feature = "tracing")]
+            if let Some(request_id) = &config.request_id {
+                call.span.record("request_id", request_id.as_str());
+            }
+            config.call = Some(Arc::new(call));
+            changed = true;
+        }
+        if changed {
+            req.extensions_mut().insert(config.clone());
+        }
+        config
+    }
+
+    /// `e` as it should be sent: through the error hook, with the request ID (if any) in its
+    /// metadata, and without debug details unless they're enabled.
+    pub(crate) fn outgoing_error(&self, mut e: RpcError) -> RpcError {
+        if let (Some(ErrorHook(hook)), Some(call)) = (&self.on_error, &self.call) {
+            let context = RpcErrorContext {
+                method: call.method.clone(),
+                latency: call.started.elapsed(),
+            };
+            hook(&mut e, &context);
+        }
+        if let Some(call) = &self.call {
+            call.error_sent(&e.code);
+        }
+        if let Some(request_id) = &self.request_id {
+            if e.metadata().get(X_REQUEST_ID.as_str()).is_none() {
+                e.metadata_mut()
+                    .append(X_REQUEST_ID.as_str(), request_id.as_str());
+            }
+        }
+        if !self.error_debug_details {
+            for detail in &mut e.details {
+                detail.debug = None;
+            }
+        }
+        e
+    }
+
+    /// Counts a request message of `size` bytes (uncompressed) for the call's metrics.
+    pub(crate) fn message_received(&self, size: usize) {
+        if let Some(call) = &self.call {
+            call.message_received(size);
+        }
+    }
+
+    /// Counts a response message of `size` bytes (uncompressed) for the call's metrics.
+    pub(crate) fn message_sent(&self, size: usize) {
+        if let Some(call) = &self.call {
+            call.message_sent(size);
+        }
+    }
+
+    /// Checks an encoded response message against `max_response_message_size`.
+    pub(crate) fn check_response_size(&self, size: usize) -> Result<(), RpcError> {
+        match self.max_response_message_size {
+            Some(max) if size > max => Err(RpcError::new(
+                RpcErrorCode::ResourceExhausted,
+                format!(
+                    "Response message of {} bytes is larger than the {} byte limit",
+                    size, max
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// The deadline for a call starting now, from the configured timeout and the one the client
+    /// asked for, whichever is shorter.
+    pub(crate) fn deadline(&self, requested: Option<Duration>) -> Option<Instant> {
+        let timeout = match (self.timeout, requested) {
+            (Some(configured), Some(requested)) => Some(configured.min(requested)),
+            (configured, requested) => configured.or(requested),
+        };
+        timeout.map(|timeout| Instant::now() + timeout)
+    }
+}
+
+/// Runs `future` to completion, or until `deadline` passes, in which case it's `None`.
+pub(crate) async fn within<F>(deadline: Option<Instant>, future: F) -> Option<F::Output>
+where
+    F: Future,
+{
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+pub(crate) fn deadline_exceeded() -> RpcError {
+    RpcError::new(
+        RpcErrorCode::DeadlineExceeded,
+        "The call didn't finish within its timeout".to_string(),
+    )
+}
@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+use axum::http::{HeaderName, Method};
+use tower_http::cors::CorsLayer;
+
+pub use tower_http::cors::AllowOrigin;
+
+/// The request headers browser Connect, gRPC-Web and gRPC clients send, beyond the safelisted ones.
+pub const ALLOWED_HEADERS: &[&str] = &[
+    "content-type",
+    "connect-protocol-version",
+    "connect-timeout-ms",
+    "connect-content-encoding",
+    "connect-accept-encoding",
+    "grpc-timeout",
+    "grpc-encoding",
+    "grpc-accept-encoding",
+    "x-grpc-web",
+    "x-user-agent",
+];
+
+/// The response headers browser clients read, which are hidden from them unless exposed.
+pub const EXPOSED_HEADERS: &[&str] = &[
+    "connect-content-encoding",
+    "connect-accept-encoding",
+    "content-encoding",
+    "grpc-status",
+    "grpc-message",
+    "grpc-status-details-bin",
+    "grpc-encoding",
+    "grpc-accept-encoding",
+];
+
+/// A CORS layer for serving RPCs to browsers (`connect-web`, gRPC-Web) on another origin, allowing
+/// the Connect and gRPC-Web headers and exposing the status and encoding headers responses are
+/// read by. The metadata the app uses has to be listed, as browsers don't take `*` for headers of
+/// requests with credentials, nor a prefix for the `trailer-` headers unary trailers are sent as:
+///
+/// ```ignore
+/// let app = Router::new()
+///     .rpc(HelloWorldService::say_hello(say_hello))
+///     .layer(
+///         RpcCors::new()
+///             .allow_origin(AllowOrigin::list([
+///                 HeaderValue::from_static("https://app.example.com"),
+///             ]))
+///             .metadata(["x-request-id", "x-tenant"])
+///             .layer(),
+///     );
+/// ```
+///
+/// Every origin is allowed by default, and preflights are cached for 2 hours, the most Chromium
+/// allows. Put it outside of `RpcRouterOptions::layer`, so preflights are answered before they
+/// reach the RPC routes.
+#[derive(Clone, Debug)]
+pub struct RpcCors {
+    origin: Option<AllowOrigin>,
+    metadata: Vec<HeaderName>,
+    credentials: bool,
+    private_network: bool,
+    max_age: Duration,
+}
+
+impl Default for RpcCors {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RpcCors {
+    pub fn new() -> Self {
+        Self {
+            origin: None,
+            metadata: vec![],
+            credentials: false,
+            private_network: false,
+            max_age: Duration::from_secs(2 * 60 * 60),
+        }
+    }
+
+    /// The origins allowed to call, instead of any.
+    pub fn allow_origin(mut self, origin: impl Into<AllowOrigin>) -> Self {
+        self.origin = Some(origin.into());
+        self
+    }
+
+    /// Metadata keys (like `x-request-id`, or `-bin` ones) that requests may send and responses
+    /// expose, both as headers and as the `trailer-` prefixed headers of unary responses.
+    ///
+    /// Panics if a key isn't a valid header name.
+    pub fn metadata<I, K>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<str>,
+    {
+        self.metadata.extend(keys.into_iter().map(|key| {
+            HeaderName::try_from(key.as_ref().to_ascii_lowercase())
+                .unwrap_or_else(|_| panic!("Invalid metadata key `{}`", key.as_ref()))
+        }));
+        self
+    }
+
+    /// Allow cookies and HTTP authentication on cross-origin calls. With any origin allowed, the
+    /// request's own origin is sent back, as browsers don't take `*` with credentials.
+    pub fn allow_credentials(mut self, enabled: bool) -> Self {
+        self.credentials = enabled;
+        self
+    }
+
+    /// Answer Private Network Access preflights, so pages on public origins can call a server on
+    /// `localhost` or the local network.
+    pub fn allow_private_network(mut self, enabled: bool) -> Self {
+        self.private_network = enabled;
+        self
+    }
+
+    /// How long browsers may cache a preflight.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    pub fn layer(self) -> CorsLayer {
+        let origin = match self.origin {
+            Some(origin) => origin,
+            None if self.credentials => AllowOrigin::mirror_request(),
+            None => AllowOrigin::any(),
+        };
+
+        let mut allowed: Vec<HeaderName> = ALLOWED_HEADERS
+            .iter()
+            .map(|name| HeaderName::from_static(name))
+            .collect();
+        let mut exposed: Vec<HeaderName> = EXPOSED_HEADERS
+            .iter()
+            .map(|name| HeaderName::from_static(name))
+            .collect();
+        for key in self.metadata {
+            let trailer = HeaderName::try_from(format!("trailer-{}", key))
+                .expect("a prefixed header name is a header name");
+            allowed.push(key.clone());
+            exposed.push(key);
+            exposed.push(trailer);
+        }
+
+        CorsLayer::new()
+            .allow_origin(origin)
+            .allow_methods([Method::GET, Method::POST])
+            .allow_headers(allowed)
+            .expose_headers(exposed)
+            .allow_credentials(self.credentials)
+            .allow_private_network(self.private_network)
+            .max_age(self.max_age)
+    }
+}
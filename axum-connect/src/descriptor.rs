@@ -0,0 +1,297 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use axum::http;
+use prost::Message;
+
+use crate::{
+    error::{RpcError, RpcErrorCode, RpcIntoError},
+    parts::RpcFromRequestParts,
+};
+
+/// What generated code knows about a service, as a `DESCRIPTOR` constant on its struct, for
+/// middleware and registries that need to know the methods without runtime reflection:
+///
+/// ```ignore
+/// for method in HelloWorldService::DESCRIPTOR.methods {
+///     metrics.register(method.path, method.kind);
+/// }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct ServiceDescriptor {
+    /// The fully qualified name, like `hello.HelloWorldService`.
+    pub name: &'static str,
+    pub package: &'static str,
+    pub methods: &'static [MethodDescriptor],
+}
+
+impl ServiceDescriptor {
+    /// The method with this (proto) name, like `SayHello`.
+    pub fn method(&self, name: &str) -> Option<&'static MethodDescriptor> {
+        self.methods.iter().find(|method| method.name == name)
+    }
+}
+
+/// One method of a `ServiceDescriptor`, also a `<METHOD>_DESCRIPTOR` constant on the service
+/// struct.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MethodDescriptor {
+    /// The proto name, like `SayHello`.
+    pub name: &'static str,
+    /// The fully qualified name of the service, like `hello.HelloWorldService`.
+    pub service: &'static str,
+    /// The route it's served on, like `/hello.HelloWorldService/SayHello`.
+    pub path: &'static str,
+    /// Fully qualified proto names of the request and response messages.
+    pub input_type: &'static str,
+    pub output_type: &'static str,
+    pub kind: MethodKind,
+    /// From the `idempotency_level` method option.
+    pub idempotency: IdempotencyLevel,
+    /// From the `deprecated` method option.
+    pub deprecated: bool,
+}
+
+impl MethodDescriptor {
+    /// Whether it's served for Connect GET requests too: unary methods with no side effects.
+    pub const fn allows_get(&self) -> bool {
+        matches!(self.kind, MethodKind::Unary)
+            && matches!(self.idempotency, IdempotencyLevel::NoSideEffects)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MethodKind {
+    Unary,
+    ServerStreaming,
+    ClientStreaming,
+    BidiStreaming,
+}
+
+impl MethodKind {
+    pub const fn is_streaming(&self) -> bool {
+        !matches!(self, MethodKind::Unary)
+    }
+
+    /// Whether the client sends a stream of messages.
+    pub const fn is_client_streaming(&self) -> bool {
+        matches!(
+            self,
+            MethodKind::ClientStreaming | MethodKind::BidiStreaming
+        )
+    }
+
+    /// Whether the server answers with a stream of messages.
+    pub const fn is_server_streaming(&self) -> bool {
+        matches!(
+            self,
+            MethodKind::ServerStreaming | MethodKind::BidiStreaming
+        )
+    }
+}
+
+/// `google.protobuf.MethodOptions.IdempotencyLevel`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IdempotencyLevel {
+    Unknown,
+    /// Safe to call as a GET, and to cache.
+    NoSideEffects,
+    /// Safe to retry.
+    Idempotent,
+}
+
+/// The RPC methods an `RpcApp` (or `RpcRoutes`) serves, at their paths there (with the prefixes of
+/// `rpc_nest` and `rpc_tenants`), sorted by path. For a startup log of the exposed RPCs, gateway
+/// config or a debug endpoint:
+///
+/// ```ignore
+/// let app = RpcApp::new()
+///     .rpc(Health::new().routes())
+///     .rpc_nest("/api", |router| {
+///         router.rpc(HelloWorldService::say_hello(say_hello))
+///     });
+/// for (path, method) in app.rpc_methods() {
+///     tracing::info!(path, kind = ?method.kind, "Serving");
+/// }
+/// ```
+///
+/// Every method registered through `RpcRouterExt::rpc_route` is listed, which is what the generated
+/// route registration (`HelloWorldService::say_hello(...)`, `from_handler`) and the ready-made
+/// services like `Health` use. Registering the same path twice lists it once.
+#[derive(Clone, Debug, Default)]
+pub struct RpcMethods {
+    methods: BTreeMap<String, &'static MethodDescriptor>,
+}
+
+impl RpcMethods {
+    /// The method served at `path`, if any.
+    pub fn get(&self, path: &str) -> Option<&'static MethodDescriptor> {
+        self.methods.get(path).copied()
+    }
+
+    /// The paths and methods, sorted by path.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &'static MethodDescriptor)> {
+        self.methods
+            .iter()
+            .map(|(path, method)| (path.as_str(), *method))
+    }
+
+    pub fn len(&self) -> usize {
+        self.methods.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.methods.is_empty()
+    }
+
+    pub(crate) fn insert(&mut self, path: String, method: &'static MethodDescriptor) {
+        self.methods.insert(path, method);
+    }
+
+    /// Adds the methods of `other`, served under `prefix` (or `""`).
+    pub(crate) fn extend(&mut self, prefix: &str, other: &RpcMethods) {
+        for (path, method) in other {
+            self.insert(format!("{}{}", prefix, path), method);
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a RpcMethods {
+    type Item = (&'a str, &'static MethodDescriptor);
+    type IntoIter = Box<dyn Iterator<Item = Self::Item> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
+
+/// One method of a service as a type, for checking handlers against it at compile time (see
+/// `#[rpc_handler]`). Generated code implements it on a unit struct per method, in a module named
+/// after the service: `hello_world_service::SayHello`.
+pub trait RpcMethod {
+    type Request: Message;
+    type Response: Message;
+    const DESCRIPTOR: &'static MethodDescriptor;
+}
+
+/// The method a request is for, in the request extensions. Generated routes add it before the
+/// handler's extractors run, so it's also an extractor. Layers on the router run before it's added
+/// though; put `RpcMethodInfoLayer` outside of those that need it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RpcMethodInfo(pub &'static MethodDescriptor);
+
+impl RpcMethodInfo {
+    /// The fully qualified service name, like `hello.HelloWorldService`.
+    pub fn service(&self) -> &'static str {
+        self.0.service
+    }
+
+    /// The method's proto name, like `SayHello`.
+    pub fn method(&self) -> &'static str {
+        self.0.name
+    }
+
+    pub fn descriptor(&self) -> &'static MethodDescriptor {
+        self.0
+    }
+}
+
+#[async_trait]
+impl<M, S> RpcFromRequestParts<M, S> for RpcMethodInfo
+where
+    M: Message,
+    S: Send + Sync,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<Self>().copied().ok_or_else(|| {
+            (
+                RpcErrorCode::Internal,
+                "RpcMethodInfo is only available on generated routes",
+            )
+                .rpc_into_error()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        handler::RpcService,
+        health::{Health, HealthCheckRequest, HealthCheckResponse, ServingStatus},
+        router::{RpcApp, RpcRouterExt, RpcRoutes},
+    };
+
+    fn paths(methods: &RpcMethods) -> Vec<&str> {
+        methods.iter().map(|(path, _)| path).collect()
+    }
+
+    #[test]
+    fn lists_the_methods_of_the_app() {
+        let app = RpcApp::<()>::new().rpc(Health::new().routes());
+        let methods = app.rpc_methods();
+
+        assert_eq!(
+            paths(methods),
+            [
+                "/grpc.health.v1.Health/Check",
+                "/grpc.health.v1.Health/Watch"
+            ]
+        );
+        let check = methods.get("/grpc.health.v1.Health/Check").unwrap();
+        assert_eq!(check.service, "grpc.health.v1.Health");
+        assert_eq!(check.name, "Check");
+        assert_eq!(check.kind, MethodKind::Unary);
+    }
+
+    #[test]
+    fn lists_prefixes() {
+        let app = RpcApp::<()>::new().rpc_nest("/api", |router| {
+            router
+                .rpc(Health::new().routes())
+                .rpc_tenants(|router| router.rpc(Health::new().routes()))
+        });
+
+        assert_eq!(
+            paths(app.rpc_methods()),
+            [
+                "/api/grpc.health.v1.Health/Check",
+                "/api/grpc.health.v1.Health/Watch",
+                "/api/{tenant}/grpc.health.v1.Health/Check",
+                "/api/{tenant}/grpc.health.v1.Health/Watch",
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_methods_registered_in_parts() {
+        async fn check(_: HealthCheckRequest) -> HealthCheckResponse {
+            HealthCheckResponse::new(ServingStatus::Serving)
+        }
+
+        // Built elsewhere, and on another thread.
+        let layered = std::thread::spawn(|| RpcApp::new().rpc(Health::new().routes()))
+            .join()
+            .unwrap();
+        let check_method = &Health::DESCRIPTOR.methods[0];
+        let routes = RpcRoutes::new().rpc(check_method, RpcService::unary(check, ()));
+
+        let app = RpcApp::<()>::new()
+            .rpc_with_config(Default::default(), layered)
+            .rpc_nest("/routes", |router| router.rpc_routes(routes));
+
+        assert_eq!(
+            paths(app.rpc_methods()),
+            [
+                "/grpc.health.v1.Health/Check",
+                "/grpc.health.v1.Health/Watch",
+                "/routes/grpc.health.v1.Health/Check",
+            ]
+        );
+    }
+}
@@ -1,14 +1,32 @@
-use axum::http::StatusCode;
+use std::{error::Error, sync::OnceLock, time::Duration};
+
+use axum::{body::Bytes, http::StatusCode};
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
 use prost::Message;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::{prelude::RpcResult, response::RpcIntoResponse};
+use crate::{
+    error_details::{BadRequest, ErrorInfo, LocalizedMessage, RetryInfo, RpcErrorDetailMessage},
+    metadata::RpcMetadata,
+    prelude::RpcResult,
+    response::RpcIntoResponse,
+};
 
-#[derive(Clone, Serialize)]
+/// An error as the Connect protocol puts it on the wire. An empty message and empty details are
+/// left out of the JSON, see: https://connect.build/docs/protocol/#error-end-stream
+///
+/// Its metadata goes out alongside it: as response headers of a unary call, or in the
+/// EndStreamResponse that a failed stream ends with.
+#[derive(Clone, Debug, Serialize)]
 pub struct RpcError {
     pub code: RpcErrorCode,
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
     pub details: Vec<RpcErrorDetail>,
+    // Boxed, as errors are returned everywhere and the metadata is rarely set.
+    #[serde(skip)]
+    metadata: Box<RpcMetadata>,
 }
 
 pub trait RpcIntoError {
@@ -27,7 +45,186 @@ impl RpcError {
             code,
             message,
             details: vec![],
+            metadata: Box::default(),
+        }
+    }
+
+    /// Adds a detail: an `RpcErrorDetail`, or a message that knows its proto name, like the
+    /// `google.rpc` ones in `error_details`.
+    pub fn with_detail(mut self, detail: impl Into<RpcErrorDetail>) -> Self {
+        self.details.push(detail.into());
+        self
+    }
+
+    /// Adds a `google.rpc.BadRequest` detail with `(field, description)` violations:
+    ///
+    /// ```ignore
+    /// RpcError::invalid_argument("Invalid user").with_bad_request([("email", "must be valid")])
+    /// ```
+    pub fn with_bad_request<I, F, D>(self, violations: I) -> Self
+    where
+        I: IntoIterator<Item = (F, D)>,
+        F: Into<String>,
+        D: Into<String>,
+    {
+        self.with_detail(violations.into_iter().collect::<BadRequest>())
+    }
+
+    /// Adds a `google.rpc.RetryInfo` detail, telling clients when to try again.
+    pub fn with_retry_info(self, retry_delay: Duration) -> Self {
+        self.with_detail(RetryInfo::new(retry_delay))
+    }
+
+    /// Adds a `google.rpc.ErrorInfo` detail, see `ErrorInfo::new` (and `with_detail` to add
+    /// metadata to it).
+    pub fn with_error_info(self, reason: impl Into<String>, domain: impl Into<String>) -> Self {
+        self.with_detail(ErrorInfo::new(reason, domain))
+    }
+
+    /// Adds a `google.rpc.LocalizedMessage` detail, the message for end users in `locale`.
+    pub fn with_localized_message(
+        self,
+        locale: impl Into<String>,
+        message: impl Into<String>,
+    ) -> Self {
+        self.with_detail(LocalizedMessage::new(locale, message))
+    }
+
+    /// The first detail of type `M`, decoded.
+    pub fn detail<M>(&self) -> Option<M>
+    where
+        M: RpcErrorDetailMessage,
+    {
+        self.details.iter().find_map(RpcErrorDetail::decode)
+    }
+
+    pub fn metadata(&self) -> &RpcMetadata {
+        &self.metadata
+    }
+
+    pub fn metadata_mut(&mut self) -> &mut RpcMetadata {
+        &mut self.metadata
+    }
+
+    pub fn with_metadata(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.metadata.append(key, value);
+        self
+    }
+
+    pub fn with_metadata_bin(mut self, key: &str, value: impl Into<Bytes>) -> Self {
+        self.metadata.append_bin(key, value);
+        self
+    }
+}
+
+macro_rules! code_constructors {
+    ($($name:ident => $code:ident),* $(,)?) => {
+        impl RpcError {
+            $(
+                #[doc = concat!("An `", stringify!($code), "` error.")]
+                pub fn $name(message: impl Into<String>) -> Self {
+                    Self::new(RpcErrorCode::$code, message.into())
+                }
+            )*
         }
+    };
+}
+
+code_constructors! {
+    canceled => Canceled,
+    unknown => Unknown,
+    invalid_argument => InvalidArgument,
+    deadline_exceeded => DeadlineExceeded,
+    not_found => NotFound,
+    already_exists => AlreadyExists,
+    permission_denied => PermissionDenied,
+    resource_exhausted => ResourceExhausted,
+    failed_precondition => FailedPrecondition,
+    aborted => Aborted,
+    out_of_range => OutOfRange,
+    unimplemented => Unimplemented,
+    internal => Internal,
+    unavailable => Unavailable,
+    data_loss => DataLoss,
+    unauthenticated => Unauthenticated,
+}
+
+/// Maps the errors handlers return with `?` (or as `anyhow::Error`) to RPC errors, for the ones
+/// that shouldn't be `Internal`. Installed once for the process with `set_error_mapper`:
+///
+/// ```ignore
+/// set_error_mapper(|e: &(dyn Error + 'static)| match e.downcast_ref::<sqlx::Error>()? {
+///     sqlx::Error::RowNotFound => Some(RpcError::not_found("Not found")),
+///     _ => None,
+/// });
+/// ```
+pub trait RpcErrorMapper: Send + Sync + 'static {
+    /// The RPC error for `error`, or `None` to leave it to its sources (and in the end, to the
+    /// default `Internal` error with its message).
+    fn map_error(&self, error: &(dyn Error + 'static)) -> Option<RpcError>;
+}
+
+impl<F> RpcErrorMapper for F
+where
+    F: Fn(&(dyn Error + 'static)) -> Option<RpcError> + Send + Sync + 'static,
+{
+    fn map_error(&self, error: &(dyn Error + 'static)) -> Option<RpcError> {
+        self(error)
+    }
+}
+
+static ERROR_MAPPER: OnceLock<Box<dyn RpcErrorMapper>> = OnceLock::new();
+
+/// Installs the `RpcErrorMapper` for the process. Only the first call does, the return value is
+/// whether it was this one.
+pub fn set_error_mapper(mapper: impl RpcErrorMapper) -> bool {
+    ERROR_MAPPER.set(Box::new(mapper)).is_ok()
+}
+
+impl RpcError {
+    /// The RPC error for any other error: what the `RpcErrorMapper` maps it (or the first of its
+    /// sources) to, or `Internal` with its message. `?` does this for `Box<dyn Error>` and (with
+    /// the `anyhow` feature) `anyhow::Error`, use it in `map_err` for the others.
+    pub fn from_error(error: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        let error: Box<dyn Error + Send + Sync> = error.into();
+
+        if let Some(mapper) = ERROR_MAPPER.get() {
+            let mut next: Option<&(dyn Error + 'static)> = Some(&*error);
+            while let Some(e) = next {
+                if let Some(mapped) = mapper.map_error(e) {
+                    return mapped;
+                }
+                next = e.source();
+            }
+        }
+
+        Self::internal(error.to_string())
+    }
+}
+
+impl From<Box<dyn Error + Send + Sync>> for RpcError {
+    fn from(error: Box<dyn Error + Send + Sync>) -> Self {
+        Self::from_error(error)
+    }
+}
+
+impl RpcIntoError for Box<dyn Error + Send + Sync> {
+    fn rpc_into_error(self) -> RpcError {
+        RpcError::from_error(self)
+    }
+}
+
+#[cfg(feature = "anyhow")]
+impl From<anyhow::Error> for RpcError {
+    fn from(error: anyhow::Error) -> Self {
+        Self::from_error(error)
+    }
+}
+
+#[cfg(feature = "anyhow")]
+impl RpcIntoError for anyhow::Error {
+    fn rpc_into_error(self) -> RpcError {
+        RpcError::from_error(self)
     }
 }
 
@@ -41,19 +238,69 @@ where
             code: self.0.into(),
             message: self.1.into(),
             details: vec![],
+            metadata: Box::default(),
         }
     }
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RpcErrorDetail {
     #[serde(rename = "type")]
     pub proto_type: String,
     #[serde(rename = "value")]
     pub proto_b62_value: String,
+    /// The detail message as JSON, for people reading the error rather than code. Only sent by
+    /// services configured to (see `RpcServiceConfig::error_debug_details`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug: Option<serde_json::Value>,
 }
 
-#[derive(Clone, Serialize)]
+impl RpcErrorDetail {
+    /// A detail carrying `message`, where `proto_type` is its fully qualified name (like
+    /// `google.rpc.QuotaFailure`).
+    pub fn new<M>(proto_type: impl Into<String>, message: &M) -> Self
+    where
+        M: Message,
+    {
+        Self {
+            proto_type: proto_type.into(),
+            proto_b62_value: STANDARD_NO_PAD.encode(message.encode_to_vec()),
+            debug: None,
+        }
+    }
+
+    /// The message this detail carries, if it's an `M`.
+    pub fn decode<M>(&self) -> Option<M>
+    where
+        M: RpcErrorDetailMessage,
+    {
+        if self.proto_type != M::TYPE_NAME {
+            return None;
+        }
+        let bytes = STANDARD_NO_PAD.decode(&self.proto_b62_value).ok()?;
+        M::decode(bytes.as_slice()).ok()
+    }
+
+    /// Adds the JSON of `message` (usually the same message the detail carries) as the debug field.
+    pub fn with_debug<M>(mut self, message: &M) -> Self
+    where
+        M: Serialize,
+    {
+        self.debug = serde_json::to_value(message).ok();
+        self
+    }
+}
+
+impl<M> From<M> for RpcErrorDetail
+where
+    M: RpcErrorDetailMessage,
+{
+    fn from(message: M) -> Self {
+        Self::new(M::TYPE_NAME, &message)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum RpcErrorCode {
     Canceled,
@@ -74,22 +321,69 @@ pub enum RpcErrorCode {
     Unauthenticated,
 }
 
+impl RpcErrorCode {
+    /// The code as it's written on the wire, like `invalid_argument`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RpcErrorCode::Canceled => "canceled",
+            RpcErrorCode::Unknown => "unknown",
+            RpcErrorCode::InvalidArgument => "invalid_argument",
+            RpcErrorCode::DeadlineExceeded => "deadline_exceeded",
+            RpcErrorCode::NotFound => "not_found",
+            RpcErrorCode::AlreadyExists => "already_exists",
+            RpcErrorCode::PermissionDenied => "permission_denied",
+            RpcErrorCode::ResourceExhausted => "resource_exhausted",
+            RpcErrorCode::FailedPrecondition => "failed_precondition",
+            RpcErrorCode::Aborted => "aborted",
+            RpcErrorCode::OutOfRange => "out_of_range",
+            RpcErrorCode::Unimplemented => "unimplemented",
+            RpcErrorCode::Internal => "internal",
+            RpcErrorCode::Unavailable => "unavailable",
+            RpcErrorCode::DataLoss => "data_loss",
+            RpcErrorCode::Unauthenticated => "unauthenticated",
+        }
+    }
+
+    /// The numeric (gRPC / `google.rpc.Code`) value of the code.
+    pub fn as_i32(&self) -> i32 {
+        match self {
+            RpcErrorCode::Canceled => 1,
+            RpcErrorCode::Unknown => 2,
+            RpcErrorCode::InvalidArgument => 3,
+            RpcErrorCode::DeadlineExceeded => 4,
+            RpcErrorCode::NotFound => 5,
+            RpcErrorCode::AlreadyExists => 6,
+            RpcErrorCode::PermissionDenied => 7,
+            RpcErrorCode::ResourceExhausted => 8,
+            RpcErrorCode::FailedPrecondition => 9,
+            RpcErrorCode::Aborted => 10,
+            RpcErrorCode::OutOfRange => 11,
+            RpcErrorCode::Unimplemented => 12,
+            RpcErrorCode::Internal => 13,
+            RpcErrorCode::Unavailable => 14,
+            RpcErrorCode::DataLoss => 15,
+            RpcErrorCode::Unauthenticated => 16,
+        }
+    }
+}
+
 impl From<RpcErrorCode> for StatusCode {
     fn from(val: RpcErrorCode) -> Self {
         match val {
-            // Spec: https://connect.build/docs/protocol/#error-codes
-            RpcErrorCode::Canceled => StatusCode::REQUEST_TIMEOUT,
+            // Spec: https://connectrpc.com/docs/protocol/#error-codes
+            // 499 Client Closed Request, as nginx has it.
+            RpcErrorCode::Canceled => StatusCode::from_u16(499).unwrap(),
             RpcErrorCode::Unknown => StatusCode::INTERNAL_SERVER_ERROR,
             RpcErrorCode::InvalidArgument => StatusCode::BAD_REQUEST,
-            RpcErrorCode::DeadlineExceeded => StatusCode::REQUEST_TIMEOUT,
+            RpcErrorCode::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
             RpcErrorCode::NotFound => StatusCode::NOT_FOUND,
             RpcErrorCode::AlreadyExists => StatusCode::CONFLICT,
             RpcErrorCode::PermissionDenied => StatusCode::FORBIDDEN,
             RpcErrorCode::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
-            RpcErrorCode::FailedPrecondition => StatusCode::PRECONDITION_FAILED,
+            RpcErrorCode::FailedPrecondition => StatusCode::BAD_REQUEST,
             RpcErrorCode::Aborted => StatusCode::CONFLICT,
             RpcErrorCode::OutOfRange => StatusCode::BAD_REQUEST,
-            RpcErrorCode::Unimplemented => StatusCode::NOT_FOUND,
+            RpcErrorCode::Unimplemented => StatusCode::NOT_IMPLEMENTED,
             RpcErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
             RpcErrorCode::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
             RpcErrorCode::DataLoss => StatusCode::INTERNAL_SERVER_ERROR,
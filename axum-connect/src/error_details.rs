@@ -0,0 +1,260 @@
+use std::{collections::HashMap, time::Duration};
+
+use prost::Message;
+use serde::{Deserialize, Serialize};
+
+/// A message that can be sent as an error detail by itself, as it knows its fully qualified proto
+/// name. The `google.rpc` detail messages here are; implement it for your own to pass them to
+/// `RpcError::with_detail` directly:
+///
+/// ```ignore
+/// impl RpcErrorDetailMessage for OutOfStock {
+///     const TYPE_NAME: &'static str = "acme.shop.v1.OutOfStock";
+/// }
+///
+/// let e = RpcError::new(RpcErrorCode::FailedPrecondition, "Sold out".into());
+/// Err(e.with_detail(OutOfStock { sku }))
+/// ```
+pub trait RpcErrorDetailMessage: Message + Default {
+    /// The fully qualified proto name, like `google.rpc.BadRequest`.
+    const TYPE_NAME: &'static str;
+}
+
+macro_rules! detail_message {
+    ($ty:ident, $name:literal) => {
+        impl RpcErrorDetailMessage for $ty {
+            const TYPE_NAME: &'static str = $name;
+        }
+    };
+}
+
+// The standard details, see:
+// https://github.com/googleapis/googleapis/blob/master/google/rpc/error_details.proto
+
+/// `google.rpc.ErrorInfo`: why the error happened, as a machine readable reason in a domain.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ErrorInfo {
+    #[prost(string, tag = "1")]
+    pub reason: String,
+    #[prost(string, tag = "2")]
+    pub domain: String,
+    #[prost(map = "string, string", tag = "3")]
+    pub metadata: HashMap<String, String>,
+}
+
+/// `google.rpc.RetryInfo`: how long clients should wait before retrying.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RetryInfo {
+    #[prost(message, optional, tag = "1")]
+    pub retry_delay: Option<pbjson_types::Duration>,
+}
+
+impl ErrorInfo {
+    /// A `reason` (an UPPER_SNAKE_CASE constant, like `API_DISABLED`) within a `domain`, usually
+    /// the service's name (like `pubsub.googleapis.com`).
+    pub fn new(reason: impl Into<String>, domain: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+            domain: domain.into(),
+            metadata: HashMap::new(),
+        }
+    }
+
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+}
+
+impl RetryInfo {
+    pub fn new(retry_delay: Duration) -> Self {
+        Self {
+            retry_delay: Some(pbjson_types::Duration {
+                seconds: retry_delay.as_secs() as i64,
+                nanos: retry_delay.subsec_nanos() as i32,
+            }),
+        }
+    }
+}
+
+/// `google.rpc.DebugInfo`: a stack trace and such, for development rather than clients.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DebugInfo {
+    #[prost(string, repeated, tag = "1")]
+    pub stack_entries: Vec<String>,
+    #[prost(string, tag = "2")]
+    pub detail: String,
+}
+
+/// `google.rpc.QuotaFailure`: the quotas a call went over.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct QuotaFailure {
+    #[prost(message, repeated, tag = "1")]
+    pub violations: Vec<QuotaViolation>,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct QuotaViolation {
+    /// What the quota is for, like `principal:alice` or `project:acme`.
+    #[prost(string, tag = "1")]
+    pub subject: String,
+    #[prost(string, tag = "2")]
+    pub description: String,
+}
+
+/// `google.rpc.PreconditionFailure`: the preconditions a call failed.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PreconditionFailure {
+    #[prost(message, repeated, tag = "1")]
+    pub violations: Vec<PreconditionViolation>,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PreconditionViolation {
+    /// A service specific kind of precondition, like `TOS`.
+    #[prost(string, tag = "1")]
+    pub r#type: String,
+    #[prost(string, tag = "2")]
+    pub subject: String,
+    #[prost(string, tag = "3")]
+    pub description: String,
+}
+
+/// `google.rpc.BadRequest`: the request fields that failed validation, which connect-es clients
+/// read from `ConnectError.details` (or `findDetails(BadRequest)`).
+///
+/// ```ignore
+/// let violations = BadRequest::new()
+///     .with_violation("name", "must not be empty")
+///     .with_violation("email", "is not an email address");
+/// Err(RpcError::new(RpcErrorCode::InvalidArgument, "Invalid user".into()).with_detail(violations))
+/// ```
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BadRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub field_violations: Vec<FieldViolation>,
+}
+
+impl BadRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a violation of `field`, a path like `address.zip` or `items[2].count`.
+    pub fn with_violation(
+        mut self,
+        field: impl Into<String>,
+        description: impl Into<String>,
+    ) -> Self {
+        self.field_violations.push(FieldViolation {
+            field: field.into(),
+            description: description.into(),
+        });
+        self
+    }
+}
+
+impl<F, D> FromIterator<(F, D)> for BadRequest
+where
+    F: Into<String>,
+    D: Into<String>,
+{
+    fn from_iter<I>(violations: I) -> Self
+    where
+        I: IntoIterator<Item = (F, D)>,
+    {
+        violations
+            .into_iter()
+            .fold(Self::new(), |bad_request, (field, description)| {
+                bad_request.with_violation(field, description)
+            })
+    }
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FieldViolation {
+    #[prost(string, tag = "1")]
+    pub field: String,
+    #[prost(string, tag = "2")]
+    pub description: String,
+}
+
+/// `google.rpc.RequestInfo`: which request failed, for bug reports and support.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RequestInfo {
+    #[prost(string, tag = "1")]
+    pub request_id: String,
+    #[prost(string, tag = "2")]
+    pub serving_data: String,
+}
+
+/// `google.rpc.ResourceInfo`: the resource a call failed on, like the one `NotFound`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ResourceInfo {
+    #[prost(string, tag = "1")]
+    pub resource_type: String,
+    #[prost(string, tag = "2")]
+    pub resource_name: String,
+    #[prost(string, tag = "3")]
+    pub owner: String,
+    #[prost(string, tag = "4")]
+    pub description: String,
+}
+
+/// `google.rpc.Help`: links to read about the error.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Help {
+    #[prost(message, repeated, tag = "1")]
+    pub links: Vec<HelpLink>,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HelpLink {
+    #[prost(string, tag = "1")]
+    pub description: String,
+    #[prost(string, tag = "2")]
+    pub url: String,
+}
+
+/// `google.rpc.LocalizedMessage`: the error message for end users, in a `locale` like `de-CH`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LocalizedMessage {
+    #[prost(string, tag = "1")]
+    pub locale: String,
+    #[prost(string, tag = "2")]
+    pub message: String,
+}
+
+impl LocalizedMessage {
+    pub fn new(locale: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            locale: locale.into(),
+            message: message.into(),
+        }
+    }
+}
+
+detail_message!(ErrorInfo, "google.rpc.ErrorInfo");
+detail_message!(RetryInfo, "google.rpc.RetryInfo");
+detail_message!(DebugInfo, "google.rpc.DebugInfo");
+detail_message!(QuotaFailure, "google.rpc.QuotaFailure");
+detail_message!(PreconditionFailure, "google.rpc.PreconditionFailure");
+detail_message!(BadRequest, "google.rpc.BadRequest");
+detail_message!(RequestInfo, "google.rpc.RequestInfo");
+detail_message!(ResourceInfo, "google.rpc.ResourceInfo");
+detail_message!(Help, "google.rpc.Help");
+detail_message!(LocalizedMessage, "google.rpc.LocalizedMessage");
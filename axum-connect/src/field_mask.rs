@@ -0,0 +1,179 @@
+use std::collections::{BTreeMap, HashMap};
+
+use axum::{extract::Query, http::request::Parts};
+use pbjson_types::FieldMask;
+use prost::Message;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{
+    error::{RpcError, RpcErrorCode},
+    handler::codec::RpcEncoding,
+    response::{RpcIntoResponse, RpcResult},
+};
+
+/// Keeps only the fields of `message` named by `mask`, for partial responses (AIP-157). Paths are
+/// dot separated proto field names (`author.display_name`); a path into a repeated field applies
+/// to each element. An empty mask, or one containing `*`, keeps everything.
+///
+/// This goes through the message's (pbjson) serde impls, so it works for any generated message
+/// but costs a JSON round trip. Paths to fields that don't exist are ignored, as they can't be told
+/// apart from fields that are merely unset.
+pub fn apply_field_mask<M>(message: M, mask: &FieldMask) -> Result<M, RpcError>
+where
+    M: Serialize + DeserializeOwned,
+{
+    if mask.paths.is_empty() || mask.paths.iter().any(|p| p == "*") {
+        return Ok(message);
+    }
+
+    let internal = |e: serde_json::Error| {
+        RpcError::new(
+            RpcErrorCode::Internal,
+            format!("Failed to apply field mask: {}", e),
+        )
+    };
+
+    let mut value = serde_json::to_value(&message).map_err(internal)?;
+    prune_json(&mut value, mask.paths.iter().map(String::as_str));
+    serde_json::from_value(value).map_err(internal)
+}
+
+/// The request side of `Masked`: a request message with a `read_mask` field.
+///
+/// ```ignore
+/// impl ReadMask for GetBookRequest {
+///     fn read_mask(&self) -> Option<&FieldMask> {
+///         self.read_mask.as_ref()
+///     }
+/// }
+/// ```
+pub trait ReadMask {
+    fn read_mask(&self) -> Option<&FieldMask>;
+}
+
+/// A response that has a field mask applied to it before it's serialized, so handlers of
+/// partial-response APIs don't prune by hand:
+///
+/// ```ignore
+/// async fn get_book(request: GetBookRequest) -> Masked<Result<Book, RpcError>> {
+///     let mask = request.read_mask().cloned();
+///     Masked::new(load_book(&request.name).await, mask)
+/// }
+/// ```
+pub struct Masked<R> {
+    response: R,
+    mask: Option<FieldMask>,
+}
+
+impl<R> Masked<R> {
+    pub fn new(response: R, mask: Option<FieldMask>) -> Self {
+        Self { response, mask }
+    }
+}
+
+impl<T, R> RpcIntoResponse<T> for Masked<R>
+where
+    T: Message + Serialize + DeserializeOwned + 'static,
+    R: RpcIntoResponse<T>,
+{
+    fn rpc_into_response(self) -> RpcResult<T> {
+        let response = self.response.rpc_into_response()?;
+        match self.mask {
+            Some(mask) => apply_field_mask(response, &mask),
+            None => Ok(response),
+        }
+    }
+}
+
+/// The `fields` query parameter of a unary call answered in JSON, like
+/// `?fields=title,author.display_name`. The serialized response is pruned to those paths (the same
+/// way as `apply_field_mask`), so bandwidth-sensitive web clients can skip what they don't need
+/// without the API growing a `read_mask` field. Other encodings ignore it.
+pub(crate) struct FieldsParam(Vec<String>);
+
+impl FieldsParam {
+    pub(crate) fn requested(parts: &Parts, encoding: &RpcEncoding) -> Option<Self> {
+        if encoding.name() != "json" {
+            return None;
+        }
+
+        let Query(query) = Query::<HashMap<String, String>>::try_from_uri(&parts.uri).ok()?;
+        let paths: Vec<String> = query
+            .get("fields")?
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(str::to_string)
+            .collect();
+
+        if paths.is_empty() || paths.iter().any(|p| p == "*") {
+            return None;
+        }
+        Some(Self(paths))
+    }
+
+    /// Prunes the JSON encoded response in `buf`, leaving it as is if it doesn't parse.
+    pub(crate) fn apply(&self, buf: Vec<u8>) -> Vec<u8> {
+        let Ok(mut value) = serde_json::from_slice::<Value>(&buf) else {
+            return buf;
+        };
+        prune_json(&mut value, self.0.iter().map(String::as_str));
+        serde_json::to_vec(&value).unwrap_or(buf)
+    }
+}
+
+/// A tree of the masked paths, where a leaf keeps its whole subtree.
+#[derive(Default)]
+struct PathTree(BTreeMap<String, PathTree>);
+
+/// Drops every field of the JSON encoded message `value` that isn't on one of `paths`. Path
+/// segments are proto field names, and are matched against the lowerCamelCase JSON names too.
+pub(crate) fn prune_json<'a>(value: &mut Value, paths: impl IntoIterator<Item = &'a str>) {
+    let mut tree = PathTree::default();
+    for path in paths {
+        let mut node = &mut tree;
+        for segment in path.split('.').filter(|s| !s.is_empty()) {
+            node = node.0.entry(to_json_name(segment)).or_default();
+        }
+    }
+    prune(value, &tree);
+}
+
+fn prune(value: &mut Value, tree: &PathTree) {
+    if tree.0.is_empty() {
+        return;
+    }
+
+    match value {
+        Value::Object(fields) => {
+            fields.retain(|name, _| tree.0.contains_key(&to_json_name(name)));
+            for (name, field) in fields.iter_mut() {
+                prune(field, &tree.0[&to_json_name(name)]);
+            }
+        }
+        Value::Array(elements) => {
+            for element in elements {
+                prune(element, tree);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// `display_name` -> `displayName`, leaving names that are already lowerCamelCase alone.
+fn to_json_name(name: &str) -> String {
+    let mut json_name = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            json_name.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            json_name.push(c);
+        }
+    }
+    json_name
+}
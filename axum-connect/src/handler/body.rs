@@ -0,0 +1,180 @@
+use std::convert::Infallible;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{rejection::BytesRejection, FromRequest, Request},
+    http::{request::Parts, StatusCode},
+    BoxError,
+};
+use bytes::{Buf, BytesMut};
+use futures::{Stream, StreamExt};
+use http_body::Frame;
+use http_body_util::{BodyExt, LengthLimitError, StreamBody};
+
+use crate::prelude::{RpcError, RpcErrorCode};
+
+/// Set on enveloped messages whose payload is compressed.
+pub(crate) const FLAG_COMPRESSED: u8 = 0x01;
+
+/// Set on the last enveloped message of a Connect stream, the `EndStreamResponse`.
+pub(crate) const FLAG_END_STREAM: u8 = 0x02;
+
+/// One enveloped message, see: https://connect.build/docs/protocol/#streaming-request
+pub(crate) struct Envelope {
+    pub flags: u8,
+    pub payload: Bytes,
+}
+
+/// Envelopes whatever `encode` appends to the buffer: a flags byte, the big-endian u32 length of
+/// the payload, then the payload itself. Encoding straight into the buffer saves a copy.
+pub(crate) fn envelope<E>(
+    flags: u8,
+    encode: impl FnOnce(&mut Vec<u8>) -> Result<(), E>,
+) -> Result<Bytes, E> {
+    let mut buf = vec![flags, 0, 0, 0, 0];
+    encode(&mut buf)?;
+    let size = ((buf.len() - 5) as u32).to_be_bytes();
+    buf[1..5].copy_from_slice(&size);
+    Ok(buf.into())
+}
+
+/// A response body from a stream of frames. Frames are either data or trailers, so this is what
+/// lets a response end with real HTTP trailers rather than only an in-band end of stream message.
+pub(crate) fn frame_body<S>(frames: S) -> Body
+where
+    S: Stream<Item = Frame<Bytes>> + Send + 'static,
+{
+    Body::new(StreamBody::new(frames.map(Ok::<_, Infallible>)))
+}
+
+/// Any request body as an axum one. HTTP/3 stacks (like `h3` over quinn or s2n-quic) hand out
+/// bodies of `impl Buf` chunks rather than `Bytes`; for `Bytes` this is free, else one copy each.
+pub(crate) fn any_body<B>(body: B) -> Body
+where
+    B: http_body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    Body::new(
+        body.map_frame(|frame| frame.map_data(|mut data| data.copy_to_bytes(data.remaining()))),
+    )
+}
+
+/// Buffers the whole body of a request for a layer that has to see all of it before the handler
+/// does, within the same limit the handlers get: axum's `DefaultBodyLimit`, read from the request
+/// extensions in `parts`.
+pub(crate) async fn buffer_body<B>(parts: &Parts, body: B) -> Result<Bytes, RpcError>
+where
+    B: http_body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    Bytes::from_request(Request::from_parts(parts.clone(), any_body(body)), &())
+        .await
+        .map_err(bytes_rejection_error)
+}
+
+/// Reads enveloped messages off a request body as its frames come in, without buffering more than
+/// the message being read. Envelopes can span several frames and a frame can hold several
+/// envelopes.
+pub(crate) struct EnvelopeReader {
+    body: Body,
+    buf: BytesMut,
+    done: bool,
+    max_message_size: Option<usize>,
+}
+
+impl EnvelopeReader {
+    pub fn new(body: Body) -> Self {
+        Self {
+            body,
+            buf: BytesMut::new(),
+            done: false,
+            max_message_size: None,
+        }
+    }
+
+    /// Fails messages bigger than `max` with `ResourceExhausted`, as soon as their size is read.
+    pub fn max_message_size(mut self, max: Option<usize>) -> Self {
+        self.max_message_size = max;
+        self
+    }
+
+    /// The next message, or `None` once the body has ended cleanly.
+    pub async fn next(&mut self) -> Result<Option<Envelope>, RpcError> {
+        loop {
+            if self.buf.len() >= 5 {
+                let size = u32::from_be_bytes([self.buf[1], self.buf[2], self.buf[3], self.buf[4]]);
+                if let Some(max) = self.max_message_size.filter(|max| size as usize > *max) {
+                    return Err(RpcError::new(
+                        RpcErrorCode::ResourceExhausted,
+                        format!(
+                            "Message of {} bytes is larger than the {} byte limit",
+                            size, max
+                        ),
+                    ));
+                }
+                let end = 5 + size as usize;
+                if self.buf.len() >= end {
+                    let mut message = self.buf.split_to(end);
+                    let flags = message[0];
+                    let payload = message.split_off(5).freeze();
+                    return Ok(Some(Envelope { flags, payload }));
+                }
+            }
+
+            if self.done {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                return Err(RpcError::new(
+                    RpcErrorCode::InvalidArgument,
+                    "Request body ended in the middle of a message".to_string(),
+                ));
+            }
+
+            match self.body.frame().await {
+                // Connect requests don't carry trailers, only data frames matter.
+                Some(Ok(frame)) => {
+                    if let Ok(data) = frame.into_data() {
+                        self.buf.extend_from_slice(&data);
+                    }
+                }
+                Some(Err(e)) => return Err(body_read_error(e)),
+                None => self.done = true,
+            }
+        }
+    }
+}
+
+/// A failure buffering a request body: `ResourceExhausted` if it went over axum's body limit,
+/// `InvalidArgument` otherwise.
+pub(crate) fn bytes_rejection_error(e: BytesRejection) -> RpcError {
+    match e.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => RpcError::new(
+            RpcErrorCode::ResourceExhausted,
+            "Request body is larger than the server's limit".to_string(),
+        ),
+        _ => RpcError::new(
+            RpcErrorCode::InvalidArgument,
+            format!("Failed to read request body. {}", e),
+        ),
+    }
+}
+
+/// A failure reading a request body: `ResourceExhausted` if it went over axum's body limit,
+/// `InvalidArgument` otherwise.
+pub(crate) fn body_read_error(e: axum::Error) -> RpcError {
+    let too_large = std::error::Error::source(&e).is_some_and(|e| e.is::<LengthLimitError>());
+    if too_large {
+        RpcError::new(
+            RpcErrorCode::ResourceExhausted,
+            "Request body is larger than the server's limit".to_string(),
+        )
+    } else {
+        RpcError::new(
+            RpcErrorCode::InvalidArgument,
+            format!("Failed to read request body. {}", e),
+        )
+    }
+}
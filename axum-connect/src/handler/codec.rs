@@ -1,197 +1,1082 @@
+use std::{convert::Infallible, sync::Arc, time::Duration};
+
+use async_stream::stream;
 use axum::{
-    body::{Bytes, HttpBody},
-    extract::FromRequest,
-    http::{header, request, Request, StatusCode},
+    body::{Body, Bytes},
+    extract::{FromRequest, Request},
+    http::{self, header, request, HeaderMap, HeaderValue, Method, StatusCode},
     response::{IntoResponse, Response},
-    BoxError,
+    RequestExt,
+};
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
 };
+use http_body_util::{BodyExt, LengthLimitError, Limited};
 use prost::Message;
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::time::Instant;
+
+use crate::{
+    codec::{Codec, ProtoCodec, RpcCodecs},
+    compression::{self, Compression, RpcCompressions},
+    config::RpcServiceConfig,
+    instrument::decode_failed,
+    metadata::RpcMetadata,
+    parts::{CancelOnDrop, RpcCancellation, RpcDeadline},
+    prelude::{RpcError, RpcErrorCode},
+    request::{RpcFromRequestMessage, RpcRequestStream},
+    response::RpcPayload,
+};
+
+use super::body::{
+    bytes_rejection_error, envelope, Envelope, EnvelopeReader, FLAG_COMPRESSED, FLAG_END_STREAM,
+};
+
+/// GET request messages are URL-safe base64, with or without padding.
+const BASE64_URL: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// The codec negotiated for a request (from its `Content-Type`), used for the response too.
+#[derive(Clone)]
+pub(crate) struct RpcEncoding(Arc<dyn Codec>);
+
+impl RpcEncoding {
+    fn from_content_type(
+        codecs: &RpcCodecs,
+        content_type: &str,
+        for_streaming: bool,
+    ) -> Option<Self> {
+        let name = if for_streaming {
+            content_type.strip_prefix("application/connect+")?
+        } else {
+            content_type.strip_prefix("application/")?
+        };
+
+        codecs.get(name).map(Self)
+    }
+
+    pub fn proto() -> Self {
+        Self(Arc::new(ProtoCodec))
+    }
+
+    /// The codec's name, like `json` or `proto`.
+    pub fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    pub fn content_type(&self, for_streaming: bool) -> String {
+        if for_streaming {
+            format!("application/connect+{}", self.0.name())
+        } else {
+            format!("application/{}", self.0.name())
+        }
+    }
+
+    /// Appends the encoded message to `buf`.
+    pub fn encode<M>(&self, message: &M, buf: &mut Vec<u8>) -> Result<(), String>
+    where
+        M: Message + Serialize,
+    {
+        self.0.encode(message, buf).map_err(|e| e.to_string())
+    }
+
+    /// Appends the encoded response to `buf`, copying an `EncodedResponse` as is when it's already
+    /// in this encoding.
+    pub fn encode_payload<M>(&self, payload: RpcPayload<M>, buf: &mut Vec<u8>) -> Result<(), String>
+    where
+        M: Message + Serialize,
+    {
+        match payload {
+            RpcPayload::Encoded(encoded) if encoded.codec_name() == self.name() => {
+                buf.extend_from_slice(encoded.bytes());
+                Ok(())
+            }
+            payload => {
+                let message = payload.into_message().map_err(|e| e.message)?;
+                self.encode(&message, buf)
+            }
+        }
+    }
 
-use crate::prelude::{RpcError, RpcErrorCode};
+    pub fn decode<M>(&self, bytes: Bytes) -> Result<M, String>
+    where
+        M: Message + DeserializeOwned + Default,
+    {
+        let mut message = M::default();
+        self.0
+            .decode(bytes, &mut message)
+            .map_err(|e| e.to_string())?;
+        Ok(message)
+    }
+}
 
 pub(crate) struct ReqResInto {
-    pub binary: bool,
+    /// What the request is encoded with.
+    pub encoding: RpcEncoding,
+    /// What the response is encoded with: the request encoding, unless a unary request's `Accept`
+    /// header prefers another registered codec. Streams always answer in the request encoding.
+    pub accept: RpcEncoding,
+    pub config: RpcServiceConfig,
+    /// When the handler has to be done by: the earlier of the client's `connect-timeout-ms` and
+    /// the configured timeout.
+    pub deadline: Option<Instant>,
+    /// What to compress the response (or a stream's messages) with, if compression is on and the
+    /// client accepts one.
+    pub response_compression: Option<Arc<dyn Compression>>,
+    /// Cancels the request's `RpcCancellation` if dropped before the handler is done with it.
+    pub cancel_on_drop: CancelOnDrop,
 }
 
-pub(crate) fn encode_error(e: &RpcError, for_streaming: bool) -> Vec<u8> {
+/// Picks the response codec from an `Accept` header like `application/proto, application/json;q=0.5`.
+/// The highest weighted registered codec wins, with ties going to the request encoding. Wildcards,
+/// a missing header or nothing acceptable all keep the request encoding, rather than failing a call
+/// that already ran.
+fn negotiate_accept(
+    codecs: &RpcCodecs,
+    headers: &HeaderMap,
+    encoding: &RpcEncoding,
+) -> RpcEncoding {
+    let mut best: Option<(f32, RpcEncoding)> = None;
+
+    for accept in headers.get_all(header::ACCEPT) {
+        for media_range in accept.to_str().unwrap_or_default().split(',') {
+            let mut params = media_range.split(';');
+            let media_type = params.next().unwrap_or_default().trim().to_lowercase();
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            let candidate = match media_type.as_str() {
+                "*/*" | "application/*" => encoding.clone(),
+                media_type => match RpcEncoding::from_content_type(codecs, media_type, false) {
+                    Some(candidate) => candidate,
+                    None => continue,
+                },
+            };
+
+            let better = match &best {
+                None => quality > 0.0,
+                Some((best_quality, best)) => {
+                    quality > *best_quality
+                        || (quality == *best_quality
+                            && candidate.name() == encoding.name()
+                            && best.name() != encoding.name())
+                }
+            };
+            if better {
+                best = Some((quality, candidate));
+            }
+        }
+    }
+
+    best.map(|(_, encoding)| encoding)
+        .unwrap_or_else(|| encoding.clone())
+}
+
+/// The `Vary` header of a successful response: the request headers its encoding (and compression)
+/// were picked from, so caches don't hand a proto response to a JSON client. Query parameters, like
+/// a GET request's encoding or `fields=`, are part of the URL caches key on already.
+pub(crate) fn vary(config: &RpcServiceConfig, for_streaming: bool) -> HeaderValue {
+    let compressed = config.compress_min_size.is_some();
+    HeaderValue::from_static(match (for_streaming, compressed) {
+        (false, false) => "content-type, accept",
+        (false, true) => "content-type, accept, accept-encoding",
+        (true, false) => "content-type",
+        (true, true) => "content-type, connect-accept-encoding",
+    })
+}
+
+pub(crate) fn encode_error(e: &RpcError, for_streaming: bool) -> Bytes {
     if for_streaming {
-        // See `encode_message` for the format. It's the same, except always JSON.
-        let mut v = vec![0x2, 0, 0, 0, 0];
-        serde_json::to_writer(&mut v, &e).unwrap();
-        let size = ((v.len() - 5) as u32).to_be_bytes();
-        v[1..5].copy_from_slice(&size);
+        encode_end_stream(Some(e), &RpcMetadata::new())
+    } else {
+        let mut buf = vec![];
+        write_error_json(e, &mut buf);
+        buf.into()
+    }
+}
 
-        v
+/// A stream message in its envelope, checked against the response size limit and compressed (and
+/// flagged as such) if it's at least the compression threshold.
+pub(crate) fn encode_stream_message<M>(
+    encoding: &RpcEncoding,
+    payload: RpcPayload<M>,
+    config: &RpcServiceConfig,
+    compression: Option<&dyn Compression>,
+) -> Result<Bytes, RpcError>
+where
+    M: Message + Serialize,
+{
+    let internal = |e| RpcError::new(RpcErrorCode::Internal, e);
+
+    let Some(compression) = compression else {
+        let message = envelope(0, |buf| encoding.encode_payload(payload, buf)).map_err(internal)?;
+        config.check_response_size(message.len() - 5)?;
+        config.message_sent(message.len() - 5);
+        return Ok(message);
+    };
+
+    let mut buf = vec![];
+    encoding
+        .encode_payload(payload, &mut buf)
+        .map_err(internal)?;
+    config.check_response_size(buf.len())?;
+    config.message_sent(buf.len());
+    let (buf, compressed) = compression::compress_response(
+        Some(compression),
+        config.compress_min_size.unwrap_or_default(),
+        buf,
+    );
+    let flags = if compressed.is_some() {
+        FLAG_COMPRESSED
     } else {
-        serde_json::to_vec(&e).unwrap()
+        0
+    };
+    envelope(flags, |envelope| {
+        envelope.extend_from_slice(&buf);
+        Ok(())
+    })
+}
+
+/// The EndStreamResponse that closes a stream, with its error if it failed and its trailing
+/// metadata, see: https://connect.build/docs/protocol/#error-end-stream. It's always JSON, no matter
+/// the stream's codec.
+pub(crate) fn encode_end_stream(error: Option<&RpcError>, trailers: &RpcMetadata) -> Bytes {
+    let mut trailers = trailers.clone();
+    if let Some(e) = error {
+        trailers.extend(e.metadata().clone());
+    }
+
+    envelope(FLAG_END_STREAM, |buf| {
+        buf.push(b'{');
+        if let Some(e) = error {
+            buf.extend_from_slice(b"\"error\":");
+            write_error_json(e, buf);
+        }
+        if !trailers.is_empty() {
+            if error.is_some() {
+                buf.push(b',');
+            }
+            buf.extend_from_slice(b"\"metadata\":");
+            // Plain strings and arrays, which always serialize.
+            let metadata =
+                serde_json::to_vec(&trailers.to_json()).unwrap_or_else(|_| b"{}".to_vec());
+            buf.extend_from_slice(&metadata);
+        }
+        buf.push(b'}');
+        Ok::<_, Infallible>(())
+    })
+    .unwrap_or_else(|never| match never {})
+}
+
+/// Appends the JSON of `e` to `buf`. Reporting an error must never fail (or panic), so if it somehow
+/// doesn't serialize, this writes a minimal error with just the code and message by hand.
+fn write_error_json(e: &RpcError, buf: &mut Vec<u8>) {
+    let len = buf.len();
+    if serde_json::to_writer(&mut *buf, e).is_ok() {
+        return;
+    }
+
+    buf.truncate(len);
+    buf.extend_from_slice(b"{\"code\":\"");
+    buf.extend_from_slice(e.code.as_str().as_bytes());
+    buf.extend_from_slice(b"\",\"message\":\"");
+    for c in e.message.chars() {
+        match c {
+            '"' => buf.extend_from_slice(b"\\\""),
+            '\\' => buf.extend_from_slice(b"\\\\"),
+            c if (c as u32) < 0x20 => {
+                buf.extend_from_slice(format!("\\u{:04x}", c as u32).as_bytes())
+            }
+            c => buf.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
     }
+    buf.extend_from_slice(b"\"}");
 }
 
-// Encode an error into a Response.
+/// How a call's errors are put on the wire, which is down to the kind of call rather than its
+/// codec: errors are always JSON. See: https://connectrpc.com/docs/protocol/#error-end-stream
+pub(crate) enum ErrorFraming {
+    /// The error as the body, with the HTTP status its code maps to and its metadata as headers.
+    Unary,
+    /// An EndStreamResponse in a `200 OK`, with the stream's content type.
+    Streaming(String),
+}
+
+impl ErrorFraming {
+    /// For a call whose codec is known.
+    pub fn new(encoding: &RpcEncoding, for_streaming: bool) -> Self {
+        match for_streaming {
+            true => ErrorFraming::Streaming(encoding.content_type(true)),
+            false => ErrorFraming::Unary,
+        }
+    }
+
+    /// For a call whose codec isn't known (yet), from its headers. The kind of call is told from
+    /// the `Content-Type` unless it's given. A streaming content type is mirrored as is rather than
+    /// looked up, as the codec may only be registered further in.
+    pub fn for_headers(headers: &HeaderMap, for_streaming: Option<bool>) -> Self {
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_lowercase();
+        let content_type = content_type.split(';').next().unwrap_or_default().trim();
+        let is_streaming = content_type.starts_with("application/connect+");
+
+        match for_streaming.unwrap_or(is_streaming) {
+            true if is_streaming => ErrorFraming::Streaming(content_type.to_string()),
+            true => ErrorFraming::new(&RpcEncoding::proto(), true),
+            false => ErrorFraming::Unary,
+        }
+    }
+
+    pub fn response(&self, e: &RpcError) -> Response {
+        match self {
+            ErrorFraming::Streaming(content_type) => (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, content_type.clone())],
+                encode_error(e, true),
+            )
+                .into_response(),
+            ErrorFraming::Unary => {
+                let mut response = (
+                    StatusCode::from(e.code.clone()),
+                    [(header::CONTENT_TYPE, "application/json")],
+                    encode_error(e, false),
+                )
+                    .into_response();
+                // Metadata that can't be a header is dropped, rather than masking the error itself.
+                let _ = e.metadata().write_headers(response.headers_mut(), "");
+                response
+            }
+        }
+    }
+}
+
+/// Encode an error into a Response, for a call whose codec is known.
 pub(crate) fn encode_error_response(
     e: &RpcError,
-    as_binary: bool,
+    encoding: &RpcEncoding,
     for_streaming: bool,
 ) -> Response {
-    if for_streaming {
-        (
-            // Streaming errors ALWAYS return the error in JSON, but the content type still mirrors
-            // what ever the request was made with.
-            StatusCode::OK,
-            [(
-                header::CONTENT_TYPE,
-                if as_binary {
-                    "application/connect+proto"
-                } else {
-                    "application/connect+json"
-                },
-            )],
-            encode_error(e, true),
-        )
-            .into_response()
-    } else {
-        (
-            StatusCode::from(e.code.clone()),
-            [(header::CONTENT_TYPE, "application/json")],
-            encode_error(e, false),
-        )
-            .into_response()
+    ErrorFraming::new(encoding, for_streaming).response(e)
+}
+
+/// Encode an error into a Response from outside a handler (like in a layer), where the encoding
+/// hasn't been negotiated yet. Streaming vs unary framing is inferred from the `Content-Type`.
+pub(crate) fn encode_error_response_for_headers(e: &RpcError, headers: &HeaderMap) -> Response {
+    ErrorFraming::for_headers(headers, None).response(e)
+}
+
+/// Whether a request looks like an RPC call, for telling those apart from other requests to the
+/// same paths: a POST in a registered codec's content type (unary or streaming) or a gRPC one, or
+/// a GET with a Connect `message` and `encoding` in the query.
+pub(crate) fn is_rpc_call<B>(req: &http::Request<B>) -> bool {
+    match *req.method() {
+        Method::POST => {
+            let content_type = req
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_lowercase();
+            let content_type = content_type.split(';').next().unwrap_or_default().trim();
+            let codecs = req
+                .extensions()
+                .get::<RpcCodecs>()
+                .unwrap_or_else(|| RpcCodecs::default_ref());
+
+            content_type.starts_with("application/grpc")
+                || RpcEncoding::from_content_type(codecs, content_type, true).is_some()
+                || RpcEncoding::from_content_type(codecs, content_type, false).is_some()
+        }
+        Method::GET => {
+            let query = req.uri().query().unwrap_or_default();
+            let has = |key: &str| {
+                query
+                    .split('&')
+                    .any(|pair| pair.split('=').next() == Some(key))
+            };
+            has("message") && has("encoding")
+        }
+        _ => false,
+    }
+}
+
+/// The response to a request in a content type no registered codec (or the wrong kind of call)
+/// takes: not a Connect error, as the client can't be assumed to speak Connect, but a plain
+/// `415 Unsupported Media Type` listing the content types that would do in `Accept-Post`.
+fn unsupported_media_type(codecs: &RpcCodecs, for_streaming: bool) -> Response {
+    let accepted: Vec<_> = codecs
+        .names()
+        .map(|name| match for_streaming {
+            true => format!("application/connect+{}", name),
+            false => format!("application/{}", name),
+        })
+        .collect();
+
+    let mut response = StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response();
+    if let Ok(accepted) = HeaderValue::from_str(&accepted.join(", ")) {
+        response.headers_mut().insert("accept-post", accepted);
     }
+    response
 }
 
+#[allow(clippy::result_large_err)]
 pub(crate) fn decode_check_headers(
     parts: &mut request::Parts,
     for_streaming: bool,
 ) -> Result<ReqResInto, Response> {
-    // Check the version header, if specified.
-    if let Some(version) = parts.headers.get("connect-protocol-version") {
-        let version = version.to_str().unwrap_or_default();
-        if version != "1" {
-            return Err(encode_error_response(
-                &RpcError::new(
+    let config = parts
+        .extensions
+        .get::<RpcServiceConfig>()
+        .cloned()
+        .unwrap_or_default();
+
+    // Check the version header, if specified (or always, when the config requires it).
+    let framing = ErrorFraming::for_headers(&parts.headers, Some(for_streaming));
+    match parts.headers.get("connect-protocol-version") {
+        Some(version) => {
+            let version = version.to_str().unwrap_or_default();
+            if version != "1" {
+                return Err(framing.response(&config.outgoing_error(RpcError::new(
                     RpcErrorCode::InvalidArgument,
                     format!("Unsupported protocol version: {}", version),
-                ),
-                true,
-                for_streaming,
-            ));
+                ))));
+            }
+        }
+        None if config.require_protocol_version => {
+            return Err(framing.response(&config.outgoing_error(RpcError::new(
+                RpcErrorCode::InvalidArgument,
+                "Missing connect-protocol-version header".to_string(),
+            ))));
         }
+        None => {}
     }
 
-    // Decode the content type (binary or JSON).
+    // Decode the content type (binary, JSON, ...) against the registered codecs.
+    let codecs = parts
+        .extensions
+        .get::<RpcCodecs>()
+        .unwrap_or_else(|| RpcCodecs::default_ref());
     // TODO: I'm not sure if this is correct. The Spec doesn't say what content type will be set for
     //       server-streaming responses.
-    let binary = match parts.headers.get("content-type") {
-        Some(content_type) => match (
-            content_type
-                .to_str()
-                .unwrap_or_default()
-                .to_lowercase()
-                .split(';')
-                .next()
-                .unwrap_or_default()
-                .trim(),
-            for_streaming,
-        ) {
-            ("application/json", false) => false,
-            ("application/proto", false) => true,
-            ("application/connect+json", true) => false,
-            ("application/connect+proto", true) => true,
-            (s, _) => {
-                return Err(encode_error_response(
-                    &RpcError::new(
-                        RpcErrorCode::InvalidArgument,
-                        format!("Wrong or unknown Content-Type: {}", s),
-                    ),
-                    true,
-                    true,
-                ))
+    let encoding = match parts.headers.get("content-type") {
+        Some(content_type) => {
+            let content_type = content_type.to_str().unwrap_or_default().to_lowercase();
+            let content_type = content_type.split(';').next().unwrap_or_default().trim();
+
+            match RpcEncoding::from_content_type(codecs, content_type, for_streaming) {
+                Some(encoding) => encoding,
+                None => return Err(unsupported_media_type(codecs, for_streaming)),
             }
-        },
-        None => {
-            return Err(encode_error_response(
-                &RpcError::new(
-                    RpcErrorCode::InvalidArgument,
-                    "Missing Content-Type header".to_string(),
-                ),
-                true,
-                true,
-            ))
         }
+        None => return Err(unsupported_media_type(codecs, for_streaming)),
     };
 
-    Ok(ReqResInto { binary })
+    let accept = if for_streaming {
+        encoding.clone()
+    } else {
+        negotiate_accept(codecs, &parts.headers, &encoding)
+    };
+
+    // The client's timeout, a positive number of milliseconds with at most 10 digits.
+    let timeout = match parts.headers.get("connect-timeout-ms") {
+        Some(timeout) => {
+            let timeout = timeout.to_str().unwrap_or_default();
+            match timeout.parse::<u64>() {
+                Ok(ms) if timeout.len() <= 10 && timeout.bytes().all(|b| b.is_ascii_digit()) => {
+                    Some(Duration::from_millis(ms))
+                }
+                _ => {
+                    return Err(encode_error_response(
+                        &config.outgoing_error(RpcError::new(
+                            RpcErrorCode::InvalidArgument,
+                            format!("Invalid connect-timeout-ms header: {}", timeout),
+                        )),
+                        &encoding,
+                        for_streaming,
+                    ))
+                }
+            }
+        }
+        None => None,
+    };
+    let deadline = config.deadline(timeout);
+    parts.extensions.insert(RpcDeadline(deadline));
+    let accept_encoding = if for_streaming {
+        "connect-accept-encoding"
+    } else {
+        "accept-encoding"
+    };
+    let response_compression = config.compress_min_size.and_then(|_| {
+        parts
+            .extensions
+            .get::<RpcCompressions>()
+            .unwrap_or_else(|| RpcCompressions::default_ref())
+            .negotiate(&parts.headers, accept_encoding)
+    });
+    let cancellation = RpcCancellation::default();
+    parts.extensions.insert(cancellation.clone());
+
+    Ok(ReqResInto {
+        encoding,
+        accept,
+        config,
+        deadline,
+        response_compression,
+        cancel_on_drop: cancellation.cancel_on_drop(),
+    })
 }
 
-pub(crate) async fn decode_request_payload<M, S, B>(
-    req: Request<B>,
+#[allow(clippy::result_large_err)]
+/// Runs the `before` of the config's interceptors, if any, on a call that passed the checks.
+pub(crate) async fn intercept(
+    parts: &request::Parts,
+    encoding: &RpcEncoding,
+    config: &RpcServiceConfig,
+    for_streaming: bool,
+) -> Result<(), Response> {
+    let Some(call) = &config.call else {
+        return Ok(());
+    };
+    call.intercept(parts, encoding.name(), for_streaming)
+        .await
+        .map_err(|e| encode_error_response(&config.outgoing_error(e), encoding, for_streaming))
+}
+
+#[allow(clippy::result_large_err)]
+/// Reads and decodes the request message, returning it with its encoded size.
+pub(crate) async fn decode_request_payload<M, T, S>(
+    req: Request,
     state: &S,
-    as_binary: bool,
+    encoding: &RpcEncoding,
+    config: &RpcServiceConfig,
     for_streaming: bool,
-) -> Result<M, Response>
+) -> Result<(T, usize), Response>
 where
     M: Message + DeserializeOwned + Default,
+    T: RpcFromRequestMessage<M>,
     S: Send + Sync + 'static,
-    B: Send + Sync + 'static,
-    B: HttpBody + Send + 'static,
-    B::Data: Send,
-    B::Error: Into<BoxError>,
 {
-    // Axum-connect only supports unary request types, so we can ignore for_streaming.
-    if as_binary {
-        let bytes = match Bytes::from_request(req, state).await {
-            Ok(bytes) => bytes,
-            Err(e) => {
-                return Err(encode_error_response(
-                    &RpcError::new(
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    let compression = request_compression(&req, encoding, config, for_streaming)?;
+    let max_decompressed = config
+        .max_request_message_size
+        .unwrap_or(compression::DEFAULT_MAX_DECOMPRESSED_SIZE);
+
+    let bytes = match (for_streaming, config.max_request_message_size) {
+        (true, max) => {
+            decode_single_envelope(req, max, compression.as_deref(), max_decompressed).await
+        }
+        (false, Some(max)) => read_limited_body(req, max).await,
+        (false, None) => Bytes::from_request(req, state)
+            .await
+            .map_err(bytes_rejection_error),
+    };
+    let bytes = match (for_streaming, &compression) {
+        (false, Some(compression)) => bytes.and_then(|bytes| {
+            compression::decompress(compression.as_ref(), &bytes, max_decompressed)
+        }),
+        _ => bytes,
+    };
+    let bytes = bytes.map_err(|e| {
+        decode_failed(&e);
+        encode_error_response(&config.outgoing_error(e), encoding, for_streaming)
+    })?;
+
+    let message = encoding.decode(bytes.clone()).map_err(|e| {
+        let e = RpcError::new(
+            RpcErrorCode::InvalidArgument,
+            format!("Failed to decode {} message. {}", encoding.0.name(), e),
+        );
+        decode_failed(&e);
+        encode_error_response(&config.outgoing_error(e), encoding, for_streaming)
+    })?;
+
+    let size = bytes.len();
+    config.message_received(size);
+    Ok((
+        T::rpc_from_request_message(message, bytes, content_type),
+        size,
+    ))
+}
+
+#[allow(clippy::result_large_err)]
+/// The compression a request's messages are in, from its `Content-Encoding` (or, for streams,
+/// `Connect-Content-Encoding`). Streams compress message by message, flagging the compressed ones.
+fn request_compression(
+    req: &Request,
+    encoding: &RpcEncoding,
+    config: &RpcServiceConfig,
+    for_streaming: bool,
+) -> Result<Option<Arc<dyn Compression>>, Response> {
+    let (compression_header, accept_header) = if for_streaming {
+        ("connect-content-encoding", "connect-accept-encoding")
+    } else {
+        ("content-encoding", "accept-encoding")
+    };
+    let compressions = req
+        .extensions()
+        .get::<RpcCompressions>()
+        .unwrap_or_else(|| RpcCompressions::default_ref());
+    compressions
+        .for_header(req.headers().get(compression_header))
+        .map_err(|e| {
+            let mut res = encode_error_response(&config.outgoing_error(e), encoding, for_streaming);
+            if let Ok(accepted) = HeaderValue::from_str(&compressions.accepted()) {
+                res.headers_mut().insert(accept_header, accepted);
+            }
+            res
+        })
+}
+
+#[allow(clippy::result_large_err)]
+/// The messages of a client-streaming request, read and decoded as they arrive. Like for server
+/// streams, a configured message size limit applies to each message and replaces axum's body
+/// limit.
+pub(crate) fn decode_request_stream<M>(
+    req: Request,
+    encoding: &RpcEncoding,
+    config: &RpcServiceConfig,
+) -> Result<RpcRequestStream<M>, Response>
+where
+    M: Message + DeserializeOwned + Default + Send + 'static,
+{
+    let compression = request_compression(&req, encoding, config, true)?;
+    let max = config.max_request_message_size;
+    let max_decompressed = max.unwrap_or(compression::DEFAULT_MAX_DECOMPRESSED_SIZE);
+    let body = match max {
+        Some(_) => req.into_body(),
+        None => req.into_limited_body(),
+    };
+    let mut reader = EnvelopeReader::new(body).max_message_size(max);
+    let encoding = encoding.clone();
+    let call = config.call.clone();
+
+    Ok(RpcRequestStream::new(stream! {
+        loop {
+            let message = match reader.next().await {
+                Ok(Some(message)) => message,
+                Ok(None) => break,
+                Err(e) => {
+                    decode_failed(&e);
+                    yield Err(e);
+                    break;
+                }
+            };
+            let payload = match envelope_payload(message, compression.as_deref(), max_decompressed) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    decode_failed(&e);
+                    yield Err(e);
+                    break;
+                }
+            };
+            if let Some(call) = &call {
+                call.message_received(payload.len());
+            }
+            match encoding.decode(payload) {
+                Ok(message) => yield Ok(message),
+                Err(e) => {
+                    let e = RpcError::new(
                         RpcErrorCode::InvalidArgument,
-                        format!("Failed to read request body. {}", e),
-                    ),
-                    as_binary,
-                    for_streaming,
-                ))
+                        format!("Failed to decode {} message. {}", encoding.name(), e),
+                    );
+                    decode_failed(&e);
+                    yield Err(e);
+                    break;
+                }
             }
-        };
+        }
+    }))
+}
 
-        let message: M = M::decode(bytes).map_err(|e| {
-            encode_error_response(
-                &RpcError::new(
-                    RpcErrorCode::InvalidArgument,
-                    format!("Failed to decode binary protobuf. {}", e),
-                ),
-                as_binary,
-                for_streaming,
-            )
-        })?;
+#[allow(clippy::result_large_err)]
+/// The POST request a Connect GET request stands for, see:
+/// https://connectrpc.com/docs/protocol/#unary-get-request. The message, its codec and its
+/// compression come from the query string, and become the body, `Content-Type` and
+/// `Content-Encoding` they'd be in a POST. Everything else (metadata, the timeout) is kept.
+pub(crate) fn get_request_into_post(req: Request) -> Result<Request, Response> {
+    let invalid = |message: String| {
+        ErrorFraming::Unary.response(&RpcError::new(RpcErrorCode::InvalidArgument, message))
+    };
 
-        Ok(message)
+    let (mut parts, _) = req.into_parts();
+    let mut message = None;
+    let mut encoding = None;
+    let mut base64 = false;
+    let mut compression = None;
+    let mut connect = None;
+    for pair in parts.uri.query().unwrap_or_default().split('&') {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = percent_decode(value);
+        match key {
+            "message" => message = Some(value),
+            "encoding" => encoding = Some(String::from_utf8_lossy(&value).into_owned()),
+            "base64" => base64 = value == b"1",
+            "compression" => compression = Some(String::from_utf8_lossy(&value).into_owned()),
+            "connect" => connect = Some(String::from_utf8_lossy(&value).into_owned()),
+            _ => {}
+        }
+    }
+
+    let message = message.ok_or_else(|| invalid("Missing `message` query parameter".into()))?;
+    let encoding = encoding.ok_or_else(|| invalid("Missing `encoding` query parameter".into()))?;
+    let codecs = parts
+        .extensions
+        .get::<RpcCodecs>()
+        .unwrap_or_else(|| RpcCodecs::default_ref());
+    if codecs.get(&encoding).is_none() {
+        return Err(unsupported_media_type(codecs, false));
+    }
+    let message = if base64 {
+        BASE64_URL
+            .decode(&message)
+            .map_err(|e| invalid(format!("Invalid base64 `message` query parameter. {}", e)))?
     } else {
-        let str = match String::from_request(req, state).await {
-            Ok(value) => value,
-            Err(e) => {
-                return Err(encode_error_response(
-                    &RpcError::new(
-                        RpcErrorCode::InvalidArgument,
-                        format!("Failed to read request body. {}", e),
-                    ),
-                    as_binary,
-                    for_streaming,
-                ));
+        message
+    };
+
+    let header = |value: &str| {
+        HeaderValue::from_str(value)
+            .map_err(|_| invalid(format!("Invalid query parameter: {}", value)))
+    };
+    parts.method = Method::POST;
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        header(&format!("application/{}", encoding))?,
+    );
+    parts.headers.remove(header::CONTENT_LENGTH);
+    match compression.as_deref() {
+        Some(compression) => {
+            parts
+                .headers
+                .insert(header::CONTENT_ENCODING, header(compression)?);
+        }
+        None => {
+            parts.headers.remove(header::CONTENT_ENCODING);
+        }
+    }
+    // The protocol version is a query parameter too, `connect=v1`.
+    if let Some(connect) = &connect {
+        let version = connect.strip_prefix('v').unwrap_or(connect);
+        parts
+            .headers
+            .insert("connect-protocol-version", header(version)?);
+    }
+
+    Ok(Request::from_parts(parts, Body::from(message)))
+}
+
+/// Percent-decodes a query string value, with `+` for a space as in form encoding.
+fn percent_decode(value: &str) -> Vec<u8> {
+    let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                (Some(high), Some(low)) => {
+                    decoded.push(high << 4 | low);
+                    i += 3;
+                    continue;
+                }
+                _ => decoded.push(b'%'),
+            },
+            b'+' => decoded.push(b' '),
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    decoded
+}
+
+/// A unary request body, up to the configured size rather than axum's body limit.
+async fn read_limited_body(req: Request, max: usize) -> Result<Bytes, RpcError> {
+    match Limited::new(req.into_body(), max).collect().await {
+        Ok(collected) => Ok(collected.to_bytes()),
+        Err(e) if e.is::<LengthLimitError>() => Err(RpcError::new(
+            RpcErrorCode::ResourceExhausted,
+            format!("Request message is larger than the {} byte limit", max),
+        )),
+        Err(e) => Err(RpcError::new(
+            RpcErrorCode::InvalidArgument,
+            format!("Failed to read request body. {}", e),
+        )),
+    }
+}
+
+/// Server-streaming requests are a single enveloped message. With a configured message size limit,
+/// that replaces axum's body limit.
+async fn decode_single_envelope(
+    req: Request,
+    max: Option<usize>,
+    compression: Option<&dyn Compression>,
+    max_decompressed: usize,
+) -> Result<Bytes, RpcError> {
+    let invalid = |message: &str| RpcError::new(RpcErrorCode::InvalidArgument, message.into());
+    let body = match max {
+        Some(_) => req.into_body(),
+        None => req.into_limited_body(),
+    };
+    let mut reader = EnvelopeReader::new(body).max_message_size(max);
+
+    let message = reader
+        .next()
+        .await?
+        .ok_or_else(|| invalid("Missing request message"))?;
+    if reader.next().await?.is_some() {
+        return Err(invalid("Expected exactly one request message"));
+    }
+
+    envelope_payload(message, compression, max_decompressed)
+}
+
+/// The payload of an enveloped request message, decompressed if it's flagged as compressed.
+fn envelope_payload(
+    message: Envelope,
+    compression: Option<&dyn Compression>,
+    max_decompressed: usize,
+) -> Result<Bytes, RpcError> {
+    match (message.flags & FLAG_COMPRESSED != 0, compression) {
+        (false, _) => Ok(message.payload),
+        (true, Some(compression)) => {
+            compression::decompress(compression, &message.payload, max_decompressed)
+        }
+        (true, None) => Err(RpcError::new(
+            RpcErrorCode::InvalidArgument,
+            "Compressed request message without a connect-content-encoding".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        handler::service::RpcService,
+        health::{Health, HealthCheckRequest, HealthCheckResponse},
+        router::RpcRouterExt,
+    };
+
+    /// A call, and how the error it gets must be framed.
+    struct Case {
+        name: &'static str,
+        path: &'static str,
+        headers: &'static [(&'static str, &'static str)],
+        body: Vec<u8>,
+        status: StatusCode,
+        content_type: Option<&'static str>,
+        /// The Connect error code, if the response is a Connect error.
+        code: Option<&'static str>,
+    }
+
+    const CHECK: &str = "/grpc.health.v1.Health/Check";
+    const WATCH: &str = "/grpc.health.v1.Health/Watch";
+    /// Fails with the code named by the `service` of the request.
+    const FAIL: &str = "/test.Errors/Fail";
+
+    /// Every code, with the HTTP status the conformance suite expects of a unary call failing
+    /// with it (see: https://connectrpc.com/docs/protocol/#error-codes).
+    const STATUSES: &[(&str, u16)] = &[
+        ("canceled", 499),
+        ("unknown", 500),
+        ("invalid_argument", 400),
+        ("deadline_exceeded", 504),
+        ("not_found", 404),
+        ("already_exists", 409),
+        ("permission_denied", 403),
+        ("resource_exhausted", 429),
+        ("failed_precondition", 400),
+        ("aborted", 409),
+        ("out_of_range", 400),
+        ("unimplemented", 501),
+        ("internal", 500),
+        ("unavailable", 503),
+        ("data_loss", 500),
+        ("unauthenticated", 401),
+    ];
+
+    async fn fail(request: HealthCheckRequest) -> Result<HealthCheckResponse, RpcError> {
+        let code = serde_json::from_value(serde_json::Value::String(request.service)).unwrap();
+        Err(RpcError::new(code, "failed".to_string()))
+    }
+
+    fn enveloped(flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut envelope = vec![flags];
+        envelope.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        envelope.extend_from_slice(payload);
+        envelope
+    }
+
+    fn cases() -> Vec<Case> {
+        let unknown = HealthCheckRequest {
+            service: "unknown".to_string(),
+        };
+        let codes = STATUSES.iter().map(|&(code, status)| Case {
+            name: code,
+            path: FAIL,
+            headers: &[("content-type", "application/json")],
+            body: format!(r#"{{"service":"{}"}}"#, code).into_bytes(),
+            status: StatusCode::from_u16(status).unwrap(),
+            content_type: Some("application/json"),
+            code: Some(code),
+        });
+        let mut cases = vec![
+            Case {
+                name: "unary JSON error",
+                path: CHECK,
+                headers: &[("content-type", "application/json")],
+                body: br#"{"service":"unknown"}"#.to_vec(),
+                status: StatusCode::NOT_FOUND,
+                content_type: Some("application/json"),
+                code: Some("not_found"),
+            },
+            Case {
+                name: "unary proto error is still JSON",
+                path: CHECK,
+                headers: &[("content-type", "application/proto")],
+                body: unknown.encode_to_vec(),
+                status: StatusCode::NOT_FOUND,
+                content_type: Some("application/json"),
+                code: Some("not_found"),
+            },
+            Case {
+                name: "unary malformed message",
+                path: CHECK,
+                headers: &[("content-type", "application/json")],
+                body: b"{".to_vec(),
+                status: StatusCode::BAD_REQUEST,
+                content_type: Some("application/json"),
+                code: Some("invalid_argument"),
+            },
+            Case {
+                name: "unary unsupported protocol version",
+                path: CHECK,
+                headers: &[
+                    ("content-type", "application/proto"),
+                    ("connect-protocol-version", "2"),
+                ],
+                body: vec![],
+                status: StatusCode::BAD_REQUEST,
+                content_type: Some("application/json"),
+                code: Some("invalid_argument"),
+            },
+            Case {
+                name: "unary unsupported content type",
+                path: CHECK,
+                headers: &[("content-type", "text/plain")],
+                body: vec![],
+                status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                content_type: None,
+                code: None,
+            },
+            Case {
+                name: "unary missing content type",
+                path: CHECK,
+                headers: &[],
+                body: vec![],
+                status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                content_type: None,
+                code: None,
+            },
+            Case {
+                name: "stream with a unary content type",
+                path: WATCH,
+                headers: &[("content-type", "application/json")],
+                body: vec![],
+                status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                content_type: None,
+                code: None,
+            },
+            Case {
+                name: "stream malformed message",
+                path: WATCH,
+                headers: &[("content-type", "application/connect+json")],
+                body: enveloped(0, b"{"),
+                status: StatusCode::OK,
+                content_type: Some("application/connect+json"),
+                code: Some("invalid_argument"),
+            },
+            Case {
+                name: "stream unsupported protocol version mirrors the content type",
+                path: WATCH,
+                headers: &[
+                    ("content-type", "application/connect+json"),
+                    ("connect-protocol-version", "2"),
+                ],
+                body: enveloped(0, b"{}"),
+                status: StatusCode::OK,
+                content_type: Some("application/connect+json"),
+                code: Some("invalid_argument"),
+            },
+        ];
+        cases.extend(codes);
+        cases
+    }
+
+    /// The Connect error code of a response body: a JSON error, or the error of an
+    /// EndStreamResponse.
+    fn error_code(body: &[u8], streaming: bool) -> Option<String> {
+        let json: serde_json::Value = match streaming {
+            true => {
+                assert_eq!(body[0], FLAG_END_STREAM, "not an EndStreamResponse");
+                serde_json::from_slice(&body[5..]).ok()?
             }
+            false => serde_json::from_slice(body).ok()?,
         };
+        let error = if streaming { &json["error"] } else { &json };
+        error["code"].as_str().map(str::to_string)
+    }
 
-        let message: M = serde_json::from_str(&str).map_err(|e| {
-            encode_error_response(
-                &RpcError::new(
-                    RpcErrorCode::InvalidArgument,
-                    format!("Failed to decode JSON protobuf. {}", e),
-                ),
-                as_binary,
-                for_streaming,
-            )
-        })?;
+    #[tokio::test]
+    async fn frames_errors_by_the_kind_of_call() {
+        let app = Router::new()
+            .rpc(Health::new().routes())
+            .route_service(FAIL, RpcService::unary(fail, ()));
 
-        Ok(message)
+        for case in cases() {
+            let mut req = http::Request::post(case.path);
+            for (name, value) in case.headers {
+                req = req.header(*name, *value);
+            }
+            let req = req.body(Body::from(case.body)).unwrap();
+
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), case.status, "{}", case.name);
+            let content_type = res
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .map(|v| v.to_str().unwrap().to_string());
+            assert_eq!(content_type.as_deref(), case.content_type, "{}", case.name);
+            if case.status == StatusCode::UNSUPPORTED_MEDIA_TYPE {
+                assert!(res.headers().contains_key("accept-post"), "{}", case.name);
+            }
+
+            let streaming = case.path == WATCH && case.status == StatusCode::OK;
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            let code = case
+                .code
+                .map(|_| error_code(&body, streaming).unwrap_or_default());
+            assert_eq!(code.as_deref(), case.code, "{}", case.name);
+        }
     }
 }
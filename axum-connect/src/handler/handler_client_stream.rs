@@ -0,0 +1,239 @@
+use std::pin::Pin;
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures::Future;
+use prost::Message;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    config::{deadline_exceeded, within, RpcServiceConfig},
+    error::RpcIntoError,
+    parts::RpcFromRequestParts,
+    request::RpcRequestStream,
+    response::{RpcIntoResponse, RpcResponseParts},
+};
+
+use super::{
+    codec::{
+        decode_check_headers, decode_request_stream, encode_end_stream, encode_error_response,
+        encode_stream_message, intercept, vary, ErrorFraming, ReqResInto,
+    },
+    panic::catch_panic,
+};
+
+/// A handler of a client-streaming RPC: it takes the request messages as an `RpcRequestStream`
+/// (after any extractors) and returns a single response. That response still goes out in the
+/// streaming format, as one enveloped message followed by the EndStreamResponse.
+pub trait RpcHandlerClientStream<TMReq, TMRes, TUid, TState>:
+    Clone + Send + Sync + Sized + 'static
+{
+    type Future: Future<Output = Response> + Send + 'static;
+
+    fn call(self, req: Request, state: TState) -> Self::Future;
+}
+
+// This is here because writing Rust macros sucks a**. So I uncomment this when I'm trying to modify
+// the below macro.
+// #[allow(unused_parens, non_snake_case, unused_mut, unused_variables)]
+// impl<TMReq, TMRes, TInto, TFnFut, TFn, TState, T1>
+//     RpcHandlerClientStream<TMReq, TMRes, (T1, TMReq), TState> for TFn
+// where
+//     TMReq: Message + DeserializeOwned + Default + Send + 'static,
+//     TMRes: Message + Serialize + Send + 'static,
+//     TInto: RpcIntoResponse<TMRes>,
+//     TFnFut: Future<Output = TInto> + Send,
+//     TFn: FnOnce(T1, RpcRequestStream<TMReq>) -> TFnFut + Clone + Send + Sync + 'static,
+//     TState: Send + Sync + 'static,
+//     T1: RpcFromRequestParts<TMRes, TState> + Send,
+// {
+//     type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
+
+//     fn call(self, mut req: Request, state: TState) -> Self::Future {
+//         let framing = ErrorFraming::for_headers(req.headers(), Some(true));
+//         let config = RpcServiceConfig::for_call(&mut req);
+//         Box::pin(catch_panic(framing, config, async move {
+//             let (mut parts, body) = req.into_parts();
+
+//             let ReqResInto { encoding, config, deadline, response_compression, cancel_on_drop, .. } = match decode_check_headers(&mut parts, true) {
+//                 Ok(value) => value,
+//                 Err(e) => return e,
+//             };
+
+//             if let Err(e) = intercept(&parts, &encoding, &config, true).await {
+//                 return e;
+//             }
+
+//             let t1 = match T1::rpc_from_request_parts(&mut parts, &state).await {
+//                 Ok(value) => value,
+//                 Err(e) => {
+//                     let e = config.outgoing_error(e.rpc_into_error());
+//                     return encode_error_response(&e, &encoding, true);
+//                 }
+//             };
+
+//             let req = Request::from_parts(parts, body);
+
+//             let requests = match decode_request_stream::<TMReq>(req, &encoding, &config) {
+//                 Ok(value) => value,
+//                 Err(e) => return e,
+//             };
+
+//             let res = match within(deadline, self(t1, requests)).await {
+//                 Some(res) => {
+//                     cancel_on_drop.disarm();
+//                     res.rpc_into_parts()
+//                 }
+//                 None => Err(deadline_exceeded()),
+//             };
+//             let RpcResponseParts { payload, headers, trailers } = match res {
+//                 Ok(value) => value,
+//                 Err(e) => {
+//                     let e = config.outgoing_error(e);
+//                     return encode_error_response(&e, &encoding, true);
+//                 }
+//             };
+
+//             let message = match encode_stream_message(&encoding, payload, &config, response_compression.as_deref()) {
+//                 Ok(value) => value,
+//                 Err(e) => return encode_error_response(&config.outgoing_error(e), &encoding, true),
+//             };
+
+//             let mut response_headers = HeaderMap::new();
+//             if let Err(e) = headers.write_headers(&mut response_headers, "") {
+//                 return encode_error_response(&config.outgoing_error(e), &encoding, true);
+//             }
+//             if let Some(compression) = &response_compression {
+//                 response_headers.insert("connect-content-encoding", HeaderValue::from_static(compression.name()));
+//             }
+//             response_headers.append(header::VARY, vary(&config, true));
+
+//             // The one response message, then the EndStreamResponse with the trailing metadata.
+//             let body = [message, encode_end_stream(None, &trailers)].concat();
+
+//             (
+//                 StatusCode::OK,
+//                 [(header::CONTENT_TYPE, encoding.content_type(true))],
+//                 response_headers,
+//                 Body::from(body),
+//             )
+//                 .into_response()
+//         }))
+//     }
+// }
+
+macro_rules! impl_handler {
+    (
+        [$($ty:ident),*]
+    ) => {
+        #[allow(unused_parens, non_snake_case, unused_mut, unused_variables)]
+        impl<TMReq, TMRes, TInto, TFnFut, TFn, TState, $($ty,)*>
+            RpcHandlerClientStream<TMReq, TMRes, ($($ty,)* TMReq), TState> for TFn
+        where
+            TMReq: Message + DeserializeOwned + Default + Send + 'static,
+            TMRes: Message + Serialize + Send + 'static,
+            TInto: RpcIntoResponse<TMRes>,
+            TFnFut: Future<Output = TInto> + Send,
+            TFn: FnOnce($($ty,)* RpcRequestStream<TMReq>) -> TFnFut + Clone + Send + Sync + 'static,
+            TState: Send + Sync + 'static,
+            $( $ty: RpcFromRequestParts<TMRes, TState> + Send, )*
+        {
+            type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
+
+            fn call(self, mut req: Request, state: TState) -> Self::Future {
+                let framing = ErrorFraming::for_headers(req.headers(), Some(true));
+                let config = RpcServiceConfig::for_call(&mut req);
+                Box::pin(catch_panic(framing, config, async move {
+                    let (mut parts, body) = req.into_parts();
+
+                    let ReqResInto { encoding, config, deadline, response_compression, cancel_on_drop, .. } = match decode_check_headers(&mut parts, true) {
+                        Ok(value) => value,
+                        Err(e) => return e,
+                    };
+
+                    if let Err(e) = intercept(&parts, &encoding, &config, true).await {
+                        return e;
+                    }
+
+                    $(
+                        let $ty = match $ty::rpc_from_request_parts(&mut parts, &state).await {
+                            Ok(value) => value,
+                            Err(e) => {
+                                let e = config.outgoing_error(e.rpc_into_error());
+                                return encode_error_response(&e, &encoding, true);
+                            }
+                        };
+                    )*
+
+                    let req = Request::from_parts(parts, body);
+
+                    let requests = match decode_request_stream::<TMReq>(req, &encoding, &config) {
+                        Ok(value) => value,
+                        Err(e) => return e,
+                    };
+
+                    let res = match within(deadline, self($($ty,)* requests)).await {
+                        Some(res) => {
+                            cancel_on_drop.disarm();
+                            res.rpc_into_parts()
+                        }
+                        None => Err(deadline_exceeded()),
+                    };
+                    let RpcResponseParts { payload, headers, trailers } = match res {
+                        Ok(value) => value,
+                        Err(e) => {
+                            let e = config.outgoing_error(e);
+                            return encode_error_response(&e, &encoding, true);
+                        }
+                    };
+
+                    let message = match encode_stream_message(&encoding, payload, &config, response_compression.as_deref()) {
+                        Ok(value) => value,
+                        Err(e) => return encode_error_response(&config.outgoing_error(e), &encoding, true),
+                    };
+
+                    let mut response_headers = HeaderMap::new();
+                    if let Err(e) = headers.write_headers(&mut response_headers, "") {
+                        return encode_error_response(&config.outgoing_error(e), &encoding, true);
+                    }
+                    if let Some(compression) = &response_compression {
+                        response_headers.insert("connect-content-encoding", HeaderValue::from_static(compression.name()));
+                    }
+                    response_headers.append(header::VARY, vary(&config, true));
+
+                    // The one response message, then the EndStreamResponse with the trailing metadata.
+                    let body = [message, encode_end_stream(None, &trailers)].concat();
+
+                    (
+                        StatusCode::OK,
+                        [(header::CONTENT_TYPE, encoding.content_type(true))],
+                        response_headers,
+                        Body::from(body),
+                    )
+                        .into_response()
+                }))
+            }
+        }
+    };
+}
+
+impl_handler!([]);
+impl_handler!([T1]);
+impl_handler!([T1, T2]);
+impl_handler!([T1, T2, T3]);
+impl_handler!([T1, T2, T3, T4]);
+impl_handler!([T1, T2, T3, T4, T5]);
+impl_handler!([T1, T2, T3, T4, T5, T6]);
+impl_handler!([T1, T2, T3, T4, T5, T6, T7]);
+impl_handler!([T1, T2, T3, T4, T5, T6, T7, T8]);
+impl_handler!([T1, T2, T3, T4, T5, T6, T7, T8, T9]);
+impl_handler!([T1, T2, T3, T4, T5, T6, T7, T8, T9, T10]);
+impl_handler!([T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11]);
+impl_handler!([T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12]);
+impl_handler!([T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13]);
+impl_handler!([T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14]);
+impl_handler!([T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15]);
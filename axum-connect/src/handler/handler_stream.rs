@@ -1,145 +1,240 @@
-use std::{convert::Infallible, pin::Pin};
+use std::pin::{pin, Pin};
 
 use async_stream::stream;
 use axum::{
-    body::{HttpBody, StreamBody},
-    http::{header, Request, StatusCode},
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
-    BoxError,
 };
-use futures::{Future, Stream, StreamExt};
+use futures::{
+    future::{select, Either},
+    stream::{self, BoxStream},
+    Future, Stream, StreamExt,
+};
+use http_body::Frame;
 use prost::Message;
 use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::mpsc;
 
 use crate::{
+    config::{deadline_exceeded, within, RpcServiceConfig},
     error::RpcIntoError,
+    instrument::CallSpan,
+    metadata::RpcMetadata,
+    middleware::shutdown::{shutting_down, RpcShutdown},
     parts::RpcFromRequestParts,
-    prelude::{RpcError, RpcErrorCode},
+    request::RpcFromRequestMessage,
     response::RpcIntoResponse,
+    stream_hooks::StreamLifecycle,
 };
 
-use super::codec::{
-    decode_check_headers, decode_request_payload, encode_error, encode_error_response, ReqResInto,
+use super::{
+    body::frame_body,
+    codec::{
+        decode_check_headers, decode_request_payload, encode_end_stream, encode_error_response,
+        encode_stream_message, intercept, vary, ErrorFraming, ReqResInto,
+    },
+    panic::{catch_panic, catch_stream_panic},
 };
 
-pub trait RpcHandlerStream<TMReq, TMRes, TUid, TState, TBody>:
-    Clone + Send + Sized + 'static
+/// A handler of a server-streaming RPC: an async fn returning any `impl Stream` of responses, like
+/// a mapped `tokio_stream` or a database row stream, of messages or `Result<_, RpcError>` (or
+/// anything else that's `RpcIntoResponse`). There's no channel in between: the stream is polled
+/// for the next message only once the previous one has been written out, so a slow client slows
+/// down the stream instead of filling up a buffer. The first error ends the stream.
+pub trait RpcHandlerStream<TMReq, TMRes, TUid, TState>:
+    Clone + Send + Sync + Sized + 'static
 {
     type Future: Future<Output = Response> + Send + 'static;
 
-    fn call(self, req: Request<TBody>, state: TState) -> Self::Future;
+    fn call(self, req: Request, state: TState) -> Self::Future;
+}
+
+/// The handler's stream, read up to `buffer` messages ahead of the client by a task of its own
+/// when a `stream_buffer` is configured, see `RpcServiceConfig::stream_buffer`. The task stops as
+/// soon as the response is dropped.
+fn read_ahead<S>(stream: S, buffer: Option<usize>) -> BoxStream<'static, S::Item>
+where
+    S: Stream + Send + 'static,
+    S::Item: Send + 'static,
+{
+    let Some(buffer) = buffer else {
+        return stream.boxed();
+    };
+
+    let (sender, receiver) = mpsc::channel(buffer.max(1));
+    tokio::spawn(async move {
+        let mut stream = pin!(stream);
+        loop {
+            let item = match select(pin!(sender.closed()), stream.next()).await {
+                Either::Left(_) | Either::Right((None, _)) => break,
+                Either::Right((Some(item), _)) => item,
+            };
+            if sender.send(item).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    stream::unfold(receiver, |mut receiver| async move {
+        let item = receiver.recv().await?;
+        Some((item, receiver))
+    })
+    .boxed()
 }
 
-// TODO: Get "connect-timeout-ms" (number as string) and apply timeout.
-// TODO: Parse request metadata from:
-//      - [0-9a-z]*!"-bin" ASCII value
-//      - [0-9a-z]*-bin" (base64 encoded binary)
-// TODO: Allow response to send back both leading and trailing metadata.
 // This is here because writing Rust macros sucks a**. So I uncomment this when I'm trying to modify
 // the below macro.
 // #[allow(unused_parens, non_snake_case, unused_mut)]
-// impl<TMReq, TMRes, TInto, TFnItem, TFnFut, TFn, TState, TBody, T1>
-//     RpcHandlerStream<TMReq, TMRes, (T1, TMReq), TState, TBody> for TFn
+// impl<TMReq, TMRes, TReq, TInto, TFnItem, TFnFut, TFn, TState, T1>
+//     RpcHandlerStream<TMReq, TMRes, (T1, TReq, TMReq), TState> for TFn
 // where
 //     TMReq: Message + DeserializeOwned + Default + Send + 'static,
 //     TMRes: Message + Serialize + Send + 'static,
+//     TReq: RpcFromRequestMessage<TMReq>,
 //     TInto: RpcIntoResponse<TMRes>,
 //     TFnItem: Stream<Item = TInto> + Send + Sized + 'static,
-//     TFnFut: Future<Output = TFnItem> + Send + Sync,
-//     TFn: FnOnce(T1, TMReq) -> TFnFut + Clone + Send + Sync + 'static,
-//     TBody: HttpBody + Send + Sync + 'static,
-//     TBody::Data: Send,
-//     TBody::Error: Into<BoxError>,
+//     TFnFut: Future<Output = TFnItem> + Send,
+//     TFn: FnOnce(T1, TReq) -> TFnFut + Clone + Send + Sync + 'static,
 //     TState: Send + Sync + 'static,
 //     T1: RpcFromRequestParts<TMRes, TState> + Send,
 // {
 //     type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
 
-//     fn call(self, req: Request<TBody>, state: TState) -> Self::Future {
-//         Box::pin(async move {
+//     fn call(self, mut req: Request, state: TState) -> Self::Future {
+//         let framing = ErrorFraming::for_headers(req.headers(), Some(true));
+//         let config = RpcServiceConfig::for_call(&mut req);
+//         Box::pin(catch_panic(framing, config, async move {
 //             let (mut parts, body) = req.into_parts();
 
-//             let ReqResInto { binary } = match decode_check_headers(&mut parts, true) {
-//                 Ok(binary) => binary,
+//             let ReqResInto { encoding, config, deadline, response_compression, cancel_on_drop, .. } = match decode_check_headers(&mut parts, true) {
+//                 Ok(value) => value,
 //                 Err(e) => return e,
 //             };
 
+//             if let Err(e) = intercept(&parts, &encoding, &config, true).await {
+//                 return e;
+//             }
+//
 //             let state = &state;
 
 //             let t1 = match T1::rpc_from_request_parts(&mut parts, state).await {
 //                 Ok(value) => value,
 //                 Err(e) => {
-//                     let e = e.rpc_into_error();
-//                     return encode_error_response(&e, binary, true);
+//                     let e = config.outgoing_error(e.rpc_into_error());
+//                     return encode_error_response(&e, &encoding, true);
 //                 }
 //             };
 
+//             let mut lifecycle = StreamLifecycle::new(&parts);
+//             let shutdown = parts.extensions.get::<RpcShutdown>().cloned();
 //             let req = Request::from_parts(parts, body);
 
-//             let proto_req: TMReq = match decode_request_payload(req, state, binary, true).await {
-//                 Ok(value) => value,
+//             let proto_req: TReq = match decode_request_payload::<TMReq, _, _>(req, state, &encoding, &config, true).await {
+//                 Ok((value, size)) => {
+//                     // Counted with its envelope, like the messages sent.
+//                     lifecycle.message_received(size + 5);
+//                     value
+//                 }
 //                 Err(e) => return e,
 //             };
 
-//             let mut res = Box::pin(self(t1, proto_req).await);
-
-//             let res = stream! {
-//                 while let Some(item) = res.next().await {
-//                     let rpc_item = item.rpc_into_response();
-//                     match rpc_item {
-//                         Ok(rpc_item) => {
-//                             if binary {
-//                                 let mut res = vec![0x2, 0, 0, 0, 0];
-//                                 if let Err(e) = rpc_item.encode(&mut res) {
-//                                     let e = RpcError::new(RpcErrorCode::Internal, e.to_string());
-//                                     yield Result::<Vec<u8>, Infallible>::Ok(encode_error(&e, true));
-//                                     break;
+//             let mut res = match within(deadline, self(t1, proto_req)).await {
+//                 Some(res) => read_ahead(catch_stream_panic(res.map(|item| item.rpc_into_parts()), &config), config.stream_buffer),
+//                 None => return encode_error_response(&config.outgoing_error(deadline_exceeded()), &encoding, true),
+//             };
+//             // A shutdown ends the stream once its drain period is over.
+//             if let Some(shutdown) = &shutdown {
+//                 res = res.take_until(shutdown.expired()).boxed();
+//             }
+
+//             // The first item is awaited before the response headers go out, for the leading metadata it
+//             // may carry.
+//             let first = within(deadline, res.next()).await;
+//             let mut headers = HeaderMap::new();
+//             if let Some(Some(Ok(item))) = &first {
+//                 if let Err(e) = item.headers.write_headers(&mut headers, "") {
+//                     return encode_error_response(&config.outgoing_error(e), &encoding, true);
+//                 }
+//             }
+//             // Messages are compressed one by one, only those over the threshold.
+//             if let Some(compression) = &response_compression {
+//                 headers.insert("connect-content-encoding", HeaderValue::from_static(compression.name()));
+//             }
+//             headers.append(header::VARY, vary(&config, true));
+//             let mut first = Some(first);
+//             let content_type = encoding.content_type(true);
+//             lifecycle.start();
+
+//             let span = CallSpan::of(&config);
+//             let frames = stream! {
+//                 // The trailing metadata of every item, sent in the EndStreamResponse.
+//                 let mut trailers = RpcMetadata::new();
+//
+//                 loop {
+//                     let item = match first.take() {
+//                         Some(item) => item,
+//                         None => within(deadline, res.next()).await,
+//                     };
+//                     let item = match item {
+//                         Some(Some(item)) => item,
+//                         Some(None) => break,
+//                         None => {
+//                             let e = config.outgoing_error(deadline_exceeded());
+//                             lifecycle.end(Some(e.code.clone()));
+//                             yield Frame::data(encode_end_stream(Some(&e), &trailers));
+//                             return;
+//                         }
+//                     };
+//
+//                     match item {
+//                         Ok(item) => {
+//                             trailers.extend(item.trailers);
+//                             let message = encode_stream_message(&encoding, item.payload, &config, response_compression.as_deref());
+//                             match message {
+//                                 Ok(message) => {
+//                                     lifecycle.message_sent(message.len());
+//                                     yield Frame::data(message);
 //                                 }
-//                                 let size = ((res.len() - 5) as u32).to_be_bytes();
-//                                 res[1..5].copy_from_slice(&size);
-//                                 yield Ok(res);
-//                             } else {
-//                                 let mut res = vec![0x2, 0, 0, 0, 0];
-//                                 if let Err(e) = serde_json::to_writer(&mut res, &rpc_item) {
-//                                     let e = RpcError::new(RpcErrorCode::Internal, e.to_string());
-//                                     yield Ok(encode_error(&e, true));
-//                                     break;
+//                                 Err(e) => {
+//                                     let e = config.outgoing_error(e);
+//                                     lifecycle.end(Some(e.code.clone()));
+//                                     yield Frame::data(encode_end_stream(Some(&e), &trailers));
+//                                     return;
 //                                 }
-//                                 let size = ((res.len() - 5) as u32).to_be_bytes();
-//                                 res[1..5].copy_from_slice(&size);
-//                                 yield Ok(res);
 //                             }
 //                         },
 //                         Err(e) => {
-//                             yield Ok(encode_error(&e, binary));
-//                             break;
+//                             cancel_on_drop.disarm();
+//                             let e = config.outgoing_error(e);
+//                             lifecycle.end(Some(e.code.clone()));
+//                             yield Frame::data(encode_end_stream(Some(&e), &trailers));
+//                             return;
 //                         }
 //                     }
 //                 }
+//                 // Ended by a shutdown, rather than by the handler.
+//                 if shutdown.as_ref().is_some_and(|shutdown| shutdown.is_expired()) {
+//                     let e = config.outgoing_error(shutting_down());
+//                     lifecycle.end(Some(e.code.clone()));
+//                     yield Frame::data(encode_end_stream(Some(&e), &trailers));
+//                     return;
+//                 }
+//                 cancel_on_drop.disarm();
 
 //                 // EndStreamResponse, see: https://connect.build/docs/protocol/#error-end-stream
-//                 // TODO: Support returning trailers (they would need to bundle in the error type).
-//                 if binary {
-//                     yield Result::<Vec<u8>, Infallible>::Ok(vec![0x2, 0, 0, 0, 0]);
-//                 } else {
-//                     yield Result::<Vec<u8>, Infallible>::Ok(vec![0x2, 0, 0, 0, 2, b'{', b'}']);
-//                 }
+//                 yield Frame::data(encode_end_stream(None, &trailers));
+//                 lifecycle.end(None);
 //             };
 
 //             (
 //                 StatusCode::OK,
-//                 [(
-//                     header::CONTENT_TYPE,
-//                     if binary {
-//                         "application/connect+proto"
-//                     } else {
-//                         "application/connect+json"
-//                     },
-//                 )],
-//                 StreamBody::new(res),
+//                 [(header::CONTENT_TYPE, content_type)],
+//                 headers,
+//                 frame_body(span.stream(frames)),
 //             )
 //                 .into_response()
-//         })
+//         }))
 //     }
 // }
 
@@ -148,111 +243,158 @@ macro_rules! impl_handler {
         [$($ty:ident),*]
     ) => {
         #[allow(unused_parens, non_snake_case, unused_mut)]
-        impl<TMReq, TMRes, TInto, TFnItem, TFnFut, TFn, TState, TBody, $($ty,)*>
-            RpcHandlerStream<TMReq, TMRes, ($($ty,)* TMReq), TState, TBody> for TFn
+        impl<TMReq, TMRes, TReq, TInto, TFnItem, TFnFut, TFn, TState, $($ty,)*>
+            RpcHandlerStream<TMReq, TMRes, ($($ty,)* TReq, TMReq), TState> for TFn
         where
             TMReq: Message + DeserializeOwned + Default + Send + 'static,
             TMRes: Message + Serialize + Send + 'static,
+            TReq: RpcFromRequestMessage<TMReq>,
             TInto: RpcIntoResponse<TMRes>,
             TFnItem: Stream<Item = TInto> + Send + Sized + 'static,
-            TFnFut: Future<Output = TFnItem> + Send + Sync,
-            TFn: FnOnce($($ty,)* TMReq) -> TFnFut + Clone + Send + Sync + 'static,
-            TBody: HttpBody + Send + Sync + 'static,
-            TBody::Data: Send,
-            TBody::Error: Into<BoxError>,
+            TFnFut: Future<Output = TFnItem> + Send,
+            TFn: FnOnce($($ty,)* TReq) -> TFnFut + Clone + Send + Sync + 'static,
             TState: Send + Sync + 'static,
             $( $ty: RpcFromRequestParts<TMRes, TState> + Send, )*
         {
 
             type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
 
-            fn call(self, req: Request<TBody>, state: TState) -> Self::Future {
-                Box::pin(async move {
+            fn call(self, mut req: Request, state: TState) -> Self::Future {
+                let framing = ErrorFraming::for_headers(req.headers(), Some(true));
+                let config = RpcServiceConfig::for_call(&mut req);
+                Box::pin(catch_panic(framing, config, async move {
                     let (mut parts, body) = req.into_parts();
 
-                    let ReqResInto { binary } = match decode_check_headers(&mut parts, true) {
-                        Ok(binary) => binary,
+                    let ReqResInto { encoding, config, deadline, response_compression, cancel_on_drop, .. } = match decode_check_headers(&mut parts, true) {
+                        Ok(value) => value,
                         Err(e) => return e,
                     };
 
+                    if let Err(e) = intercept(&parts, &encoding, &config, true).await {
+                        return e;
+                    }
+
                     let state = &state;
 
                     $(
                     let $ty = match $ty::rpc_from_request_parts(&mut parts, state).await {
                         Ok(value) => value,
                         Err(e) => {
-                            let e = e.rpc_into_error();
-                            return encode_error_response(&e, binary, true);
+                            let e = config.outgoing_error(e.rpc_into_error());
+                            return encode_error_response(&e, &encoding, true);
                         }
                     };
                     )*
 
+                    let mut lifecycle = StreamLifecycle::new(&parts);
+                    let shutdown = parts.extensions.get::<RpcShutdown>().cloned();
                     let req = Request::from_parts(parts, body);
 
-                    let proto_req: TMReq = match decode_request_payload(req, state, binary, true).await {
-                        Ok(value) => value,
+                    let proto_req: TReq = match decode_request_payload::<TMReq, _, _>(req, state, &encoding, &config, true).await {
+                        Ok((value, size)) => {
+                            // Counted with its envelope, like the messages sent.
+                            lifecycle.message_received(size + 5);
+                            value
+                        }
                         Err(e) => return e,
                     };
 
-                    let mut res = Box::pin(self($($ty,)* proto_req).await);
-
-                    let res = stream! {
-                        while let Some(item) = res.next().await {
-                            let rpc_item = item.rpc_into_response();
-                            match rpc_item {
-                                Ok(rpc_item) => {
-                                    if binary {
-                                        let mut res = vec![0x2, 0, 0, 0, 0];
-                                        if let Err(e) = rpc_item.encode(&mut res) {
-                                            let e = RpcError::new(RpcErrorCode::Internal, e.to_string());
-                                            yield Result::<Vec<u8>, Infallible>::Ok(encode_error(&e, true));
-                                            break;
+                    let mut res = match within(deadline, self($($ty,)* proto_req)).await {
+                        Some(res) => read_ahead(catch_stream_panic(res.map(|item| item.rpc_into_parts()), &config), config.stream_buffer),
+                        None => return encode_error_response(&config.outgoing_error(deadline_exceeded()), &encoding, true),
+                    };
+                    // A shutdown ends the stream once its drain period is over.
+                    if let Some(shutdown) = &shutdown {
+                        res = res.take_until(shutdown.expired()).boxed();
+                    }
+
+                    // The first item is awaited before the response headers go out, for the leading metadata it
+                    // may carry.
+                    let first = within(deadline, res.next()).await;
+                    let mut headers = HeaderMap::new();
+                    if let Some(Some(Ok(item))) = &first {
+                        if let Err(e) = item.headers.write_headers(&mut headers, "") {
+                            return encode_error_response(&config.outgoing_error(e), &encoding, true);
+                        }
+                    }
+                    // Messages are compressed one by one, only those over the threshold.
+                    if let Some(compression) = &response_compression {
+                        headers.insert("connect-content-encoding", HeaderValue::from_static(compression.name()));
+                    }
+                    headers.append(header::VARY, vary(&config, true));
+                    let mut first = Some(first);
+                    let content_type = encoding.content_type(true);
+                    lifecycle.start();
+
+                    let span = CallSpan::of(&config);
+                    let frames = stream! {
+                        // The trailing metadata of every item, sent in the EndStreamResponse.
+                        let mut trailers = RpcMetadata::new();
+
+                        loop {
+                            let item = match first.take() {
+                                Some(item) => item,
+                                None => within(deadline, res.next()).await,
+                            };
+                            let item = match item {
+                                Some(Some(item)) => item,
+                                Some(None) => break,
+                                None => {
+                                    let e = config.outgoing_error(deadline_exceeded());
+                                    lifecycle.end(Some(e.code.clone()));
+                                    yield Frame::data(encode_end_stream(Some(&e), &trailers));
+                                    return;
+                                }
+                            };
+
+                            match item {
+                                Ok(item) => {
+                                    trailers.extend(item.trailers);
+                                    let message = encode_stream_message(&encoding, item.payload, &config, response_compression.as_deref());
+                                    match message {
+                                        Ok(message) => {
+                                            lifecycle.message_sent(message.len());
+                                            yield Frame::data(message);
                                         }
-                                        let size = ((res.len() - 5) as u32).to_be_bytes();
-                                        res[1..5].copy_from_slice(&size);
-                                        yield Ok(res);
-                                    } else {
-                                        let mut res = vec![0x2, 0, 0, 0, 0];
-                                        if let Err(e) = serde_json::to_writer(&mut res, &rpc_item) {
-                                            let e = RpcError::new(RpcErrorCode::Internal, e.to_string());
-                                            yield Ok(encode_error(&e, true));
-                                            break;
+                                        Err(e) => {
+                                            let e = config.outgoing_error(e);
+                                            lifecycle.end(Some(e.code.clone()));
+                                            yield Frame::data(encode_end_stream(Some(&e), &trailers));
+                                            return;
                                         }
-                                        let size = ((res.len() - 5) as u32).to_be_bytes();
-                                        res[1..5].copy_from_slice(&size);
-                                        yield Ok(res);
                                     }
                                 },
                                 Err(e) => {
-                                    yield Ok(encode_error(&e, binary));
-                                    break;
+                                    cancel_on_drop.disarm();
+                                    let e = config.outgoing_error(e);
+                                    lifecycle.end(Some(e.code.clone()));
+                                    yield Frame::data(encode_end_stream(Some(&e), &trailers));
+                                    return;
                                 }
                             }
                         }
+                        // Ended by a shutdown, rather than by the handler.
+                        if shutdown.as_ref().is_some_and(|shutdown| shutdown.is_expired()) {
+                            let e = config.outgoing_error(shutting_down());
+                            lifecycle.end(Some(e.code.clone()));
+                            yield Frame::data(encode_end_stream(Some(&e), &trailers));
+                            return;
+                        }
+                        cancel_on_drop.disarm();
 
                         // EndStreamResponse, see: https://connect.build/docs/protocol/#error-end-stream
-                        // TODO: Support returning trailers (they would need to bundle in the error type).
-                        if binary {
-                            yield Result::<Vec<u8>, Infallible>::Ok(vec![0x2, 0, 0, 0, 0]);
-                        } else {
-                            yield Result::<Vec<u8>, Infallible>::Ok(vec![0x2, 0, 0, 0, 2, b'{', b'}']);
-                        }
+                        yield Frame::data(encode_end_stream(None, &trailers));
+                        lifecycle.end(None);
                     };
 
                     (
                         StatusCode::OK,
-                        [(
-                            header::CONTENT_TYPE,
-                            if binary {
-                                "application/connect+proto"
-                            } else {
-                                "application/connect+json"
-                            },
-                        )],
-                        StreamBody::new(res),
+                        [(header::CONTENT_TYPE, content_type)],
+                        headers,
+                        frame_body(span.stream(frames)),
                     )
                         .into_response()
-                })
+                }))
             }
         }
     };
@@ -1,124 +1,174 @@
 use std::{convert::Infallible, pin::Pin};
 
 use axum::{
-    body::HttpBody,
-    http::{header, Request, StatusCode},
+    extract::Request,
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
-    BoxError,
 };
 use futures::Future;
 use prost::Message;
 use serde::{de::DeserializeOwned, Serialize};
 
 use crate::{
+    compression::compress_response,
+    config::{deadline_exceeded, within, RpcServiceConfig},
     error::RpcIntoError,
+    field_mask::FieldsParam,
     parts::RpcFromRequestParts,
     prelude::{RpcError, RpcErrorCode},
-    response::RpcIntoResponse,
+    request::RpcFromRequestMessage,
+    response::{RpcIntoResponse, RpcResponseParts},
+    text_format::{to_text_format, TextFormatDebug},
 };
 
-use super::codec::{
-    decode_check_headers, decode_request_payload, encode_error_response, ReqResInto,
+use super::{
+    codec::{
+        decode_check_headers, decode_request_payload, encode_error_response, intercept, vary,
+        ErrorFraming, ReqResInto,
+    },
+    panic::catch_panic,
 };
 
-pub trait RpcHandlerUnary<TMReq, TMRes, TUid, TState, TBody>:
-    Clone + Send + Sized + 'static
+pub trait RpcHandlerUnary<TMReq, TMRes, TUid, TState>:
+    Clone + Send + Sync + Sized + 'static
 {
     type Future: Future<Output = Response> + Send + 'static;
 
-    fn call(self, req: Request<TBody>, state: TState) -> Self::Future;
+    fn call(self, req: Request, state: TState) -> Self::Future;
 }
 
 // This is for Unary.
-// TODO: Check that the header "connect-protocol-version" == "1"
-// TODO: Get "connect-timeout-ms" (number as string) and apply timeout.
-// TODO: Parse request metadata from:
-//      - [0-9a-z]*!"-bin" ASCII value
-//      - [0-9a-z]*-bin" (base64 encoded binary)
-// TODO: Allow response to send back both leading and trailing metadata.
 
 // This is here because writing Rust macros sucks a**. So I uncomment this when I'm trying to modify
 // the below macro.
 // #[allow(unused_parens, non_snake_case, unused_mut)]
-// impl<TMReq, TMRes, TInto, TFnFut, TFn, TState, TBody, T1>
-//     RpcHandlerUnary<TMReq, TMRes, (T1, TMReq), TState, TBody> for TFn
+// impl<TMReq, TMRes, TReq, TInto, TFnFut, TFn, TState, T1>
+//     RpcHandlerUnary<TMReq, TMRes, (T1, TReq, TMReq), TState> for TFn
 // where
-//     TMReq: Message + DeserializeOwned + Default + Send + 'static,
+//     TMReq: Message + Serialize + DeserializeOwned + Default + Send + 'static,
 //     TMRes: Message + Serialize + Send + 'static,
+//     TReq: RpcFromRequestMessage<TMReq>,
 //     TInto: RpcIntoResponse<TMRes>,
 //     TFnFut: Future<Output = TInto> + Send,
-//     TFn: FnOnce(T1, TMReq) -> TFnFut + Clone + Send + 'static,
-//     TBody: HttpBody + Send + Sync + 'static,
-//     TBody::Data: Send,
-//     TBody::Error: Into<BoxError>,
+//     TFn: FnOnce(T1, TReq) -> TFnFut + Clone + Send + Sync + 'static,
 //     TState: Send + Sync + 'static,
 //     T1: RpcFromRequestParts<TMRes, TState> + Send,
 // {
 //     type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
 
-//     fn call(self, req: Request<TBody>, state: TState) -> Self::Future {
-//         Box::pin(async move {
+//     fn call(self, mut req: Request, state: TState) -> Self::Future {
+//         let config = RpcServiceConfig::for_call(&mut req);
+//         Box::pin(catch_panic(ErrorFraming::Unary, config, async move {
 //             let (mut parts, body) = req.into_parts();
 
-//             let ReqResInto { binary } = match decode_check_headers(&mut parts, false) {
-//                 Ok(binary) => binary,
+//             let ReqResInto { encoding, accept, config, deadline, response_compression, cancel_on_drop } = match decode_check_headers(&mut parts, false) {
+//                 Ok(value) => value,
 //                 Err(e) => return e,
 //             };
 
+//             if let Err(e) = intercept(&parts, &encoding, &config, false).await {
+//                 return e;
+//             }
+
+//             let debug_text = TextFormatDebug::requested(&parts);
+//             let fields = FieldsParam::requested(&parts, &accept);
+
 //             let state = &state;
 
 //             let t1 = match T1::rpc_from_request_parts(&mut parts, state).await {
 //                 Ok(value) => value,
 //                 Err(e) => {
-//                     let e = e.rpc_into_error();
-//                     return encode_error_response(&e, binary, false);
+//                     let e = config.outgoing_error(e.rpc_into_error());
+//                     return encode_error_response(&e, &encoding, false);
 //                 }
 //             };
 
 //             let req = Request::from_parts(parts, body);
 
-//             let proto_req: TMReq = match decode_request_payload(req, state, binary, false).await {
-//                 Ok(value) => value,
+//             let proto_req: TReq = match decode_request_payload::<TMReq, _, _>(req, state, &encoding, &config, false).await {
+//                 Ok((value, _)) => value,
 //                 Err(e) => return e,
 //             };
 
-//             let res = self(t1, proto_req).await.rpc_into_response();
-//             let res = match res {
-//                 Ok(res) => {
-//                     if binary {
-//                         res.encode_to_vec()
-//                     } else {
-//                         match serde_json::to_vec(&res) {
-//                             Ok(res) => res,
-//                             Err(e) => {
-//                                 let e = RpcError::new(
-//                                     RpcErrorCode::Internal,
-//                                     format!("Failed to serialize response: {}", e),
-//                                 );
-//                                 return encode_error_response(&e, binary, false);
-//                             }
-//                         }
+//             let debug_request = debug_text.then(|| to_text_format(proto_req.message()));
+
+//             let res = match within(deadline, self(t1, proto_req)).await {
+//                 Some(res) => {
+//                     cancel_on_drop.disarm();
+//                     res.rpc_into_parts()
+//                 }
+//                 None => Err(deadline_exceeded()),
+//             };
+
+//             let res = match (debug_request, res) {
+//                 (Some(debug_request), Ok(res)) => match res.payload.into_message() {
+//                     Ok(res) => {
+//                         return (
+//                             StatusCode::OK,
+//                             [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+//                             format!("# request\n{}\n# response\n{}", debug_request, to_text_format(&res)),
+//                         )
+//                             .into_response();
+//                     }
+//                     Err(e) => Err(e),
+//                 },
+//                 (_, res) => res,
+//             };
+
+//             let (res, headers, trailers) = match res {
+//                 Ok(RpcResponseParts { payload, headers, trailers }) => {
+//                     let mut buf = vec![];
+//                     if let Err(e) = accept.encode_payload(payload, &mut buf) {
+//                         let e = RpcError::new(
+//                             RpcErrorCode::Internal,
+//                             format!("Failed to serialize response: {}", e),
+//                         );
+//                         return encode_error_response(&config.outgoing_error(e), &encoding, false);
 //                     }
+//                     let buf = match &fields {
+//                         Some(fields) => fields.apply(buf),
+//                         None => buf,
+//                     };
+//                     if let Err(e) = config.check_response_size(buf.len()) {
+//                         return encode_error_response(&config.outgoing_error(e), &encoding, false);
+//                     }
+//                     config.message_sent(buf.len());
+//                     (buf, headers, trailers)
 //                 }
 //                 Err(e) => {
-//                     return encode_error_response(&e, binary, false);
+//                     let e = config.outgoing_error(e);
+//                     return encode_error_response(&e, &encoding, false);
 //                 }
 //             };
 
-//             (
+//             let (res, content_encoding) = compress_response(
+//                 response_compression.as_deref(),
+//                 config.compress_min_size.unwrap_or_default(),
+//                 res,
+//             );
+
+//             let mut res = (
 //                 StatusCode::OK,
-//                 [(
-//                     header::CONTENT_TYPE,
-//                     if binary {
-//                         "application/proto"
-//                     } else {
-//                         "application/json"
-//                     },
-//                 )],
+//                 [(header::CONTENT_TYPE, accept.content_type(false))],
 //                 Result::<Vec<u8>, Infallible>::Ok(res),
 //             )
-//                 .into_response()
-//         })
+//                 .into_response();
+//             if let Some(content_encoding) = content_encoding {
+//                 res.headers_mut()
+//                     .insert(header::CONTENT_ENCODING, HeaderValue::from_static(content_encoding));
+//             }
+//             res.headers_mut().append(header::VARY, vary(&config, false));
+
+//             // Unary trailing metadata goes in `trailer-` prefixed headers, see:
+//             // https://connect.build/docs/protocol/#unary-response
+//             let metadata = headers
+//                 .write_headers(res.headers_mut(), "")
+//                 .and_then(|()| trailers.write_headers(res.headers_mut(), "trailer-"));
+//             match metadata {
+//                 Ok(()) => res,
+//                 Err(e) => encode_error_response(&config.outgoing_error(e), &encoding, false),
+//             }
+//         }))
 //     }
 // }
 
@@ -127,87 +177,135 @@ macro_rules! impl_handler {
         [$($ty:ident),*]
     ) => {
         #[allow(unused_parens, non_snake_case, unused_mut)]
-        impl<TMReq, TMRes, TInto, TFnFut, TFn, TState, TBody, $($ty,)*>
-            RpcHandlerUnary<TMReq, TMRes, ($($ty,)* TMReq), TState, TBody> for TFn
+        impl<TMReq, TMRes, TReq, TInto, TFnFut, TFn, TState, $($ty,)*>
+            RpcHandlerUnary<TMReq, TMRes, ($($ty,)* TReq, TMReq), TState> for TFn
         where
-            TMReq: Message + DeserializeOwned + Default + Send + 'static,
+            TMReq: Message + Serialize + DeserializeOwned + Default + Send + 'static,
             TMRes: Message + Serialize + Send + 'static,
+            TReq: RpcFromRequestMessage<TMReq>,
             TInto: RpcIntoResponse<TMRes>,
             TFnFut: Future<Output = TInto> + Send,
-            TFn: FnOnce($($ty,)* TMReq) -> TFnFut + Clone + Send + 'static,
-            TBody: HttpBody + Send + Sync + 'static,
-            TBody::Data: Send,
-            TBody::Error: Into<BoxError>,
+            TFn: FnOnce($($ty,)* TReq) -> TFnFut + Clone + Send + Sync + 'static,
             TState: Send + Sync + 'static,
             $( $ty: RpcFromRequestParts<TMRes, TState> + Send, )*
         {
             type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
 
-            fn call(self, req: Request<TBody>, state: TState) -> Self::Future {
-                Box::pin(async move {
+            fn call(self, mut req: Request, state: TState) -> Self::Future {
+                let config = RpcServiceConfig::for_call(&mut req);
+                Box::pin(catch_panic(ErrorFraming::Unary, config, async move {
                     let (mut parts, body) = req.into_parts();
 
-                    let ReqResInto { binary } = match decode_check_headers(&mut parts, false) {
-                        Ok(binary) => binary,
+                    let ReqResInto { encoding, accept, config, deadline, response_compression, cancel_on_drop } = match decode_check_headers(&mut parts, false) {
+                        Ok(value) => value,
                         Err(e) => return e,
                     };
 
+                    if let Err(e) = intercept(&parts, &encoding, &config, false).await {
+                        return e;
+                    }
+
+                    let debug_text = TextFormatDebug::requested(&parts);
+                    let fields = FieldsParam::requested(&parts, &accept);
+
                     let state = &state;
 
                     $(
                         let $ty = match $ty::rpc_from_request_parts(&mut parts, state).await {
                             Ok(value) => value,
                             Err(e) => {
-                                let e = e.rpc_into_error();
-                                return encode_error_response(&e, binary, false);
+                                let e = config.outgoing_error(e.rpc_into_error());
+                                return encode_error_response(&e, &encoding, false);
                             }
                         };
                     )*
 
                     let req = Request::from_parts(parts, body);
 
-                    let proto_req: TMReq = match decode_request_payload(req, state, binary, false).await {
-                        Ok(value) => value,
+                    let proto_req: TReq = match decode_request_payload::<TMReq, _, _>(req, state, &encoding, &config, false).await {
+                        Ok((value, _)) => value,
                         Err(e) => return e,
                     };
 
-                    let res = self($($ty,)* proto_req).await.rpc_into_response();
-                    let res = match res {
-                        Ok(res) => {
-                            if binary {
-                                res.encode_to_vec()
-                            } else {
-                                match serde_json::to_vec(&res) {
-                                    Ok(res) => res,
-                                    Err(e) => {
-                                        let e = RpcError::new(
-                                            RpcErrorCode::Internal,
-                                            format!("Failed to serialize response: {}", e),
-                                        );
-                                        return encode_error_response(&e, binary, false);
-                                    }
-                                }
+                    let debug_request = debug_text.then(|| to_text_format(proto_req.message()));
+
+                    let res = match within(deadline, self($($ty,)* proto_req)).await {
+                        Some(res) => {
+                            cancel_on_drop.disarm();
+                            res.rpc_into_parts()
+                        }
+                        None => Err(deadline_exceeded()),
+                    };
+
+                    let res = match (debug_request, res) {
+                        (Some(debug_request), Ok(res)) => match res.payload.into_message() {
+                            Ok(res) => {
+                                return (
+                                    StatusCode::OK,
+                                    [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+                                    format!("# request\n{}\n# response\n{}", debug_request, to_text_format(&res)),
+                                )
+                                    .into_response();
+                            }
+                            Err(e) => Err(e),
+                        },
+                        (_, res) => res,
+                    };
+
+                    let (res, headers, trailers) = match res {
+                        Ok(RpcResponseParts { payload, headers, trailers }) => {
+                            let mut buf = vec![];
+                            if let Err(e) = accept.encode_payload(payload, &mut buf) {
+                                let e = RpcError::new(
+                                    RpcErrorCode::Internal,
+                                    format!("Failed to serialize response: {}", e),
+                                );
+                                return encode_error_response(&config.outgoing_error(e), &encoding, false);
                             }
+                            let buf = match &fields {
+                                Some(fields) => fields.apply(buf),
+                                None => buf,
+                            };
+                            if let Err(e) = config.check_response_size(buf.len()) {
+                                return encode_error_response(&config.outgoing_error(e), &encoding, false);
+                            }
+                            config.message_sent(buf.len());
+                            (buf, headers, trailers)
                         }
                         Err(e) => {
-                            return encode_error_response(&e, binary, false);
+                            let e = config.outgoing_error(e);
+                            return encode_error_response(&e, &encoding, false);
                         }
                     };
 
-                    (
+                    let (res, content_encoding) = compress_response(
+                        response_compression.as_deref(),
+                        config.compress_min_size.unwrap_or_default(),
+                        res,
+                    );
+
+                    let mut res = (
                         StatusCode::OK,
-                        [(
-                            header::CONTENT_TYPE,
-                            if binary {
-                                "application/proto"
-                            } else {
-                                "application/json"
-                            },
-                        )],
+                        [(header::CONTENT_TYPE, accept.content_type(false))],
                         Result::<Vec<u8>, Infallible>::Ok(res),
                     )
-                        .into_response()
-                })
+                        .into_response();
+                    if let Some(content_encoding) = content_encoding {
+                        res.headers_mut()
+                            .insert(header::CONTENT_ENCODING, HeaderValue::from_static(content_encoding));
+                    }
+                    res.headers_mut().append(header::VARY, vary(&config, false));
+
+                    // Unary trailing metadata goes in `trailer-` prefixed headers, see:
+                    // https://connect.build/docs/protocol/#unary-response
+                    let metadata = headers
+                        .write_headers(res.headers_mut(), "")
+                        .and_then(|()| trailers.write_headers(res.headers_mut(), "trailer-"));
+                    match metadata {
+                        Ok(()) => res,
+                        Err(e) => encode_error_response(&config.outgoing_error(e), &encoding, false),
+                    }
+                }))
             }
         }
     };
@@ -1,7 +1,15 @@
+pub mod handler_client_stream;
 pub mod handler_stream;
 pub mod handler_unary;
+#[doc(hidden)]
+pub mod rpc_handler;
+pub mod service;
 
-mod codec;
+pub(crate) mod body;
+pub(crate) mod codec;
+pub(crate) mod panic;
 
+pub use handler_client_stream::*;
 pub use handler_stream::*;
 pub use handler_unary::*;
+pub use service::*;
@@ -0,0 +1,76 @@
+use std::{any::Any, future::Future, panic::AssertUnwindSafe};
+
+use axum::response::Response;
+use futures::{FutureExt, Stream, StreamExt};
+
+use crate::{
+    config::RpcServiceConfig,
+    error::RpcErrorDetail,
+    error_details::DebugInfo,
+    instrument::CallSpan,
+    prelude::{RpcError, RpcErrorCode, RpcResult},
+};
+
+use super::codec::ErrorFraming;
+
+/// The error a panicking handler's call fails with. The panic itself is left to the panic hook
+/// (which prints it by default), as its message may leak internals: it's only sent, as a
+/// `DebugInfo` detail, by services with `RpcServiceConfig::error_debug_details` on.
+pub(crate) fn handler_panicked(payload: Box<dyn Any + Send>, debug: bool) -> RpcError {
+    let error = RpcError::new(RpcErrorCode::Internal, "The handler panicked".to_string());
+    if !debug {
+        return error;
+    }
+
+    let detail = match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .unwrap_or_default(),
+    };
+    let info = DebugInfo {
+        stack_entries: vec![],
+        detail,
+    };
+    error.with_detail(RpcErrorDetail::from(info.clone()).with_debug(&info))
+}
+
+/// Runs a handler's call, answering a panic in it (or in its extractors) with an `Internal` error
+/// framed like `framing`, instead of the connection being dropped. The `config` is the call's, see
+/// `RpcServiceConfig::for_call`, whose span the call runs in.
+pub(crate) async fn catch_panic<F>(
+    framing: ErrorFraming,
+    config: RpcServiceConfig,
+    call: F,
+) -> Response
+where
+    F: Future<Output = Response>,
+{
+    let call = CallSpan::of(&config).future(call);
+    let res = match AssertUnwindSafe(call).catch_unwind().await {
+        Ok(res) => res,
+        Err(payload) => framing.response(
+            &config.outgoing_error(handler_panicked(payload, config.error_debug_details)),
+        ),
+    };
+    if let Some(call) = &config.call {
+        call.responded();
+    }
+    res
+}
+
+/// The items of a server stream, ending with an `Internal` error if it panics, which goes out as
+/// the EndStreamResponse.
+pub(crate) fn catch_stream_panic<S, T>(
+    stream: S,
+    config: &RpcServiceConfig,
+) -> impl Stream<Item = RpcResult<T>>
+where
+    S: Stream<Item = RpcResult<T>>,
+{
+    let debug = config.error_debug_details;
+    AssertUnwindSafe(stream)
+        .catch_unwind()
+        .map(move |item| item.unwrap_or_else(|payload| Err(handler_panicked(payload, debug))))
+}
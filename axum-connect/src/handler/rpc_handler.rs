@@ -0,0 +1,51 @@
+//! What `#[rpc_handler]` expands to. The functions check a handler against its `RpcMethod` one
+//! argument at a time: each only has bounds, so a mismatch is reported against the argument (or
+//! return type) it was called for, rather than as a missing `RpcHandlerUnary` impl for the whole
+//! function.
+
+use std::marker::PhantomData;
+
+use crate::{
+    descriptor::RpcMethod,
+    parts::RpcFromRequestParts,
+    request::{RpcFromRequestMessage, RpcRequestStream},
+    response::RpcIntoResponse,
+};
+
+pub fn request<M, T>()
+where
+    M: RpcMethod,
+    T: RpcFromRequestMessage<M::Request>,
+{
+}
+
+/// Takes the handler's `RpcRequestStream<...>` argument as a `PhantomData`, so a stream of the wrong
+/// message is a type mismatch on it.
+pub fn request_stream<M>(_request: PhantomData<RpcRequestStream<M::Request>>)
+where
+    M: RpcMethod,
+{
+}
+
+pub fn extractor<M, T, S>()
+where
+    M: RpcMethod,
+    T: RpcFromRequestParts<M::Response, S> + Send,
+    S: Send + Sync,
+{
+}
+
+pub fn response<M, T>()
+where
+    M: RpcMethod,
+    T: RpcIntoResponse<M::Response>,
+{
+}
+
+pub const fn is_client_streaming<M: RpcMethod>() -> bool {
+    M::DESCRIPTOR.kind.is_client_streaming()
+}
+
+pub const fn is_server_streaming<M: RpcMethod>() -> bool {
+    M::DESCRIPTOR.kind.is_server_streaming()
+}
@@ -0,0 +1,732 @@
+use std::{
+    convert::Infallible,
+    task::{Context, Poll},
+};
+
+use axum::{
+    extract::Request,
+    http::{self, header, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+    BoxError, Extension,
+};
+use futures::future::BoxFuture;
+use tower::Service;
+
+use crate::{
+    config::RpcServiceConfig,
+    descriptor::RpcMethodInfo,
+    error::{RpcError, RpcErrorCode},
+};
+
+use super::{
+    body::any_body,
+    codec::{encode_error, get_request_into_post},
+    RpcHandlerClientStream, RpcHandlerStream, RpcHandlerUnary,
+};
+
+/// A single RPC method as a `tower::Service`, for serving it without an axum `Router`: from a bare
+/// hyper server, a lambda runtime, or a router of your own. Requests go through exactly the same
+/// codec and extractor logic as with `.rpc(...)`, but are not routed, so the caller decides which
+/// path leads here.
+///
+/// Request bodies may be of any `Buf` chunks, as from HTTP/3 stacks. The generated
+/// `<method>_service` functions build these, and the matching `<METHOD>_PATH` constants hold the
+/// path `.rpc(...)` would use.
+///
+/// ```ignore
+/// let service = HelloWorldService::say_hello_service(say_hello, state);
+/// let response = service.oneshot(request).await?;
+/// ```
+pub struct RpcService<H, S> {
+    handler: H,
+    state: S,
+    call: fn(H, Request, S) -> BoxFuture<'static, Response>,
+}
+
+impl<H, S> Clone for RpcService<H, S>
+where
+    H: Clone,
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            handler: self.handler.clone(),
+            state: self.state.clone(),
+            call: self.call,
+        }
+    }
+}
+
+impl<H, S> RpcService<H, S> {
+    pub fn unary<TMReq, TMRes, T>(handler: H, state: S) -> Self
+    where
+        H: RpcHandlerUnary<TMReq, TMRes, T, S>,
+    {
+        Self {
+            handler,
+            state,
+            call: |handler, req, state| Box::pin(handler.call(req, state)),
+        }
+    }
+
+    pub fn server_stream<TMReq, TMRes, T>(handler: H, state: S) -> Self
+    where
+        H: RpcHandlerStream<TMReq, TMRes, T, S>,
+    {
+        Self {
+            handler,
+            state,
+            call: |handler, req, state| Box::pin(handler.call(req, state)),
+        }
+    }
+
+    pub fn client_stream<TMReq, TMRes, T>(handler: H, state: S) -> Self
+    where
+        H: RpcHandlerClientStream<TMReq, TMRes, T, S>,
+    {
+        Self {
+            handler,
+            state,
+            call: |handler, req, state| Box::pin(handler.call(req, state)),
+        }
+    }
+}
+
+impl<H, S, B> Service<http::Request<B>> for RpcService<H, S>
+where
+    H: Clone,
+    S: Clone,
+    B: http_body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        // The same as the `post` method router `.rpc(...)` registers.
+        if req.method() != Method::POST {
+            let method = req.method().clone();
+            return Box::pin(async move { Ok(method_not_allowed(method, None).await) });
+        }
+
+        let res = (self.call)(self.handler.clone(), req.map(any_body), self.state.clone());
+        Box::pin(async move { Ok(res.await) })
+    }
+}
+
+/// The response to an RPC request with any method but POST (or GET, for methods that allow it): a
+/// `405 Method Not Allowed` with an `Allow` header, like axum's, and a Connect error body that
+/// clients can show. The generated routes use it as their method fallback; it's public for routes
+/// registered by hand:
+///
+/// ```ignore
+/// let app = Router::new().route("/acme.v1.Echo/Say", post(say).fallback(method_not_allowed));
+/// ```
+pub async fn method_not_allowed(
+    method: Method,
+    info: Option<Extension<RpcMethodInfo>>,
+) -> Response {
+    let allow = match info {
+        Some(Extension(info)) if info.0.allows_get() => "GET, POST",
+        _ => "POST",
+    };
+    let e = RpcError::new(
+        RpcErrorCode::Unimplemented,
+        format!(
+            "HTTP method {} is not allowed, Connect calls are {}",
+            method, allow
+        ),
+    );
+    (
+        StatusCode::METHOD_NOT_ALLOWED,
+        [
+            (header::ALLOW, allow),
+            (header::CONTENT_TYPE, "application/json"),
+        ],
+        encode_error(&e, false),
+    )
+        .into_response()
+}
+
+/// Serves a Connect GET request (see: https://connectrpc.com/docs/protocol/#unary-get-request)
+/// with a unary handler, decoding the message from the query string. Only meant for methods
+/// without side effects (`idempotency_level = NO_SIDE_EFFECTS`), whose generated routes use it,
+/// so their responses can be cached by browsers and CDNs.
+///
+/// Successful responses get the configured `RpcServiceConfig::cache_control` unless the handler
+/// set its own, and those with an `ETag` (see `RpcResponse::etag`) that the request's
+/// `If-None-Match` names are answered with a bodyless `304 Not Modified`.
+pub async fn call_unary_get<TMReq, TMRes, T, H, S>(handler: H, req: Request, state: S) -> Response
+where
+    H: RpcHandlerUnary<TMReq, TMRes, T, S>,
+{
+    let if_none_match = req.headers().get(header::IF_NONE_MATCH).cloned();
+    let cache_control = req
+        .extensions()
+        .get::<RpcServiceConfig>()
+        .and_then(|config| config.cache_control.clone());
+
+    let mut res = match get_request_into_post(req) {
+        Ok(req) => handler.call(req, state).await,
+        Err(res) => return res,
+    };
+    if res.status() != StatusCode::OK {
+        return res;
+    }
+
+    if let Some(cache_control) = cache_control {
+        res.headers_mut()
+            .entry(header::CACHE_CONTROL)
+            .or_insert(cache_control);
+    }
+
+    let not_modified = match (&if_none_match, res.headers().get(header::ETAG)) {
+        (Some(if_none_match), Some(etag)) => etag_matches(if_none_match, etag),
+        _ => false,
+    };
+    if not_modified {
+        let (mut parts, _) = res.into_parts();
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts.headers.remove(header::CONTENT_ENCODING);
+        res = Response::from_parts(parts, Default::default());
+    }
+    res
+}
+
+/// Whether an `If-None-Match` list (or `*`) names `etag`, by the weak comparison it calls for.
+fn etag_matches(if_none_match: &HeaderValue, etag: &HeaderValue) -> bool {
+    let weak = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let (Ok(if_none_match), Ok(etag)) = (if_none_match.to_str(), etag.to_str()) else {
+        return false;
+    };
+    let etag = weak(etag);
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || weak(tag) == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, time::Duration};
+
+    use bytes::{Buf, Bytes};
+    use futures::{stream, StreamExt};
+    use http_body::Frame;
+    use http_body_util::{BodyExt, StreamBody};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        health::{HealthCheckRequest, HealthCheckResponse, ServingStatus},
+        response::RpcResponse,
+        router::RpcRoutes,
+    };
+
+    /// Splits `body` into `VecDeque<u8>` chunks of `size` bytes, like an HTTP/3 stack may hand out.
+    fn chunked(
+        body: &[u8],
+        size: usize,
+    ) -> StreamBody<impl futures::Stream<Item = Result<Frame<VecDeque<u8>>, Infallible>>> {
+        let chunks: Vec<_> = body
+            .chunks(size)
+            .map(|chunk| Ok(Frame::data(chunk.iter().copied().collect::<VecDeque<u8>>())))
+            .collect();
+        StreamBody::new(stream::iter(chunks))
+    }
+
+    fn envelope(flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut envelope = vec![flags];
+        envelope.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        envelope.extend_from_slice(payload);
+        envelope
+    }
+
+    /// The enveloped messages of a streaming response body, as their flags and payloads.
+    fn envelopes(mut body: Bytes) -> Vec<(u8, String)> {
+        let mut envelopes = vec![];
+        while body.has_remaining() {
+            let flags = body.get_u8();
+            let len = body.get_u32() as usize;
+            let payload = body.split_to(len);
+            envelopes.push((flags, String::from_utf8(payload.to_vec()).unwrap()));
+        }
+        envelopes
+    }
+
+    async fn check(_: HealthCheckRequest) -> HealthCheckResponse {
+        HealthCheckResponse::new(ServingStatus::Serving)
+    }
+
+    async fn watch(
+        _: HealthCheckRequest,
+    ) -> impl futures::Stream<Item = Result<HealthCheckResponse, RpcError>> {
+        stream::iter([Ok(HealthCheckResponse::new(ServingStatus::Serving))])
+    }
+
+    /// Calls `service` with `body`, as configured by `config`, returning the response status and
+    /// the Connect error code: of the JSON error of a unary call, or the EndStreamResponse of a
+    /// stream.
+    async fn error_code<H, S>(
+        service: RpcService<H, S>,
+        config: RpcServiceConfig,
+        content_type: &str,
+        body: Vec<u8>,
+    ) -> (StatusCode, Option<String>)
+    where
+        H: Clone,
+        S: Clone,
+    {
+        let mut req = http::Request::post("/grpc.health.v1.Health/Check")
+            .header(header::CONTENT_TYPE, content_type)
+            .body(axum::body::Body::from(body))
+            .unwrap();
+        req.extensions_mut().insert(config);
+        call(service, req).await
+    }
+
+    /// Calls `service` with `req`, returning the response status and the Connect error code.
+    async fn call<H, S>(
+        service: RpcService<H, S>,
+        req: Request<axum::body::Body>,
+    ) -> (StatusCode, Option<String>)
+    where
+        H: Clone,
+        S: Clone,
+    {
+        let (status, error) = call_error(service, req).await;
+        (status, error["code"].as_str().map(str::to_string))
+    }
+
+    /// Calls `service` with `req`, returning the response status and the Connect error as JSON.
+    async fn call_error<H, S>(
+        service: RpcService<H, S>,
+        req: Request<axum::body::Body>,
+    ) -> (StatusCode, serde_json::Value)
+    where
+        H: Clone,
+        S: Clone,
+    {
+        let streaming = req.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("application/connect+");
+        let res = service.oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let error: serde_json::Value = match streaming {
+            true => {
+                let (flags, end) = envelopes(body).pop().unwrap();
+                assert_eq!(flags, 2, "not an EndStreamResponse");
+                serde_json::from_str::<serde_json::Value>(&end).unwrap()["error"].clone()
+            }
+            false => serde_json::from_slice(&body).unwrap_or_default(),
+        };
+        (status, error)
+    }
+
+    #[tokio::test]
+    async fn rejects_request_messages_over_the_limit() {
+        let config = RpcServiceConfig::new().max_request_message_size(16);
+        let message = br#"{"service":"a-long-service-name"}"#;
+        let exhausted = Some("resource_exhausted".to_string());
+
+        let unary = RpcService::unary(check, ());
+        assert_eq!(
+            error_code(
+                unary.clone(),
+                config.clone(),
+                "application/json",
+                message.to_vec()
+            )
+            .await,
+            (StatusCode::TOO_MANY_REQUESTS, exhausted.clone())
+        );
+        assert_eq!(
+            error_code(unary, config.clone(), "application/json", b"{}".to_vec()).await,
+            (StatusCode::OK, None)
+        );
+
+        let stream = RpcService::server_stream(watch, ());
+        assert_eq!(
+            error_code(
+                stream,
+                config,
+                "application/connect+json",
+                envelope(0, message)
+            )
+            .await,
+            (StatusCode::OK, exhausted)
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_response_messages_over_the_limit() {
+        // `{"status":"SERVING"}` is 20 bytes.
+        let config = RpcServiceConfig::new().max_response_message_size(16);
+        let exhausted = Some("resource_exhausted".to_string());
+
+        let unary = RpcService::unary(check, ());
+        assert_eq!(
+            error_code(unary.clone(), config, "application/json", b"{}".to_vec()).await,
+            (StatusCode::TOO_MANY_REQUESTS, exhausted.clone())
+        );
+        let config = RpcServiceConfig::new().max_response_message_size(20);
+        assert_eq!(
+            error_code(unary, config, "application/json", b"{}".to_vec()).await,
+            (StatusCode::OK, None)
+        );
+
+        let config = RpcServiceConfig::new().max_response_message_size(16);
+        let stream = RpcService::server_stream(watch, ());
+        assert_eq!(
+            error_code(
+                stream,
+                config,
+                "application/connect+json",
+                envelope(0, b"{}")
+            )
+            .await,
+            (StatusCode::OK, exhausted)
+        );
+    }
+
+    fn with_timeout(content_type: &str, timeout: &str, body: Vec<u8>) -> Request<axum::body::Body> {
+        http::Request::post("/grpc.health.v1.Health/Check")
+            .header(header::CONTENT_TYPE, content_type)
+            .header("connect-timeout-ms", timeout)
+            .body(axum::body::Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_timeouts() {
+        let unary = RpcService::unary(check, ());
+        for timeout in ["", "soon", "-1", "1.5", " 10", "12345678901"] {
+            let req = with_timeout("application/json", timeout, b"{}".to_vec());
+            assert_eq!(
+                call(unary.clone(), req).await,
+                (
+                    StatusCode::BAD_REQUEST,
+                    Some("invalid_argument".to_string())
+                ),
+                "{:?}",
+                timeout
+            );
+        }
+
+        let req = with_timeout("application/json", "1234567890", b"{}".to_vec());
+        assert_eq!(call(unary, req).await, (StatusCode::OK, None));
+    }
+
+    #[tokio::test]
+    async fn fails_unary_calls_past_the_deadline() {
+        let slow = |_: HealthCheckRequest| async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            HealthCheckResponse::new(ServingStatus::Serving)
+        };
+        let exceeded = (
+            StatusCode::GATEWAY_TIMEOUT,
+            Some("deadline_exceeded".to_string()),
+        );
+
+        let req = with_timeout("application/json", "20", b"{}".to_vec());
+        assert_eq!(call(RpcService::unary(slow, ()), req).await, exceeded);
+
+        // The configured timeout applies without a connect-timeout-ms, and caps it.
+        let config = RpcServiceConfig::new().timeout(Duration::from_millis(20));
+        let unary = RpcService::unary(slow, ());
+        assert_eq!(
+            error_code(
+                unary.clone(),
+                config.clone(),
+                "application/json",
+                b"{}".to_vec()
+            )
+            .await,
+            exceeded
+        );
+        let mut req = with_timeout("application/json", "60000", b"{}".to_vec());
+        req.extensions_mut().insert(config);
+        assert_eq!(call(unary, req).await, exceeded);
+    }
+
+    #[tokio::test]
+    async fn ends_streams_past_the_deadline() {
+        // One message, then nothing for as long as the client waits.
+        let stalled = |_: HealthCheckRequest| async {
+            stream::iter([Ok::<_, RpcError>(HealthCheckResponse::new(
+                ServingStatus::Serving,
+            ))])
+            .chain(stream::pending())
+        };
+
+        let req = with_timeout("application/connect+json", "20", envelope(0, b"{}"));
+        let res = RpcService::server_stream(stalled, ())
+            .oneshot(req)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let envelopes = envelopes(body);
+        assert_eq!(envelopes[0], (0, r#"{"status":"SERVING"}"#.to_string()));
+        let (flags, end) = &envelopes[1];
+        assert_eq!(*flags, 2);
+        let end: serde_json::Value = serde_json::from_str(end).unwrap();
+        assert_eq!(end["error"]["code"], "deadline_exceeded");
+    }
+
+    #[tokio::test]
+    async fn sets_the_configured_cache_control_on_get_responses() {
+        let check = |cached: bool| async move {
+            let res = RpcResponse::new(HealthCheckResponse::new(ServingStatus::Serving));
+            match cached {
+                true => res.cache_control("private, max-age=5"),
+                false => res,
+            }
+        };
+        let config =
+            RpcServiceConfig::new().cache_control(HeaderValue::from_static("public, max-age=60"));
+
+        for (cached, cache_control) in [(false, "public, max-age=60"), (true, "private, max-age=5")]
+        {
+            let mut req = Request::get("/grpc.health.v1.Health/Check?encoding=json&message=%7B%7D")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            req.extensions_mut().insert(config.clone());
+            let handler = move |_: HealthCheckRequest| check(cached);
+
+            let res = call_unary_get(handler, req, ()).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers()[header::CACHE_CONTROL], cache_control);
+        }
+    }
+
+    async fn check_named(request: HealthCheckRequest) -> Result<HealthCheckResponse, RpcError> {
+        match request.service.as_str() {
+            "a b/c" => Ok(HealthCheckResponse::new(ServingStatus::Serving)),
+            service => Err(RpcError::new(RpcErrorCode::NotFound, service.to_string())),
+        }
+    }
+
+    async fn get(query: &str, if_none_match: Option<&str>) -> Response {
+        let mut req = Request::get(format!("/grpc.health.v1.Health/Check?{}", query));
+        if let Some(if_none_match) = if_none_match {
+            req = req.header(header::IF_NONE_MATCH, if_none_match);
+        }
+        let handler = |request: HealthCheckRequest| async move {
+            RpcResponse::new(check_named(request).await).etag("v1")
+        };
+        call_unary_get(handler, req.body(axum::body::Body::empty()).unwrap(), ()).await
+    }
+
+    #[tokio::test]
+    async fn serves_get_requests() {
+        use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+        use prost::Message;
+
+        use crate::compression::{Compression, Gzip};
+
+        let request = HealthCheckRequest {
+            service: "a b/c".to_string(),
+        };
+        let json = r#"{"service":"a b/c"}"#;
+        let gzipped = Gzip.compress(json.as_bytes()).unwrap();
+        let queries = [
+            // Percent-encoded, with `+` for a space.
+            "encoding=json&message=%7B%22service%22%3A%22a+b%2Fc%22%7D&connect=v1".to_string(),
+            format!(
+                "encoding=proto&base64=1&message={}",
+                URL_SAFE_NO_PAD.encode(request.encode_to_vec())
+            ),
+            format!(
+                "message={}&encoding=json&base64=1&compression=gzip",
+                URL_SAFE_NO_PAD.encode(&gzipped)
+            ),
+        ];
+        for query in queries {
+            let res = get(&query, None).await;
+            assert_eq!(res.status(), StatusCode::OK, "{query}");
+            let content_type = res.headers()[header::CONTENT_TYPE].clone();
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            let response = match content_type.to_str().unwrap() {
+                "application/proto" => HealthCheckResponse::decode(body).unwrap(),
+                _ => serde_json::from_slice(&body).unwrap(),
+            };
+            assert_eq!(response.status(), ServingStatus::Serving, "{query}");
+        }
+
+        for query in [
+            "encoding=json&base64=1&message=%7B%7D",
+            "encoding=json&base64=1&message=e30*",
+            "encoding=json",
+            "message=%7B%7D",
+        ] {
+            let res = get(query, None).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{query}");
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(error["code"], "invalid_argument", "{query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_get_requests_for_methods_with_side_effects() {
+        use axum::routing::post_service;
+
+        use crate::{
+            descriptor::{IdempotencyLevel, MethodDescriptor},
+            health::Health,
+            router::RpcRouterExt,
+        };
+
+        static CACHEABLE: MethodDescriptor = MethodDescriptor {
+            idempotency: IdempotencyLevel::NoSideEffects,
+            ..Health::DESCRIPTOR.methods[0]
+        };
+
+        for (descriptor, method, allow) in [
+            (&Health::DESCRIPTOR.methods[0], Method::GET, "POST"),
+            (&CACHEABLE, Method::PUT, "GET, POST"),
+        ] {
+            let app = axum::Router::new().rpc_route(
+                descriptor,
+                post_service(RpcService::unary(check, ())).fallback(method_not_allowed),
+            );
+            let req = Request::builder()
+                .method(method)
+                .uri("/grpc.health.v1.Health/Check?encoding=json&message=%7B%7D")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+            assert_eq!(res.headers()[header::ALLOW], allow);
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(error["code"], "unimplemented");
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_matching_etags_with_not_modified() {
+        let query = "encoding=json&message=%7B%22service%22%3A%22a+b%2Fc%22%7D";
+
+        let res = get(query, None).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let etag = res.headers()[header::ETAG].to_str().unwrap().to_string();
+        assert_eq!(etag, "\"v1\"");
+
+        for if_none_match in [etag.as_str(), "W/\"v1\"", "\"v0\", \"v1\"", "*"] {
+            let res = get(query, Some(if_none_match)).await;
+            assert_eq!(res.status(), StatusCode::NOT_MODIFIED, "{if_none_match}");
+            assert_eq!(res.headers()[header::ETAG], etag.as_str());
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            assert!(body.is_empty());
+        }
+
+        let res = get(query, Some("\"v0\"")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // Errors are never cached.
+        let query = "encoding=json&message=%7B%7D";
+        let res = get(query, Some(&etag)).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn serves_server_streams_from_buf_chunks() {
+        let watch = |request: HealthCheckRequest| async move {
+            let statuses = match request.service.as_str() {
+                "db" => vec![ServingStatus::Serving, ServingStatus::NotServing],
+                _ => vec![ServingStatus::ServiceUnknown],
+            };
+            stream::iter(
+                statuses
+                    .into_iter()
+                    .map(|status| Ok::<_, RpcError>(HealthCheckResponse::new(status))),
+            )
+        };
+        let router = RpcRoutes::new().route(
+            "/grpc.health.v1.Health/Watch",
+            RpcService::server_stream(watch, ()),
+        );
+
+        let body = envelope(0, br#"{"service":"db"}"#);
+        let req = http::Request::post("/grpc.health.v1.Health/Watch")
+            .header(header::CONTENT_TYPE, "application/connect+json")
+            .body(chunked(&body, 3))
+            .unwrap();
+        let res = router.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()[header::CONTENT_TYPE],
+            "application/connect+json"
+        );
+
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            envelopes(body),
+            vec![
+                (0, r#"{"status":"SERVING"}"#.to_string()),
+                (0, r#"{"status":"NOT_SERVING"}"#.to_string()),
+                (2, "{}".to_string()),
+            ]
+        );
+    }
+
+    async fn panics(_: HealthCheckRequest) -> HealthCheckResponse {
+        panic!("secret {}", "sauce")
+    }
+
+    async fn panics_mid_stream(
+        _: HealthCheckRequest,
+    ) -> impl futures::Stream<Item = Result<HealthCheckResponse, RpcError>> {
+        stream::iter([Ok(HealthCheckResponse::new(ServingStatus::Serving))]).chain(stream::poll_fn(
+            |_| -> std::task::Poll<Option<_>> { panic!("secret sauce") },
+        ))
+    }
+
+    #[tokio::test]
+    async fn answers_panics_with_internal_errors() {
+        let request = |content_type: &str, body: Vec<u8>, config: RpcServiceConfig| {
+            let mut req = http::Request::post("/grpc.health.v1.Health/Check")
+                .header(header::CONTENT_TYPE, content_type)
+                .body(axum::body::Body::from(body))
+                .unwrap();
+            req.extensions_mut().insert(config);
+            req
+        };
+
+        for debug in [false, true] {
+            let config = RpcServiceConfig::new().error_debug_details(debug);
+
+            let unary = RpcService::unary(panics, ());
+            let req = request("application/json", b"{}".to_vec(), config.clone());
+            let (status, unary) = call_error(unary, req).await;
+            assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+
+            let stream = RpcService::server_stream(panics_mid_stream, ());
+            let req = request("application/connect+json", envelope(0, b"{}"), config);
+            let (status, stream) = call_error(stream, req).await;
+            assert_eq!(status, StatusCode::OK);
+
+            for error in [unary, stream] {
+                assert_eq!(error["code"], "internal");
+                assert_eq!(error["message"], "The handler panicked");
+                if debug {
+                    assert_eq!(error["details"][0]["type"], "google.rpc.DebugInfo");
+                    assert_eq!(error["details"][0]["debug"]["detail"], "secret sauce");
+                } else {
+                    assert!(!error.to_string().contains("secret"), "{error}");
+                }
+            }
+        }
+    }
+}
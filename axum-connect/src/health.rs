@@ -0,0 +1,298 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use async_stream::stream;
+use futures::Stream;
+use prost::Message;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tokio::sync::watch;
+
+use crate::{
+    descriptor::{IdempotencyLevel, MethodDescriptor, MethodKind, ServiceDescriptor},
+    error::{RpcError, RpcErrorCode},
+    operations::{server_stream, unary},
+    router::RpcRouterExt,
+};
+
+/// `grpc.health.v1.HealthCheckRequest`. An empty `service` is the server as a whole.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HealthCheckRequest {
+    #[prost(string, tag = "1")]
+    pub service: String,
+}
+
+/// `grpc.health.v1.HealthCheckResponse`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HealthCheckResponse {
+    /// A `ServingStatus`, sent by name in JSON.
+    #[prost(int32, tag = "1")]
+    #[serde(with = "status_name")]
+    pub status: i32,
+}
+
+impl HealthCheckResponse {
+    pub fn new(status: ServingStatus) -> Self {
+        Self {
+            status: status as i32,
+        }
+    }
+
+    /// The status, `Unknown` if it isn't one this version knows of.
+    pub fn status(&self) -> ServingStatus {
+        ServingStatus::from_i32(self.status).unwrap_or(ServingStatus::Unknown)
+    }
+}
+
+/// `grpc.health.v1.HealthCheckResponse.ServingStatus`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ServingStatus {
+    Unknown = 0,
+    Serving = 1,
+    NotServing = 2,
+    /// Only sent by `Watch`, for a service the server doesn't know of (yet).
+    ServiceUnknown = 3,
+}
+
+impl ServingStatus {
+    pub fn from_i32(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Unknown),
+            1 => Some(Self::Serving),
+            2 => Some(Self::NotServing),
+            3 => Some(Self::ServiceUnknown),
+            _ => None,
+        }
+    }
+}
+
+// The proto JSON mapping of the enum field: the value's name, or its number if unknown. Both are
+// accepted when reading.
+mod status_name {
+    use super::*;
+
+    pub fn serialize<S>(value: &i32, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match ServingStatus::from_i32(*value) {
+            Some(status) => status.serialize(serializer),
+            None => serializer.serialize_i32(*value),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<i32, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum NameOrNumber {
+            Name(ServingStatus),
+            Number(i32),
+        }
+
+        Ok(match NameOrNumber::deserialize(deserializer)? {
+            NameOrNumber::Name(status) => status as i32,
+            NameOrNumber::Number(value) => value,
+        })
+    }
+}
+
+/// The standard gRPC health checking service (`grpc.health.v1.Health`), for Kubernetes gRPC
+/// probes, load balancers and `grpc_health_probe`. Clones share the statuses, so keep one to flip
+/// them as the server's dependencies come and go:
+///
+/// ```ignore
+/// let health = Health::new();
+/// health.set_not_serving("hello.HelloWorldService");
+///
+/// let app = Router::new()
+///     .rpc(HelloWorldService::say_hello(say_hello))
+///     .rpc(health.clone().routes())
+///     .layer(
+///         RpcRouterOptions::new()
+///             .grpc(true)
+///             .layer([&HelloWorldService::DESCRIPTOR, &Health::DESCRIPTOR]),
+///     );
+///
+/// // Once the database is reachable.
+/// health.set_serving("hello.HelloWorldService");
+/// ```
+///
+/// The server as a whole (the empty service name) starts out `Serving`, other services are unknown
+/// until given a status. `Check` of an unknown service fails with `NotFound`, `Watch` of one sends
+/// `ServiceUnknown` and then the status it's given later, if any. Call `shutdown` before draining
+/// the server, so load balancers stop sending it new calls.
+#[derive(Clone)]
+pub struct Health {
+    statuses: Arc<Mutex<HashMap<String, watch::Sender<ServingStatus>>>>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Health {
+    pub const DESCRIPTOR: ServiceDescriptor = ServiceDescriptor {
+        name: "grpc.health.v1.Health",
+        package: "grpc.health.v1",
+        methods: &[
+            MethodDescriptor {
+                name: "Check",
+                service: "grpc.health.v1.Health",
+                path: "/grpc.health.v1.Health/Check",
+                input_type: "grpc.health.v1.HealthCheckRequest",
+                output_type: "grpc.health.v1.HealthCheckResponse",
+                kind: MethodKind::Unary,
+                idempotency: IdempotencyLevel::Unknown,
+                deprecated: false,
+            },
+            MethodDescriptor {
+                name: "Watch",
+                service: "grpc.health.v1.Health",
+                path: "/grpc.health.v1.Health/Watch",
+                input_type: "grpc.health.v1.HealthCheckRequest",
+                output_type: "grpc.health.v1.HealthCheckResponse",
+                kind: MethodKind::ServerStreaming,
+                idempotency: IdempotencyLevel::Unknown,
+                deprecated: false,
+            },
+        ],
+    };
+
+    pub fn new() -> Self {
+        let health = Self {
+            statuses: Default::default(),
+        };
+        health.set_serving("");
+        health
+    }
+
+    /// Also for services registered with their `DESCRIPTOR`, all starting out `Serving`.
+    pub fn with_services<'a, I>(services: I) -> Self
+    where
+        I: IntoIterator<Item = &'a ServiceDescriptor>,
+    {
+        let health = Self::new();
+        for service in services {
+            health.set_serving(service.name);
+        }
+        health
+    }
+
+    /// Sets the status of `service`, a fully qualified name like `hello.HelloWorldService`, or ""
+    /// for the server as a whole. Watchers are only told of actual changes.
+    pub fn set_status(&self, service: &str, status: ServingStatus) {
+        let mut statuses = self.statuses.lock().unwrap();
+        match statuses.get(service) {
+            Some(sender) => {
+                sender.send_if_modified(|current| {
+                    let modified = *current != status;
+                    *current = status;
+                    modified
+                });
+            }
+            None => {
+                statuses.insert(service.to_string(), watch::Sender::new(status));
+            }
+        }
+    }
+
+    pub fn set_serving(&self, service: &str) {
+        self.set_status(service, ServingStatus::Serving);
+    }
+
+    pub fn set_not_serving(&self, service: &str) {
+        self.set_status(service, ServingStatus::NotServing);
+    }
+
+    /// The status of `service`, `None` if it has none.
+    pub fn status(&self, service: &str) -> Option<ServingStatus> {
+        let statuses = self.statuses.lock().unwrap();
+        statuses
+            .get(service)
+            .map(|sender| *sender.borrow())
+            .filter(|status| *status != ServingStatus::ServiceUnknown)
+    }
+
+    /// Sets every service, and the server, `NotServing`. Later `set_status` calls still apply.
+    pub fn shutdown(&self) {
+        let statuses = self.statuses.lock().unwrap();
+        for sender in statuses.values() {
+            sender.send_if_modified(|current| {
+                let modified = *current != ServingStatus::NotServing;
+                *current = ServingStatus::NotServing;
+                modified
+            });
+        }
+    }
+
+    pub fn check(&self, service: &str) -> Result<HealthCheckResponse, RpcError> {
+        self.status(service)
+            .map(HealthCheckResponse::new)
+            .ok_or_else(|| {
+                RpcError::new(
+                    RpcErrorCode::NotFound,
+                    format!("Unknown service '{}'", service),
+                )
+            })
+    }
+
+    /// The status of `service` and then every change of it, until the client goes away.
+    pub fn watch(
+        &self,
+        service: &str,
+    ) -> impl Stream<Item = Result<HealthCheckResponse, RpcError>> + Send {
+        let mut receiver = self
+            .statuses
+            .lock()
+            .unwrap()
+            .entry(service.to_string())
+            .or_insert_with(|| watch::Sender::new(ServingStatus::ServiceUnknown))
+            .subscribe();
+
+        stream! {
+            loop {
+                let status = *receiver.borrow_and_update();
+                yield Ok(HealthCheckResponse::new(status));
+                if receiver.changed().await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Registers `Check` and `Watch` of the `grpc.health.v1.Health` service, use it with
+    /// `RpcRouterExt::rpc`.
+    pub fn routes<S, R>(self) -> impl FnOnce(R) -> R
+    where
+        S: Clone + Send + Sync + 'static,
+        R: RpcRouterExt<S>,
+    {
+        let [check, watch] = Self::DESCRIPTOR.methods else {
+            unreachable!()
+        };
+        move |router: R| {
+            let health = self.clone();
+            let router = unary(
+                router,
+                check,
+                move |request: HealthCheckRequest| async move { health.check(&request.service) },
+            );
+
+            let health = self;
+            server_stream(
+                router,
+                watch,
+                move |request: HealthCheckRequest| async move { health.watch(&request.service) },
+            )
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Per-RPC `tracing` spans and metrics, with the `tracing` and `metrics` features. Without them
+//! these are no-ops.
+
+use std::future::Future;
+#[cfg(feature = "metrics")]
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use futures::Stream;
+
+#[cfg(feature = "metrics")]
+use crate::error::RpcErrorCode;
+use crate::{config::RpcServiceConfig, error::RpcError};
+
+/// The protocol a call came in over, set by `GrpcLayer` on the requests it translates.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct CallProtocol(pub &'static str);
+
+/// The span of a call to `method` (as `package.Service/Method`), with the codec, request ID and
+/// status code recorded later. With the `opentelemetry` feature it also has the fields of the RPC
+/// semantic conventions, see `telemetry`.
+#[cfg(feature = "tracing")]
+pub(crate) fn call_span(method: &str, protocol: &'static str) -> tracing::Span {
+    let full_method = method;
+    let (service, method) = method.rsplit_once('/').unwrap_or(("", method));
+    #[cfg(not(feature = "opentelemetry"))]
+    let _ = full_method;
+    #[cfg(feature = "opentelemetry")]
+    return tracing::info_span!(
+        "rpc",
+        service,
+        method,
+        protocol,
+        codec = tracing::field::Empty,
+        request_id = tracing::field::Empty,
+        rpc.connect.status_code = tracing::field::Empty,
+        otel.name = full_method,
+        otel.kind = "server",
+        otel.status_code = tracing::field::Empty,
+        rpc.system = rpc_system(protocol),
+        rpc.service = service,
+        rpc.method = method,
+        rpc.connect_rpc.error_code = tracing::field::Empty,
+        rpc.grpc.status_code = tracing::field::Empty,
+    );
+    #[cfg(not(feature = "opentelemetry"))]
+    tracing::info_span!(
+        "rpc",
+        service,
+        method,
+        protocol,
+        codec = tracing::field::Empty,
+        request_id = tracing::field::Empty,
+        rpc.connect.status_code = tracing::field::Empty,
+    )
+}
+
+/// The `rpc.system` of the OpenTelemetry semantic conventions for a protocol.
+#[cfg(any(feature = "opentelemetry", feature = "metrics"))]
+fn rpc_system(protocol: &str) -> &'static str {
+    if protocol == "connect" {
+        "connect_rpc"
+    } else {
+        "grpc"
+    }
+}
+
+/// The RPC server metrics of the OpenTelemetry semantic conventions, recorded through the
+/// `metrics` facade for a call (see: https://opentelemetry.io/docs/specs/semconv/rpc/rpc-metrics/):
+///
+/// - `rpc.server.duration`, a histogram of call durations, in milliseconds.
+/// - `rpc.server.request.size` and `rpc.server.response.size`, histograms of the (uncompressed)
+///   size of each message, in bytes.
+/// - `rpc.server.requests_per_rpc` and `rpc.server.responses_per_rpc`, histograms of the number of
+///   messages of each call.
+///
+/// All are labeled with `rpc.system` (`connect_rpc` or `grpc`), `rpc.service` and `rpc.method`,
+/// and the per call ones also with `rpc.connect.status_code`: the code a call failed with, or
+/// `ok`. The number of calls is the count of `rpc.server.duration`.
+#[cfg(feature = "metrics")]
+pub(crate) struct CallMetrics {
+    labels: Vec<metrics::Label>,
+    /// Set once the request was checked, which makes it a call (rather than, say, a 415).
+    checked: AtomicBool,
+    requests: AtomicU64,
+    responses: AtomicU64,
+}
+
+#[cfg(feature = "metrics")]
+impl CallMetrics {
+    pub fn new(method: &str, protocol: &'static str) -> Self {
+        static DESCRIBED: std::sync::Once = std::sync::Once::new();
+        DESCRIBED.call_once(|| {
+            use metrics::{describe_histogram, Unit};
+            describe_histogram!(
+                "rpc.server.duration",
+                Unit::Milliseconds,
+                "RPC call duration"
+            );
+            describe_histogram!("rpc.server.request.size", Unit::Bytes, "RPC request size");
+            describe_histogram!("rpc.server.response.size", Unit::Bytes, "RPC response size");
+            describe_histogram!(
+                "rpc.server.requests_per_rpc",
+                "RPC request messages per call"
+            );
+            describe_histogram!(
+                "rpc.server.responses_per_rpc",
+                "RPC response messages per call"
+            );
+        });
+
+        let (service, method) = method.rsplit_once('/').unwrap_or(("", method));
+        Self {
+            labels: vec![
+                metrics::Label::new("rpc.system", rpc_system(protocol)),
+                metrics::Label::new("rpc.service", service.to_string()),
+                metrics::Label::new("rpc.method", method.to_string()),
+            ],
+            checked: AtomicBool::new(false),
+            requests: AtomicU64::new(0),
+            responses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn checked(&self) {
+        self.checked.store(true, Ordering::Release);
+    }
+
+    pub fn message_received(&self, bytes: usize) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        metrics::histogram!("rpc.server.request.size", self.labels.clone()).record(bytes as f64);
+    }
+
+    pub fn message_sent(&self, bytes: usize) {
+        self.responses.fetch_add(1, Ordering::Relaxed);
+        metrics::histogram!("rpc.server.response.size", self.labels.clone()).record(bytes as f64);
+    }
+
+    /// Records the call, unless it was turned away before it was checked without an error.
+    pub fn finish(&self, code: Option<&RpcErrorCode>, latency: Duration) {
+        if code.is_none() && !self.checked.load(Ordering::Acquire) {
+            return;
+        }
+        let mut labels = self.labels.clone();
+        labels.push(metrics::Label::new(
+            "rpc.connect.status_code",
+            code.map_or("ok", RpcErrorCode::as_str),
+        ));
+        let requests = self.requests.load(Ordering::Relaxed) as f64;
+        let responses = self.responses.load(Ordering::Relaxed) as f64;
+        metrics::histogram!("rpc.server.duration", labels.clone())
+            .record(latency.as_secs_f64() * 1000.0);
+        metrics::histogram!("rpc.server.requests_per_rpc", labels.clone()).record(requests);
+        metrics::histogram!("rpc.server.responses_per_rpc", labels).record(responses);
+    }
+}
+
+/// The span of a call, to run its future and response stream in, for the events of handlers.
+pub(crate) struct CallSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl CallSpan {
+    pub fn of(config: &RpcServiceConfig) -> Self {
+        #[cfg(not(feature = "tracing"))]
+        let _ = config;
+        Self {
+            #[cfg(feature = "tracing")]
+            span: config
+                .call
+                .as_ref()
+                .map_or_else(tracing::Span::none, |call| call.span.clone()),
+        }
+    }
+
+    pub fn future<F: Future>(self, future: F) -> impl Future<Output = F::Output> {
+        #[cfg(feature = "tracing")]
+        return tracing::Instrument::instrument(future, self.span);
+        #[cfg(not(feature = "tracing"))]
+        future
+    }
+
+    pub fn stream<S: Stream>(self, stream: S) -> impl Stream<Item = S::Item> {
+        #[cfg(feature = "tracing")]
+        {
+            let mut stream = Box::pin(stream);
+            futures::stream::poll_fn(move |cx| {
+                let _entered = self.span.enter();
+                stream.as_mut().poll_next(cx)
+            })
+        }
+        #[cfg(not(feature = "tracing"))]
+        stream
+    }
+}
+
+/// A request (message) that couldn't be read or decoded.
+pub(crate) fn decode_failed(e: &RpcError) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(code = e.code.as_str(), "{}", e.message);
+    #[cfg(not(feature = "tracing"))]
+    let _ = e;
+}
@@ -0,0 +1,221 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use axum::http::request::Parts;
+use tokio::time::Instant;
+
+use crate::{
+    error::{RpcError, RpcErrorCode},
+    metadata::RpcMetadata,
+};
+
+/// Connect-aware middleware, like connect-go's interceptors: `before` runs once a request has been
+/// checked and its codec negotiated, just before the extractors and the handler, and can reject
+/// the call; `after` runs once it completed (for server streams, once the stream ended), with the
+/// code it failed with and how long it took. Registered on a `RpcServiceConfig`, they run in the
+/// order added:
+///
+/// ```ignore
+/// struct RequireToken;
+///
+/// #[async_trait]
+/// impl RpcInterceptor for RequireToken {
+///     async fn before(&self, call: &RpcCall) -> Result<(), RpcError> {
+///         match call.metadata.get("authorization") {
+///             Some(token) if verify(token).await => Ok(()),
+///             _ => Err(RpcError::unauthenticated("Missing or invalid token")),
+///         }
+///     }
+///
+///     fn after(&self, call: &RpcCall, code: Option<&RpcErrorCode>, latency: Duration) {
+///         REQUESTS.with_label_values(&[&call.method, code.map_or("ok", |c| c.as_str())]).inc();
+///     }
+/// }
+///
+/// let app = Router::new()
+///     .rpc(HelloWorldService::say_hello(say_hello))
+///     .layer(Extension(RpcServiceConfig::new().interceptor(RequireToken)));
+/// ```
+///
+/// Unlike tower layers, they only see calls that made it past the protocol checks. `after` is
+/// called exactly once for every call `before` was called for, rejected ones included, with
+/// `Canceled` for calls the client went away from.
+#[async_trait]
+pub trait RpcInterceptor: Send + Sync + 'static {
+    /// Rejects the call with the error returned, which skips the `before` of later interceptors.
+    async fn before(&self, call: &RpcCall) -> Result<(), RpcError> {
+        let _ = call;
+        Ok(())
+    }
+
+    /// `code` is `None` for a call that succeeded.
+    fn after(&self, call: &RpcCall, code: Option<&RpcErrorCode>, latency: Duration) {
+        let _ = (call, code, latency);
+    }
+}
+
+/// The call an interceptor runs for.
+#[derive(Clone, Debug)]
+pub struct RpcCall {
+    /// The RPC method, as `package.Service/Method`.
+    pub method: String,
+    /// The request's custom metadata, empty if it isn't valid (which the `RpcMetadata` extractor
+    /// rejects).
+    pub metadata: RpcMetadata,
+    /// The codec of the request, like `proto` or `json`.
+    pub codec: &'static str,
+    /// Whether the call is streaming (client or server), rather than unary.
+    pub streaming: bool,
+}
+
+/// The state of a call, for the hooks that run over its whole lifetime: the error hook, the
+/// interceptors, its `tracing` span and its metrics. Shared by the call's copies of its `RpcServiceConfig`, and dropped with the last.
+pub(crate) struct CallState {
+    pub method: String,
+    pub started: Instant,
+    #[cfg(feature = "tracing")]
+    pub span: tracing::Span,
+    #[cfg(feature = "opentelemetry")]
+    protocol: &'static str,
+    #[cfg(feature = "metrics")]
+    metrics: crate::instrument::CallMetrics,
+    interceptors: Arc<[Arc<dyn RpcInterceptor>]>,
+    /// Set once the interceptors ran `before`.
+    call: OnceLock<RpcCall>,
+    /// The code of the last error sent.
+    code: Mutex<Option<RpcErrorCode>>,
+    /// The response is a server stream, which ends the call when it does.
+    streaming: AtomicBool,
+    done: AtomicBool,
+}
+
+impl fmt::Debug for CallState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CallState")
+            .field("method", &self.method)
+            .field("started", &self.started)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CallState {
+    pub fn new(
+        path: &str,
+        protocol: &'static str,
+        interceptors: Arc<[Arc<dyn RpcInterceptor>]>,
+    ) -> Self {
+        let method = path.trim_start_matches('/').to_string();
+        #[cfg(not(feature = "tracing"))]
+        let _ = protocol;
+        Self {
+            #[cfg(feature = "tracing")]
+            span: crate::instrument::call_span(&method, protocol),
+            #[cfg(feature = "opentelemetry")]
+            protocol,
+            #[cfg(feature = "metrics")]
+            metrics: crate::instrument::CallMetrics::new(&method, protocol),
+            method,
+            started: Instant::now(),
+            interceptors,
+            call: OnceLock::new(),
+            code: Mutex::new(None),
+            streaming: AtomicBool::new(false),
+            done: AtomicBool::new(false),
+        }
+    }
+
+    /// Runs the `before` of the interceptors, in order, until one rejects the call.
+    pub async fn intercept(
+        &self,
+        parts: &Parts,
+        codec: &'static str,
+        streaming: bool,
+    ) -> Result<(), RpcError> {
+        #[cfg(feature = "tracing")]
+        self.span.record("codec", codec);
+        #[cfg(feature = "metrics")]
+        self.metrics.checked();
+        if self.interceptors.is_empty() {
+            return Ok(());
+        }
+
+        let call = self.call.get_or_init(|| RpcCall {
+            method: self.method.clone(),
+            metadata: RpcMetadata::from_headers(&parts.headers).unwrap_or_default(),
+            codec,
+            streaming,
+        });
+        for interceptor in self.interceptors.iter() {
+            interceptor.before(call).await?;
+        }
+        Ok(())
+    }
+
+    pub fn error_sent(&self, code: &RpcErrorCode) {
+        *self.code.lock().unwrap() = Some(code.clone());
+    }
+
+    /// The response has been made: the end of the call, unless it's a stream.
+    pub fn responded(&self) {
+        if !self.streaming.load(Ordering::Acquire) {
+            let code = self.code.lock().unwrap().take();
+            self.finish(code.as_ref());
+        }
+    }
+
+    /// A request message of `bytes` (uncompressed) was read.
+    pub fn message_received(&self, bytes: usize) {
+        #[cfg(feature = "metrics")]
+        self.metrics.message_received(bytes);
+        #[cfg(not(feature = "metrics"))]
+        let _ = bytes;
+    }
+
+    /// A response message of `bytes` (uncompressed) is being sent.
+    pub fn message_sent(&self, bytes: usize) {
+        #[cfg(feature = "metrics")]
+        self.metrics.message_sent(bytes);
+        #[cfg(not(feature = "metrics"))]
+        let _ = bytes;
+    }
+
+    pub fn stream_started(&self) {
+        self.streaming.store(true, Ordering::Release);
+    }
+
+    /// The end of the call, calling the `after` of the interceptors (once).
+    pub fn finish(&self, code: Option<&RpcErrorCode>) {
+        if self.done.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        #[cfg(feature = "tracing")]
+        self.span.record(
+            "rpc.connect.status_code",
+            code.map_or("ok", RpcErrorCode::as_str),
+        );
+        #[cfg(feature = "opentelemetry")]
+        crate::telemetry::record_status(&self.span, self.protocol, code);
+        #[cfg(feature = "metrics")]
+        self.metrics.finish(code, self.started.elapsed());
+        let Some(call) = self.call.get() else {
+            return;
+        };
+        let latency = self.started.elapsed();
+        for interceptor in self.interceptors.iter() {
+            interceptor.after(call, code, latency);
+        }
+    }
+}
+
+impl Drop for CallState {
+    fn drop(&mut self) {
+        self.finish(Some(&RpcErrorCode::Canceled));
+    }
+}
@@ -1,19 +1,105 @@
+#[cfg(feature = "chunked")]
+pub mod chunked;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod codec;
+pub mod compression;
+pub mod config;
+#[cfg(feature = "cors")]
+pub mod cors;
+pub mod descriptor;
 pub mod error;
+pub mod error_details;
+pub mod field_mask;
 pub mod handler;
+pub mod health;
+pub(crate) mod instrument;
+pub mod interceptor;
+pub mod metadata;
+pub mod middleware;
+#[cfg(feature = "oauth2")]
+pub mod oauth2;
+pub mod operations;
+#[cfg(feature = "pagination")]
+pub mod pagination;
 pub mod parts;
+pub mod provide;
+pub mod reflection;
+pub mod request;
 pub mod response;
+pub mod resume;
 pub mod router;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod stream_hooks;
+#[cfg(feature = "opentelemetry")]
+pub mod telemetry;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+pub mod text_format;
+pub mod transport;
 
 // Re-export several crates
+pub use async_trait;
+pub use erased_serde;
 pub use futures;
-pub use pbjson;
-pub use pbjson_types;
-pub use prost;
 pub use serde;
 
+#[cfg(feature = "opentelemetry")]
+pub use opentelemetry;
+
+#[cfg(feature = "macros")]
+pub use axum_connect_macros::{rpc_handler, RpcIntoError};
+
+// The prost stack is picked with a `prost-*` feature and re-exported under its usual names, which
+// the rest of the crate (and generated code) refers to it by. When more than one is enabled, the
+// newest wins: 0.13 over 0.12 over 0.11. Features are unified across the dependency graph, so one
+// crate leaving the default `prost-0-11` on while another picks `prost-0-13` mustn't fail the
+// build, which a `compile_error!` on conflicting selections would.
+#[cfg(feature = "prost-0-13")]
+pub extern crate pbjson_0_7 as pbjson;
+#[cfg(feature = "prost-0-13")]
+pub extern crate pbjson_types_0_7 as pbjson_types;
+#[cfg(feature = "prost-0-13")]
+pub extern crate prost_0_13 as prost;
+
+#[cfg(all(feature = "prost-0-12", not(feature = "prost-0-13")))]
+pub extern crate pbjson_0_6 as pbjson;
+#[cfg(all(feature = "prost-0-12", not(feature = "prost-0-13")))]
+pub extern crate pbjson_types_0_6 as pbjson_types;
+#[cfg(all(feature = "prost-0-12", not(feature = "prost-0-13")))]
+pub extern crate prost_0_12 as prost;
+
+#[cfg(all(
+    feature = "prost-0-11",
+    not(any(feature = "prost-0-12", feature = "prost-0-13"))
+))]
+pub extern crate pbjson_0_5 as pbjson;
+#[cfg(all(
+    feature = "prost-0-11",
+    not(any(feature = "prost-0-12", feature = "prost-0-13"))
+))]
+pub extern crate pbjson_types_0_5 as pbjson_types;
+#[cfg(all(
+    feature = "prost-0-11",
+    not(any(feature = "prost-0-12", feature = "prost-0-13"))
+))]
+pub extern crate prost_0_11 as prost;
+
+#[cfg(not(any(feature = "prost-0-11", feature = "prost-0-12", feature = "prost-0-13")))]
+compile_error!("axum-connect needs one of the `prost-0-11`, `prost-0-12` or `prost-0-13` features");
+
 pub mod prelude {
+    pub use crate::config::RpcServiceConfig;
     pub use crate::error::*;
+    pub use crate::error_details::RpcErrorDetailMessage;
+    pub use crate::metadata::RpcMetadata;
     pub use crate::parts::*;
+    pub use crate::request::*;
     pub use crate::response::*;
     pub use crate::router::RpcRouterExt;
+    #[cfg(feature = "opentelemetry")]
+    pub use crate::telemetry::RpcTraceContext;
+    #[cfg(feature = "macros")]
+    pub use axum_connect_macros::RpcIntoError;
 }
@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use axum::http::{self, HeaderMap, HeaderName, HeaderValue};
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+use bytes::Bytes;
+use prost::Message;
+use serde_json::Value;
+
+use crate::{
+    error::{RpcError, RpcErrorCode},
+    parts::RpcFromRequestParts,
+};
+
+/// Binary metadata values are sent unpadded, and accepted either way.
+pub(crate) const BINARY: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new()
+        .with_encode_padding(false)
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Custom metadata, the headers of a Connect request that aren't part of the protocol itself (see:
+/// https://connect.build/docs/protocol/#unary-request). Keys are lowercase. Keys ending in `-bin`
+/// hold binary values, base64 encoded on the wire, and are read with `get_bin`; all others are
+/// ASCII, read with `get`. A key may have several values.
+///
+/// As an extractor it fails with `InvalidArgument` on a non ASCII value or invalid base64.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RpcMetadata {
+    ascii: HashMap<String, Vec<String>>,
+    binary: HashMap<String, Vec<Bytes>>,
+}
+
+impl RpcMetadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, RpcError> {
+        let invalid = |key: &str, problem: &str| {
+            RpcError::new(
+                RpcErrorCode::InvalidArgument,
+                format!("Metadata `{}` {}", key, problem),
+            )
+        };
+        let mut metadata = Self::new();
+
+        for (key, value) in headers {
+            let key = key.as_str();
+            if is_protocol_header(key) {
+                continue;
+            }
+
+            let value = value.to_str().map_err(|_| invalid(key, "is not ASCII"))?;
+            if key.ends_with("-bin") {
+                // Binary values can also be comma separated within one header.
+                for value in value.split(',') {
+                    let value = BINARY
+                        .decode(value.trim())
+                        .map_err(|_| invalid(key, "is not valid base64"))?;
+                    metadata.append_bin(key, value);
+                }
+            } else {
+                metadata.append(key, value);
+            }
+        }
+
+        Ok(metadata)
+    }
+
+    /// The first value of an ASCII key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.get_all(key).first().map(String::as_str)
+    }
+
+    pub fn get_all(&self, key: &str) -> &[String] {
+        self.ascii.get(key).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// The first value of a binary (`-bin`) key, decoded.
+    pub fn get_bin(&self, key: &str) -> Option<&[u8]> {
+        self.get_all_bin(key).first().map(|value| &value[..])
+    }
+
+    pub fn get_all_bin(&self, key: &str) -> &[Bytes] {
+        self.binary.get(key).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Adds a value to an ASCII key, which must not end in `-bin`.
+    pub fn append(&mut self, key: &str, value: impl Into<String>) {
+        debug_assert!(!key.ends_with("-bin"), "binary metadata goes in append_bin");
+        self.ascii
+            .entry(key.to_ascii_lowercase())
+            .or_default()
+            .push(value.into());
+    }
+
+    /// Adds a value to a binary key, which must end in `-bin`.
+    pub fn append_bin(&mut self, key: &str, value: impl Into<Bytes>) {
+        debug_assert!(key.ends_with("-bin"), "binary metadata keys end in -bin");
+        self.binary
+            .entry(key.to_ascii_lowercase())
+            .or_default()
+            .push(value.into());
+    }
+
+    /// Adds all of `other`'s values.
+    pub fn extend(&mut self, other: RpcMetadata) {
+        for (key, values) in other.ascii {
+            self.ascii.entry(key).or_default().extend(values);
+        }
+        for (key, values) in other.binary {
+            self.binary.entry(key).or_default().extend(values);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ascii.is_empty() && self.binary.is_empty()
+    }
+
+    /// As the `metadata` of an EndStreamResponse: an array of values per key, with binary values
+    /// base64 encoded.
+    pub(crate) fn to_json(&self) -> Value {
+        let ascii = self.ascii.iter().map(|(key, values)| {
+            let values = values.iter().cloned().map(Value::String).collect();
+            (key.clone(), Value::Array(values))
+        });
+        let binary = self.binary.iter().map(|(key, values)| {
+            let values = values
+                .iter()
+                .map(|value| Value::String(BINARY.encode(value)))
+                .collect();
+            (key.clone(), Value::Array(values))
+        });
+
+        Value::Object(ascii.chain(binary).collect())
+    }
+
+    /// Appends the metadata to response `headers`, with keys prefixed by `prefix`. Protocol headers
+    /// and keys or values that can't be headers fail with `Internal`.
+    pub(crate) fn write_headers(
+        &self,
+        headers: &mut HeaderMap,
+        prefix: &str,
+    ) -> Result<(), RpcError> {
+        let invalid = |key: &str| {
+            RpcError::new(
+                RpcErrorCode::Internal,
+                format!("Invalid response metadata `{}`", key),
+            )
+        };
+        let ascii = self.ascii.iter().flat_map(|(key, values)| {
+            values
+                .iter()
+                .map(move |value| (key, HeaderValue::from_str(value)))
+        });
+        let binary = self.binary.iter().flat_map(|(key, values)| {
+            values
+                .iter()
+                .map(move |value| (key, HeaderValue::from_str(&BINARY.encode(value))))
+        });
+
+        for (key, value) in ascii.chain(binary) {
+            if is_protocol_header(key) {
+                return Err(invalid(key));
+            }
+            let name = HeaderName::from_bytes(format!("{}{}", prefix, key).as_bytes())
+                .map_err(|_| invalid(key))?;
+            headers.append(name, value.map_err(|_| invalid(key))?);
+        }
+
+        Ok(())
+    }
+}
+
+/// Headers the Connect protocol (or HTTP framing) uses, which are not metadata.
+fn is_protocol_header(key: &str) -> bool {
+    key.starts_with("connect-")
+        || matches!(
+            key,
+            "content-type" | "content-length" | "content-encoding" | "accept-encoding" | "te"
+        )
+}
+
+#[async_trait]
+impl<M, S> RpcFromRequestParts<M, S> for RpcMetadata
+where
+    M: Message,
+    S: Send + Sync,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Self::from_headers(&parts.headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers<const N: usize>(pairs: [(&'static str, &'static str); N]) -> HeaderMap {
+        pairs
+            .into_iter()
+            .map(|(key, value)| {
+                (
+                    HeaderName::from_static(key),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_binary_metadata() {
+        let metadata = RpcMetadata::from_headers(&headers([
+            ("x-padded-bin", "AAEC/w=="),
+            ("x-unpadded-bin", "AAEC/w"),
+            ("x-listed-bin", "AQ, Ag=="),
+            ("x-user", "alice"),
+            ("content-type", "application/json"),
+            ("connect-timeout-ms", "1000"),
+        ]))
+        .unwrap();
+
+        assert_eq!(metadata.get_bin("x-padded-bin"), Some(&[0, 1, 2, 255][..]));
+        assert_eq!(
+            metadata.get_bin("x-unpadded-bin"),
+            Some(&[0, 1, 2, 255][..])
+        );
+        assert_eq!(
+            metadata.get_all_bin("x-listed-bin"),
+            [Bytes::from_static(&[1]), Bytes::from_static(&[2])]
+        );
+        assert_eq!(metadata.get("x-user"), Some("alice"));
+        assert_eq!(metadata.get("content-type"), None);
+        assert_eq!(metadata.get("connect-timeout-ms"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_binary_metadata() {
+        for value in ["not base64!", "A", "AA=A", "AAEC_w"] {
+            let error = RpcMetadata::from_headers(&headers([("x-cost-bin", value)])).unwrap_err();
+            assert_eq!(error.code, RpcErrorCode::InvalidArgument, "{value}");
+            assert_eq!(error.message, "Metadata `x-cost-bin` is not valid base64");
+        }
+
+        let mut non_ascii = HeaderMap::new();
+        non_ascii.insert("x-user", HeaderValue::from_bytes(b"caf\xe9").unwrap());
+        let error = RpcMetadata::from_headers(&non_ascii).unwrap_err();
+        assert_eq!(error.code, RpcErrorCode::InvalidArgument);
+    }
+
+    #[test]
+    fn encodes_binary_metadata_unpadded() {
+        let mut metadata = RpcMetadata::new();
+        metadata.append_bin("x-cost-bin", vec![0, 1, 2, 255]);
+        metadata.append("x-user", "alice");
+
+        let mut headers = HeaderMap::new();
+        metadata.write_headers(&mut headers, "trailer-").unwrap();
+        assert_eq!(headers["trailer-x-cost-bin"], "AAEC/w");
+        assert_eq!(headers["trailer-x-user"], "alice");
+
+        assert_eq!(
+            metadata.to_json(),
+            serde_json::json!({ "x-cost-bin": ["AAEC/w"], "x-user": ["alice"] })
+        );
+
+        // What's sent decodes back to the same bytes.
+        let decoded = RpcMetadata::from_headers(&headers).unwrap();
+        assert_eq!(
+            decoded.get_bin("trailer-x-cost-bin"),
+            Some(&[0, 1, 2, 255][..])
+        );
+    }
+}
@@ -0,0 +1,495 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock, Weak},
+    task::{Context, Poll},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    http::{request::Parts, Request},
+    response::Response,
+    BoxError,
+};
+use futures::future::{ready, Either, Ready};
+use serde::Deserialize;
+use tower::{Layer, Service};
+
+use crate::{
+    handler::codec::encode_error_response_for_headers,
+    prelude::{PeerIdentity, RpcError, RpcErrorCode},
+};
+
+/// Access policy, kept outside of code: which principals or scopes may call which methods.
+///
+/// ```json
+/// {
+///   "rules": [
+///     { "principals": ["spiffe://example.org/ns/prod/sa/checkout"], "allow": ["billing.*"] },
+///     { "scopes": ["orders:read"], "allow": ["orders.OrderService/Get*"] },
+///     { "principals": ["*"], "allow": ["grpc.health.v1.Health/*"] }
+///   ]
+/// }
+/// ```
+///
+/// Principals and methods (`package.Service/Method`) are matched as globs, where `*` matches
+/// anything and `?` any one character. Scopes are matched exactly. Anything no rule allows is
+/// denied.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct AclConfig {
+    pub rules: Vec<AclRule>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct AclRule {
+    /// Callers with any of these principals match the rule.
+    #[serde(default)]
+    pub principals: Vec<String>,
+    /// Callers with any of these scopes match the rule.
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// The methods the rule allows.
+    pub allow: Vec<String>,
+}
+
+/// Who's calling, as far as the ACL is concerned.
+#[derive(Clone, Debug, Default)]
+pub struct AclSubject {
+    pub principals: Vec<String>,
+    pub scopes: Vec<String>,
+}
+
+impl AclConfig {
+    pub fn allows(&self, subject: &AclSubject, method: &str) -> bool {
+        self.rules.iter().any(|rule| {
+            let matches_subject = rule.principals.iter().any(|pattern| {
+                subject
+                    .principals
+                    .iter()
+                    .any(|principal| glob_match(pattern, principal))
+            }) || rule
+                .scopes
+                .iter()
+                .any(|scope| subject.scopes.contains(scope));
+
+            matches_subject && rule.allow.iter().any(|pattern| glob_match(pattern, method))
+        })
+    }
+}
+
+/// The current `AclConfig`, swappable at runtime. Clones share it, so the policy can be reloaded
+/// (by hand with `set`, or by `watch_file`) while `AclLayer` enforces it.
+#[derive(Clone, Default)]
+pub struct AclPolicy {
+    inner: Arc<PolicyInner>,
+}
+
+#[derive(Default)]
+struct PolicyInner {
+    config: RwLock<Arc<AclConfig>>,
+    last_error: RwLock<Option<String>>,
+}
+
+impl AclPolicy {
+    pub fn new(config: AclConfig) -> Self {
+        let policy = Self::default();
+        policy.set(config);
+        policy
+    }
+
+    /// Loads the policy from a JSON file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, BoxError> {
+        Ok(Self::new(read_config(path.as_ref())?))
+    }
+
+    /// Loads the policy from a JSON file, then checks it for changes every `interval` on a
+    /// background thread and reloads it. A file that fails to load keeps the previous policy in
+    /// place (see `last_error`). The thread stops once every clone of the policy is dropped.
+    pub fn watch_file(path: impl Into<PathBuf>, interval: Duration) -> Result<Self, BoxError> {
+        let path = path.into();
+        let policy = Self::from_file(&path)?;
+        let weak = Arc::downgrade(&policy.inner);
+        let modified = modified_at(&path);
+
+        thread::Builder::new()
+            .name("axum-connect-acl-watch".to_string())
+            .spawn(move || watch(weak, path, interval, modified))?;
+
+        Ok(policy)
+    }
+
+    pub fn set(&self, config: AclConfig) {
+        *self.inner.config.write().unwrap() = Arc::new(config);
+    }
+
+    pub fn config(&self) -> Arc<AclConfig> {
+        self.inner.config.read().unwrap().clone()
+    }
+
+    /// Why the watched file last failed to reload, cleared by the next successful reload.
+    pub fn last_error(&self) -> Option<String> {
+        self.inner.last_error.read().unwrap().clone()
+    }
+}
+
+fn watch(
+    policy: Weak<PolicyInner>,
+    path: PathBuf,
+    interval: Duration,
+    mut modified: Option<SystemTime>,
+) {
+    loop {
+        thread::sleep(interval);
+        let Some(policy) = policy.upgrade() else {
+            return;
+        };
+
+        let now_modified = modified_at(&path);
+        if now_modified == modified {
+            continue;
+        }
+        modified = now_modified;
+
+        match read_config(&path) {
+            Ok(config) => {
+                *policy.config.write().unwrap() = Arc::new(config);
+                *policy.last_error.write().unwrap() = None;
+            }
+            Err(e) => *policy.last_error.write().unwrap() = Some(e.to_string()),
+        }
+    }
+}
+
+fn read_config(path: &Path) -> Result<AclConfig, BoxError> {
+    Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// `*` matches any run of characters (including none), `?` exactly one.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was, and the text position it's currently matched up to.
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // Let the last `*` swallow one more character and retry from there.
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+type SubjectFn = dyn Fn(&Parts) -> AclSubject + Send + Sync;
+
+/// Enforces an `AclPolicy`. Denied callers get `PermissionDenied`, and callers with neither a
+/// principal nor a scope get `Unauthenticated`.
+///
+/// ```ignore
+/// let policy = AclPolicy::watch_file("/etc/my-service/acl.json", Duration::from_secs(10))?;
+/// let app = Router::new()
+///     .rpc(BillingService::charge(charge))
+///     .layer(AclLayer::new(policy).subject(|parts| AclSubject {
+///         principals: vec![api_key_owner(parts)],
+///         scopes: vec![],
+///     }));
+/// ```
+///
+/// The subject's principals are the caller's `PeerIdentity` names by default (SPIFFE ID and
+/// certificate subject), with no scopes; set your own with `subject`. The layer has to sit inside
+/// of whatever puts what `subject` reads on the request.
+#[derive(Clone)]
+pub struct AclLayer {
+    config: Arc<AclLayerConfig>,
+}
+
+#[derive(Clone)]
+struct AclLayerConfig {
+    policy: AclPolicy,
+    subject: Arc<SubjectFn>,
+}
+
+impl AclLayer {
+    pub fn new(policy: AclPolicy) -> Self {
+        Self {
+            config: Arc::new(AclLayerConfig {
+                policy,
+                subject: Arc::new(|parts| AclSubject {
+                    principals: parts
+                        .extensions
+                        .get::<PeerIdentity>()
+                        .map(|identity| identity.names().map(str::to_string).collect())
+                        .unwrap_or_default(),
+                    scopes: vec![],
+                }),
+            }),
+        }
+    }
+
+    /// How to find the caller's principals and scopes.
+    pub fn subject<F>(mut self, subject: F) -> Self
+    where
+        F: Fn(&Parts) -> AclSubject + Send + Sync + 'static,
+    {
+        self.config_mut().subject = Arc::new(subject);
+        self
+    }
+
+    fn config_mut(&mut self) -> &mut AclLayerConfig {
+        Arc::make_mut(&mut self.config)
+    }
+}
+
+impl<S> Layer<S> for AclLayer {
+    type Service = Acl<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Acl {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+/// The service produced by `AclLayer`.
+#[derive(Clone)]
+pub struct Acl<S> {
+    inner: S,
+    config: Arc<AclLayerConfig>,
+}
+
+impl<S, B> Service<Request<B>> for Acl<S>
+where
+    S: Service<Request<B>, Response = Response>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Either<Ready<Result<Response, S::Error>>, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let (parts, body) = req.into_parts();
+
+        // RPC paths end in `/package.Service/Method`, possibly under a prefix (like a tenant).
+        let mut segments = parts.uri.path().rsplit('/');
+        let method = match (segments.next(), segments.next()) {
+            (Some(method), Some(service)) => format!("{}/{}", service, method),
+            _ => parts.uri.path().to_string(),
+        };
+
+        let subject = (self.config.subject)(&parts);
+        if self.config.policy.config().allows(&subject, &method) {
+            return Either::Right(self.inner.call(Request::from_parts(parts, body)));
+        }
+
+        let error = match subject.principals.first() {
+            Some(principal) => RpcError::new(
+                RpcErrorCode::PermissionDenied,
+                format!("{} may not call {}", principal, method),
+            ),
+            None if !subject.scopes.is_empty() => RpcError::new(
+                RpcErrorCode::PermissionDenied,
+                format!("Granted scopes don't allow calling {}", method),
+            ),
+            None => RpcError::new(
+                RpcErrorCode::Unauthenticated,
+                "No credentials to authorize the call with".to_string(),
+            ),
+        };
+
+        Either::Left(ready(Ok(encode_error_response_for_headers(
+            &error,
+            &parts.headers,
+        ))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use axum::{
+        body::{to_bytes, Body},
+        http::StatusCode,
+    };
+    use tower::{service_fn, ServiceExt};
+
+    use super::*;
+
+    #[test]
+    fn matches_globs() {
+        let cases = [
+            ("*", "", true),
+            ("*", "billing.BillingService/Charge", true),
+            ("billing.*", "billing.BillingService/Charge", true),
+            ("billing.*", "billingx.BillingService/Charge", false),
+            (
+                "orders.OrderService/Get*",
+                "orders.OrderService/GetOrder",
+                true,
+            ),
+            (
+                "orders.OrderService/Get*",
+                "orders.OrderService/ListOrders",
+                false,
+            ),
+            ("*/Get*", "orders.OrderService/GetOrder", true),
+            ("*Service/*Order", "orders.OrderService/GetOrder", true),
+            ("*Service/*Order", "orders.OrderService/GetOrders", false),
+            ("a*b*c", "abbbc", true),
+            ("a*b*c", "acb", false),
+            ("v?", "v1", true),
+            ("v?", "v", false),
+            ("v?", "v10", false),
+            (
+                "spiffe://example.org/*",
+                "spiffe://example.org/ns/prod",
+                true,
+            ),
+            ("exact", "exact", true),
+            ("exact", "exactly", false),
+            ("", "", true),
+            ("", "x", false),
+            ("**", "x", true),
+            ("é?", "éa", true),
+        ];
+        for (pattern, text, matches) in cases {
+            assert_eq!(glob_match(pattern, text), matches, "{} ~ {}", pattern, text);
+        }
+    }
+
+    fn policy() -> AclPolicy {
+        AclPolicy::new(
+            serde_json::from_str(
+                r#"{
+                    "rules": [
+                        { "principals": ["checkout"], "allow": ["billing.*"] },
+                        { "scopes": ["orders:read"], "allow": ["orders.OrderService/Get*"] },
+                        { "principals": ["*"], "allow": ["grpc.health.v1.Health/*"] }
+                    ]
+                }"#,
+            )
+            .unwrap(),
+        )
+    }
+
+    fn subject(principals: &[&str], scopes: &[&str]) -> AclSubject {
+        AclSubject {
+            principals: principals.iter().map(|p| p.to_string()).collect(),
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn allows_what_a_rule_allows() {
+        let config = policy().config();
+
+        assert!(config.allows(&subject(&["checkout"], &[]), "billing.Billing/Charge"));
+        assert!(!config.allows(&subject(&["checkout"], &[]), "orders.OrderService/GetOrder"));
+        assert!(config.allows(
+            &subject(&[], &["orders:read"]),
+            "orders.OrderService/GetOrder"
+        ));
+        assert!(!config.allows(
+            &subject(&[], &["orders:read"]),
+            "orders.OrderService/Delete"
+        ));
+        assert!(!config.allows(&subject(&[], &["orders"]), "orders.OrderService/GetOrder"));
+        assert!(config.allows(&subject(&["anyone"], &[]), "grpc.health.v1.Health/Check"));
+        // `*` matches any principal, but there has to be one.
+        assert!(!config.allows(&subject(&[], &[]), "grpc.health.v1.Health/Check"));
+        assert!(
+            !AclConfig::default().allows(&subject(&["checkout"], &[]), "billing.Billing/Charge")
+        );
+    }
+
+    async fn call(layer: &AclLayer, path: &str, principal: Option<&str>) -> (StatusCode, String) {
+        let service = layer.layer(service_fn(|_: Request<Body>| async {
+            Ok::<_, Infallible>(Response::new(Body::from("{}")))
+        }));
+        let mut req = Request::post(path).header("content-type", "application/json");
+        if let Some(principal) = principal {
+            req = req.header("x-principal", principal);
+        }
+        let res = service
+            .oneshot(req.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = res.status();
+        let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn layer(policy: AclPolicy) -> AclLayer {
+        AclLayer::new(policy).subject(|parts| AclSubject {
+            principals: parts
+                .headers
+                .get("x-principal")
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+                .into_iter()
+                .collect(),
+            scopes: vec![],
+        })
+    }
+
+    #[tokio::test]
+    async fn rejects_denied_and_anonymous_callers() {
+        let layer = layer(policy());
+
+        let (status, _) = call(&layer, "/billing.Billing/Charge", Some("checkout")).await;
+        assert_eq!(status, StatusCode::OK);
+        // Under a prefix.
+        let (status, _) = call(&layer, "/api/billing.Billing/Charge", Some("checkout")).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, body) = call(&layer, "/billing.Billing/Charge", Some("mallory")).await;
+        assert!(body.contains("permission_denied"), "{}", body);
+        let (_, body) = call(&layer, "/billing.Billing/Charge", None).await;
+        assert!(body.contains("unauthenticated"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn enforces_the_current_policy() {
+        let policy = policy();
+        let layer = layer(policy.clone());
+
+        policy.set(AclConfig::default());
+        let (_, body) = call(&layer, "/billing.Billing/Charge", Some("checkout")).await;
+        assert!(body.contains("permission_denied"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn can_be_configured_after_being_cloned() {
+        let layer = AclLayer::new(policy());
+        let _clone = layer.clone();
+        let layer = layer.subject(|_| subject(&["checkout"], &[]));
+
+        let (status, _) = call(&layer, "/billing.Billing/Charge", None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
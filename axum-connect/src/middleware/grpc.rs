@@ -0,0 +1,829 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use async_stream::stream;
+use axum::{
+    body::{Body, Bytes},
+    extract::Request,
+    http::{header, request, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::Response,
+    BoxError,
+};
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use futures::{
+    future::{ready, BoxFuture},
+    stream as futures_stream,
+};
+use http_body::Frame;
+use http_body_util::BodyExt;
+use prost::Message;
+use serde::Deserialize;
+use tower::{Layer, Service};
+
+use crate::{
+    compression::DEFAULT_MAX_DECOMPRESSED_SIZE,
+    config::RpcServiceConfig,
+    descriptor::{MethodDescriptor, ServiceDescriptor},
+    error::{RpcError, RpcErrorCode, RpcErrorDetail},
+    handler::body::{
+        any_body, envelope, frame_body, EnvelopeReader, FLAG_COMPRESSED, FLAG_END_STREAM,
+    },
+    instrument::CallProtocol,
+    operations::Status,
+    router::RpcRouterOptions,
+};
+
+/// Flags the last message of a gRPC-Web response, which holds the trailers.
+const FLAG_WEB_TRAILERS: u8 = 0x80;
+
+/// Serves gRPC clients (tonic, grpcurl, grpc-go) on the Connect routes of the given services, with
+/// the same handlers. Requests with an `application/grpc` content type (`+proto`, `+json` or any
+/// other registered codec) are turned into the equivalent Connect requests, and the responses back
+/// into gRPC ones, with the status in `grpc-status` / `grpc-message` trailers. Everything else
+/// passes through untouched.
+///
+/// ```ignore
+/// let app = Router::new()
+///     .rpc(HelloWorldService::say_hello(say_hello))
+///     .layer(GrpcLayer::new([&HelloWorldService::DESCRIPTOR]));
+/// ```
+///
+/// gRPC needs HTTP/2 for its trailers, which `axum::serve` speaks, over TLS or to plaintext clients
+/// with prior knowledge (like `grpcurl -plaintext`). Put this layer outside of the others, which
+/// then only ever see Connect calls. Like Connect, bidi streaming methods aren't served.
+///
+/// How a call is translated depends on whether its method streams, which only the descriptors
+/// tell, so gRPC calls of methods of services that aren't listed get `Unimplemented`.
+///
+/// This is the layer `RpcRouterOptions` builds, sniffing each request's protocol: to also serve
+/// gRPC-Web (binary, not `grpc-web-text`), or to turn Connect off, build it from those instead.
+#[derive(Clone)]
+pub struct GrpcLayer {
+    methods: Arc<HashMap<String, &'static MethodDescriptor>>,
+    options: RpcRouterOptions,
+}
+
+impl GrpcLayer {
+    /// Serves Connect and gRPC.
+    pub fn new<I>(services: I) -> Self
+    where
+        I: IntoIterator<Item = &'static ServiceDescriptor>,
+    {
+        RpcRouterOptions::new().grpc(true).layer(services)
+    }
+
+    pub(crate) fn with_options<I>(options: RpcRouterOptions, services: I) -> Self
+    where
+        I: IntoIterator<Item = &'static ServiceDescriptor>,
+    {
+        let methods = services
+            .into_iter()
+            .flat_map(|service| service.methods)
+            .map(|method| (format!("{}/{}", method.service, method.name), method))
+            .collect();
+
+        Self {
+            methods: Arc::new(methods),
+            options,
+        }
+    }
+}
+
+impl<S> Layer<S> for GrpcLayer {
+    type Service = Grpc<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Grpc {
+            inner,
+            methods: self.methods.clone(),
+            options: self.options,
+        }
+    }
+}
+
+/// The service produced by `GrpcLayer`.
+#[derive(Clone)]
+pub struct Grpc<S> {
+    inner: S,
+    methods: Arc<HashMap<String, &'static MethodDescriptor>>,
+    options: RpcRouterOptions,
+}
+
+impl<S, B> Service<Request<B>> for Grpc<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Send,
+    B: http_body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // The clone might not be ready, keep the one that was polled.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let (parts, body) = req.into_parts();
+        let body = any_body(body);
+        let protocol = Protocol::of(&parts.headers);
+        let enabled = match &protocol {
+            Protocol::Connect => self.options.connect,
+            Protocol::Grpc { web: false, .. } => self.options.grpc,
+            Protocol::Grpc { web: true, .. } => self.options.grpc_web,
+        };
+        if !enabled {
+            return Box::pin(ready(Ok(unsupported_protocol(&self.options))));
+        }
+        let Protocol::Grpc { codec, web } = protocol else {
+            return Box::pin(inner.call(Request::from_parts(parts, body)));
+        };
+
+        // RPC paths end in `/package.Service/Method`, possibly under a prefix (like a tenant).
+        let mut segments = parts.uri.path().rsplit('/');
+        let method = match (segments.next(), segments.next()) {
+            (Some(method), Some(service)) => self
+                .methods
+                .get(&format!("{}/{}", service, method))
+                .copied(),
+            _ => None,
+        };
+        let content_type = parts.headers[header::CONTENT_TYPE].clone();
+        let Some(method) = method else {
+            let e = RpcError::new(
+                RpcErrorCode::Unimplemented,
+                format!("Unknown method {}", parts.uri.path()),
+            );
+            return Box::pin(ready(Ok(grpc_error_response(
+                &e,
+                HeaderMap::new(),
+                content_type,
+            ))));
+        };
+
+        Box::pin(async move {
+            let streaming = method.kind.is_streaming();
+            let mut req = match connect_request(parts, body, &codec, streaming).await {
+                Ok(req) => req,
+                Err(e) => return Ok(grpc_error_response(&e, HeaderMap::new(), content_type)),
+            };
+            let call_protocol = if web { "grpc-web" } else { "grpc" };
+            req.extensions_mut().insert(CallProtocol(call_protocol));
+
+            let res = inner.call(req).await?;
+            Ok(grpc_response(res, content_type, web).await)
+        })
+    }
+}
+
+/// The protocol a request is made over, told by its content type.
+enum Protocol {
+    Connect,
+    /// gRPC or gRPC-Web, with the codec name: `application/grpc` alone is proto.
+    Grpc {
+        codec: String,
+        web: bool,
+    },
+}
+
+impl Protocol {
+    fn of(headers: &HeaderMap) -> Self {
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        let media_type = content_type.split(';').next().unwrap_or_default();
+        let media_type = media_type.trim().to_lowercase();
+
+        let (codec, web) = if let Some(codec) = media_type.strip_prefix("application/grpc-web") {
+            (codec, true)
+        } else if let Some(codec) = media_type.strip_prefix("application/grpc") {
+            (codec, false)
+        } else {
+            return Protocol::Connect;
+        };
+        // Anything else, like `application/grpc-web-text`, is left to fail as Connect does.
+        let codec = match codec {
+            "" => "proto",
+            codec => match codec.strip_prefix('+') {
+                Some(codec) => codec,
+                None => return Protocol::Connect,
+            },
+        };
+        Protocol::Grpc {
+            codec: codec.to_string(),
+            web,
+        }
+    }
+}
+
+/// Whether a request is a gRPC call, not gRPC-Web or Connect.
+#[cfg(feature = "tonic")]
+pub(crate) fn is_grpc(headers: &HeaderMap) -> bool {
+    matches!(Protocol::of(headers), Protocol::Grpc { web: false, .. })
+}
+
+/// The response to a request over a protocol that's turned off: a `415 Unsupported Media Type`
+/// listing the content types that would do, as connect-go answers.
+fn unsupported_protocol(options: &RpcRouterOptions) -> Response {
+    let mut accept = vec![];
+    if options.connect {
+        accept.extend([
+            "application/proto",
+            "application/json",
+            "application/connect+proto",
+            "application/connect+json",
+        ]);
+    }
+    if options.grpc {
+        accept.extend([
+            "application/grpc",
+            "application/grpc+proto",
+            "application/grpc+json",
+        ]);
+    }
+    if options.grpc_web {
+        accept.extend([
+            "application/grpc-web",
+            "application/grpc-web+proto",
+            "application/grpc-web+json",
+        ]);
+    }
+
+    let mut res = Response::new(Body::empty());
+    *res.status_mut() = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+    if let Ok(accept) = HeaderValue::from_str(&accept.join(", ")) {
+        res.headers_mut().insert("accept-post", accept);
+    }
+    res
+}
+
+/// The Connect request for a gRPC one. Streaming calls are framed alike and only need their
+/// headers renamed; a unary call's one message is taken out of its envelope.
+async fn connect_request(
+    mut parts: request::Parts,
+    body: Body,
+    codec: &str,
+    streaming: bool,
+) -> Result<Request, RpcError> {
+    let headers = &mut parts.headers;
+    let encoding = headers.remove("grpc-encoding");
+    let accept_encoding = headers.remove("grpc-accept-encoding");
+    if let Some(timeout) = headers.remove("grpc-timeout") {
+        let timeout = grpc_timeout_ms(&timeout).ok_or_else(|| {
+            RpcError::new(
+                RpcErrorCode::InvalidArgument,
+                format!("Invalid grpc-timeout header: {:?}", timeout),
+            )
+        })?;
+        headers.insert("connect-timeout-ms", timeout.into());
+    }
+    headers.remove(header::TE);
+    headers.remove(header::CONTENT_LENGTH);
+    headers.insert("connect-protocol-version", HeaderValue::from_static("1"));
+
+    let content_type = match streaming {
+        true => format!("application/connect+{}", codec),
+        false => format!("application/{}", codec),
+    };
+    let content_type = HeaderValue::from_str(&content_type).map_err(|_| {
+        RpcError::new(
+            RpcErrorCode::InvalidArgument,
+            format!("Invalid gRPC codec: {}", codec),
+        )
+    })?;
+    headers.insert(header::CONTENT_TYPE, content_type);
+
+    if streaming {
+        if let Some(encoding) = encoding {
+            headers.insert("connect-content-encoding", encoding);
+        }
+        if let Some(accept_encoding) = accept_encoding {
+            headers.insert("connect-accept-encoding", accept_encoding);
+        }
+        return Ok(Request::from_parts(parts, body));
+    }
+
+    if let Some(accept_encoding) = accept_encoding {
+        headers.insert(header::ACCEPT_ENCODING, accept_encoding);
+    }
+
+    let max = parts
+        .extensions
+        .get::<RpcServiceConfig>()
+        .and_then(|config| config.max_request_message_size)
+        .unwrap_or(DEFAULT_MAX_DECOMPRESSED_SIZE);
+    let mut reader = EnvelopeReader::new(body).max_message_size(Some(max));
+    let message = reader.next().await?.ok_or_else(|| {
+        RpcError::new(
+            RpcErrorCode::InvalidArgument,
+            "Unary request has no message".to_string(),
+        )
+    })?;
+    if reader.next().await?.is_some() {
+        return Err(RpcError::new(
+            RpcErrorCode::InvalidArgument,
+            "Unary request has more than one message".to_string(),
+        ));
+    }
+
+    if message.flags & FLAG_COMPRESSED != 0 {
+        let encoding = encoding.ok_or_else(|| {
+            RpcError::new(
+                RpcErrorCode::InvalidArgument,
+                "Compressed message without a grpc-encoding header".to_string(),
+            )
+        })?;
+        parts.headers.insert(header::CONTENT_ENCODING, encoding);
+    }
+
+    Ok(Request::from_parts(parts, Body::from(message.payload)))
+}
+
+/// A `grpc-timeout` (like `250m` or `5S`) in whole milliseconds, rounded up.
+fn grpc_timeout_ms(value: &HeaderValue) -> Option<u64> {
+    let value = value.to_str().ok()?;
+    let (amount, unit) = value.split_at(value.len().checked_sub(1)?);
+    if amount.is_empty() || amount.len() > 8 {
+        return None;
+    }
+    let amount: u64 = amount.parse().ok()?;
+    let nanos_per_unit: u64 = match unit {
+        "H" => 3_600_000_000_000,
+        "M" => 60_000_000_000,
+        "S" => 1_000_000_000,
+        "m" => 1_000_000,
+        "u" => 1_000,
+        "n" => 1,
+        _ => return None,
+    };
+    Some((amount as u128 * nanos_per_unit as u128).div_ceil(1_000_000) as u64)
+}
+
+/// The gRPC (or gRPC-Web) response for a Connect one, told apart by its content type.
+async fn grpc_response(res: Response, content_type: HeaderValue, web: bool) -> Response {
+    let streaming = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/connect+"));
+
+    let (mut parts, body) = res.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    if streaming {
+        if let Some(encoding) = parts.headers.remove("connect-content-encoding") {
+            parts.headers.insert("grpc-encoding", encoding);
+        }
+        parts.headers.insert(header::CONTENT_TYPE, content_type);
+        return Response::from_parts(parts, grpc_stream_body(body, web));
+    }
+
+    let bytes = match body.collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(e) => {
+            let e = RpcError::new(RpcErrorCode::Internal, e.to_string());
+            return grpc_error_response(&e, HeaderMap::new(), content_type);
+        }
+    };
+    parts.headers.remove(header::CONTENT_TYPE);
+
+    if parts.status != StatusCode::OK {
+        let e = match serde_json::from_slice::<WireError>(&bytes) {
+            Ok(e) => e.into_error(),
+            // Not from a handler, like the router's 404.
+            Err(_) => RpcError::new(code_for_http_status(parts.status), parts.status.to_string()),
+        };
+        return grpc_error_response(&e, parts.headers, content_type);
+    }
+
+    // A compressed unary body is one compressed message.
+    let flags = match parts.headers.remove(header::CONTENT_ENCODING) {
+        Some(encoding) => {
+            parts.headers.insert("grpc-encoding", encoding);
+            FLAG_COMPRESSED
+        }
+        None => 0,
+    };
+    let message = envelope(flags, |buf| {
+        buf.extend_from_slice(&bytes);
+        Ok::<_, Infallible>(())
+    });
+
+    // Unary trailing metadata came as `trailer-` prefixed headers, here it's real trailers.
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", HeaderValue::from_static("0"));
+    let headers = std::mem::take(&mut parts.headers);
+    for (name, value) in headers.iter() {
+        match name.as_str().strip_prefix("trailer-") {
+            Some(key) => {
+                if let Ok(key) = HeaderName::from_bytes(key.as_bytes()) {
+                    trailers.append(key, value.clone());
+                }
+            }
+            None => {
+                parts.headers.append(name.clone(), value.clone());
+            }
+        }
+    }
+    parts.headers.insert(header::CONTENT_TYPE, content_type);
+
+    let frames = [
+        Frame::data(message.unwrap_or_else(|e| match e {})),
+        trailers_frame(trailers, web),
+    ];
+    Response::from_parts(parts, frame_body(futures_stream::iter(frames)))
+}
+
+/// A Connect stream's messages passed on as they are, as gRPC frames them alike, with its
+/// EndStreamResponse turned into trailers.
+fn grpc_stream_body(body: Body, web: bool) -> Body {
+    frame_body(stream! {
+        let mut reader = EnvelopeReader::new(body);
+        loop {
+            match reader.next().await {
+                Ok(Some(message)) if message.flags & FLAG_END_STREAM != 0 => {
+                    let end = serde_json::from_slice::<WireEndStream>(&message.payload);
+                    let trailers = match end {
+                        Ok(end) => end.into_trailers(),
+                        Err(e) => grpc_status(&RpcError::new(RpcErrorCode::Internal, e.to_string())),
+                    };
+                    yield trailers_frame(trailers, web);
+                    return;
+                }
+                Ok(Some(message)) => {
+                    let message = envelope(message.flags, |buf| {
+                        buf.extend_from_slice(&message.payload);
+                        Ok::<_, Infallible>(())
+                    });
+                    yield Frame::data(message.unwrap_or_else(|e| match e {}));
+                }
+                Ok(None) => {
+                    let e = RpcError::new(RpcErrorCode::Internal, "Stream ended without a status".to_string());
+                    yield trailers_frame(grpc_status(&e), web);
+                    return;
+                }
+                Err(e) => {
+                    yield trailers_frame(grpc_status(&e), web);
+                    return;
+                }
+            }
+        }
+    })
+}
+
+/// The frame ending a response: HTTP trailers for gRPC, and for gRPC-Web (as browsers can't read
+/// trailers) a last message flagged as such, holding them as HTTP/1 header lines.
+fn trailers_frame(trailers: HeaderMap, web: bool) -> Frame<Bytes> {
+    if !web {
+        return Frame::trailers(trailers);
+    }
+    let message = envelope(FLAG_WEB_TRAILERS, |buf| {
+        for (name, value) in &trailers {
+            buf.extend_from_slice(name.as_str().as_bytes());
+            buf.extend_from_slice(b": ");
+            buf.extend_from_slice(value.as_bytes());
+            buf.extend_from_slice(b"\r\n");
+        }
+        Ok::<_, Infallible>(())
+    });
+    Frame::data(message.unwrap_or_else(|e| match e {}))
+}
+
+/// A Trailers-Only gRPC response: the status and any metadata in the headers, and no body.
+fn grpc_error_response(
+    e: &RpcError,
+    mut headers: HeaderMap,
+    content_type: HeaderValue,
+) -> Response {
+    headers.extend(grpc_status(e));
+    let _ = e.metadata().write_headers(&mut headers, "");
+    headers.insert(header::CONTENT_TYPE, content_type);
+
+    let mut res = Response::new(Body::empty());
+    *res.headers_mut() = headers;
+    res
+}
+
+/// The `grpc-status`, `grpc-message` and `grpc-status-details-bin` of an error.
+fn grpc_status(e: &RpcError) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("grpc-status", e.code.as_i32().into());
+    if let Ok(message) = HeaderValue::from_str(&percent_encode(&e.message)) {
+        headers.insert("grpc-message", message);
+    }
+    if !e.details.is_empty() {
+        let details = STANDARD_NO_PAD.encode(Status::from(e).encode_to_vec());
+        if let Ok(details) = HeaderValue::from_str(&details) {
+            headers.insert("grpc-status-details-bin", details);
+        }
+    }
+    headers
+}
+
+/// Percent-encodes a `grpc-message`: everything but printable ASCII (and `%` itself).
+fn percent_encode(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+    for byte in message.bytes() {
+        match byte {
+            b' '..=b'~' if byte != b'%' => encoded.push(byte as char),
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// The code of an error response without a Connect error body, see:
+/// https://connectrpc.com/docs/protocol/#http-to-error-code
+pub(crate) fn code_for_http_status(status: StatusCode) -> RpcErrorCode {
+    match status {
+        StatusCode::BAD_REQUEST => RpcErrorCode::Internal,
+        StatusCode::UNAUTHORIZED => RpcErrorCode::Unauthenticated,
+        StatusCode::FORBIDDEN => RpcErrorCode::PermissionDenied,
+        StatusCode::NOT_FOUND => RpcErrorCode::Unimplemented,
+        StatusCode::TOO_MANY_REQUESTS
+        | StatusCode::BAD_GATEWAY
+        | StatusCode::SERVICE_UNAVAILABLE
+        | StatusCode::GATEWAY_TIMEOUT => RpcErrorCode::Unavailable,
+        _ => RpcErrorCode::Unknown,
+    }
+}
+
+/// A Connect error as it's written on the wire.
+#[derive(Deserialize)]
+pub(crate) struct WireError {
+    code: RpcErrorCode,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    details: Vec<RpcErrorDetail>,
+}
+
+impl WireError {
+    pub(crate) fn into_error(self) -> RpcError {
+        let mut e = RpcError::new(self.code, self.message);
+        e.details = self.details;
+        e
+    }
+}
+
+/// A Connect EndStreamResponse.
+#[derive(Deserialize)]
+pub(crate) struct WireEndStream {
+    pub error: Option<WireError>,
+    #[serde(default)]
+    pub metadata: HashMap<String, Vec<String>>,
+}
+
+impl WireEndStream {
+    fn into_trailers(self) -> HeaderMap {
+        let mut trailers = match self.error {
+            Some(e) => grpc_status(&e.into_error()),
+            None => HeaderMap::from_iter([(
+                HeaderName::from_static("grpc-status"),
+                HeaderValue::from_static("0"),
+            )]),
+        };
+        // Binary values are base64 in both protocols.
+        for (key, values) in self.metadata {
+            let Ok(key) = HeaderName::from_bytes(key.as_bytes()) else {
+                continue;
+            };
+            for value in values {
+                if let Ok(value) = HeaderValue::from_str(&value) {
+                    trailers.append(key.clone(), value);
+                }
+            }
+        }
+        trailers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use http_body_util::{BodyExt, Collected};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        health::{Health, HealthCheckRequest, HealthCheckResponse, ServingStatus},
+        router::RpcRouterExt,
+    };
+
+    fn app() -> Router {
+        Router::new().rpc(Health::new().routes()).layer(
+            RpcRouterOptions::new()
+                .grpc(true)
+                .grpc_web(true)
+                .layer([&Health::DESCRIPTOR]),
+        )
+    }
+
+    fn enveloped(flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut envelope = vec![flags];
+        envelope.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        envelope.extend_from_slice(payload);
+        envelope
+    }
+
+    fn check(service: &str) -> Vec<u8> {
+        let request = HealthCheckRequest {
+            service: service.to_string(),
+        };
+        enveloped(0, &request.encode_to_vec())
+    }
+
+    async fn call(
+        path: &str,
+        content_type: &str,
+        body: Vec<u8>,
+    ) -> (axum::http::response::Parts, Collected<Bytes>) {
+        let req = Request::post(path)
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::TE, "trailers")
+            .body(Body::from(body))
+            .unwrap();
+        let (parts, body) = app().oneshot(req).await.unwrap().into_parts();
+        (parts, body.collect().await.unwrap())
+    }
+
+    /// The payloads of the enveloped messages in `body`, with their flags.
+    fn messages(mut body: Bytes) -> Vec<(u8, Bytes)> {
+        let mut messages = vec![];
+        while !body.is_empty() {
+            let flags = body[0];
+            let len = u32::from_be_bytes(body[1..5].try_into().unwrap()) as usize;
+            messages.push((flags, body.slice(5..5 + len)));
+            body = body.slice(5 + len..);
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn serves_unary_calls() {
+        let (parts, body) = call(
+            "/grpc.health.v1.Health/Check",
+            "application/grpc",
+            check(""),
+        )
+        .await;
+        assert_eq!(parts.status, StatusCode::OK);
+        assert_eq!(parts.headers[header::CONTENT_TYPE], "application/grpc");
+        assert_eq!(body.trailers().unwrap()["grpc-status"], "0");
+
+        let messages = messages(body.to_bytes());
+        assert_eq!(messages.len(), 1);
+        let res = HealthCheckResponse::decode(messages[0].1.clone()).unwrap();
+        assert_eq!(res.status(), ServingStatus::Serving);
+    }
+
+    #[tokio::test]
+    async fn serves_unary_calls_in_other_codecs() {
+        let body = enveloped(0, br#"{"service":""}"#);
+        let (parts, body) = call(
+            "/grpc.health.v1.Health/Check",
+            "application/grpc+json",
+            body,
+        )
+        .await;
+        assert_eq!(parts.headers[header::CONTENT_TYPE], "application/grpc+json");
+        assert_eq!(body.trailers().unwrap()["grpc-status"], "0");
+        assert_eq!(messages(body.to_bytes())[0].1, r#"{"status":"SERVING"}"#);
+    }
+
+    #[tokio::test]
+    async fn answers_unary_errors_trailers_only() {
+        let (parts, body) = call(
+            "/grpc.health.v1.Health/Check",
+            "application/grpc",
+            check("unknown"),
+        )
+        .await;
+        assert_eq!(parts.status, StatusCode::OK);
+        assert_eq!(parts.headers["grpc-status"], "5");
+        assert_eq!(parts.headers["grpc-message"], "Unknown service 'unknown'");
+        assert!(body.to_bytes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn serves_server_streams() {
+        let req = Request::post("/grpc.health.v1.Health/Watch")
+            .header(header::CONTENT_TYPE, "application/grpc")
+            .body(Body::from(check("")))
+            .unwrap();
+        let res = app().oneshot(req).await.unwrap();
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "application/grpc");
+
+        // Watching goes on until the client leaves, only the first status is read.
+        let mut body = res.into_body();
+        let frame = body.frame().await.unwrap().unwrap().into_data().unwrap();
+        let message = &messages(frame)[0];
+        assert_eq!(message.0, 0);
+        let res = HealthCheckResponse::decode(message.1.clone()).unwrap();
+        assert_eq!(res.status(), ServingStatus::Serving);
+    }
+
+    #[tokio::test]
+    async fn ends_failed_streams_with_the_status_in_trailers() {
+        let body = enveloped(0, b"\xff");
+        let (_, body) = call("/grpc.health.v1.Health/Watch", "application/grpc", body).await;
+        assert_eq!(body.trailers().unwrap()["grpc-status"], "3");
+        assert!(body.to_bytes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_methods_it_has_no_descriptor_of() {
+        for path in ["/grpc.health.v1.Health/Nope", "/other.v1.Other/Watch"] {
+            let (parts, _) = call(path, "application/grpc", check("")).await;
+            assert_eq!(parts.headers["grpc-status"], "12", "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_grpc_web_with_trailers_in_the_body() {
+        let (parts, body) = call(
+            "/grpc.health.v1.Health/Check",
+            "application/grpc-web+proto",
+            check(""),
+        )
+        .await;
+        assert_eq!(
+            parts.headers[header::CONTENT_TYPE],
+            "application/grpc-web+proto"
+        );
+        assert!(body.trailers().is_none());
+
+        let messages = messages(body.to_bytes());
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].0, 0);
+        assert_eq!(messages[1].0, FLAG_WEB_TRAILERS);
+        assert_eq!(messages[1].1, "grpc-status: 0\r\n");
+    }
+
+    #[tokio::test]
+    async fn passes_connect_calls_through() {
+        let (parts, body) = call(
+            "/grpc.health.v1.Health/Check",
+            "application/json",
+            br#"{"service":""}"#.to_vec(),
+        )
+        .await;
+        assert_eq!(parts.status, StatusCode::OK);
+        assert_eq!(body.to_bytes(), r#"{"status":"SERVING"}"#);
+    }
+
+    #[tokio::test]
+    async fn rejects_protocols_that_are_off() {
+        let app = Router::new()
+            .rpc(Health::new().routes())
+            .layer(RpcRouterOptions::new().layer([&Health::DESCRIPTOR]));
+        let req = Request::post("/grpc.health.v1.Health/Check")
+            .header(header::CONTENT_TYPE, "application/grpc")
+            .body(Body::from(check("")))
+            .unwrap();
+
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(!res.headers()["accept-post"]
+            .to_str()
+            .unwrap()
+            .contains("grpc"));
+    }
+
+    #[test]
+    fn converts_grpc_timeouts() {
+        let ms = |value: &'static str| grpc_timeout_ms(&HeaderValue::from_static(value));
+        assert_eq!(ms("1H"), Some(3_600_000));
+        assert_eq!(ms("2M"), Some(120_000));
+        assert_eq!(ms("5S"), Some(5_000));
+        assert_eq!(ms("250m"), Some(250));
+        assert_eq!(ms("1u"), Some(1));
+        assert_eq!(ms("1500000n"), Some(2));
+        assert_eq!(ms("99999999S"), Some(99_999_999_000));
+        assert_eq!(ms("100000000S"), None);
+        assert_eq!(ms("S"), None);
+        assert_eq!(ms("5"), None);
+        assert_eq!(ms("5s"), None);
+        assert_eq!(ms(""), None);
+    }
+
+    #[test]
+    fn percent_encodes_grpc_messages() {
+        assert_eq!(percent_encode("plain text"), "plain text");
+        assert_eq!(percent_encode("100%"), "100%25");
+        assert_eq!(percent_encode("line\nbreak"), "line%0Abreak");
+        assert_eq!(percent_encode("héllo"), "h%C3%A9llo");
+    }
+}
@@ -0,0 +1,337 @@
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::Body,
+    http::{HeaderMap, HeaderName, Request},
+    response::Response,
+    BoxError,
+};
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tower::{Layer, Service};
+
+use crate::{
+    handler::{body::buffer_body, codec::encode_error_response_for_headers},
+    prelude::{RpcError, RpcErrorCode},
+};
+
+/// Verifies an HMAC-SHA256 request signature, for webhook-style and B2B callers that share a secret
+/// rather than holding user credentials.
+///
+/// The signed message is the Unix timestamp (seconds), the request path (`/package.Service/Method`)
+/// and the raw request body, joined by newlines:
+///
+/// ```text
+/// {timestamp}\n{path}\n{body}
+/// ```
+///
+/// The caller sends the timestamp and the hex encoded signature (optionally prefixed with
+/// `sha256=`) in the `x-signature-timestamp` and `x-signature` headers. Requests with a missing or
+/// bad signature, or with a timestamp outside of the replay window (5 minutes by default, either
+/// direction), are rejected with `Unauthenticated` before they reach the handler.
+///
+/// ```ignore
+/// let app = Router::new()
+///     .rpc(WebhookService::deliver(deliver))
+///     .layer(HmacVerifyLayer::new(secret).replay_window(Duration::from_secs(60)));
+/// ```
+///
+/// The timestamp window is the only replay protection: a captured request can be sent again, as is,
+/// until its timestamp falls out of the window. Keep the window short, and make the handlers
+/// idempotent (say, by the ID of the delivered event, or with `IdempotencyLayer`) if replays within
+/// it matter.
+///
+/// The whole request body is buffered to verify it, within axum's `DefaultBodyLimit` (larger bodies
+/// fail with `ResourceExhausted`), so this only makes sense for unary RPCs.
+#[derive(Clone)]
+pub struct HmacVerifyLayer {
+    config: Arc<HmacConfig>,
+}
+
+#[derive(Clone)]
+struct HmacConfig {
+    secret: Vec<u8>,
+    signature_header: HeaderName,
+    timestamp_header: HeaderName,
+    replay_window: Duration,
+}
+
+impl HmacVerifyLayer {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            config: Arc::new(HmacConfig {
+                secret: secret.into(),
+                signature_header: HeaderName::from_static("x-signature"),
+                timestamp_header: HeaderName::from_static("x-signature-timestamp"),
+                replay_window: Duration::from_secs(5 * 60),
+            }),
+        }
+    }
+
+    /// The header carrying the signature, `x-signature` by default.
+    pub fn signature_header(mut self, name: HeaderName) -> Self {
+        self.config_mut().signature_header = name;
+        self
+    }
+
+    /// The header carrying the timestamp, `x-signature-timestamp` by default.
+    pub fn timestamp_header(mut self, name: HeaderName) -> Self {
+        self.config_mut().timestamp_header = name;
+        self
+    }
+
+    /// How far the signed timestamp may be from the server clock, in either direction.
+    pub fn replay_window(mut self, window: Duration) -> Self {
+        self.config_mut().replay_window = window;
+        self
+    }
+
+    fn config_mut(&mut self) -> &mut HmacConfig {
+        Arc::make_mut(&mut self.config)
+    }
+}
+
+impl<S> Layer<S> for HmacVerifyLayer {
+    type Service = HmacVerify<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HmacVerify {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+/// The service produced by `HmacVerifyLayer`.
+#[derive(Clone)]
+pub struct HmacVerify<S> {
+    inner: S,
+    config: Arc<HmacConfig>,
+}
+
+impl<S, B> Service<Request<B>> for HmacVerify<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: http_body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // The clone might not be ready, keep the one that was polled.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+
+            let body = match buffer_body(&parts, body).await {
+                Ok(body) => body,
+                Err(error) => return Ok(encode_error_response_for_headers(&error, &parts.headers)),
+            };
+
+            if let Err(message) = config.verify(&parts.headers, parts.uri.path(), &body) {
+                return Ok(encode_error_response_for_headers(
+                    &RpcError::new(RpcErrorCode::Unauthenticated, message.to_string()),
+                    &parts.headers,
+                ));
+            }
+
+            inner
+                .call(Request::from_parts(parts, Body::from(body)))
+                .await
+        })
+    }
+}
+
+impl HmacConfig {
+    fn verify(&self, headers: &HeaderMap, path: &str, body: &[u8]) -> Result<(), &'static str> {
+        let timestamp = headers
+            .get(&self.timestamp_header)
+            .and_then(|v| v.to_str().ok())
+            .ok_or("Missing signature timestamp")?;
+        let signed_at = timestamp
+            .parse::<u64>()
+            .map_err(|_| "Malformed signature timestamp")?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        if now.abs_diff(signed_at) > self.replay_window.as_secs() {
+            return Err("Signature timestamp is outside of the replay window");
+        }
+
+        let signature = headers
+            .get(&self.signature_header)
+            .and_then(|v| v.to_str().ok())
+            .ok_or("Missing signature")?;
+        let signature = signature.strip_prefix("sha256=").unwrap_or(signature);
+        let signature = hex::decode(signature).map_err(|_| "Malformed signature")?;
+
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(timestamp.as_bytes());
+        mac.update(b"\n");
+        mac.update(path.as_bytes());
+        mac.update(b"\n");
+        mac.update(body);
+
+        // Constant time comparison.
+        mac.verify_slice(&signature)
+            .map_err(|_| "Invalid signature")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use axum::{body::to_bytes, extract::DefaultBodyLimit, http::StatusCode};
+    use tower::{service_fn, ServiceExt};
+
+    use super::*;
+
+    const SECRET: &[u8] = b"secret";
+    const PATH: &str = "/hooks.WebhookService/Deliver";
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn sign(secret: &[u8], timestamp: u64, path: &str, body: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+        mac.update(format!("{}\n{}\n{}", timestamp, path, body).as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    /// Calls the layer over a handler echoing the body it got, returning the status and body.
+    async fn call(
+        layer: HmacVerifyLayer,
+        req: axum::http::request::Builder,
+        body: &str,
+    ) -> (StatusCode, String) {
+        let service = layer.layer(service_fn(|req: Request<Body>| async move {
+            Ok::<_, Infallible>(Response::new(req.into_body()))
+        }));
+        let req = req
+            .method("POST")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let res = service.oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn signed(timestamp: u64, signature: String) -> axum::http::request::Builder {
+        Request::builder()
+            .uri(PATH)
+            .header("x-signature-timestamp", timestamp.to_string())
+            .header("x-signature", signature)
+    }
+
+    #[tokio::test]
+    async fn accepts_signed_requests() {
+        let ts = now();
+        let req = signed(ts, sign(SECRET, ts, PATH, "{}"));
+        assert_eq!(
+            call(HmacVerifyLayer::new(SECRET), req, "{}").await,
+            (StatusCode::OK, "{}".to_string())
+        );
+
+        let req = signed(ts, format!("sha256={}", sign(SECRET, ts, PATH, "{}")));
+        assert_eq!(
+            call(HmacVerifyLayer::new(SECRET), req, "{}").await.0,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_signatures() {
+        let ts = now();
+        let cases = [
+            signed(ts, sign(b"other", ts, PATH, "{}")),
+            signed(ts, sign(SECRET, ts, "/hooks.WebhookService/Other", "{}")),
+            signed(ts, sign(SECRET, ts, PATH, r#"{"a":1}"#)),
+            signed(ts, "not hex".to_string()),
+            Request::builder()
+                .uri(PATH)
+                .header("x-signature-timestamp", ts.to_string()),
+            Request::builder()
+                .uri(PATH)
+                .header("x-signature", sign(SECRET, ts, PATH, "{}")),
+        ];
+        for req in cases {
+            let (status, body) = call(HmacVerifyLayer::new(SECRET), req, "{}").await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            assert!(body.contains("unauthenticated"), "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_timestamps_outside_of_the_window() {
+        let layer = || HmacVerifyLayer::new(SECRET).replay_window(Duration::from_secs(60));
+        for ts in [now() - 120, now() + 120] {
+            let req = signed(ts, sign(SECRET, ts, PATH, "{}"));
+            assert_eq!(call(layer(), req, "{}").await.0, StatusCode::UNAUTHORIZED);
+        }
+
+        let ts = now() - 30;
+        let req = signed(ts, sign(SECRET, ts, PATH, "{}"));
+        assert_eq!(call(layer(), req, "{}").await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn rejects_bodies_over_the_limit() {
+        let body = "x".repeat(100);
+        let ts = now();
+        let service = HmacVerifyLayer::new(SECRET).layer(service_fn(|_: Request<Body>| async {
+            Ok::<_, Infallible>(Response::new(Body::empty()))
+        }));
+        let service = DefaultBodyLimit::max(10).layer(service);
+        let req = signed(ts, sign(SECRET, ts, PATH, &body))
+            .method("POST")
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap();
+
+        let res = service.oneshot(req).await.unwrap();
+        let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("resource_exhausted"));
+    }
+
+    #[tokio::test]
+    async fn can_be_configured_after_being_cloned() {
+        let layer = HmacVerifyLayer::new(SECRET);
+        let _clone = layer.clone();
+        let layer = layer
+            .signature_header(HeaderName::from_static("x-hub-signature-256"))
+            .timestamp_header(HeaderName::from_static("x-hub-timestamp"));
+
+        let ts = now();
+        let req = Request::builder()
+            .uri(PATH)
+            .header("x-hub-timestamp", ts.to_string())
+            .header("x-hub-signature-256", sign(SECRET, ts, PATH, "{}"));
+        assert_eq!(call(layer, req, "{}").await.0, StatusCode::OK);
+    }
+}
@@ -0,0 +1,734 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    http::{header, request::Parts, HeaderName, HeaderValue, Request, StatusCode},
+    response::Response,
+    BoxError,
+};
+use futures::future::BoxFuture;
+use sha2::{Digest, Sha256};
+use tower::{Layer, Service};
+
+use crate::{
+    handler::{
+        body::{any_body, buffer_body},
+        codec::encode_error_response_for_headers,
+    },
+    prelude::{PeerIdentity, RpcError, RpcErrorCode},
+};
+
+/// Identifies one logical request: the client's key, scoped to the caller and the method so keys
+/// can't collide (or be replayed) across them.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IdempotencyKey {
+    pub principal: String,
+    /// Like `hello.HelloWorldService/SayHello`.
+    pub method: String,
+    /// The value of the idempotency key header.
+    pub key: String,
+}
+
+/// A response kept for replaying to retries.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub body: Bytes,
+}
+
+/// The state of an idempotency key when a request with it arrives.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IdempotencyState {
+    /// First time seen, the key is now claimed by this request.
+    Claimed,
+    /// Claimed by a request that hasn't finished yet.
+    InProgress,
+    /// The request finished, here's what it responded.
+    Completed(StoredResponse),
+    /// Claimed by a request with a different body.
+    Mismatch,
+}
+
+/// Where `IdempotencyLayer` keeps claimed keys and their responses.
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync + 'static {
+    /// Atomically claims `key` for `lock_for` if it isn't known, keeping `request_hash` (the
+    /// SHA-256 of the request body) with it. If it is known, returns its state, or `Mismatch` if
+    /// it was claimed with another hash.
+    async fn claim(
+        &self,
+        key: &IdempotencyKey,
+        request_hash: &[u8],
+        lock_for: Duration,
+    ) -> Result<IdempotencyState, BoxError>;
+
+    /// Stores the response of a claimed key, kept for `ttl`.
+    async fn complete(
+        &self,
+        key: &IdempotencyKey,
+        response: StoredResponse,
+        ttl: Duration,
+    ) -> Result<(), BoxError>;
+
+    /// Releases a claimed key without a response, so a retry runs the request again.
+    async fn release(&self, key: &IdempotencyKey) -> Result<(), BoxError>;
+}
+
+/// Keeps keys in process memory, for tests and single instance deployments.
+#[derive(Default)]
+pub struct MemoryIdempotencyStore {
+    entries: Mutex<HashMap<IdempotencyKey, MemoryEntry>>,
+}
+
+struct MemoryEntry {
+    request_hash: Vec<u8>,
+    response: Option<StoredResponse>,
+    expires_at: Instant,
+}
+
+impl MemoryIdempotencyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for MemoryIdempotencyStore {
+    async fn claim(
+        &self,
+        key: &IdempotencyKey,
+        request_hash: &[u8],
+        lock_for: Duration,
+    ) -> Result<IdempotencyState, BoxError> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        entries.retain(|_, entry| entry.expires_at > now);
+
+        Ok(match entries.get(key) {
+            Some(entry) if entry.request_hash != request_hash => IdempotencyState::Mismatch,
+            Some(MemoryEntry {
+                response: Some(response),
+                ..
+            }) => IdempotencyState::Completed(response.clone()),
+            Some(_) => IdempotencyState::InProgress,
+            None => {
+                entries.insert(
+                    key.clone(),
+                    MemoryEntry {
+                        request_hash: request_hash.to_vec(),
+                        response: None,
+                        expires_at: now + lock_for,
+                    },
+                );
+                IdempotencyState::Claimed
+            }
+        })
+    }
+
+    async fn complete(
+        &self,
+        key: &IdempotencyKey,
+        response: StoredResponse,
+        ttl: Duration,
+    ) -> Result<(), BoxError> {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(key) {
+            entry.response = Some(response);
+            entry.expires_at = Instant::now() + ttl;
+        }
+        Ok(())
+    }
+
+    async fn release(&self, key: &IdempotencyKey) -> Result<(), BoxError> {
+        self.entries.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+/// Keeps keys in Redis, shared by all instances. Each key is a hash with a `state` field (`pending`
+/// or `done`), the `request_hash` it was claimed with and, once done, the `status`, `headers` and
+/// `body` of the response.
+#[cfg(feature = "redis")]
+#[derive(Clone)]
+pub struct RedisIdempotencyStore {
+    connection: redis::aio::ConnectionManager,
+    prefix: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisIdempotencyStore {
+    pub fn new(connection: redis::aio::ConnectionManager) -> Self {
+        Self {
+            connection,
+            prefix: "axum-connect:idempotency".to_string(),
+        }
+    }
+
+    /// Prefix of the Redis keys, `axum-connect:idempotency` by default.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    fn redis_key(&self, key: &IdempotencyKey) -> String {
+        redis_key(&self.prefix, key)
+    }
+}
+
+/// The Redis key of `key`, each part prefixed with its length so that parts containing `:` can't
+/// make two keys the same.
+#[cfg(feature = "redis")]
+fn redis_key(prefix: &str, key: &IdempotencyKey) -> String {
+    let mut redis_key = prefix.to_string();
+    for part in [&key.principal, &key.method, &key.key] {
+        redis_key.push_str(&format!(":{}:{}", part.len(), part));
+    }
+    redis_key
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl IdempotencyStore for RedisIdempotencyStore {
+    async fn claim(
+        &self,
+        key: &IdempotencyKey,
+        request_hash: &[u8],
+        lock_for: Duration,
+    ) -> Result<IdempotencyState, BoxError> {
+        // Claims the key, or reads what's there, in one step.
+        let fields: Vec<Option<Vec<u8>>> = redis::Script::new(
+            r"
+            if redis.call('HSETNX', KEYS[1], 'state', 'pending') == 1 then
+                redis.call('HSET', KEYS[1], 'request_hash', ARGV[2])
+                redis.call('PEXPIRE', KEYS[1], ARGV[1])
+                return {}
+            end
+            return redis.call('HMGET', KEYS[1], 'state', 'request_hash', 'status', 'headers', 'body')
+            ",
+        )
+        .key(self.redis_key(key))
+        .arg(lock_for.as_millis() as u64)
+        .arg(request_hash)
+        .invoke_async(&mut self.connection.clone())
+        .await?;
+
+        let mut fields = fields.into_iter();
+        let mut next = || fields.next().flatten();
+        Ok(match (next(), next(), next(), next(), next()) {
+            (None, ..) => IdempotencyState::Claimed,
+            (Some(_), stored_hash, ..) if stored_hash.as_deref() != Some(request_hash) => {
+                IdempotencyState::Mismatch
+            }
+            (Some(state), _, Some(status), headers, body) if state == b"done" => {
+                IdempotencyState::Completed(StoredResponse {
+                    status: String::from_utf8(status)?.parse()?,
+                    headers: serde_json::from_slice(&headers.unwrap_or_default())?,
+                    body: body.unwrap_or_default().into(),
+                })
+            }
+            _ => IdempotencyState::InProgress,
+        })
+    }
+
+    async fn complete(
+        &self,
+        key: &IdempotencyKey,
+        response: StoredResponse,
+        ttl: Duration,
+    ) -> Result<(), BoxError> {
+        let redis_key = self.redis_key(key);
+        redis::pipe()
+            .atomic()
+            .hset_multiple(
+                &redis_key,
+                &[
+                    ("state", b"done".to_vec()),
+                    ("status", response.status.to_string().into_bytes()),
+                    ("headers", serde_json::to_vec(&response.headers)?),
+                    ("body", response.body.to_vec()),
+                ],
+            )
+            .ignore()
+            .pexpire(&redis_key, ttl.as_millis() as usize)
+            .ignore()
+            .query_async::<_, ()>(&mut self.connection.clone())
+            .await?;
+        Ok(())
+    }
+
+    async fn release(&self, key: &IdempotencyKey) -> Result<(), BoxError> {
+        redis::cmd("DEL")
+            .arg(self.redis_key(key))
+            .query_async::<_, ()>(&mut self.connection.clone())
+            .await?;
+        Ok(())
+    }
+}
+
+type PrincipalFn = dyn Fn(&Parts) -> Option<String> + Send + Sync;
+
+/// Makes unary calls safe to retry: a request carrying an `idempotency-key` header runs once, and
+/// retries with the same key get the original response back (marked with an
+/// `idempotent-replayed: true` header) instead of running the handler, and its side effects, again.
+///
+/// ```ignore
+/// let app = Router::new()
+///     .rpc(PaymentService::charge(charge))
+///     .layer(IdempotencyLayer::new(MemoryIdempotencyStore::new()).ttl(Duration::from_secs(3600)));
+/// ```
+///
+/// Keys are scoped to the principal (the caller's `PeerIdentity` by default, set your own with
+/// `principal`) and the method. Callers without a principal can't use keys, as they would all share
+/// them: their requests with the header fail with `Unauthenticated`. A key is tied to the body of the
+/// request that claimed it, and reusing it with another body fails with `InvalidArgument`. A retry
+/// that arrives while the first request is still running fails with `Aborted`. Responses the client
+/// should retry anyway (timeouts, `ResourceExhausted` and server errors) aren't stored, and release
+/// the key. Requests without the header, and streaming calls, pass straight through.
+///
+/// Bodies of requests with a key are buffered to hash them, within axum's `DefaultBodyLimit`.
+#[derive(Clone)]
+pub struct IdempotencyLayer {
+    config: Arc<IdempotencyConfig>,
+}
+
+#[derive(Clone)]
+struct IdempotencyConfig {
+    store: Arc<dyn IdempotencyStore>,
+    header: HeaderName,
+    ttl: Duration,
+    lock_for: Duration,
+    principal: Arc<PrincipalFn>,
+}
+
+impl IdempotencyLayer {
+    pub fn new<T>(store: T) -> Self
+    where
+        T: IdempotencyStore,
+    {
+        Self::with_shared_store(Arc::new(store))
+    }
+
+    /// Like `new`, for a store that's shared with something else.
+    pub fn with_shared_store(store: Arc<dyn IdempotencyStore>) -> Self {
+        Self {
+            config: Arc::new(IdempotencyConfig {
+                store,
+                header: HeaderName::from_static("idempotency-key"),
+                ttl: Duration::from_secs(24 * 60 * 60),
+                lock_for: Duration::from_secs(60),
+                principal: Arc::new(|parts| {
+                    parts
+                        .extensions
+                        .get::<PeerIdentity>()
+                        .and_then(|identity| identity.names().next().map(str::to_string))
+                }),
+            }),
+        }
+    }
+
+    /// The header carrying the key, `idempotency-key` by default.
+    pub fn header(mut self, header: HeaderName) -> Self {
+        self.config_mut().header = header;
+        self
+    }
+
+    /// How long responses are kept for replaying, a day by default.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.config_mut().ttl = ttl;
+        self
+    }
+
+    /// How long a key stays claimed by a request that never finishes (say, the server crashed), a
+    /// minute by default. Should be longer than the slowest request.
+    pub fn lock_for(mut self, lock_for: Duration) -> Self {
+        self.config_mut().lock_for = lock_for;
+        self
+    }
+
+    /// How to name the caller. Requests with a key that return `None` are refused.
+    pub fn principal<F>(mut self, principal: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<String> + Send + Sync + 'static,
+    {
+        self.config_mut().principal = Arc::new(principal);
+        self
+    }
+
+    fn config_mut(&mut self) -> &mut IdempotencyConfig {
+        Arc::make_mut(&mut self.config)
+    }
+}
+
+impl<S> Layer<S> for IdempotencyLayer {
+    type Service = Idempotency<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Idempotency {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+/// The service produced by `IdempotencyLayer`.
+#[derive(Clone)]
+pub struct Idempotency<S> {
+    inner: S,
+    config: Arc<IdempotencyConfig>,
+}
+
+impl<S, B> Service<Request<B>> for Idempotency<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Send,
+    B: http_body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // The clone might not be ready, keep the one that was polled.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+
+            let streaming = parts
+                .headers
+                .get(header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.starts_with("application/connect+"));
+            let key = match parts.headers.get(&config.header) {
+                Some(key) if !streaming => key,
+                _ => return inner.call(Request::from_parts(parts, any_body(body))).await,
+            };
+
+            let key = match key.to_str() {
+                Ok(key) if !key.is_empty() && key.len() <= 255 => key.to_string(),
+                _ => {
+                    let error = RpcError::new(
+                        RpcErrorCode::InvalidArgument,
+                        format!(
+                            "{} must be 1 to 255 visible ASCII characters",
+                            config.header
+                        ),
+                    );
+                    return Ok(encode_error_response_for_headers(&error, &parts.headers));
+                }
+            };
+
+            // RPC paths end in `/package.Service/Method`, possibly under a prefix (like a tenant).
+            let mut segments = parts.uri.path().rsplit('/');
+            let method = match (segments.next(), segments.next()) {
+                (Some(method), Some(service)) => format!("{}/{}", service, method),
+                _ => parts.uri.path().to_string(),
+            };
+            let Some(principal) = (config.principal)(&parts) else {
+                let error = RpcError::new(
+                    RpcErrorCode::Unauthenticated,
+                    format!("{} needs an authenticated caller", config.header),
+                );
+                return Ok(encode_error_response_for_headers(&error, &parts.headers));
+            };
+            let key = IdempotencyKey {
+                principal,
+                method,
+                key,
+            };
+
+            let body = match buffer_body(&parts, body).await {
+                Ok(body) => body,
+                Err(error) => return Ok(encode_error_response_for_headers(&error, &parts.headers)),
+            };
+            let request_hash = Sha256::digest(&body);
+
+            let error = match config
+                .store
+                .claim(&key, &request_hash, config.lock_for)
+                .await
+            {
+                Ok(IdempotencyState::Claimed) => None,
+                Ok(IdempotencyState::Completed(stored)) => return Ok(replay(stored)),
+                Ok(IdempotencyState::InProgress) => Some(RpcError::new(
+                    RpcErrorCode::Aborted,
+                    "A request with this idempotency key is still in progress".to_string(),
+                )),
+                Ok(IdempotencyState::Mismatch) => Some(RpcError::new(
+                    RpcErrorCode::InvalidArgument,
+                    "This idempotency key was used for a different request".to_string(),
+                )),
+                Err(e) => Some(RpcError::new(
+                    RpcErrorCode::Unavailable,
+                    format!("Failed to check idempotency key. {}", e),
+                )),
+            };
+            if let Some(error) = error {
+                return Ok(encode_error_response_for_headers(&error, &parts.headers));
+            }
+
+            let res = match inner
+                .call(Request::from_parts(parts, Body::from(body)))
+                .await
+            {
+                Ok(res) => res,
+                Err(e) => {
+                    let _ = config.store.release(&key).await;
+                    return Err(e);
+                }
+            };
+
+            let status = res.status();
+            // 499 is `Canceled`.
+            if status.is_server_error()
+                || status.as_u16() == 499
+                || status == StatusCode::TOO_MANY_REQUESTS
+            {
+                let _ = config.store.release(&key).await;
+                return Ok(res);
+            }
+
+            let (res_parts, res_body) = res.into_parts();
+            let body = match to_bytes(res_body, usize::MAX).await {
+                Ok(body) => body,
+                Err(e) => {
+                    let _ = config.store.release(&key).await;
+                    let error = RpcError::new(
+                        RpcErrorCode::Internal,
+                        format!("Failed to read response. {}", e),
+                    );
+                    return Ok(encode_error_response_for_headers(
+                        &error,
+                        &res_parts.headers,
+                    ));
+                }
+            };
+
+            let stored = StoredResponse {
+                status: res_parts.status.as_u16(),
+                headers: res_parts
+                    .headers
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.as_bytes().to_vec()))
+                    .collect(),
+                body,
+            };
+            // The call already happened, so a failing store shouldn't fail it. The retry will run
+            // it again though.
+            if config
+                .store
+                .complete(&key, stored.clone(), config.ttl)
+                .await
+                .is_err()
+            {
+                let _ = config.store.release(&key).await;
+            }
+
+            Ok(Response::from_parts(res_parts, Body::from(stored.body)))
+        })
+    }
+}
+
+fn replay(stored: StoredResponse) -> Response {
+    let mut res = Response::new(Body::from(stored.body));
+    *res.status_mut() = StatusCode::from_u16(stored.status).unwrap_or(StatusCode::OK);
+
+    let headers = res.headers_mut();
+    for (name, value) in stored.headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::from_bytes(&value))
+        {
+            headers.append(name, value);
+        }
+    }
+    headers.insert(
+        HeaderName::from_static("idempotent-replayed"),
+        HeaderValue::from_static("true"),
+    );
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use tower::{service_fn, ServiceExt};
+
+    use super::*;
+
+    const PATH: &str = "/payments.PaymentService/Charge";
+
+    fn layer() -> IdempotencyLayer {
+        IdempotencyLayer::new(MemoryIdempotencyStore::new()).principal(|parts| {
+            parts
+                .headers
+                .get("x-user")
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        })
+    }
+
+    /// Calls the layer over a handler that answers the number of times it ran.
+    async fn call(
+        layer: &IdempotencyLayer,
+        calls: &Arc<AtomicUsize>,
+        headers: &[(&str, &str)],
+        body: &'static str,
+    ) -> Response {
+        let calls = calls.clone();
+        let service = layer.layer(service_fn(move |_: Request<Body>| {
+            let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            async move { Ok::<_, Infallible>(Response::new(Body::from(n.to_string()))) }
+        }));
+
+        let mut req = Request::post(PATH).header(header::CONTENT_TYPE, "application/json");
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        service
+            .oneshot(req.body(Body::from(body)).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn body(res: Response) -> String {
+        String::from_utf8(
+            to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap()
+                .to_vec(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn replays_the_response_to_retries() {
+        let (layer, calls) = (layer(), Arc::new(AtomicUsize::new(0)));
+        let headers = [("x-user", "alice"), ("idempotency-key", "k1")];
+
+        let first = call(&layer, &calls, &headers, "{}").await;
+        assert!(first.headers().get("idempotent-replayed").is_none());
+        assert_eq!(body(first).await, "1");
+
+        let retry = call(&layer, &calls, &headers, "{}").await;
+        assert_eq!(retry.headers()["idempotent-replayed"], "true");
+        assert_eq!(body(retry).await, "1");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn scopes_keys_to_the_principal() {
+        let (layer, calls) = (layer(), Arc::new(AtomicUsize::new(0)));
+
+        call(
+            &layer,
+            &calls,
+            &[("x-user", "alice"), ("idempotency-key", "k1")],
+            "{}",
+        )
+        .await;
+        let bob = call(
+            &layer,
+            &calls,
+            &[("x-user", "bob"), ("idempotency-key", "k1")],
+            "{}",
+        )
+        .await;
+        assert_eq!(body(bob).await, "2");
+    }
+
+    #[tokio::test]
+    async fn rejects_a_key_reused_for_another_request() {
+        let (layer, calls) = (layer(), Arc::new(AtomicUsize::new(0)));
+        let headers = [("x-user", "alice"), ("idempotency-key", "k1")];
+
+        call(&layer, &calls, &headers, r#"{"amount":1}"#).await;
+        let res = call(&layer, &calls, &headers, r#"{"amount":100}"#).await;
+        assert!(body(res).await.contains("invalid_argument"));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn refuses_keys_of_callers_without_a_principal() {
+        let (layer, calls) = (layer(), Arc::new(AtomicUsize::new(0)));
+
+        let res = call(&layer, &calls, &[("idempotency-key", "k1")], "{}").await;
+        assert!(body(res).await.contains("unauthenticated"));
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn passes_requests_without_a_key_through() {
+        let (layer, calls) = (layer(), Arc::new(AtomicUsize::new(0)));
+
+        call(&layer, &calls, &[], "{}").await;
+        let res = call(&layer, &calls, &[], "{}").await;
+        assert_eq!(body(res).await, "2");
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_keys() {
+        let (layer, calls) = (layer(), Arc::new(AtomicUsize::new(0)));
+        let long = "k".repeat(256);
+
+        let res = call(
+            &layer,
+            &calls,
+            &[("x-user", "alice"), ("idempotency-key", &long)],
+            "{}",
+        )
+        .await;
+        assert!(body(res).await.contains("invalid_argument"));
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn can_be_configured_after_being_cloned() {
+        let layer = layer();
+        let _clone = layer.clone();
+        let layer = layer.header(HeaderName::from_static("x-idempotency-key"));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let headers = [("x-user", "alice"), ("x-idempotency-key", "k1")];
+
+        call(&layer, &calls, &headers, "{}").await;
+        let retry = call(&layer, &calls, &headers, "{}").await;
+        assert_eq!(retry.headers()["idempotent-replayed"], "true");
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    fn redis_keys_of_different_keys_differ() {
+        let key = |principal: &str, method: &str, key: &str| IdempotencyKey {
+            principal: principal.to_string(),
+            method: method.to_string(),
+            key: key.to_string(),
+        };
+
+        assert_ne!(
+            redis_key("p", &key("a:b", "m", "k")),
+            redis_key("p", &key("a", "b:m", "k"))
+        );
+        assert_ne!(
+            redis_key("p", &key("a", "m:k", "x")),
+            redis_key("p", &key("a", "m", "k:x"))
+        );
+    }
+}
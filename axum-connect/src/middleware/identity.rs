@@ -0,0 +1,201 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{http::Request, response::Response};
+use futures::future::{ready, Either, Ready};
+use tower::{Layer, Service};
+
+use crate::{
+    handler::codec::encode_error_response_for_headers,
+    prelude::{PeerIdentity, RpcError, RpcErrorCode},
+};
+
+/// Restricts services to an allowed set of mTLS peers (see `PeerIdentity`). A peer is allowed if
+/// either its SPIFFE ID or its certificate subject is in the service's list. Services that were
+/// never mentioned aren't restricted.
+///
+/// ```ignore
+/// let app = Router::new()
+///     .rpc(BillingService::charge(charge))
+///     .layer(AllowedPeersLayer::new().allow(
+///         "billing.BillingService",
+///         ["spiffe://example.org/ns/prod/sa/checkout"],
+///     ));
+/// ```
+///
+/// Requests without a `PeerIdentity` to restricted services fail with `Unauthenticated`, peers not
+/// in the list with `PermissionDenied`. The layer has to sit inside of whatever inserts the
+/// `PeerIdentity` extension.
+#[derive(Clone, Default)]
+pub struct AllowedPeersLayer {
+    services: Arc<HashMap<String, HashSet<String>>>,
+}
+
+impl AllowedPeersLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows `identities` to call `service` (fully qualified, like `hello.HelloWorldService`).
+    /// Can be called more than once for the same service.
+    pub fn allow<I>(mut self, service: impl Into<String>, identities: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Arc::make_mut(&mut self.services)
+            .entry(service.into())
+            .or_default()
+            .extend(identities.into_iter().map(Into::into));
+        self
+    }
+}
+
+impl<S> Layer<S> for AllowedPeersLayer {
+    type Service = AllowedPeers<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AllowedPeers {
+            inner,
+            services: self.services.clone(),
+        }
+    }
+}
+
+/// The service produced by `AllowedPeersLayer`.
+#[derive(Clone)]
+pub struct AllowedPeers<S> {
+    inner: S,
+    services: Arc<HashMap<String, HashSet<String>>>,
+}
+
+impl<S, B> Service<Request<B>> for AllowedPeers<S>
+where
+    S: Service<Request<B>, Response = Response>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Either<Ready<Result<Response, S::Error>>, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // RPC paths end in `/package.Service/Method`, possibly under a prefix (like a tenant).
+        let service = req.uri().path().rsplit('/').nth(1).unwrap_or_default();
+
+        let Some(allowed) = self.services.get(service) else {
+            return Either::Right(self.inner.call(req));
+        };
+
+        let error = match req.extensions().get::<PeerIdentity>() {
+            Some(identity) if identity.names().any(|name| allowed.contains(name)) => {
+                return Either::Right(self.inner.call(req));
+            }
+            Some(identity) => RpcError::new(
+                RpcErrorCode::PermissionDenied,
+                format!(
+                    "Peer {} may not call {}",
+                    identity.names().next().unwrap_or("<anonymous>"),
+                    service
+                ),
+            ),
+            None => RpcError::new(
+                RpcErrorCode::Unauthenticated,
+                "No verified client certificate".to_string(),
+            ),
+        };
+
+        Either::Left(ready(Ok(encode_error_response_for_headers(
+            &error,
+            req.headers(),
+        ))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use axum::{
+        body::{to_bytes, Body},
+        http::StatusCode,
+    };
+    use tower::{service_fn, ServiceExt};
+
+    use super::*;
+
+    const CHECKOUT: &str = "spiffe://example.org/ns/prod/sa/checkout";
+
+    fn layer() -> AllowedPeersLayer {
+        AllowedPeersLayer::new()
+            .allow("billing.BillingService", [CHECKOUT])
+            .allow("billing.BillingService", ["CN=admin, O=Example"])
+    }
+
+    async fn call(path: &str, identity: Option<PeerIdentity>) -> (StatusCode, serde_json::Value) {
+        let service = layer().layer(service_fn(|_: Request<Body>| async {
+            Ok::<_, Infallible>(Response::new(Body::from("{}")))
+        }));
+        let mut req = Request::post(path)
+            .header("content-type", "application/json")
+            .body(Body::empty())
+            .unwrap();
+        if let Some(identity) = identity {
+            req.extensions_mut().insert(identity);
+        }
+        let res = service.oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn spiffe(id: &str) -> Option<PeerIdentity> {
+        Some(PeerIdentity {
+            spiffe_id: Some(id.to_string()),
+            subject: Some("CN=checkout, O=Example".to_string()),
+        })
+    }
+
+    #[tokio::test]
+    async fn allows_listed_peers() {
+        let (status, _) = call("/billing.BillingService/Charge", spiffe(CHECKOUT)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // By subject, and under a prefix.
+        let admin = PeerIdentity {
+            spiffe_id: None,
+            subject: Some("CN=admin, O=Example".to_string()),
+        };
+        let (status, _) = call("/acme/billing.BillingService/Charge", Some(admin)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Services that aren't restricted, even without an identity.
+        let (status, _) = call("/grpc.health.v1.Health/Check", None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn rejects_other_peers() {
+        let (status, error) = call(
+            "/billing.BillingService/Charge",
+            spiffe("spiffe://example.org/ns/prod/sa/mallory"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(error["code"], "permission_denied");
+        assert_eq!(
+            error["message"],
+            "Peer spiffe://example.org/ns/prod/sa/mallory may not call billing.BillingService"
+        );
+
+        let (status, error) = call("/billing.BillingService/Charge", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(error["code"], "unauthenticated");
+        assert_eq!(error["message"], "No verified client certificate");
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+};
+
+use axum::{http::Request, response::Response};
+use futures::future::{ready, Either, Ready};
+use tower::{Layer, Service};
+
+use crate::{
+    handler::codec::encode_error_response_for_headers,
+    prelude::{RpcError, RpcErrorCode},
+};
+
+/// Switches for turning off individual methods (or whole services) at runtime, without a deploy.
+/// Clones share the same switches, so keep one around to flip them from an admin endpoint, a
+/// config watcher or a feature flag client while `KillSwitchLayer` enforces them.
+///
+/// ```ignore
+/// let switches = KillSwitches::new();
+/// let app = Router::new()
+///     .rpc(SearchService::search(search))
+///     .layer(KillSwitchLayer::new(switches.clone()));
+///
+/// // Later, while search is melting the database:
+/// switches.disable("search.SearchService/Search", "Search is temporarily disabled");
+/// ```
+///
+/// Methods are named like `package.Service/Method`, services like `package.Service`.
+#[derive(Clone, Default)]
+pub struct KillSwitches {
+    disabled: Arc<RwLock<HashMap<String, RpcError>>>,
+}
+
+impl KillSwitches {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rejects calls to `target` (a method or a service) with `Unavailable` and `message`.
+    pub fn disable(&self, target: impl Into<String>, message: impl Into<String>) {
+        self.disable_with(target, RpcErrorCode::Unavailable, message);
+    }
+
+    /// Rejects calls to `target` with the given code, usually `Unavailable` for a temporary outage
+    /// clients may retry, or `Unimplemented` for something that's gone for good.
+    pub fn disable_with(
+        &self,
+        target: impl Into<String>,
+        code: RpcErrorCode,
+        message: impl Into<String>,
+    ) {
+        self.disabled
+            .write()
+            .unwrap()
+            .insert(target.into(), RpcError::new(code, message.into()));
+    }
+
+    /// Turns `target` back on. Enabling a method doesn't enable it if its whole service is off.
+    pub fn enable(&self, target: &str) {
+        self.disabled.write().unwrap().remove(target);
+    }
+
+    /// Replaces every switch at once, for applying a freshly loaded config.
+    pub fn set_all<I>(&self, disabled: I)
+    where
+        I: IntoIterator<Item = (String, RpcError)>,
+    {
+        *self.disabled.write().unwrap() = disabled.into_iter().collect();
+    }
+
+    /// The methods and services that are currently off.
+    pub fn disabled(&self) -> Vec<String> {
+        self.disabled.read().unwrap().keys().cloned().collect()
+    }
+
+    fn check(&self, service: &str, method: &str) -> Option<RpcError> {
+        let disabled = self.disabled.read().unwrap();
+        disabled
+            .get(&format!("{}/{}", service, method))
+            .or_else(|| disabled.get(service))
+            .cloned()
+    }
+}
+
+/// Rejects calls to the methods turned off in its `KillSwitches`.
+#[derive(Clone)]
+pub struct KillSwitchLayer {
+    switches: KillSwitches,
+}
+
+impl KillSwitchLayer {
+    pub fn new(switches: KillSwitches) -> Self {
+        Self { switches }
+    }
+}
+
+impl<S> Layer<S> for KillSwitchLayer {
+    type Service = KillSwitch<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        KillSwitch {
+            inner,
+            switches: self.switches.clone(),
+        }
+    }
+}
+
+/// The service produced by `KillSwitchLayer`.
+#[derive(Clone)]
+pub struct KillSwitch<S> {
+    inner: S,
+    switches: KillSwitches,
+}
+
+impl<S, B> Service<Request<B>> for KillSwitch<S>
+where
+    S: Service<Request<B>, Response = Response>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Either<Ready<Result<Response, S::Error>>, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // RPC paths end in `/package.Service/Method`, possibly under a prefix (like a tenant).
+        let mut segments = req.uri().path().rsplit('/');
+        let error = match (segments.next(), segments.next()) {
+            (Some(method), Some(service)) => self.switches.check(service, method),
+            _ => None,
+        };
+
+        match error {
+            Some(error) => Either::Left(ready(Ok(encode_error_response_for_headers(
+                &error,
+                req.headers(),
+            )))),
+            None => Either::Right(self.inner.call(req)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use axum::{
+        body::{to_bytes, Body},
+        http::StatusCode,
+    };
+    use tower::{service_fn, ServiceExt};
+
+    use super::*;
+
+    async fn call(switches: &KillSwitches, path: &str) -> (StatusCode, serde_json::Value) {
+        let service =
+            KillSwitchLayer::new(switches.clone()).layer(service_fn(|_: Request<Body>| async {
+                Ok::<_, Infallible>(Response::new(Body::from("{}")))
+            }));
+        let req = Request::post(path)
+            .header("content-type", "application/json")
+            .body(Body::empty())
+            .unwrap();
+        let res = service.oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn allows_methods_that_are_on() {
+        let switches = KillSwitches::new();
+        switches.disable("search.SearchService/Search", "Search is off");
+
+        let (status, _) = call(&switches, "/search.SearchService/Suggest").await;
+        assert_eq!(status, StatusCode::OK);
+
+        switches.enable("search.SearchService/Search");
+        let (status, _) = call(&switches, "/search.SearchService/Search").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn rejects_methods_and_services_that_are_off() {
+        let switches = KillSwitches::new();
+        switches.disable("search.SearchService/Search", "Search is off");
+        switches.disable_with("legacy.LegacyService", RpcErrorCode::Unimplemented, "Gone");
+
+        let (status, error) = call(&switches, "/search.SearchService/Search").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(error["code"], "unavailable");
+        assert_eq!(error["message"], "Search is off");
+
+        // Under a prefix, and a whole service.
+        let (status, error) = call(&switches, "/acme/legacy.LegacyService/Get").await;
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+        assert_eq!(error["code"], "unimplemented");
+        assert_eq!(error["message"], "Gone");
+
+        // Enabling a method doesn't enable it while its service is off.
+        switches.enable("legacy.LegacyService/Get");
+        let (_, error) = call(&switches, "/legacy.LegacyService/Get").await;
+        assert_eq!(error["code"], "unimplemented");
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    http::{header, HeaderValue, Request},
+    response::Response,
+};
+use futures::future::{ready, Either, Ready};
+use tower::{Layer, Service};
+
+use crate::{
+    error_details::RetryInfo,
+    handler::codec::encode_error_response_for_headers,
+    prelude::{RpcError, RpcErrorCode},
+};
+
+/// A maintenance switch for the whole router, flipped at runtime during migrations or incidents.
+/// While it's on, `MaintenanceLayer` rejects every RPC except the allow-listed ones with
+/// `Unavailable`, a `google.rpc.RetryInfo` detail and a `Retry-After` header. Clones share the
+/// switch.
+///
+/// ```ignore
+/// let maintenance = MaintenanceMode::new().allow("grpc.health.v1.Health");
+/// let app = Router::new()
+///     .rpc(OrderService::place_order(place_order))
+///     .layer(MaintenanceLayer::new(maintenance.clone()));
+///
+/// maintenance.enable("Migrating the orders database", Duration::from_secs(300));
+/// // ...
+/// maintenance.disable();
+/// ```
+#[derive(Clone, Default)]
+pub struct MaintenanceMode {
+    state: Arc<RwLock<Option<Maintenance>>>,
+    allowed: Arc<HashSet<String>>,
+}
+
+struct Maintenance {
+    message: String,
+    retry_after: Duration,
+}
+
+impl MaintenanceMode {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps `target` (a method like `package.Service/Method`, or a whole `package.Service`)
+    /// available during maintenance, like health checks or the admin service that ends it.
+    pub fn allow(mut self, target: impl Into<String>) -> Self {
+        Arc::make_mut(&mut self.allowed).insert(target.into());
+        self
+    }
+
+    /// Turns maintenance on, telling clients to come back after `retry_after`. Calling it again
+    /// updates the message and delay.
+    pub fn enable(&self, message: impl Into<String>, retry_after: Duration) {
+        *self.state.write().unwrap() = Some(Maintenance {
+            message: message.into(),
+            retry_after,
+        });
+    }
+
+    pub fn disable(&self) {
+        *self.state.write().unwrap() = None;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state.read().unwrap().is_some()
+    }
+
+    fn check(&self, service: &str, method: &str) -> Option<(RpcError, Duration)> {
+        let state = self.state.read().unwrap();
+        let maintenance = state.as_ref()?;
+
+        if self.allowed.contains(service) || self.allowed.contains(&format!("{service}/{method}")) {
+            return None;
+        }
+
+        let error = RpcError::new(RpcErrorCode::Unavailable, maintenance.message.clone())
+            .with_detail(RetryInfo::new(maintenance.retry_after));
+
+        Some((error, maintenance.retry_after))
+    }
+}
+
+/// Rejects RPCs while its `MaintenanceMode` is on.
+#[derive(Clone)]
+pub struct MaintenanceLayer {
+    mode: MaintenanceMode,
+}
+
+impl MaintenanceLayer {
+    pub fn new(mode: MaintenanceMode) -> Self {
+        Self { mode }
+    }
+}
+
+impl<S> Layer<S> for MaintenanceLayer {
+    type Service = MaintenanceGate<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MaintenanceGate {
+            inner,
+            mode: self.mode.clone(),
+        }
+    }
+}
+
+/// The service produced by `MaintenanceLayer`.
+#[derive(Clone)]
+pub struct MaintenanceGate<S> {
+    inner: S,
+    mode: MaintenanceMode,
+}
+
+impl<S, B> Service<Request<B>> for MaintenanceGate<S>
+where
+    S: Service<Request<B>, Response = Response>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Either<Ready<Result<Response, S::Error>>, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // RPC paths end in `/package.Service/Method`, possibly under a prefix (like a tenant).
+        let mut segments = req.uri().path().rsplit('/');
+        let rejection = match (segments.next(), segments.next()) {
+            (Some(method), Some(service)) => self.mode.check(service, method),
+            _ => self.mode.check("", ""),
+        };
+
+        let Some((error, retry_after)) = rejection else {
+            return Either::Right(self.inner.call(req));
+        };
+
+        let mut res = encode_error_response_for_headers(&error, req.headers());
+        // Whole seconds only, rounded up so clients don't come back early.
+        let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        res.headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        Either::Left(ready(Ok(res)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use axum::{
+        body::{to_bytes, Body},
+        http::StatusCode,
+    };
+    use tower::{service_fn, ServiceExt};
+
+    use super::*;
+
+    async fn call(
+        mode: &MaintenanceMode,
+        path: &str,
+    ) -> (StatusCode, Option<HeaderValue>, serde_json::Value) {
+        let service =
+            MaintenanceLayer::new(mode.clone()).layer(service_fn(|_: Request<Body>| async {
+                Ok::<_, Infallible>(Response::new(Body::from("{}")))
+            }));
+        let req = Request::post(path)
+            .header("content-type", "application/json")
+            .body(Body::empty())
+            .unwrap();
+        let res = service.oneshot(req).await.unwrap();
+        let status = res.status();
+        let retry_after = res.headers().get(header::RETRY_AFTER).cloned();
+        let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        (status, retry_after, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn mode() -> MaintenanceMode {
+        MaintenanceMode::new()
+            .allow("grpc.health.v1.Health")
+            .allow("admin.AdminService/EndMaintenance")
+    }
+
+    #[tokio::test]
+    async fn allows_calls_outside_of_maintenance_and_allowed_ones_during_it() {
+        let mode = mode();
+        let (status, retry_after, _) = call(&mode, "/orders.OrderService/PlaceOrder").await;
+        assert_eq!((status, retry_after), (StatusCode::OK, None));
+
+        mode.enable("Migrating", Duration::from_secs(300));
+        for path in [
+            "/grpc.health.v1.Health/Check",
+            "/admin.AdminService/EndMaintenance",
+        ] {
+            let (status, _, _) = call(&mode, path).await;
+            assert_eq!(status, StatusCode::OK, "{path}");
+        }
+
+        mode.disable();
+        let (status, _, _) = call(&mode, "/orders.OrderService/PlaceOrder").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn rejects_calls_during_maintenance() {
+        let mode = mode();
+        mode.enable("Migrating", Duration::from_millis(300_500));
+        assert!(mode.is_enabled());
+
+        for path in [
+            "/orders.OrderService/PlaceOrder",
+            "/admin.AdminService/StartMaintenance",
+        ] {
+            let (status, retry_after, error) = call(&mode, path).await;
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{path}");
+            // Rounded up to whole seconds.
+            assert_eq!(retry_after.unwrap(), "301");
+            assert_eq!(error["code"], "unavailable");
+            assert_eq!(error["message"], "Migrating");
+            assert_eq!(error["details"][0]["type"], "google.rpc.RetryInfo");
+        }
+    }
+}
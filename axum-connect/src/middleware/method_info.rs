@@ -0,0 +1,87 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::http::Request;
+use tower::{Layer, Service};
+
+use crate::descriptor::{MethodDescriptor, RpcMethodInfo, ServiceDescriptor};
+
+/// Adds `RpcMethodInfo` to requests for the given services' methods, for layers that key on the
+/// method (logging, rate limiting, metrics) and sit outside of the generated routes. Requests for
+/// anything else pass through without it.
+///
+/// ```ignore
+/// let app = Router::new()
+///     .rpc(HelloWorldService::say_hello(say_hello))
+///     .layer(RateLimitLayer::new(...))
+///     .layer(RpcMethodInfoLayer::new([&HelloWorldService::DESCRIPTOR]));
+/// ```
+#[derive(Clone)]
+pub struct RpcMethodInfoLayer {
+    methods: Arc<HashMap<String, &'static MethodDescriptor>>,
+}
+
+impl RpcMethodInfoLayer {
+    pub fn new<I>(services: I) -> Self
+    where
+        I: IntoIterator<Item = &'static ServiceDescriptor>,
+    {
+        let methods = services
+            .into_iter()
+            .flat_map(|service| service.methods)
+            .map(|method| (format!("{}/{}", method.service, method.name), method))
+            .collect();
+
+        Self {
+            methods: Arc::new(methods),
+        }
+    }
+}
+
+impl<S> Layer<S> for RpcMethodInfoLayer {
+    type Service = MethodInfo<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MethodInfo {
+            inner,
+            methods: self.methods.clone(),
+        }
+    }
+}
+
+/// The service produced by `RpcMethodInfoLayer`.
+#[derive(Clone)]
+pub struct MethodInfo<S> {
+    inner: S,
+    methods: Arc<HashMap<String, &'static MethodDescriptor>>,
+}
+
+impl<S, B> Service<Request<B>> for MethodInfo<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        // RPC paths end in `/package.Service/Method`, possibly under a prefix (like a tenant).
+        let mut segments = req.uri().path().rsplit('/');
+        let method = match (segments.next(), segments.next()) {
+            (Some(method), Some(service)) => self.methods.get(&format!("{}/{}", service, method)),
+            _ => None,
+        };
+
+        if let Some(&method) = method {
+            req.extensions_mut().insert(RpcMethodInfo(method));
+        }
+        self.inner.call(req)
+    }
+}
@@ -0,0 +1,15 @@
+//! Tower layers for concerns that sit in front of the RPC handlers, rejecting requests with
+//! properly framed Connect errors (or, like `RpcMethodInfoLayer`, telling other layers about them).
+
+pub mod acl;
+pub mod grpc;
+#[cfg(feature = "hmac")]
+pub mod hmac;
+pub mod idempotency;
+pub mod identity;
+pub mod kill_switch;
+pub mod maintenance;
+pub mod method_info;
+pub mod request_id;
+pub mod shutdown;
+pub mod usage;
@@ -0,0 +1,207 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+
+use axum::{
+    http::{HeaderName, HeaderValue, Request},
+    response::Response,
+};
+use futures::future::BoxFuture;
+use tower::{Layer, Service};
+
+use crate::parts::RpcRequestId;
+
+/// The header request IDs are read from and echoed in.
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Gives every request an ID to correlate its logs, traces and errors by: the caller's
+/// `x-request-id` when it sent a usable one (1 to 128 visible ASCII characters), otherwise a new
+/// random one. Handlers read it with the `RpcRequestId` extractor.
+///
+/// ```ignore
+/// let app = Router::new()
+///     .rpc(HelloWorldService::say_hello(say_hello))
+///     .layer(RequestIdLayer::new());
+/// ```
+///
+/// The ID goes back in the `x-request-id` response header and in the metadata of the errors RPCs
+/// fail with, so it also reaches clients in the EndStreamResponse of a stream (or the trailers of
+/// a gRPC call). With the `tracing` feature, RPC spans have it as their `request_id`.
+#[derive(Clone, Debug, Default)]
+pub struct RequestIdLayer {}
+
+impl RequestIdLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestId<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestId { inner }
+    }
+}
+
+/// The service produced by `RequestIdLayer`.
+#[derive(Clone)]
+pub struct RequestId<S> {
+    inner: S,
+}
+
+impl<S, B> Service<Request<B>> for RequestId<S>
+where
+    S: Service<Request<B>, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        let id = match req.headers().get(&X_REQUEST_ID).map(HeaderValue::to_str) {
+            Some(Ok(id)) if is_usable(id) => id.to_string(),
+            _ => generate(),
+        };
+        let value = HeaderValue::from_str(&id).expect("request IDs are visible ASCII");
+        req.headers_mut().insert(X_REQUEST_ID, value.clone());
+        req.extensions_mut().insert(RpcRequestId(id));
+
+        let res = self.inner.call(req);
+        Box::pin(async move {
+            let mut res = res.await?;
+            res.headers_mut().insert(X_REQUEST_ID, value);
+            Ok(res)
+        })
+    }
+}
+
+fn is_usable(id: &str) -> bool {
+    (1..=128).contains(&id.len()) && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// 32 random hex digits. Random enough to not collide, but not meant to be unguessable.
+fn generate() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    // Every `RandomState` is keyed differently, from keys seeded randomly per thread.
+    let state = RandomState::new();
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let hash = |half: u64| {
+        let mut hasher = state.build_hasher();
+        hasher.write_u64(n);
+        hasher.write_u64(half);
+        hasher.finish()
+    };
+    format!("{:016x}{:016x}", hash(0), hash(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{header, StatusCode},
+    };
+    use futures::{stream, Stream};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        error::{RpcError, RpcErrorCode},
+        handler::RpcService,
+        health::{HealthCheckRequest, HealthCheckResponse},
+    };
+
+    /// Fails with the request ID it got as the message.
+    async fn check(
+        RpcRequestId(id): RpcRequestId,
+        _: HealthCheckRequest,
+    ) -> Result<HealthCheckResponse, RpcError> {
+        Err(RpcError::new(RpcErrorCode::NotFound, id))
+    }
+
+    async fn watch(
+        RpcRequestId(id): RpcRequestId,
+        _: HealthCheckRequest,
+    ) -> impl Stream<Item = Result<HealthCheckResponse, RpcError>> {
+        stream::iter([Err(RpcError::new(RpcErrorCode::NotFound, id))])
+    }
+
+    async fn call(id: Option<&str>) -> (StatusCode, String, serde_json::Value) {
+        let service = RequestIdLayer::new().layer(RpcService::unary(check, ()));
+        let mut req = Request::post("/grpc.health.v1.Health/Check")
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(id) = id {
+            req = req.header(X_REQUEST_ID, id);
+        }
+        let res = service
+            .oneshot(req.body(Body::from("{}")).unwrap())
+            .await
+            .unwrap();
+        let status = res.status();
+        let header = res.headers()[X_REQUEST_ID].to_str().unwrap().to_string();
+        let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        (status, header, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn keeps_usable_request_ids() {
+        let (status, header, error) = call(Some("req-123")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(header, "req-123");
+        assert_eq!(error["code"], "not_found");
+        assert_eq!(error["message"], "req-123");
+    }
+
+    #[tokio::test]
+    async fn replaces_missing_and_unusable_request_ids() {
+        let too_long = "x".repeat(129);
+        for id in [
+            None,
+            Some("has space"),
+            Some("naïve"),
+            Some(too_long.as_str()),
+        ] {
+            let (status, header, error) = call(id).await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(header.len(), 32, "{id:?}");
+            assert!(header.bytes().all(|b| b.is_ascii_hexdigit()), "{header}");
+            assert_eq!(error["code"], "not_found");
+            assert_eq!(error["message"], header.as_str());
+        }
+
+        let (_, first, _) = call(None).await;
+        let (_, second, _) = call(None).await;
+        assert_ne!(first, second);
+    }
+
+    #[tokio::test]
+    async fn adds_request_ids_to_stream_errors() {
+        let service = RequestIdLayer::new().layer(RpcService::server_stream(watch, ()));
+        let mut body = vec![0, 0, 0, 0, 2];
+        body.extend_from_slice(b"{}");
+        let req = Request::post("/grpc.health.v1.Health/Watch")
+            .header(header::CONTENT_TYPE, "application/connect+json")
+            .header(X_REQUEST_ID, "req-123")
+            .body(Body::from(body))
+            .unwrap();
+        let res = service.oneshot(req).await.unwrap();
+        assert_eq!(res.headers()[X_REQUEST_ID], "req-123");
+
+        let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        // The EndStreamResponse is the only message.
+        assert_eq!(body[0], 2);
+        let end: serde_json::Value = serde_json::from_slice(&body[5..]).unwrap();
+        assert_eq!(end["error"]["code"], "not_found");
+        assert_eq!(end["error"]["message"], "req-123");
+        assert_eq!(end["metadata"]["x-request-id"][0], "req-123");
+    }
+}
@@ -0,0 +1,228 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    http::Request,
+    response::Response,
+};
+use futures::future::{ready, BoxFuture, Either, Ready};
+use http_body::{Frame, SizeHint};
+use tokio::sync::watch;
+use tower::{Layer, Service};
+
+use crate::{
+    handler::codec::encode_error_response_for_headers,
+    prelude::{RpcError, RpcErrorCode},
+};
+
+/// Graceful shutdown for the RPCs behind a `ShutdownLayer`. Once `shutdown` is called, new RPCs
+/// are rejected with `Unavailable`, those in flight get a drain period to finish, and server
+/// streams still open after it are ended with an `Unavailable` EndStreamResponse (which clients
+/// can retry against another instance). Clones share the state. Hook it into axum's graceful
+/// shutdown:
+///
+/// ```ignore
+/// let shutdown = RpcShutdown::new();
+/// let app = Router::new()
+///     .rpc(HelloWorldService::say_hello_stream(say_hello_stream))
+///     .layer(ShutdownLayer::new(shutdown.clone()));
+///
+/// axum::serve(listener, app)
+///     .with_graceful_shutdown(async move {
+///         tokio::signal::ctrl_c().await.unwrap();
+///         shutdown.shutdown(Duration::from_secs(30)).await;
+///     })
+///     .await?;
+/// ```
+#[derive(Clone)]
+pub struct RpcShutdown {
+    phase: Arc<watch::Sender<Phase>>,
+    in_flight: Arc<watch::Sender<usize>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    Running,
+    Draining,
+    /// The drain period is over, open streams are ended.
+    Expired,
+}
+
+impl RpcShutdown {
+    pub fn new() -> Self {
+        Self {
+            phase: Arc::new(watch::Sender::new(Phase::Running)),
+            in_flight: Arc::new(watch::Sender::new(0)),
+        }
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        *self.phase.borrow() != Phase::Running
+    }
+
+    /// The number of RPCs running, counting streams until their response has been sent.
+    pub fn in_flight(&self) -> usize {
+        *self.in_flight.borrow()
+    }
+
+    /// Stops accepting RPCs, waits up to `drain_period` for those in flight to finish, then ends
+    /// the server streams still open. Resolves once every RPC is done. Unary calls are never cut
+    /// off, bound them with a timeout (see `RpcServiceConfig::timeout`) for this to resolve in time.
+    pub async fn shutdown(&self, drain_period: Duration) {
+        self.phase.send_if_modified(|phase| {
+            let running = *phase == Phase::Running;
+            if running {
+                *phase = Phase::Draining;
+            }
+            running
+        });
+
+        let _ = tokio::time::timeout(drain_period, self.drained()).await;
+        self.phase.send_replace(Phase::Expired);
+        self.drained().await;
+    }
+
+    async fn drained(&self) {
+        let mut in_flight = self.in_flight.subscribe();
+        let _ = in_flight.wait_for(|in_flight| *in_flight == 0).await;
+    }
+
+    /// Resolves once the drain period is over, for streams to end themselves.
+    pub(crate) fn expired(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut phase = self.phase.subscribe();
+        async move {
+            let _ = phase.wait_for(|phase| *phase == Phase::Expired).await;
+        }
+    }
+
+    pub(crate) fn is_expired(&self) -> bool {
+        *self.phase.borrow() == Phase::Expired
+    }
+
+    fn track(&self) -> InFlight {
+        self.in_flight.send_modify(|in_flight| *in_flight += 1);
+        InFlight(self.in_flight.clone())
+    }
+}
+
+impl Default for RpcShutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts an RPC as in flight until dropped.
+struct InFlight(Arc<watch::Sender<usize>>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.send_modify(|in_flight| *in_flight -= 1);
+    }
+}
+
+/// The error RPCs are rejected (and streams ended) with during a shutdown.
+pub(crate) fn shutting_down() -> RpcError {
+    RpcError::new(
+        RpcErrorCode::Unavailable,
+        "The server is shutting down".to_string(),
+    )
+}
+
+/// Tracks the RPCs in flight for an `RpcShutdown`, and rejects new ones once it has started.
+#[derive(Clone)]
+pub struct ShutdownLayer {
+    shutdown: RpcShutdown,
+}
+
+impl ShutdownLayer {
+    pub fn new(shutdown: RpcShutdown) -> Self {
+        Self { shutdown }
+    }
+}
+
+impl<S> Layer<S> for ShutdownLayer {
+    type Service = ShutdownGate<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ShutdownGate {
+            inner,
+            shutdown: self.shutdown.clone(),
+        }
+    }
+}
+
+/// The service produced by `ShutdownLayer`.
+#[derive(Clone)]
+pub struct ShutdownGate<S> {
+    inner: S,
+    shutdown: RpcShutdown,
+}
+
+impl<S, B> Service<Request<B>> for ShutdownGate<S>
+where
+    S: Service<Request<B>, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future =
+        Either<Ready<Result<Response, S::Error>>, BoxFuture<'static, Result<Response, S::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        if self.shutdown.is_shutting_down() {
+            let res = encode_error_response_for_headers(&shutting_down(), req.headers());
+            return Either::Left(ready(Ok(res)));
+        }
+
+        // Counted until the response body is done, which for streams is their end.
+        let in_flight = self.shutdown.track();
+        req.extensions_mut().insert(self.shutdown.clone());
+        let res = self.inner.call(req);
+
+        Either::Right(Box::pin(async move {
+            let res = res.await?;
+            Ok(res.map(|body| {
+                Body::new(TrackedBody {
+                    body,
+                    _in_flight: in_flight,
+                })
+            }))
+        }))
+    }
+}
+
+/// A response body that keeps its RPC counted as in flight.
+struct TrackedBody {
+    body: Body,
+    _in_flight: InFlight,
+}
+
+impl http_body::Body for TrackedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Pin::new(&mut self.body).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
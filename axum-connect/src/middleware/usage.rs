@@ -0,0 +1,511 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use axum::{
+    http::{header, request::Parts, Request},
+    response::Response,
+    BoxError,
+};
+use futures::future::BoxFuture;
+use http_body::Body;
+use tower::{Layer, Service};
+
+use crate::{
+    error_details::{QuotaFailure, QuotaViolation},
+    handler::codec::encode_error_response_for_headers,
+    prelude::{PeerIdentity, RpcError, RpcErrorCode},
+};
+
+/// Usage of one method by one principal, within one accounting period.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub calls: u64,
+    pub request_bytes: u64,
+    pub response_bytes: u64,
+}
+
+impl Usage {
+    pub fn bytes(&self) -> u64 {
+        self.request_bytes + self.response_bytes
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct UsageKey {
+    pub principal: String,
+    /// Like `hello.HelloWorldService/SayHello`.
+    pub method: String,
+    /// Start of the accounting period, in seconds since the Unix epoch.
+    pub period_start: u64,
+}
+
+/// Where `UsageLayer` keeps its counters.
+#[async_trait]
+pub trait UsageStore: Send + Sync + 'static {
+    /// The usage so far (zero if there's none).
+    async fn get(&self, key: &UsageKey) -> Result<Usage, BoxError>;
+
+    /// Adds `usage` to the counters for `key`.
+    async fn add(&self, key: &UsageKey, usage: Usage) -> Result<(), BoxError>;
+}
+
+/// Keeps usage in process memory, for tests and single instance deployments. Only the current and
+/// the previous accounting period are kept.
+#[derive(Default)]
+pub struct MemoryUsageStore {
+    usage: Mutex<HashMap<UsageKey, Usage>>,
+}
+
+impl MemoryUsageStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A copy of all counters, for reporting.
+    pub fn snapshot(&self) -> HashMap<UsageKey, Usage> {
+        self.usage.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl UsageStore for MemoryUsageStore {
+    async fn get(&self, key: &UsageKey) -> Result<Usage, BoxError> {
+        Ok(self
+            .usage
+            .lock()
+            .unwrap()
+            .get(key)
+            .copied()
+            .unwrap_or_default())
+    }
+
+    async fn add(&self, key: &UsageKey, usage: Usage) -> Result<(), BoxError> {
+        let mut all = self.usage.lock().unwrap();
+
+        // Forget periods before the previous one, whatever their length.
+        let previous_period = all
+            .keys()
+            .map(|k| k.period_start)
+            .filter(|start| *start < key.period_start)
+            .max();
+        if let Some(previous_period) = previous_period {
+            all.retain(|k, _| k.period_start >= previous_period);
+        }
+
+        let total = all.entry(key.clone()).or_default();
+        total.calls += usage.calls;
+        total.request_bytes += usage.request_bytes;
+        total.response_bytes += usage.response_bytes;
+        Ok(())
+    }
+}
+
+/// Keeps usage in Redis, shared by all instances. Each `UsageKey` is a hash with `calls`,
+/// `request_bytes` and `response_bytes` fields, expiring a while (7 days by default) after it was
+/// last written.
+#[cfg(feature = "redis")]
+#[derive(Clone)]
+pub struct RedisUsageStore {
+    connection: redis::aio::ConnectionManager,
+    prefix: String,
+    expire_after: Duration,
+}
+
+#[cfg(feature = "redis")]
+impl RedisUsageStore {
+    pub fn new(connection: redis::aio::ConnectionManager) -> Self {
+        Self {
+            connection,
+            prefix: "axum-connect:usage".to_string(),
+            expire_after: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+
+    /// Prefix of the Redis keys, `axum-connect:usage` by default.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    pub fn expire_after(mut self, expire_after: Duration) -> Self {
+        self.expire_after = expire_after;
+        self
+    }
+
+    fn redis_key(&self, key: &UsageKey) -> String {
+        format!(
+            "{}:{}:{}:{}",
+            self.prefix, key.period_start, key.principal, key.method
+        )
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl UsageStore for RedisUsageStore {
+    async fn get(&self, key: &UsageKey) -> Result<Usage, BoxError> {
+        let (calls, request_bytes, response_bytes): (Option<u64>, Option<u64>, Option<u64>) =
+            redis::cmd("HMGET")
+                .arg(self.redis_key(key))
+                .arg(&["calls", "request_bytes", "response_bytes"])
+                .query_async(&mut self.connection.clone())
+                .await?;
+
+        Ok(Usage {
+            calls: calls.unwrap_or_default(),
+            request_bytes: request_bytes.unwrap_or_default(),
+            response_bytes: response_bytes.unwrap_or_default(),
+        })
+    }
+
+    async fn add(&self, key: &UsageKey, usage: Usage) -> Result<(), BoxError> {
+        let redis_key = self.redis_key(key);
+        redis::pipe()
+            .atomic()
+            .hincr(&redis_key, "calls", usage.calls)
+            .ignore()
+            .hincr(&redis_key, "request_bytes", usage.request_bytes)
+            .ignore()
+            .hincr(&redis_key, "response_bytes", usage.response_bytes)
+            .ignore()
+            .expire(&redis_key, self.expire_after.as_secs() as usize)
+            .ignore()
+            .query_async::<_, ()>(&mut self.connection.clone())
+            .await?;
+        Ok(())
+    }
+}
+
+/// A hard limit on the usage of a method by one principal, per accounting period. Bytes are request
+/// plus response bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quota {
+    pub max_calls: Option<u64>,
+    pub max_bytes: Option<u64>,
+}
+
+type PrincipalFn = dyn Fn(&Parts) -> Option<String> + Send + Sync;
+
+/// Records per-principal, per-method call counts and payload bytes to a `UsageStore`, optionally
+/// enforcing `Quota`s.
+///
+/// ```ignore
+/// let app = Router::new()
+///     .rpc(HelloWorldService::say_hello(say_hello))
+///     .layer(
+///         UsageLayer::new(MemoryUsageStore::new())
+///             .period(Duration::from_secs(60 * 60))
+///             .quota("hello.HelloWorldService", Quota { max_calls: Some(1000), max_bytes: None }),
+///     );
+/// ```
+///
+/// The principal is the caller's `PeerIdentity` by default, or `anonymous`; set your own with
+/// `principal`. Over quota calls fail with `ResourceExhausted` and a `google.rpc.QuotaFailure`
+/// detail. Quotas are checked before the call and usage is recorded after it, so concurrent calls
+/// can overshoot a limit slightly.
+///
+/// Request bytes come from `Content-Length` and response bytes from the body size, when it's known
+/// up front. That means streaming responses only count as calls.
+#[derive(Clone)]
+pub struct UsageLayer {
+    config: Arc<UsageConfig>,
+}
+
+#[derive(Clone)]
+struct UsageConfig {
+    store: Arc<dyn UsageStore>,
+    period: Duration,
+    principal: Arc<PrincipalFn>,
+    quotas: HashMap<String, Quota>,
+}
+
+impl UsageLayer {
+    pub fn new<T>(store: T) -> Self
+    where
+        T: UsageStore,
+    {
+        Self::with_shared_store(Arc::new(store))
+    }
+
+    /// Like `new`, for a store you keep a handle to (say, to report from a `MemoryUsageStore`).
+    pub fn with_shared_store(store: Arc<dyn UsageStore>) -> Self {
+        Self {
+            config: Arc::new(UsageConfig {
+                store,
+                period: Duration::from_secs(24 * 60 * 60),
+                principal: Arc::new(|parts| {
+                    parts
+                        .extensions
+                        .get::<PeerIdentity>()
+                        .and_then(|identity| identity.names().next().map(str::to_string))
+                }),
+                quotas: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Length of the accounting period that usage is bucketed into (and quotas reset after), a day
+    /// by default. Periods are aligned to the Unix epoch.
+    pub fn period(mut self, period: Duration) -> Self {
+        assert!(
+            period.as_secs() > 0,
+            "usage period must be at least a second"
+        );
+        self.config_mut().period = period;
+        self
+    }
+
+    /// How to name the caller. Requests that return `None` are accounted to `anonymous`.
+    pub fn principal<F>(mut self, principal: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<String> + Send + Sync + 'static,
+    {
+        self.config_mut().principal = Arc::new(principal);
+        self
+    }
+
+    /// Limits calls to `target`, which is a method (`hello.HelloWorldService/SayHello`), a whole
+    /// service (`hello.HelloWorldService`) or `*` for anything else. The most specific one applies.
+    pub fn quota(mut self, target: impl Into<String>, quota: Quota) -> Self {
+        self.config_mut().quotas.insert(target.into(), quota);
+        self
+    }
+
+    fn config_mut(&mut self) -> &mut UsageConfig {
+        Arc::make_mut(&mut self.config)
+    }
+}
+
+impl UsageConfig {
+    fn quota_for(&self, method: &str) -> Option<&Quota> {
+        let service = method.split('/').next().unwrap_or_default();
+        self.quotas
+            .get(method)
+            .or_else(|| self.quotas.get(service))
+            .or_else(|| self.quotas.get("*"))
+    }
+
+    fn quota_failure(&self, key: &UsageKey, quota: &Quota, usage: &Usage) -> Option<RpcError> {
+        let description = match (quota.max_calls, quota.max_bytes) {
+            (Some(max_calls), _) if usage.calls >= max_calls => {
+                format!("Call quota of {} per period exceeded", max_calls)
+            }
+            (_, Some(max_bytes)) if usage.bytes() >= max_bytes => {
+                format!("Byte quota of {} per period exceeded", max_bytes)
+            }
+            _ => return None,
+        };
+
+        let failure = QuotaFailure {
+            violations: vec![QuotaViolation {
+                subject: format!("principal:{}", key.principal),
+                description: description.clone(),
+            }],
+        };
+
+        Some(
+            RpcError::new(
+                RpcErrorCode::ResourceExhausted,
+                format!("{} for {}", description, key.method),
+            )
+            .with_detail(failure),
+        )
+    }
+}
+
+impl<S> Layer<S> for UsageLayer {
+    type Service = UsageAccounting<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        UsageAccounting {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+/// The service produced by `UsageLayer`.
+#[derive(Clone)]
+pub struct UsageAccounting<S> {
+    inner: S,
+    config: Arc<UsageConfig>,
+}
+
+impl<S, B> Service<Request<B>> for UsageAccounting<S>
+where
+    S: Service<Request<B>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // The clone might not be ready, keep the one that was polled.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+
+            // RPC paths end in `/package.Service/Method`, possibly under a prefix (like a tenant).
+            let mut segments = parts.uri.path().rsplit('/');
+            let method = match (segments.next(), segments.next()) {
+                (Some(method), Some(service)) => format!("{}/{}", service, method),
+                _ => parts.uri.path().to_string(),
+            };
+
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let period = config.period.as_secs();
+            let key = UsageKey {
+                principal: (config.principal)(&parts).unwrap_or_else(|| "anonymous".to_string()),
+                method,
+                period_start: now - now % period,
+            };
+
+            if let Some(quota) = config.quota_for(&key.method) {
+                let error = match config.store.get(&key).await {
+                    Ok(usage) => config.quota_failure(&key, quota, &usage),
+                    Err(e) => Some(RpcError::new(
+                        RpcErrorCode::Unavailable,
+                        format!("Failed to check quota. {}", e),
+                    )),
+                };
+
+                if let Some(error) = error {
+                    return Ok(encode_error_response_for_headers(&error, &parts.headers));
+                }
+            }
+
+            let request_bytes = parts
+                .headers
+                .get(header::CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default();
+
+            let res = inner.call(Request::from_parts(parts, body)).await?;
+
+            let usage = Usage {
+                calls: 1,
+                request_bytes,
+                response_bytes: res.body().size_hint().exact().unwrap_or_default(),
+            };
+            // Accounting is best effort, a failing store shouldn't fail the call after the fact.
+            let _ = config.store.add(&key, usage).await;
+
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use axum::body::{to_bytes, Body};
+    use tower::{service_fn, ServiceExt};
+
+    use super::*;
+
+    async fn call(layer: &UsageLayer, path: &str) -> Response {
+        let service = layer.layer(service_fn(|_: Request<Body>| async {
+            Ok::<_, Infallible>(Response::new(Body::from("{}")))
+        }));
+        let req = Request::post(path)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::CONTENT_LENGTH, "2")
+            .body(Body::from("{}"))
+            .unwrap();
+        service.oneshot(req).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn counts_calls_and_enforces_quotas() {
+        let store = Arc::new(MemoryUsageStore::new());
+        let layer = UsageLayer::with_shared_store(store.clone())
+            .quota(
+                "hello.HelloWorldService",
+                Quota {
+                    max_calls: Some(2),
+                    max_bytes: None,
+                },
+            )
+            .quota(
+                "hello.HelloWorldService/SayHello",
+                Quota {
+                    max_calls: Some(1),
+                    max_bytes: None,
+                },
+            );
+
+        assert!(call(&layer, "/hello.HelloWorldService/SayHello")
+            .await
+            .status()
+            .is_success());
+        let res = call(&layer, "/hello.HelloWorldService/SayHello").await;
+        let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("resource_exhausted"));
+
+        // The service quota applies to its other methods.
+        assert!(call(&layer, "/hello.HelloWorldService/Other")
+            .await
+            .status()
+            .is_success());
+        assert!(call(&layer, "/hello.HelloWorldService/Other")
+            .await
+            .status()
+            .is_success());
+        assert!(!call(&layer, "/hello.HelloWorldService/Other")
+            .await
+            .status()
+            .is_success());
+
+        let usage = store
+            .snapshot()
+            .into_iter()
+            .find(|(key, _)| key.method == "hello.HelloWorldService/SayHello")
+            .unwrap();
+        assert_eq!(usage.0.principal, "anonymous");
+        assert_eq!(
+            usage.1,
+            Usage {
+                calls: 1,
+                request_bytes: 2,
+                response_bytes: 2,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn can_be_configured_after_being_cloned() {
+        let layer = UsageLayer::new(MemoryUsageStore::new());
+        let _clone = layer.clone();
+        let layer = layer.quota(
+            "*",
+            Quota {
+                max_calls: Some(0),
+                max_bytes: None,
+            },
+        );
+
+        assert!(!call(&layer, "/hello.HelloWorldService/SayHello")
+            .await
+            .status()
+            .is_success());
+    }
+}
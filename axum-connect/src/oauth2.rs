@@ -0,0 +1,342 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use axum::http::{self, header};
+use prost::Message;
+use serde::Deserialize;
+
+use crate::{
+    error::{RpcError, RpcErrorCode, RpcIntoError},
+    parts::RpcFromRequestParts,
+};
+
+/// Validates opaque bearer tokens against an OAuth2 (RFC 7662) token introspection endpoint.
+/// Added to the router as an extension, it backs the `Introspection` extractor:
+///
+/// ```ignore
+/// let introspector = TokenIntrospector::new("https://auth.example.com/oauth2/introspect")
+///     .client_credentials("my-api", "secret")
+///     .cache_ttl(Duration::from_secs(30));
+///
+/// let app = Router::new()
+///     .rpc(HelloWorldService::say_hello(say_hello))
+///     .layer(Extension(introspector));
+///
+/// async fn say_hello(token: Introspection, request: HelloRequest) -> RpcResult<HelloResponse> {
+///     if !token.has_scope("hello:write") { ... }
+/// }
+/// ```
+///
+/// Active tokens are cached for `cache_ttl` (one minute by default, never past the token's own
+/// `exp`), so a token revoked at the authorization server can still be accepted for that long.
+#[derive(Clone)]
+pub struct TokenIntrospector {
+    config: Arc<IntrospectorConfig>,
+    cache: Arc<Mutex<HashMap<String, (Instant, Introspection)>>>,
+}
+
+#[derive(Clone)]
+struct IntrospectorConfig {
+    endpoint: String,
+    credentials: Option<(String, String)>,
+    cache_ttl: Duration,
+    client: reqwest::Client,
+}
+
+/// The introspection response for an active token. Use it as an extractor to require a valid
+/// bearer token (see `TokenIntrospector`).
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Introspection {
+    pub active: bool,
+    /// Space separated scopes, see `scopes` and `has_scope`.
+    pub scope: Option<String>,
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub sub: Option<String>,
+    pub aud: Option<serde_json::Value>,
+    pub iss: Option<String>,
+    /// Expiry, in seconds since the Unix epoch.
+    pub exp: Option<u64>,
+    /// Any other (extension) members of the response.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+impl Introspection {
+    pub fn scopes(&self) -> impl Iterator<Item = &str> {
+        self.scope.as_deref().unwrap_or_default().split_whitespace()
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes().any(|s| s == scope)
+    }
+}
+
+impl TokenIntrospector {
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            config: Arc::new(IntrospectorConfig {
+                endpoint: endpoint.into(),
+                credentials: None,
+                cache_ttl: Duration::from_secs(60),
+                client: reqwest::Client::new(),
+            }),
+            cache: Default::default(),
+        }
+    }
+
+    /// Authenticates to the introspection endpoint with HTTP Basic auth, as most servers require.
+    pub fn client_credentials(
+        mut self,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+    ) -> Self {
+        self.config_mut().credentials = Some((client_id.into(), client_secret.into()));
+        self
+    }
+
+    /// How long an active token is trusted without asking the endpoint again. Zero disables the
+    /// cache.
+    pub fn cache_ttl(mut self, ttl: Duration) -> Self {
+        self.config_mut().cache_ttl = ttl;
+        self
+    }
+
+    /// Uses `client` for calls to the introspection endpoint, for custom TLS roots, proxies or
+    /// timeouts.
+    pub fn http_client(mut self, client: reqwest::Client) -> Self {
+        self.config_mut().client = client;
+        self
+    }
+
+    fn config_mut(&mut self) -> &mut IntrospectorConfig {
+        // Tokens checked by clones configured before don't count for this one.
+        self.cache = Default::default();
+        Arc::make_mut(&mut self.config)
+    }
+
+    /// Introspects `token`, failing with `Unauthenticated` unless it's active.
+    pub async fn introspect(&self, token: &str) -> Result<Introspection, RpcError> {
+        if let Some((expires_at, introspection)) = self.cache.lock().unwrap().get(token) {
+            if *expires_at > Instant::now() {
+                return Ok(introspection.clone());
+            }
+        }
+
+        let mut request = self
+            .config
+            .client
+            .post(&self.config.endpoint)
+            .form(&[("token", token), ("token_type_hint", "access_token")]);
+        if let Some((client_id, client_secret)) = &self.config.credentials {
+            request = request.basic_auth(client_id, Some(client_secret));
+        }
+
+        let introspection = request
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| unauthenticated(format!("Token introspection failed. {}", e)))?
+            .json::<Introspection>()
+            .await
+            .map_err(|e| unauthenticated(format!("Invalid introspection response. {}", e)))?;
+
+        if !introspection.active {
+            return Err(unauthenticated("Bearer token is not active".to_string()));
+        }
+
+        let mut ttl = self.config.cache_ttl;
+        if let Some(exp) = introspection.exp {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            ttl = ttl.min(Duration::from_secs(exp.saturating_sub(now)));
+        }
+        if !ttl.is_zero() {
+            let now = Instant::now();
+            let mut cache = self.cache.lock().unwrap();
+            cache.retain(|_, (expires_at, _)| *expires_at > now);
+            cache.insert(token.to_string(), (now + ttl, introspection.clone()));
+        }
+
+        Ok(introspection)
+    }
+}
+
+fn unauthenticated(message: String) -> RpcError {
+    RpcError::new(RpcErrorCode::Unauthenticated, message)
+}
+
+#[async_trait]
+impl<M, S> RpcFromRequestParts<M, S> for Introspection
+where
+    M: Message,
+    S: Send + Sync,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let introspector = parts
+            .extensions
+            .get::<TokenIntrospector>()
+            .cloned()
+            .ok_or_else(|| {
+                (
+                    RpcErrorCode::Internal,
+                    "Missing TokenIntrospector extension",
+                )
+                    .rpc_into_error()
+            })?;
+
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .ok_or_else(|| unauthenticated("Missing bearer token".to_string()))?;
+
+        introspector.introspect(token.trim()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{extract::State, routing::post, Form, Json, Router};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Serves an introspection endpoint that knows the `active` token, counting its calls.
+    async fn endpoint() -> (String, Arc<AtomicUsize>) {
+        async fn introspect(
+            State(calls): State<Arc<AtomicUsize>>,
+            headers: http::HeaderMap,
+            Form(form): Form<HashMap<String, String>>,
+        ) -> Result<Json<serde_json::Value>, http::StatusCode> {
+            calls.fetch_add(1, Ordering::SeqCst);
+            // `api:secret`
+            if headers
+                .get(header::AUTHORIZATION)
+                .is_some_and(|v| v != "Basic YXBpOnNlY3JldA==")
+            {
+                return Err(http::StatusCode::UNAUTHORIZED);
+            }
+            Ok(Json(match form["token"].as_str() {
+                "active" => serde_json::json!({
+                    "active": true,
+                    "scope": "hello:read hello:write",
+                    "sub": "alice",
+                    "tenant": "acme",
+                }),
+                "fail" => return Err(http::StatusCode::INTERNAL_SERVER_ERROR),
+                _ => serde_json::json!({ "active": false }),
+            }))
+        }
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route("/introspect", post(introspect))
+            .with_state(calls.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/introspect", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, calls)
+    }
+
+    #[tokio::test]
+    async fn accepts_active_tokens_and_caches_them() {
+        let (url, calls) = endpoint().await;
+        let introspector = TokenIntrospector::new(url);
+
+        let token = introspector.introspect("active").await.unwrap();
+        assert_eq!(token.sub.as_deref(), Some("alice"));
+        assert!(token.has_scope("hello:write"));
+        assert!(!token.has_scope("hello"));
+        assert_eq!(token.extra["tenant"], "acme");
+
+        introspector.introspect("active").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_cache_with_a_zero_ttl() {
+        let (url, calls) = endpoint().await;
+        let introspector = TokenIntrospector::new(url).cache_ttl(Duration::ZERO);
+
+        introspector.introspect("active").await.unwrap();
+        introspector.introspect("active").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn rejects_inactive_tokens_and_failed_introspections() {
+        let (url, _) = endpoint().await;
+        let introspector = TokenIntrospector::new(url.clone());
+
+        for token in ["revoked", "fail"] {
+            let e = introspector.introspect(token).await.unwrap_err();
+            assert_eq!(e.code, RpcErrorCode::Unauthenticated);
+        }
+
+        let e = TokenIntrospector::new(url)
+            .client_credentials("api", "wrong")
+            .introspect("active")
+            .await
+            .unwrap_err();
+        assert_eq!(e.code, RpcErrorCode::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn can_be_configured_after_being_cloned() {
+        let (url, _) = endpoint().await;
+        let introspector = TokenIntrospector::new(url);
+        let _clone = introspector.clone();
+
+        let introspector = introspector.client_credentials("api", "secret");
+        assert!(introspector.introspect("active").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn extracts_bearer_tokens() {
+        let (url, _) = endpoint().await;
+        let extract = |authorization: Option<&str>, introspector: Option<TokenIntrospector>| {
+            let mut req = http::Request::builder();
+            if let Some(authorization) = authorization {
+                req = req.header(header::AUTHORIZATION, authorization);
+            }
+            let (mut parts, _) = req.body(()).unwrap().into_parts();
+            if let Some(introspector) = introspector {
+                parts.extensions.insert(introspector);
+            }
+            async move {
+                <Introspection as RpcFromRequestParts<(), ()>>::rpc_from_request_parts(
+                    &mut parts,
+                    &(),
+                )
+                .await
+            }
+        };
+        let introspector = TokenIntrospector::new(url);
+
+        let token = extract(Some("Bearer active"), Some(introspector.clone())).await;
+        assert!(token.unwrap().active);
+
+        for authorization in [None, Some("Basic YXBpOnNlY3JldA=="), Some("Bearer revoked")] {
+            let e = extract(authorization, Some(introspector.clone())).await;
+            assert_eq!(e.unwrap_err().code, RpcErrorCode::Unauthenticated);
+        }
+
+        let e = extract(Some("Bearer active"), None).await;
+        assert_eq!(e.unwrap_err().code, RpcErrorCode::Internal);
+    }
+}
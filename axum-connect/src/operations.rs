@@ -0,0 +1,645 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_stream::stream;
+use async_trait::async_trait;
+use axum::{
+    extract::{Request, State},
+    routing::post,
+    BoxError,
+};
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use futures::{
+    channel::mpsc,
+    future::ready,
+    stream::{self, BoxStream},
+    Stream, StreamExt,
+};
+use pbjson_types::{Any, Empty};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    descriptor::{IdempotencyLevel, MethodDescriptor, MethodKind, ServiceDescriptor},
+    error::{RpcError, RpcErrorCode},
+    handler::{method_not_allowed, RpcHandlerStream, RpcHandlerUnary},
+    router::RpcRouterExt,
+};
+
+/// `google.longrunning.Operation`. `axum-connect-build` maps the proto message to this type, so
+/// RPCs declared to return an `Operation` can return it directly.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Operation {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(message, optional, tag = "2")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Any>,
+    #[prost(bool, tag = "3")]
+    pub done: bool,
+    #[prost(oneof = "operation::Result", tags = "4, 5")]
+    #[serde(flatten)]
+    pub result: Option<operation::Result>,
+}
+
+pub mod operation {
+    use pbjson_types::Any;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, PartialEq, prost::Oneof, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub enum Result {
+        #[prost(message, tag = "4")]
+        Error(super::Status),
+        #[prost(message, tag = "5")]
+        Response(Any),
+    }
+}
+
+/// `google.rpc.Status`, the error of a failed `Operation`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Status {
+    #[prost(int32, tag = "1")]
+    pub code: i32,
+    #[prost(string, tag = "2")]
+    pub message: String,
+    #[prost(message, repeated, tag = "3")]
+    pub details: Vec<Any>,
+}
+
+impl From<&RpcError> for Status {
+    fn from(error: &RpcError) -> Self {
+        Self {
+            code: error.code.as_i32(),
+            message: error.message.clone(),
+            details: error
+                .details
+                .iter()
+                .map(|detail| Any {
+                    type_url: format!("type.googleapis.com/{}", detail.proto_type),
+                    value: STANDARD_NO_PAD
+                        .decode(&detail.proto_b62_value)
+                        .unwrap_or_default()
+                        .into(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// `google.longrunning.GetOperationRequest`, also used by `WatchOperation`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GetOperationRequest {
+    #[prost(string, tag = "1")]
+    pub name: String,
+}
+
+/// `google.longrunning.CancelOperationRequest`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CancelOperationRequest {
+    #[prost(string, tag = "1")]
+    pub name: String,
+}
+
+/// `google.longrunning.DeleteOperationRequest`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DeleteOperationRequest {
+    #[prost(string, tag = "1")]
+    pub name: String,
+}
+
+/// Packs `message` into an `Any`, where `type_name` is its fully qualified name (like
+/// `library.ExportBooksResponse`).
+pub fn pack_any<M>(type_name: &str, message: &M) -> Any
+where
+    M: Message,
+{
+    Any {
+        type_url: format!("type.googleapis.com/{}", type_name),
+        value: message.encode_to_vec().into(),
+    }
+}
+
+/// Where `Operations` keeps operations. Implement it over your database to have operations survive
+/// restarts or be polled through any instance.
+#[async_trait]
+pub trait OperationStore: Send + Sync + 'static {
+    async fn get(&self, name: &str) -> Result<Option<Operation>, BoxError>;
+
+    /// Inserts or replaces the operation.
+    async fn put(&self, operation: Operation) -> Result<(), BoxError>;
+
+    async fn delete(&self, name: &str) -> Result<(), BoxError>;
+
+    /// The operation's current state followed by every later `put` of it, ending once it's done or
+    /// deleted. Empty if there is no such operation.
+    fn watch(&self, name: &str) -> BoxStream<'static, Operation>;
+}
+
+/// Keeps operations in process memory. They are never evicted, delete them once they're consumed.
+#[derive(Default)]
+pub struct MemoryOperationStore {
+    state: Mutex<MemoryState>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    operations: HashMap<String, Operation>,
+    watchers: HashMap<String, Vec<mpsc::UnboundedSender<Operation>>>,
+}
+
+impl MemoryOperationStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl OperationStore for MemoryOperationStore {
+    async fn get(&self, name: &str) -> Result<Option<Operation>, BoxError> {
+        Ok(self.state.lock().unwrap().operations.get(name).cloned())
+    }
+
+    async fn put(&self, operation: Operation) -> Result<(), BoxError> {
+        let mut state = self.state.lock().unwrap();
+
+        if operation.done {
+            // Dropping the senders ends the watch streams, after this last update.
+            for watcher in state.watchers.remove(&operation.name).unwrap_or_default() {
+                let _ = watcher.unbounded_send(operation.clone());
+            }
+        } else if let Some(watchers) = state.watchers.get_mut(&operation.name) {
+            watchers.retain(|watcher| watcher.unbounded_send(operation.clone()).is_ok());
+        }
+
+        state.operations.insert(operation.name.clone(), operation);
+        Ok(())
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), BoxError> {
+        let mut state = self.state.lock().unwrap();
+        state.operations.remove(name);
+        state.watchers.remove(name);
+        Ok(())
+    }
+
+    fn watch(&self, name: &str) -> BoxStream<'static, Operation> {
+        let mut state = self.state.lock().unwrap();
+        let Some(current) = state.operations.get(name).cloned() else {
+            return stream::empty().boxed();
+        };
+
+        if current.done {
+            return stream::once(ready(current)).boxed();
+        }
+
+        let (sender, receiver) = mpsc::unbounded();
+        state
+            .watchers
+            .entry(name.to_string())
+            .or_default()
+            .push(sender);
+
+        stream::once(ready(current)).chain(receiver).boxed()
+    }
+}
+
+/// Long-running operations, in the style of `google.longrunning.Operations`: a handler starts an
+/// operation, returns it right away and finishes the work in the background, while the client polls
+/// (`GetOperation`), watches (`WatchOperation`) or cancels (`CancelOperation`) it.
+///
+/// ```ignore
+/// let operations = Operations::new(MemoryOperationStore::new());
+///
+/// let app = Router::new()
+///     .rpc(LibraryService::export_books(export_books))
+///     .rpc(operations.clone().routes())
+///     .with_state(operations);
+///
+/// async fn export_books(
+///     State(operations): State<Operations>,
+///     request: ExportBooksRequest,
+/// ) -> RpcResult<Operation> {
+///     let handle = operations.start(None).await?;
+///     let operation = handle.operation();
+///     tokio::spawn(async move {
+///         match export(request, &handle).await {
+///             Ok(response) => handle.complete("library.ExportBooksResponse", &response).await,
+///             Err(e) => handle.fail(&e).await,
+///         }
+///     });
+///     Ok(operation)
+/// }
+/// ```
+///
+/// `WatchOperation` is not part of the standard service: it's a server stream of the operation,
+/// sent on every change until it's done.
+#[derive(Clone)]
+pub struct Operations {
+    store: Arc<dyn OperationStore>,
+    running: Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>,
+}
+
+impl Operations {
+    pub const DESCRIPTOR: ServiceDescriptor = ServiceDescriptor {
+        name: "google.longrunning.Operations",
+        package: "google.longrunning",
+        methods: &[
+            MethodDescriptor {
+                name: "GetOperation",
+                service: "google.longrunning.Operations",
+                path: "/google.longrunning.Operations/GetOperation",
+                input_type: "google.longrunning.GetOperationRequest",
+                output_type: "google.longrunning.Operation",
+                kind: MethodKind::Unary,
+                idempotency: IdempotencyLevel::Unknown,
+                deprecated: false,
+            },
+            MethodDescriptor {
+                name: "CancelOperation",
+                service: "google.longrunning.Operations",
+                path: "/google.longrunning.Operations/CancelOperation",
+                input_type: "google.longrunning.CancelOperationRequest",
+                output_type: "google.protobuf.Empty",
+                kind: MethodKind::Unary,
+                idempotency: IdempotencyLevel::Unknown,
+                deprecated: false,
+            },
+            MethodDescriptor {
+                name: "DeleteOperation",
+                service: "google.longrunning.Operations",
+                path: "/google.longrunning.Operations/DeleteOperation",
+                input_type: "google.longrunning.DeleteOperationRequest",
+                output_type: "google.protobuf.Empty",
+                kind: MethodKind::Unary,
+                idempotency: IdempotencyLevel::Unknown,
+                deprecated: false,
+            },
+            MethodDescriptor {
+                name: "WatchOperation",
+                service: "google.longrunning.Operations",
+                path: "/google.longrunning.Operations/WatchOperation",
+                input_type: "google.longrunning.GetOperationRequest",
+                output_type: "google.longrunning.Operation",
+                kind: MethodKind::ServerStreaming,
+                idempotency: IdempotencyLevel::Unknown,
+                deprecated: false,
+            },
+        ],
+    };
+
+    pub fn new<T>(store: T) -> Self
+    where
+        T: OperationStore,
+    {
+        Self {
+            store: Arc::new(store),
+            running: Default::default(),
+        }
+    }
+
+    /// Creates a new, not yet done, operation.
+    pub async fn start(&self, metadata: Option<Any>) -> Result<OperationHandle, RpcError> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let operation = Operation {
+            name: format!(
+                "operations/{:016x}{:08x}",
+                nanos,
+                NEXT_ID.fetch_add(1, Ordering::Relaxed) as u32
+            ),
+            metadata,
+            done: false,
+            result: None,
+        };
+        self.store
+            .put(operation.clone())
+            .await
+            .map_err(store_error)?;
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.running
+            .lock()
+            .unwrap()
+            .insert(operation.name.clone(), cancelled.clone());
+
+        Ok(OperationHandle {
+            operations: self.clone(),
+            operation,
+            cancelled,
+        })
+    }
+
+    pub async fn get(&self, name: &str) -> Result<Operation, RpcError> {
+        self.store
+            .get(name)
+            .await
+            .map_err(store_error)?
+            .ok_or_else(|| not_found(name))
+    }
+
+    /// Asks the operation to stop. It's marked done with a `Canceled` error right away, and
+    /// `OperationHandle::is_cancelled` starts returning true if it runs in this process.
+    pub async fn cancel(&self, name: &str) -> Result<(), RpcError> {
+        let mut operation = self.get(name).await?;
+
+        if let Some(cancelled) = self.running.lock().unwrap().get(name) {
+            cancelled.store(true, Ordering::Relaxed);
+        }
+
+        if !operation.done {
+            operation.done = true;
+            operation.result = Some(operation::Result::Error(Status::from(&RpcError::new(
+                RpcErrorCode::Canceled,
+                "Operation was cancelled".to_string(),
+            ))));
+            self.store.put(operation).await.map_err(store_error)?;
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the operation. Doesn't cancel it.
+    pub async fn delete(&self, name: &str) -> Result<(), RpcError> {
+        self.get(name).await?;
+        self.store.delete(name).await.map_err(store_error)
+    }
+
+    /// The operation, every time it changes, until it's done.
+    pub fn watch(&self, name: &str) -> impl Stream<Item = Result<Operation, RpcError>> + Send {
+        let name = name.to_string();
+        let mut updates = self.store.watch(&name);
+
+        stream! {
+            let mut found = false;
+            while let Some(operation) = updates.next().await {
+                found = true;
+                let done = operation.done;
+                yield Ok(operation);
+                if done {
+                    break;
+                }
+            }
+
+            if !found {
+                yield Err(not_found(&name));
+            }
+        }
+    }
+
+    /// Registers `GetOperation`, `CancelOperation`, `DeleteOperation` and `WatchOperation` of the
+    /// `google.longrunning.Operations` service, use it with `RpcRouterExt::rpc`.
+    pub fn routes<S, R>(self) -> impl FnOnce(R) -> R
+    where
+        S: Clone + Send + Sync + 'static,
+        R: RpcRouterExt<S>,
+    {
+        let [get, cancel, delete, watch] = Self::DESCRIPTOR.methods else {
+            unreachable!()
+        };
+        move |router: R| {
+            let operations = self.clone();
+            let router = unary(
+                router,
+                get,
+                move |request: GetOperationRequest| async move { operations.get(&request.name).await },
+            );
+
+            let operations = self.clone();
+            let router = unary(
+                router,
+                cancel,
+                move |request: CancelOperationRequest| async move {
+                    operations.cancel(&request.name).await.map(|()| Empty {})
+                },
+            );
+
+            let operations = self.clone();
+            let router = unary(
+                router,
+                delete,
+                move |request: DeleteOperationRequest| async move {
+                    operations.delete(&request.name).await.map(|()| Empty {})
+                },
+            );
+
+            let operations = self;
+            server_stream(
+                router,
+                watch,
+                move |request: GetOperationRequest| async move { operations.watch(&request.name) },
+            )
+        }
+    }
+}
+
+// Same as the generated route registration, see `axum-connect-build`.
+pub(crate) fn unary<TReq, TRes, T, H, S, R>(
+    router: R,
+    method: &'static MethodDescriptor,
+    handler: H,
+) -> R
+where
+    H: RpcHandlerUnary<TReq, TRes, T, S>,
+    T: 'static,
+    S: Clone + Send + Sync + 'static,
+    R: RpcRouterExt<S>,
+{
+    router.rpc_route(
+        method,
+        post(|State(state): State<S>, request: Request| async move {
+            handler.call(request, state).await
+        })
+        .fallback(method_not_allowed),
+    )
+}
+
+pub(crate) fn server_stream<TReq, TRes, T, H, S, R>(
+    router: R,
+    method: &'static MethodDescriptor,
+    handler: H,
+) -> R
+where
+    H: RpcHandlerStream<TReq, TRes, T, S>,
+    T: 'static,
+    S: Clone + Send + Sync + 'static,
+    R: RpcRouterExt<S>,
+{
+    router.rpc_route(
+        method,
+        post(|State(state): State<S>, request: Request| async move {
+            handler.call(request, state).await
+        })
+        .fallback(method_not_allowed),
+    )
+}
+
+/// A running operation, for the code doing the work. Finish it with `complete` or `fail`.
+pub struct OperationHandle {
+    operations: Operations,
+    operation: Operation,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl OperationHandle {
+    /// The operation as it was started, to return from the handler.
+    pub fn operation(&self) -> Operation {
+        self.operation.clone()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.operation.name
+    }
+
+    /// Whether a client cancelled the operation. The work should stop, its result is ignored.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Replaces the metadata (say, progress) of the operation.
+    pub async fn set_metadata(&mut self, metadata: Any) -> Result<(), RpcError> {
+        self.operation.metadata = Some(metadata);
+        self.finish(None).await
+    }
+
+    /// Marks the operation done with `response`, where `type_name` is the fully qualified name of
+    /// the message.
+    pub async fn complete<M>(mut self, type_name: &str, response: &M) -> Result<(), RpcError>
+    where
+        M: Message,
+    {
+        self.finish(Some(operation::Result::Response(pack_any(
+            type_name, response,
+        ))))
+        .await
+    }
+
+    /// Marks the operation done with `error`.
+    pub async fn fail(mut self, error: &RpcError) -> Result<(), RpcError> {
+        self.finish(Some(operation::Result::Error(Status::from(error))))
+            .await
+    }
+
+    // Writes the operation, done if there's a result. Does nothing once it's done (cancelled, say).
+    async fn finish(&mut self, result: Option<operation::Result>) -> Result<(), RpcError> {
+        let stored = self.operations.get(self.name()).await?;
+        if stored.done {
+            return Ok(());
+        }
+
+        let mut operation = self.operation.clone();
+        operation.done = result.is_some();
+        operation.result = result;
+        self.operations
+            .store
+            .put(operation)
+            .await
+            .map_err(store_error)
+    }
+}
+
+impl Drop for OperationHandle {
+    fn drop(&mut self) {
+        self.operations
+            .running
+            .lock()
+            .unwrap()
+            .remove(&self.operation.name);
+    }
+}
+
+fn store_error(e: BoxError) -> RpcError {
+    RpcError::new(
+        RpcErrorCode::Internal,
+        format!("Operation store failed. {}", e),
+    )
+}
+
+fn not_found(name: &str) -> RpcError {
+    RpcError::new(
+        RpcErrorCode::NotFound,
+        format!("No such operation: {}", name),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_details::ErrorInfo;
+
+    #[tokio::test]
+    async fn fails_operations_with_the_status_of_the_error() {
+        let operations = Operations::new(MemoryOperationStore::new());
+        let handle = operations.start(None).await.unwrap();
+        let name = handle.name().to_string();
+
+        let error = RpcError::new(
+            RpcErrorCode::ResourceExhausted,
+            "Quota exceeded".to_string(),
+        )
+        .with_error_info("QUOTA_EXCEEDED", "library.example.com");
+        handle.fail(&error).await.unwrap();
+
+        let operation = operations.get(&name).await.unwrap();
+        assert!(operation.done);
+        let status = match &operation.result {
+            Some(operation::Result::Error(status)) => status.clone(),
+            _ => panic!("expected an error"),
+        };
+        assert_eq!(status.code, RpcErrorCode::ResourceExhausted.as_i32());
+        assert_eq!(status.message, "Quota exceeded");
+        assert_eq!(status.details.len(), 1);
+        assert_eq!(
+            status.details[0].type_url,
+            "type.googleapis.com/google.rpc.ErrorInfo"
+        );
+        assert!(
+            ErrorInfo::decode(status.details[0].value.clone()).unwrap()
+                == error.detail::<ErrorInfo>().unwrap()
+        );
+
+        // And it's the same operation once through the wire, in either encoding.
+        assert!(Operation::decode(operation.encode_to_vec().as_slice()).unwrap() == operation);
+        let json = serde_json::to_string(&operation).unwrap();
+        assert!(serde_json::from_str::<Operation>(&json).unwrap() == operation);
+    }
+
+    #[tokio::test]
+    async fn cancels_operations_with_canceled_errors() {
+        let operations = Operations::new(MemoryOperationStore::new());
+        let handle = operations.start(None).await.unwrap();
+        let name = handle.name().to_string();
+
+        operations.cancel(&name).await.unwrap();
+        assert!(handle.is_cancelled());
+        // Completing it afterwards changes nothing.
+        handle
+            .complete("google.protobuf.Empty", &Empty {})
+            .await
+            .unwrap();
+
+        let operation = operations.get(&name).await.unwrap();
+        assert!(operation.done);
+        match operation.result {
+            Some(operation::Result::Error(status)) => {
+                assert_eq!(status.code, RpcErrorCode::Canceled.as_i32())
+            }
+            _ => panic!("expected an error"),
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use hmac::{Hmac, Mac};
+use prost::Message;
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::{RpcError, RpcErrorCode},
+    response::{RpcIntoResponse, RpcResult},
+};
+
+const SIGNED: u8 = 1;
+const ENCRYPTED: u8 = 2;
+
+/// Turns a cursor (any serde type, like the last key of a page) into an opaque `page_token` and
+/// back, as described by AIP-158. Tokens are signed so clients can't forge or edit them, and can
+/// optionally be encrypted so they don't leak what's inside either.
+///
+/// ```ignore
+/// let tokens = PageTokens::new(secret).encrypted();
+///
+/// async fn list_books(State(tokens): State<PageTokens>, request: ListBooksRequest)
+///     -> RpcResult<ListBooksResponse>
+/// {
+///     let page_size = clamp_page_size(request.page_size, 50, 1000)?;
+///     let after: Option<String> = tokens.decode(&request.page_token)?;
+///     let books = db.books_after(after, page_size + 1).await;
+///     Ok(Paginated::from_overfetch(books, page_size, &tokens, |book| book.name.clone())?.into_message())
+/// }
+/// ```
+///
+/// Put anything the page depends on (filters, ordering) in the cursor too, so a token can't be
+/// replayed against a different query.
+#[derive(Clone)]
+pub struct PageTokens {
+    secret: Vec<u8>,
+    cipher: Option<ChaCha20Poly1305>,
+}
+
+impl PageTokens {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+            cipher: None,
+        }
+    }
+
+    /// Encrypts tokens (ChaCha20-Poly1305, keyed from the secret) instead of just signing them.
+    /// Tokens of either kind are accepted by `decode`.
+    pub fn encrypted(mut self) -> Self {
+        let key = Sha256::new()
+            .chain_update(b"axum-connect page token\n")
+            .chain_update(&self.secret)
+            .finalize();
+        self.cipher = Some(ChaCha20Poly1305::new(Key::from_slice(&key)));
+        self
+    }
+
+    /// Encodes `cursor` as a `page_token`, failing with `Internal` if it doesn't serialize to JSON
+    /// (like a map with non-string keys).
+    pub fn encode<T>(&self, cursor: &T) -> Result<String, RpcError>
+    where
+        T: Serialize,
+    {
+        let internal = |message: String| RpcError::new(RpcErrorCode::Internal, message);
+        let payload = serde_json::to_vec(cursor)
+            .map_err(|e| internal(format!("Failed to serialize page cursor. {}", e)))?;
+
+        let token = match &self.cipher {
+            Some(cipher) => {
+                let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+                let ciphertext = cipher
+                    .encrypt(&nonce, payload.as_slice())
+                    .map_err(|e| internal(format!("Failed to encrypt page token. {}", e)))?;
+                [&[ENCRYPTED], nonce.as_slice(), &ciphertext].concat()
+            }
+            None => {
+                let signature = self.mac(&payload).finalize().into_bytes();
+                [&[SIGNED], payload.as_slice(), &signature].concat()
+            }
+        };
+
+        Ok(URL_SAFE_NO_PAD.encode(token))
+    }
+
+    /// Decodes a `page_token`. An empty token (the first page) is `None`, anything that isn't a
+    /// token from `encode` fails with `InvalidArgument`.
+    pub fn decode<T>(&self, token: &str) -> Result<Option<T>, RpcError>
+    where
+        T: DeserializeOwned,
+    {
+        if token.is_empty() {
+            return Ok(None);
+        }
+
+        let invalid = || RpcError::new(RpcErrorCode::InvalidArgument, "Invalid page_token".into());
+        let token = URL_SAFE_NO_PAD.decode(token).map_err(|_| invalid())?;
+
+        let payload = match token.split_first() {
+            Some((&SIGNED, rest)) if rest.len() >= 32 => {
+                let (payload, signature) = rest.split_at(rest.len() - 32);
+                self.mac(payload)
+                    .verify_slice(signature)
+                    .map_err(|_| invalid())?;
+                payload.to_vec()
+            }
+            Some((&ENCRYPTED, rest)) if rest.len() >= 12 => {
+                let cipher = self.cipher.as_ref().ok_or_else(invalid)?;
+                let (nonce, ciphertext) = rest.split_at(12);
+                cipher
+                    .decrypt(Nonce::from_slice(nonce), ciphertext)
+                    .map_err(|_| invalid())?
+            }
+            _ => return Err(invalid()),
+        };
+
+        serde_json::from_slice(&payload)
+            .map(Some)
+            .map_err(|_| invalid())
+    }
+
+    fn mac(&self, payload: &[u8]) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.secret)
+            .expect("HMAC accepts keys of any length");
+        mac.update(payload);
+        mac
+    }
+}
+
+/// The AIP-158 `page_size` rules: negative is an error, zero (unset) means `default`, and anything
+/// above `max` is silently lowered to `max`.
+pub fn clamp_page_size(page_size: i32, default: usize, max: usize) -> Result<usize, RpcError> {
+    match page_size {
+        size if size < 0 => Err(RpcError::new(
+            RpcErrorCode::InvalidArgument,
+            "page_size must not be negative".into(),
+        )),
+        0 => Ok(default.min(max)),
+        size => Ok((size as usize).min(max)),
+    }
+}
+
+/// A list response message, built from one page of items and the token of the next one. Usually
+/// implemented for the generated `List*Response` types:
+///
+/// ```ignore
+/// impl ListResponse<Book> for ListBooksResponse {
+///     fn from_page(books: Vec<Book>, next_page_token: String) -> Self {
+///         Self { books, next_page_token }
+///     }
+/// }
+/// ```
+pub trait ListResponse<T>: Message {
+    fn from_page(items: Vec<T>, next_page_token: String) -> Self;
+}
+
+/// One page of a list RPC. Handlers can return it in place of the response message, for any
+/// message that implements `ListResponse`, or convert it with `into_message`.
+#[derive(Clone, Debug)]
+pub struct Paginated<T> {
+    pub items: Vec<T>,
+    /// Empty on the last page.
+    pub next_page_token: String,
+}
+
+impl<T> Paginated<T> {
+    pub fn new(items: Vec<T>, next_page_token: String) -> Self {
+        Self {
+            items,
+            next_page_token,
+        }
+    }
+
+    /// Builds a page from `page_size + 1` fetched items: if the extra one is there, it's dropped
+    /// and the next page token is the cursor of the last item that's kept. Fails like
+    /// `PageTokens::encode`.
+    pub fn from_overfetch<C, F>(
+        mut items: Vec<T>,
+        page_size: usize,
+        tokens: &PageTokens,
+        cursor: F,
+    ) -> Result<Self, RpcError>
+    where
+        C: Serialize,
+        F: FnOnce(&T) -> C,
+    {
+        let next_page_token = if items.len() > page_size {
+            items.truncate(page_size);
+            match items.last() {
+                Some(last) => tokens.encode(&cursor(last))?,
+                None => String::new(),
+            }
+        } else {
+            String::new()
+        };
+
+        Ok(Self::new(items, next_page_token))
+    }
+
+    pub fn into_message<M>(self) -> M
+    where
+        M: ListResponse<T>,
+    {
+        M::from_page(self.items, self.next_page_token)
+    }
+}
+
+impl<T, M> RpcIntoResponse<M> for Paginated<T>
+where
+    T: Send + Sync + 'static,
+    M: ListResponse<T>,
+{
+    fn rpc_into_response(self) -> RpcResult<M> {
+        Ok(self.into_message())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Cursor {
+        after: String,
+        filter: String,
+    }
+
+    fn cursor() -> Cursor {
+        Cursor {
+            after: "books/42".to_string(),
+            filter: "author=le-guin".to_string(),
+        }
+    }
+
+    fn is_invalid<T: std::fmt::Debug>(result: Result<Option<T>, RpcError>) -> bool {
+        result.is_err_and(|e| e.code == RpcErrorCode::InvalidArgument)
+    }
+
+    #[test]
+    fn round_trips_signed_and_encrypted_tokens() {
+        for tokens in [
+            PageTokens::new("secret"),
+            PageTokens::new("secret").encrypted(),
+        ] {
+            let token = tokens.encode(&cursor()).unwrap();
+            assert_eq!(tokens.decode::<Cursor>(&token).unwrap(), Some(cursor()));
+        }
+    }
+
+    #[test]
+    fn decodes_the_empty_token_as_the_first_page() {
+        assert_eq!(
+            PageTokens::new("secret").decode::<Cursor>("").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn hides_the_cursor_only_when_encrypted() {
+        let signed = PageTokens::new("secret").encode(&cursor()).unwrap();
+        let signed = URL_SAFE_NO_PAD.decode(signed).unwrap();
+        assert!(String::from_utf8_lossy(&signed).contains("books/42"));
+
+        let encrypted = PageTokens::new("secret")
+            .encrypted()
+            .encode(&cursor())
+            .unwrap();
+        let encrypted = URL_SAFE_NO_PAD.decode(encrypted).unwrap();
+        assert!(!String::from_utf8_lossy(&encrypted).contains("books/42"));
+
+        // Nonces are random, so the same cursor gives different tokens.
+        let tokens = PageTokens::new("secret").encrypted();
+        assert_ne!(
+            tokens.encode(&cursor()).unwrap(),
+            tokens.encode(&cursor()).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_edited_tokens() {
+        for tokens in [
+            PageTokens::new("secret"),
+            PageTokens::new("secret").encrypted(),
+        ] {
+            let token = tokens.encode(&cursor()).unwrap();
+            let mut bytes = URL_SAFE_NO_PAD.decode(&token).unwrap();
+            bytes[10] ^= 1;
+            let edited = URL_SAFE_NO_PAD.encode(&bytes);
+            assert!(is_invalid(tokens.decode::<Cursor>(&edited)));
+
+            let truncated = URL_SAFE_NO_PAD.encode(&bytes[..bytes.len() - 1]);
+            assert!(is_invalid(tokens.decode::<Cursor>(&truncated)));
+        }
+    }
+
+    #[test]
+    fn rejects_tokens_of_another_secret() {
+        for tokens in [
+            PageTokens::new("other"),
+            PageTokens::new("other").encrypted(),
+        ] {
+            let token = tokens.encode(&cursor()).unwrap();
+            assert!(is_invalid(
+                PageTokens::new("secret")
+                    .encrypted()
+                    .decode::<Cursor>(&token)
+            ));
+        }
+    }
+
+    #[test]
+    fn accepts_signed_tokens_once_encrypted_but_not_the_other_way_around() {
+        let signed = PageTokens::new("secret").encode(&cursor()).unwrap();
+        let decoded = PageTokens::new("secret")
+            .encrypted()
+            .decode::<Cursor>(&signed);
+        assert_eq!(decoded.unwrap(), Some(cursor()));
+
+        let encrypted = PageTokens::new("secret")
+            .encrypted()
+            .encode(&cursor())
+            .unwrap();
+        assert!(is_invalid(
+            PageTokens::new("secret").decode::<Cursor>(&encrypted)
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_tokens() {
+        let tokens = PageTokens::new("secret").encrypted();
+        for token in ["not base64!", "AA", "AQ", "Ag", "Aw"] {
+            assert!(is_invalid(tokens.decode::<Cursor>(token)), "{}", token);
+        }
+        // A valid token, of another cursor type.
+        let token = tokens.encode(&42).unwrap();
+        assert!(is_invalid(tokens.decode::<Cursor>(&token)));
+    }
+
+    #[test]
+    fn fails_on_cursors_that_do_not_serialize() {
+        let cursor = HashMap::from([((1, 2), "a")]);
+        let e = PageTokens::new("secret").encode(&cursor).unwrap_err();
+        assert_eq!(e.code, RpcErrorCode::Internal);
+    }
+
+    #[test]
+    fn clamps_page_sizes() {
+        assert_eq!(clamp_page_size(0, 50, 1000).unwrap(), 50);
+        assert_eq!(clamp_page_size(0, 50, 10).unwrap(), 10);
+        assert_eq!(clamp_page_size(20, 50, 1000).unwrap(), 20);
+        assert_eq!(clamp_page_size(5000, 50, 1000).unwrap(), 1000);
+        assert!(clamp_page_size(-1, 50, 1000).is_err());
+    }
+
+    #[test]
+    fn pages_overfetched_items() {
+        let tokens = PageTokens::new("secret");
+
+        let page = Paginated::from_overfetch(vec![1, 2, 3], 2, &tokens, |n| *n).unwrap();
+        assert_eq!(page.items, vec![1, 2]);
+        assert_eq!(
+            tokens.decode::<i32>(&page.next_page_token).unwrap(),
+            Some(2)
+        );
+
+        let last = Paginated::from_overfetch(vec![1, 2], 2, &tokens, |n| *n).unwrap();
+        assert_eq!(last.items, vec![1, 2]);
+        assert_eq!(last.next_page_token, "");
+    }
+}
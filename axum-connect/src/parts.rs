@@ -1,13 +1,25 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
 use async_trait::async_trait;
 use axum::{
     extract::{
-        connect_info::MockConnectInfo, ConnectInfo, FromRef, FromRequestParts, Host, Query, State,
+        connect_info::MockConnectInfo, ConnectInfo, FromRef, FromRequestParts, Path, Query, State,
     },
     http::{self},
     Extension,
 };
+use axum_extra::extract::Host;
 use prost::Message;
 use serde::de::DeserializeOwned;
+use tokio::{sync::Notify, time::Instant};
 
 use crate::error::{RpcError, RpcErrorCode, RpcIntoError};
 
@@ -88,6 +100,27 @@ where
     }
 }
 
+/// The router's state, or any part of it with a `FromRef` impl, as in axum handlers. Handlers can
+/// take just the database pool of a composite app state:
+///
+/// ```ignore
+/// #[derive(Clone, FromRef)]
+/// struct AppState {
+///     pool: PgPool,
+///     config: Arc<Config>,
+/// }
+///
+/// async fn say_hello(
+///     State(pool): State<PgPool>,
+///     request: HelloRequest,
+/// ) -> RpcResult<HelloResponse> {
+///     // ...
+/// }
+///
+/// let app = Router::new()
+///     .rpc(HelloWorldService::say_hello(say_hello))
+///     .with_state(AppState { pool, config });
+/// ```
 #[async_trait]
 impl<M, OuterState, InnerState> RpcFromRequestParts<M, OuterState> for State<InnerState>
 where
@@ -105,3 +138,438 @@ where
         Ok(Self(inner_state))
     }
 }
+
+/// All of the request's headers as they came, protocol ones included. `RpcMetadata` is usually what
+/// handlers want instead.
+#[async_trait]
+impl<M, S> RpcFromRequestParts<M, S> for http::HeaderMap
+where
+    M: Message,
+    S: Send + Sync,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(parts.headers.clone())
+    }
+}
+
+/// The tenant an RPC was addressed to, for serving many isolated tenants from one binary.
+///
+/// By default it's the `{tenant}` path prefix the services were mounted under (see
+/// `RpcRouterExt::rpc_tenants`). Add `Extension(TenantSource::Subdomain)` to the router to take it
+/// from the left-most label of the host instead (`acme.example.com` -> `acme`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tenant(pub String);
+
+/// Where the `Tenant` extractor looks for the tenant.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TenantSource {
+    #[default]
+    PathPrefix,
+    /// The subdomain of the `Host` header (or the URI authority, for HTTP/2), never of the
+    /// forwarding headers, which any client can send.
+    Subdomain,
+    /// The subdomain of the host the client asked a proxy for: `X-Forwarded-Host` or the
+    /// `Forwarded` header's `host`, falling back to `Host`. Only use it behind a trusted proxy that
+    /// overwrites those headers, or clients pick their own tenant.
+    ForwardedSubdomain,
+}
+
+#[async_trait]
+impl<M, S> RpcFromRequestParts<M, S> for Tenant
+where
+    M: Message,
+    S: Send + Sync,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let source = parts
+            .extensions
+            .get::<TenantSource>()
+            .copied()
+            .unwrap_or_default();
+
+        match source {
+            TenantSource::PathPrefix => {
+                let Path(mut params) =
+                    Path::<HashMap<String, String>>::from_request_parts(parts, state)
+                        .await
+                        .map_err(|e| (RpcErrorCode::Internal, e.to_string()).rpc_into_error())?;
+
+                params.remove("tenant").map(Tenant).ok_or_else(|| {
+                    (
+                        RpcErrorCode::Internal,
+                        "Service is not mounted under a {tenant} path prefix",
+                    )
+                        .rpc_into_error()
+                })
+            }
+            TenantSource::Subdomain => {
+                let host = parts
+                    .headers
+                    .get(http::header::HOST)
+                    .and_then(|host| host.to_str().ok())
+                    .or_else(|| parts.uri.host())
+                    .ok_or_else(|| {
+                        (RpcErrorCode::InvalidArgument, "No host in request").rpc_into_error()
+                    })?;
+
+                subdomain_tenant(host)
+            }
+            TenantSource::ForwardedSubdomain => {
+                let Host(host) = Host::from_request_parts(parts, state)
+                    .await
+                    .map_err(|e| (RpcErrorCode::InvalidArgument, e.to_string()).rpc_into_error())?;
+
+                subdomain_tenant(&host)
+            }
+        }
+    }
+}
+
+fn subdomain_tenant(host: &str) -> Result<Tenant, RpcError> {
+    // Strip the port, if any. IPv6 literals never carry a tenant anyway.
+    let host = host.rsplit_once(':').map(|(h, _)| h).unwrap_or(host);
+
+    match host.split_once('.') {
+        Some((tenant, _)) if host.parse::<IpAddr>().is_err() && !tenant.is_empty() => {
+            Ok(Tenant(tenant.to_string()))
+        }
+        _ => Err((
+            RpcErrorCode::InvalidArgument,
+            format!("No tenant subdomain in host: {}", host),
+        )
+            .rpc_into_error()),
+    }
+}
+
+/// The verified identity of the TLS client, when mutual TLS is terminated in-process.
+///
+/// Whatever accepts the TLS connection inserts it as a request extension once the handshake is
+/// done, typically by serving each connection with `app.clone().layer(Extension(identity))`. With
+/// the `mtls` feature, `PeerIdentity::from_certificate_der` builds one from the peer certificate.
+/// Extracting it fails with `Unauthenticated` on connections without a client certificate.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PeerIdentity {
+    /// The `spiffe://` URI SAN of the certificate, if it has one.
+    pub spiffe_id: Option<String>,
+    /// The certificate subject, like `CN=billing, O=Example`.
+    pub subject: Option<String>,
+}
+
+impl PeerIdentity {
+    /// Both the SPIFFE ID and the subject, preferring the SPIFFE ID.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.spiffe_id
+            .iter()
+            .chain(self.subject.iter())
+            .map(String::as_str)
+    }
+
+    /// Reads the identity from a DER encoded X.509 certificate, which should already have been
+    /// verified by the TLS stack.
+    #[cfg(feature = "mtls")]
+    pub fn from_certificate_der(
+        der: &[u8],
+    ) -> Result<Self, x509_parser::nom::Err<x509_parser::error::X509Error>> {
+        use x509_parser::{extensions::GeneralName, prelude::FromDer};
+
+        let (_, cert) = x509_parser::certificate::X509Certificate::from_der(der)?;
+        let spiffe_id = cert
+            .subject_alternative_name()
+            .ok()
+            .flatten()
+            .and_then(|san| {
+                san.value.general_names.iter().find_map(|name| match name {
+                    GeneralName::URI(uri) if uri.starts_with("spiffe://") => Some(uri.to_string()),
+                    _ => None,
+                })
+            });
+
+        Ok(Self {
+            spiffe_id,
+            subject: Some(cert.subject().to_string()),
+        })
+    }
+}
+
+#[async_trait]
+impl<M, S> RpcFromRequestParts<M, S> for PeerIdentity
+where
+    M: Message,
+    S: Send + Sync,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<PeerIdentity>()
+            .cloned()
+            .ok_or_else(|| {
+                (
+                    RpcErrorCode::Unauthenticated,
+                    "No verified client certificate",
+                )
+                    .rpc_into_error()
+            })
+    }
+}
+
+/// When the call has to be done by: the earlier of the client's `connect-timeout-ms` and the
+/// configured `RpcServiceConfig::timeout`, or `None` without either. Pass `remaining()` on to
+/// downstream calls instead of doing work the client has already given up on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RpcDeadline(pub Option<Instant>);
+
+impl RpcDeadline {
+    /// The time left, zero once the deadline has passed.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.0
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.0.is_some_and(|deadline| deadline <= Instant::now())
+    }
+}
+
+#[async_trait]
+impl<M, S> RpcFromRequestParts<M, S> for RpcDeadline
+where
+    M: Message,
+    S: Send + Sync,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<RpcDeadline>()
+            .copied()
+            .unwrap_or(RpcDeadline(None)))
+    }
+}
+
+/// Cancelled when the call is abandoned before its handler finished: the client went away, which
+/// drops the response, or the deadline passed. The handler's own future is dropped then and stops
+/// at its next `.await`, but work it handed off, like a spawned task or a query on a blocking
+/// thread, keeps running unless it watches this:
+///
+/// ```ignore
+/// async fn run_report(cancellation: RpcCancellation, request: ReportRequest) -> RpcResult<Report> {
+///     tokio::spawn(async move {
+///         tokio::select! {
+///             report = expensive_query(request) => report,
+///             _ = cancellation.cancelled() => Err(RpcError::new(RpcErrorCode::Canceled, "Canceled".into())),
+///         }
+///     })
+///     .await
+///     .unwrap()
+/// }
+/// ```
+///
+/// A stream handler's token is cancelled when its response stream is dropped before it ended.
+#[derive(Clone, Debug, Default)]
+pub struct RpcCancellation(Arc<CancellationState>);
+
+#[derive(Debug, Default)]
+struct CancellationState {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl RpcCancellation {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Resolves once the call is cancelled, never if it completes.
+    pub async fn cancelled(&self) {
+        // Registered before checking, so a cancellation in between isn't missed.
+        let notified = self.0.notify.notified();
+        if !self.is_cancelled() {
+            notified.await;
+        }
+    }
+
+    /// A guard that cancels the call when dropped without being disarmed.
+    pub(crate) fn cancel_on_drop(&self) -> CancelOnDrop {
+        CancelOnDrop(Some(self.clone()))
+    }
+
+    fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::Release);
+        self.0.notify.notify_waiters();
+    }
+}
+
+/// Held while the handler runs, and disarmed once it's done.
+pub(crate) struct CancelOnDrop(Option<RpcCancellation>);
+
+impl CancelOnDrop {
+    pub fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(cancellation) = self.0.take() {
+            cancellation.cancel();
+        }
+    }
+}
+
+#[async_trait]
+impl<M, S> RpcFromRequestParts<M, S> for RpcCancellation
+where
+    M: Message,
+    S: Send + Sync,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<RpcCancellation>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+/// The ID of the request, from `RequestIdLayer`: the caller's `x-request-id`, or a generated one.
+/// Fails with `Internal` without the layer.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RpcRequestId(pub String);
+
+impl RpcRequestId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for RpcRequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[async_trait]
+impl<M, S> RpcFromRequestParts<M, S> for RpcRequestId
+where
+    M: Message,
+    S: Send + Sync,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<RpcRequestId>()
+            .cloned()
+            .ok_or_else(|| {
+                RpcError::new(
+                    RpcErrorCode::Internal,
+                    "No request ID, is the RequestIdLayer missing?".to_string(),
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+
+    use super::*;
+    use crate::health::HealthCheckResponse;
+
+    async fn tenant(request: Request<()>) -> Result<Tenant, RpcError> {
+        let (mut parts, ()) = request.into_parts();
+        <Tenant as RpcFromRequestParts<HealthCheckResponse, ()>>::rpc_from_request_parts(
+            &mut parts,
+            &(),
+        )
+        .await
+    }
+
+    fn builder(source: TenantSource) -> http::request::Builder {
+        Request::builder().extension(source)
+    }
+
+    #[tokio::test]
+    async fn takes_the_tenant_from_the_host() {
+        let request = builder(TenantSource::Subdomain)
+            .uri("/hello.HelloWorldService/SayHello")
+            .header(http::header::HOST, "acme.example.com:8080");
+        assert_eq!(
+            tenant(request.body(()).unwrap()).await.unwrap(),
+            Tenant("acme".into())
+        );
+
+        // HTTP/2 requests carry it in the URI instead.
+        let request = builder(TenantSource::Subdomain)
+            .uri("https://acme.example.com/hello.HelloWorldService/SayHello");
+        assert_eq!(
+            tenant(request.body(()).unwrap()).await.unwrap(),
+            Tenant("acme".into())
+        );
+
+        for host in ["example", "127.0.0.1:8080", ".example.com"] {
+            let request = builder(TenantSource::Subdomain).header(http::header::HOST, host);
+            let error = tenant(request.body(()).unwrap()).await.unwrap_err();
+            assert_eq!(error.code, RpcErrorCode::InvalidArgument, "{host}");
+        }
+    }
+
+    #[tokio::test]
+    async fn ignores_forwarded_hosts_unless_asked_to() {
+        let spoofed = |source| {
+            builder(source)
+                .header(http::header::HOST, "acme.example.com")
+                .header("x-forwarded-host", "evil.example.com")
+                .header(http::header::FORWARDED, "host=evil.example.com")
+                .body(())
+                .unwrap()
+        };
+
+        assert_eq!(
+            tenant(spoofed(TenantSource::Subdomain)).await.unwrap(),
+            Tenant("acme".into())
+        );
+        assert_eq!(
+            tenant(spoofed(TenantSource::ForwardedSubdomain))
+                .await
+                .unwrap(),
+            Tenant("evil".into())
+        );
+
+        // Without a `Host` header to fall back on, it's rejected rather than forwarded.
+        let request = builder(TenantSource::Subdomain)
+            .header("x-forwarded-host", "evil.example.com")
+            .body(())
+            .unwrap();
+        let error = tenant(request).await.unwrap_err();
+        assert_eq!(error.code, RpcErrorCode::InvalidArgument);
+    }
+}
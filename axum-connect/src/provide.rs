@@ -0,0 +1,182 @@
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::HashMap,
+    future::Future,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use axum::http::{self, request::Parts};
+use futures::{future::BoxFuture, lock::Mutex};
+use prost::Message;
+
+use crate::{
+    error::{RpcError, RpcErrorCode, RpcIntoError},
+    parts::RpcFromRequestParts,
+};
+
+/// A registry of the dependencies handlers can ask for with `Provide<T>`, so they don't all have
+/// to be threaded through one big `State` struct. Each type gets an async factory, run either once
+/// for the whole process (`singleton`) or once per request (`scoped`). Add it to the router as an
+/// extension:
+///
+/// ```ignore
+/// let providers = Providers::new()
+///     .value(config.clone())
+///     .singleton(|| async { Ok(Arc::new(PgPool::connect(&url).await.map_err(internal)?)) })
+///     .scoped(|parts| {
+///         let tenant = parts.headers.get("x-tenant").cloned();
+///         async move { Ok(OrdersRepo::for_tenant(tenant)) }
+///     });
+///
+/// let app = Router::new()
+///     .rpc(OrderService::get_order(get_order))
+///     .layer(Extension(providers));
+///
+/// async fn get_order(Provide(repo): Provide<OrdersRepo>, request: GetOrderRequest) -> ... {}
+/// ```
+///
+/// Provided types are cloned out to each handler, so wrap anything expensive to clone in an `Arc`.
+#[derive(Clone, Default)]
+pub struct Providers {
+    providers: Arc<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>,
+}
+
+type Factory<T> = Arc<dyn Fn() -> BoxFuture<'static, Result<T, RpcError>> + Send + Sync>;
+type ScopedFactory<T> =
+    Arc<dyn Fn(&Parts) -> BoxFuture<'static, Result<T, RpcError>> + Send + Sync>;
+
+enum Provider<T> {
+    Value(T),
+    Singleton {
+        factory: Factory<T>,
+        value: Mutex<Option<T>>,
+    },
+    Scoped(ScopedFactory<T>),
+}
+
+/// Where a scoped value is kept once built, so every `Provide<T>` of a request shares it.
+#[derive(Clone)]
+struct Scoped<T>(T);
+
+impl Providers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Provides a clone of `value`.
+    pub fn value<T>(self, value: T) -> Self
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.insert(Provider::Value(value))
+    }
+
+    /// Provides the value `factory` builds the first time it's asked for, then clones of it. A
+    /// failed build isn't kept, the next request tries again.
+    pub fn singleton<T, F, Fut>(self, factory: F) -> Self
+    where
+        T: Clone + Send + Sync + 'static,
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<T, RpcError>> + Send + 'static,
+    {
+        self.insert(Provider::Singleton {
+            factory: Arc::new(move || Box::pin(factory())),
+            value: Mutex::new(None),
+        })
+    }
+
+    /// Provides a value built by `factory` for each request that asks for it, from the request's
+    /// headers and extensions. Copy what the value needs out of the parts before going async.
+    pub fn scoped<T, F, Fut>(self, factory: F) -> Self
+    where
+        T: Clone + Send + Sync + 'static,
+        F: Fn(&Parts) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<T, RpcError>> + Send + 'static,
+    {
+        self.insert(Provider::Scoped(Arc::new(move |parts: &Parts| {
+            Box::pin(factory(parts))
+        })))
+    }
+
+    fn insert<T>(mut self, provider: Provider<T>) -> Self
+    where
+        T: Send + Sync + 'static,
+    {
+        Arc::make_mut(&mut self.providers).insert(TypeId::of::<T>(), Arc::new(provider));
+        self
+    }
+
+    /// Resolves a `T` for the request with these parts, for code that isn't an extractor (like a
+    /// middleware).
+    pub async fn get<T>(&self, parts: &mut Parts) -> Result<T, RpcError>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        let provider = self
+            .providers
+            .get(&TypeId::of::<T>())
+            .and_then(|provider| provider.downcast_ref::<Provider<T>>())
+            .ok_or_else(|| {
+                RpcError::new(
+                    RpcErrorCode::Internal,
+                    format!("Nothing provides {}", type_name::<T>()),
+                )
+            })?;
+
+        match provider {
+            Provider::Value(value) => Ok(value.clone()),
+            Provider::Singleton { factory, value } => {
+                // Held while building, so concurrent first requests wait for one build.
+                let mut value = value.lock().await;
+                if let Some(value) = value.as_ref() {
+                    return Ok(value.clone());
+                }
+                let built = factory().await?;
+                *value = Some(built.clone());
+                Ok(built)
+            }
+            Provider::Scoped(factory) => {
+                if let Some(Scoped(value)) = parts.extensions.get::<Scoped<T>>() {
+                    return Ok(value.clone());
+                }
+                let built = factory(parts).await?;
+                parts.extensions.insert(Scoped(built.clone()));
+                Ok(built)
+            }
+        }
+    }
+}
+
+/// Extracts a `T` from the `Providers` added to the router.
+#[derive(Clone, Debug)]
+pub struct Provide<T>(pub T);
+
+#[async_trait]
+impl<M, S, T> RpcFromRequestParts<M, S> for Provide<T>
+where
+    M: Message,
+    S: Send + Sync,
+    T: Clone + Send + Sync + 'static,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let providers = parts
+            .extensions
+            .get::<Providers>()
+            .cloned()
+            .ok_or_else(|| {
+                (
+                    RpcErrorCode::Internal,
+                    "Providers extension is missing from the router",
+                )
+                    .rpc_into_error()
+            })?;
+
+        providers.get(parts).await.map(Provide)
+    }
+}
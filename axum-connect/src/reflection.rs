@@ -0,0 +1,727 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use async_stream::stream;
+use axum::{
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use futures::StreamExt;
+use http_body::Frame;
+use prost::{DecodeError, Message};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{deadline_exceeded, within, RpcServiceConfig},
+    descriptor::{IdempotencyLevel, MethodDescriptor, MethodKind, ServiceDescriptor},
+    error::RpcErrorCode,
+    handler::{
+        body::frame_body,
+        codec::{
+            decode_check_headers, decode_request_stream, encode_end_stream, encode_stream_message,
+            intercept, vary, ErrorFraming, ReqResInto,
+        },
+        method_not_allowed,
+        panic::catch_panic,
+    },
+    metadata::RpcMetadata,
+    response::RpcPayload,
+    router::RpcRouterExt,
+};
+
+/// `grpc.reflection.v1.ServerReflectionRequest`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ServerReflectionRequest {
+    #[prost(string, tag = "1")]
+    pub host: String,
+    #[prost(
+        oneof = "server_reflection_request::MessageRequest",
+        tags = "3, 4, 5, 6, 7"
+    )]
+    #[serde(flatten)]
+    pub message_request: Option<server_reflection_request::MessageRequest>,
+}
+
+pub mod server_reflection_request {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, PartialEq, prost::Oneof, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub enum MessageRequest {
+        /// A file by its name, like `hello/hello.proto`.
+        #[prost(string, tag = "3")]
+        FileByFilename(String),
+        /// The file declaring a fully qualified symbol, like `hello.HelloWorldService`.
+        #[prost(string, tag = "4")]
+        FileContainingSymbol(String),
+        #[prost(message, tag = "5")]
+        FileContainingExtension(super::ExtensionRequest),
+        #[prost(string, tag = "6")]
+        AllExtensionNumbersOfType(String),
+        /// The services, whatever the string is.
+        #[prost(string, tag = "7")]
+        ListServices(String),
+    }
+}
+
+/// `grpc.reflection.v1.ExtensionRequest`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ExtensionRequest {
+    #[prost(string, tag = "1")]
+    pub containing_type: String,
+    #[prost(int32, tag = "2")]
+    pub extension_number: i32,
+}
+
+/// `grpc.reflection.v1.ServerReflectionResponse`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ServerReflectionResponse {
+    #[prost(string, tag = "1")]
+    pub valid_host: String,
+    #[prost(message, optional, tag = "2")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_request: Option<ServerReflectionRequest>,
+    #[prost(
+        oneof = "server_reflection_response::MessageResponse",
+        tags = "4, 5, 6, 7"
+    )]
+    #[serde(flatten)]
+    pub message_response: Option<server_reflection_response::MessageResponse>,
+}
+
+pub mod server_reflection_response {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, PartialEq, prost::Oneof, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub enum MessageResponse {
+        #[prost(message, tag = "4")]
+        FileDescriptorResponse(super::FileDescriptorResponse),
+        #[prost(message, tag = "5")]
+        AllExtensionNumbersResponse(super::ExtensionNumberResponse),
+        #[prost(message, tag = "6")]
+        ListServicesResponse(super::ListServiceResponse),
+        #[prost(message, tag = "7")]
+        ErrorResponse(super::ErrorResponse),
+    }
+}
+
+/// `grpc.reflection.v1.FileDescriptorResponse`: encoded `FileDescriptorProto`s, the requested file
+/// first, then those it imports.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FileDescriptorResponse {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    #[serde(with = "base64_list")]
+    pub file_descriptor_proto: Vec<Vec<u8>>,
+}
+
+/// `grpc.reflection.v1.ExtensionNumberResponse`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ExtensionNumberResponse {
+    #[prost(string, tag = "1")]
+    pub base_type_name: String,
+    #[prost(int32, repeated, tag = "2")]
+    pub extension_number: Vec<i32>,
+}
+
+/// `grpc.reflection.v1.ListServiceResponse`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ListServiceResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub service: Vec<ServiceResponse>,
+}
+
+/// `grpc.reflection.v1.ServiceResponse`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ServiceResponse {
+    #[prost(string, tag = "1")]
+    pub name: String,
+}
+
+/// `grpc.reflection.v1.ErrorResponse`, answering a request that failed. The stream goes on.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ErrorResponse {
+    #[prost(int32, tag = "1")]
+    pub error_code: i32,
+    #[prost(string, tag = "2")]
+    pub error_message: String,
+}
+
+// `repeated bytes` in the proto JSON mapping, base64 strings.
+mod base64_list {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(value: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(value.iter().map(|bytes| STANDARD.encode(bytes)))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|value| STANDARD.decode(value).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+// Just the parts of `google.protobuf.FileDescriptorSet` that say what's declared where. Files are
+// kept as they were encoded, to be sent back as is.
+#[derive(Clone, PartialEq, Message)]
+struct FileDescriptorSet {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    file: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct FileDescriptorProto {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    package: String,
+    #[prost(string, repeated, tag = "3")]
+    dependency: Vec<String>,
+    #[prost(message, repeated, tag = "4")]
+    message_type: Vec<DescriptorProto>,
+    #[prost(message, repeated, tag = "5")]
+    enum_type: Vec<NamedDescriptor>,
+    #[prost(message, repeated, tag = "6")]
+    service: Vec<ServiceDescriptorProto>,
+    #[prost(message, repeated, tag = "7")]
+    extension: Vec<FieldDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct DescriptorProto {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(message, repeated, tag = "2")]
+    field: Vec<FieldDescriptorProto>,
+    #[prost(message, repeated, tag = "3")]
+    nested_type: Vec<DescriptorProto>,
+    #[prost(message, repeated, tag = "4")]
+    enum_type: Vec<NamedDescriptor>,
+    #[prost(message, repeated, tag = "6")]
+    extension: Vec<FieldDescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct FieldDescriptorProto {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    extendee: String,
+    #[prost(int32, tag = "3")]
+    number: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ServiceDescriptorProto {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(message, repeated, tag = "2")]
+    method: Vec<NamedDescriptor>,
+}
+
+/// An enum or a method, of which only the name is needed.
+#[derive(Clone, PartialEq, Message)]
+struct NamedDescriptor {
+    #[prost(string, tag = "1")]
+    name: String,
+}
+
+/// What's declared in which file.
+#[derive(Default)]
+struct Index {
+    /// The encoded `FileDescriptorProto`s by file name.
+    files: HashMap<String, Vec<u8>>,
+    dependencies: HashMap<String, Vec<String>>,
+    /// Fully qualified names of messages, fields, enums, services and methods, to their file.
+    symbols: HashMap<String, String>,
+    /// Every message, to its extensions' numbers and files.
+    extensions: HashMap<String, Vec<(i32, String)>>,
+    services: Vec<String>,
+}
+
+impl Index {
+    fn add_file(&mut self, encoded: Vec<u8>) -> Result<(), DecodeError> {
+        let file = FileDescriptorProto::decode(encoded.as_slice())?;
+        let name = file.name;
+        let scope = match file.package.is_empty() {
+            true => String::new(),
+            false => format!("{}.", file.package),
+        };
+
+        for message in &file.message_type {
+            self.add_message(&scope, message, &name);
+        }
+        for enum_type in &file.enum_type {
+            self.add_symbol(format!("{}{}", scope, enum_type.name), &name);
+        }
+        for service in &file.service {
+            let service_name = format!("{}{}", scope, service.name);
+            for method in &service.method {
+                self.add_symbol(format!("{}.{}", service_name, method.name), &name);
+            }
+            self.add_symbol(service_name.clone(), &name);
+            self.services.push(service_name);
+        }
+        for extension in &file.extension {
+            self.add_extension(&scope, extension, &name);
+        }
+
+        self.dependencies.insert(name.clone(), file.dependency);
+        self.files.insert(name, encoded);
+        Ok(())
+    }
+
+    fn add_message(&mut self, scope: &str, message: &DescriptorProto, file: &str) {
+        let message_name = format!("{}{}", scope, message.name);
+        self.extensions.entry(message_name.clone()).or_default();
+        for field in &message.field {
+            self.add_symbol(format!("{}.{}", message_name, field.name), file);
+        }
+
+        let scope = format!("{}.", message_name);
+        for nested in &message.nested_type {
+            self.add_message(&scope, nested, file);
+        }
+        for enum_type in &message.enum_type {
+            self.add_symbol(format!("{}{}", scope, enum_type.name), file);
+        }
+        for extension in &message.extension {
+            self.add_extension(&scope, extension, file);
+        }
+        self.add_symbol(message_name, file);
+    }
+
+    fn add_extension(&mut self, scope: &str, extension: &FieldDescriptorProto, file: &str) {
+        self.add_symbol(format!("{}{}", scope, extension.name), file);
+        let extendee = extension.extendee.trim_start_matches('.').to_string();
+        self.extensions
+            .entry(extendee)
+            .or_default()
+            .push((extension.number, file.to_string()));
+    }
+
+    fn add_symbol(&mut self, symbol: String, file: &str) {
+        self.symbols.insert(symbol, file.to_string());
+    }
+
+    /// The file and, transitively, those it imports, each once.
+    fn file_with_dependencies(&self, name: &str) -> Option<FileDescriptorResponse> {
+        self.files.get(name)?;
+
+        let mut seen = HashSet::new();
+        let mut pending = vec![name];
+        let mut file_descriptor_proto = vec![];
+        while let Some(name) = pending.pop() {
+            if !seen.insert(name) {
+                continue;
+            }
+            // Imports missing from the set are left for the client to complain about.
+            if let Some(file) = self.files.get(name) {
+                file_descriptor_proto.push(file.clone());
+            }
+            if let Some(dependencies) = self.dependencies.get(name) {
+                pending.extend(dependencies.iter().rev().map(String::as_str));
+            }
+        }
+
+        Some(FileDescriptorResponse {
+            file_descriptor_proto,
+        })
+    }
+}
+
+/// The gRPC server reflection service (`grpc.reflection.v1.ServerReflection`, and the older
+/// `v1alpha` one still asked for by some clients), so `grpcurl` and `buf curl --reflect` can list
+/// and call the services without their protos. It serves the protos of the encoded
+/// `FileDescriptorSet`s given to it, like the `FILE_DESCRIPTOR_SET` that `axum-connect-build` adds
+/// to the generated code with `AxumConnectGenSettings::file_descriptor_set`:
+///
+/// ```ignore
+/// let reflection = Reflection::new([proto::hello::FILE_DESCRIPTOR_SET])?;
+///
+/// let app = Router::new()
+///     .rpc(HelloWorldService::say_hello(say_hello))
+///     .rpc(reflection.routes())
+///     .layer(RpcRouterOptions::new().grpc(true).layer([
+///         &HelloWorldService::DESCRIPTOR,
+///         &Reflection::DESCRIPTOR,
+///         &Reflection::V1ALPHA_DESCRIPTOR,
+///     ]));
+/// ```
+///
+/// ```sh
+/// grpcurl -plaintext localhost:3030 list
+/// buf curl --http2-prior-knowledge --reflect --data '{"name": "Alec"}' \
+///     http://localhost:3030/hello.HelloWorldService/SayHello
+/// ```
+///
+/// It's a bidi stream, the one kind of RPC that's otherwise not served: each request is answered
+/// as soon as it comes in, with the stream staying open until the client ends it. Like any bidi
+/// stream this needs HTTP/2. Every service in the sets is listed, served or not.
+#[derive(Clone)]
+pub struct Reflection {
+    index: Arc<Index>,
+}
+
+impl Reflection {
+    pub const DESCRIPTOR: ServiceDescriptor = ServiceDescriptor {
+        name: "grpc.reflection.v1.ServerReflection",
+        package: "grpc.reflection.v1",
+        methods: &[MethodDescriptor {
+            name: "ServerReflectionInfo",
+            service: "grpc.reflection.v1.ServerReflection",
+            path: "/grpc.reflection.v1.ServerReflection/ServerReflectionInfo",
+            input_type: "grpc.reflection.v1.ServerReflectionRequest",
+            output_type: "grpc.reflection.v1.ServerReflectionResponse",
+            kind: MethodKind::BidiStreaming,
+            idempotency: IdempotencyLevel::Unknown,
+            deprecated: false,
+        }],
+    };
+
+    /// The same service under its pre-release name, with the same messages.
+    pub const V1ALPHA_DESCRIPTOR: ServiceDescriptor = ServiceDescriptor {
+        name: "grpc.reflection.v1alpha.ServerReflection",
+        package: "grpc.reflection.v1alpha",
+        methods: &[MethodDescriptor {
+            name: "ServerReflectionInfo",
+            service: "grpc.reflection.v1alpha.ServerReflection",
+            path: "/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo",
+            input_type: "grpc.reflection.v1alpha.ServerReflectionRequest",
+            output_type: "grpc.reflection.v1alpha.ServerReflectionResponse",
+            kind: MethodKind::BidiStreaming,
+            idempotency: IdempotencyLevel::Unknown,
+            deprecated: false,
+        }],
+    };
+
+    /// Serves the protos of the encoded `FileDescriptorSet`s. A file in more than one set is served
+    /// from the last.
+    pub fn new<'a, I>(file_descriptor_sets: I) -> Result<Self, DecodeError>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let mut index = Index::default();
+        for set in file_descriptor_sets {
+            for file in FileDescriptorSet::decode(set)?.file {
+                index.add_file(file)?;
+            }
+        }
+        index.services.sort();
+        index.services.dedup();
+
+        Ok(Self {
+            index: Arc::new(index),
+        })
+    }
+
+    /// Answers one request of the stream. Failures are answered with an `ErrorResponse`.
+    pub fn respond(&self, request: ServerReflectionRequest) -> ServerReflectionResponse {
+        use server_reflection_request::MessageRequest;
+        use server_reflection_response::MessageResponse;
+
+        let index = &self.index;
+        let file_response = |file: Option<&str>, what: String| {
+            file.and_then(|file| index.file_with_dependencies(file))
+                .map(MessageResponse::FileDescriptorResponse)
+                .unwrap_or_else(|| not_found(what))
+        };
+
+        let response = match &request.message_request {
+            Some(MessageRequest::FileByFilename(name)) => {
+                file_response(Some(name), format!("Unknown file '{}'", name))
+            }
+            Some(MessageRequest::FileContainingSymbol(symbol)) => {
+                let file = index.symbols.get(symbol.trim_start_matches('.'));
+                file_response(
+                    file.map(String::as_str),
+                    format!("Unknown symbol '{}'", symbol),
+                )
+            }
+            Some(MessageRequest::FileContainingExtension(extension)) => {
+                let file = index
+                    .extensions
+                    .get(extension.containing_type.trim_start_matches('.'))
+                    .and_then(|extensions| {
+                        extensions
+                            .iter()
+                            .find(|(number, _)| *number == extension.extension_number)
+                    });
+                file_response(
+                    file.map(|(_, file)| file.as_str()),
+                    format!(
+                        "Unknown extension {} of '{}'",
+                        extension.extension_number, extension.containing_type
+                    ),
+                )
+            }
+            Some(MessageRequest::AllExtensionNumbersOfType(type_name)) => {
+                let base_type_name = type_name.trim_start_matches('.');
+                match index.extensions.get(base_type_name) {
+                    Some(extensions) => {
+                        MessageResponse::AllExtensionNumbersResponse(ExtensionNumberResponse {
+                            base_type_name: base_type_name.to_string(),
+                            extension_number: extensions.iter().map(|(n, _)| *n).collect(),
+                        })
+                    }
+                    None => not_found(format!("Unknown message '{}'", type_name)),
+                }
+            }
+            Some(MessageRequest::ListServices(_)) => {
+                MessageResponse::ListServicesResponse(ListServiceResponse {
+                    service: index
+                        .services
+                        .iter()
+                        .map(|name| ServiceResponse { name: name.clone() })
+                        .collect(),
+                })
+            }
+            None => MessageResponse::ErrorResponse(ErrorResponse {
+                error_code: RpcErrorCode::InvalidArgument.as_i32(),
+                error_message: "Empty reflection request".to_string(),
+            }),
+        };
+
+        ServerReflectionResponse {
+            valid_host: request.host.clone(),
+            original_request: Some(request),
+            message_response: Some(response),
+        }
+    }
+
+    /// Registers `ServerReflectionInfo` of both the `v1` and `v1alpha` services, use it with
+    /// `RpcRouterExt::rpc`.
+    pub fn routes<S, R>(self) -> impl FnOnce(R) -> R
+    where
+        S: Clone + Send + Sync + 'static,
+        R: RpcRouterExt<S>,
+    {
+        move |router: R| {
+            [&Self::DESCRIPTOR, &Self::V1ALPHA_DESCRIPTOR]
+                .into_iter()
+                .fold(router, |router, service| {
+                    let reflection = self.clone();
+                    router.rpc_route(
+                        &service.methods[0],
+                        post(move |req: Request| reflection.serve(req))
+                            .fallback(method_not_allowed),
+                    )
+                })
+        }
+    }
+
+    /// The bidi stream: the requests are read as they come, each answer written right away.
+    async fn serve(self, mut req: Request) -> Response {
+        let framing = ErrorFraming::for_headers(req.headers(), Some(true));
+        let config = RpcServiceConfig::for_call(&mut req);
+        catch_panic(framing, config, async move {
+            let (mut parts, body) = req.into_parts();
+            let ReqResInto {
+                encoding,
+                config,
+                deadline,
+                response_compression,
+                cancel_on_drop,
+                ..
+            } = match decode_check_headers(&mut parts, true) {
+                Ok(value) => value,
+                Err(e) => return e,
+            };
+
+            if let Err(e) = intercept(&parts, &encoding, &config, true).await {
+                return e;
+            }
+
+            let req = Request::from_parts(parts, body);
+            let mut requests =
+                match decode_request_stream::<ServerReflectionRequest>(req, &encoding, &config) {
+                    Ok(value) => value,
+                    Err(e) => return e,
+                };
+
+            let mut headers = HeaderMap::new();
+            if let Some(compression) = &response_compression {
+                headers.insert(
+                    "connect-content-encoding",
+                    HeaderValue::from_static(compression.name()),
+                );
+            }
+            headers.append(header::VARY, vary(&config, true));
+            let content_type = encoding.content_type(true);
+
+            let frames = stream! {
+                let _cancel_on_drop = cancel_on_drop;
+                let trailers = RpcMetadata::new();
+                loop {
+                    let request = match within(deadline, requests.next()).await {
+                        Some(Some(Ok(request))) => request,
+                        Some(None) => break,
+                        Some(Some(Err(e))) => {
+                            yield Frame::data(encode_end_stream(Some(&config.outgoing_error(e)), &trailers));
+                            return;
+                        }
+                        None => {
+                            let e = config.outgoing_error(deadline_exceeded());
+                            yield Frame::data(encode_end_stream(Some(&e), &trailers));
+                            return;
+                        }
+                    };
+
+                    let response = RpcPayload::Message(self.respond(request));
+                    match encode_stream_message(&encoding, response, &config, response_compression.as_deref()) {
+                        Ok(message) => yield Frame::data(message),
+                        Err(e) => {
+                            yield Frame::data(encode_end_stream(Some(&config.outgoing_error(e)), &trailers));
+                            return;
+                        }
+                    }
+                }
+                yield Frame::data(encode_end_stream(None, &trailers));
+            };
+
+            (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, content_type)],
+                headers,
+                frame_body(frames),
+            )
+                .into_response()
+        })
+        .await
+    }
+}
+
+fn not_found(message: String) -> server_reflection_response::MessageResponse {
+    server_reflection_response::MessageResponse::ErrorResponse(ErrorResponse {
+        error_code: RpcErrorCode::NotFound.as_i32(),
+        error_message: message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{server_reflection_request::MessageRequest, *};
+    use server_reflection_response::MessageResponse;
+
+    fn reflection() -> (Reflection, Vec<u8>, Vec<u8>) {
+        let empty = FileDescriptorProto {
+            name: "google/protobuf/empty.proto".to_string(),
+            package: "google.protobuf".to_string(),
+            message_type: vec![DescriptorProto {
+                name: "Empty".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+        .encode_to_vec();
+        let hello = FileDescriptorProto {
+            name: "hello/hello.proto".to_string(),
+            package: "hello".to_string(),
+            dependency: vec!["google/protobuf/empty.proto".to_string()],
+            message_type: vec![DescriptorProto {
+                name: "HelloRequest".to_string(),
+                field: vec![FieldDescriptorProto {
+                    name: "name".to_string(),
+                    number: 1,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            service: vec![ServiceDescriptorProto {
+                name: "HelloWorldService".to_string(),
+                method: vec![NamedDescriptor {
+                    name: "SayHello".to_string(),
+                }],
+            }],
+            ..Default::default()
+        }
+        .encode_to_vec();
+        let set = FileDescriptorSet {
+            file: vec![empty.clone(), hello.clone()],
+        }
+        .encode_to_vec();
+
+        (Reflection::new([set.as_slice()]).unwrap(), hello, empty)
+    }
+
+    fn respond(reflection: &Reflection, request: MessageRequest) -> MessageResponse {
+        reflection
+            .respond(ServerReflectionRequest {
+                host: String::new(),
+                message_request: Some(request),
+            })
+            .message_response
+            .unwrap()
+    }
+
+    #[test]
+    fn lists_the_services() {
+        let (reflection, ..) = reflection();
+
+        assert!(
+            respond(&reflection, MessageRequest::ListServices(String::new()))
+                == MessageResponse::ListServicesResponse(ListServiceResponse {
+                    service: vec![ServiceResponse {
+                        name: "hello.HelloWorldService".to_string()
+                    }],
+                })
+        );
+    }
+
+    #[test]
+    fn finds_the_file_containing_a_symbol() {
+        let (reflection, hello, empty) = reflection();
+        let file_with_dependencies =
+            MessageResponse::FileDescriptorResponse(FileDescriptorResponse {
+                file_descriptor_proto: vec![hello, empty],
+            });
+
+        for symbol in [
+            "hello.HelloWorldService",
+            ".hello.HelloWorldService.SayHello",
+            "hello.HelloRequest",
+            "hello.HelloRequest.name",
+        ] {
+            assert!(
+                respond(
+                    &reflection,
+                    MessageRequest::FileContainingSymbol(symbol.to_string())
+                ) == file_with_dependencies,
+                "{}",
+                symbol
+            );
+        }
+
+        match respond(
+            &reflection,
+            MessageRequest::FileContainingSymbol("hello.Goodbye".to_string()),
+        ) {
+            MessageResponse::ErrorResponse(e) => {
+                assert_eq!(e.error_code, RpcErrorCode::NotFound.as_i32());
+                assert_eq!(e.error_message, "Unknown symbol 'hello.Goodbye'");
+            }
+            _ => panic!("expected an error response"),
+        }
+    }
+}
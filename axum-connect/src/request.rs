@@ -0,0 +1,110 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::body::Bytes;
+use futures::{stream::BoxStream, Stream};
+use prost::Message;
+
+use crate::error::RpcError;
+
+/// The last argument of a handler, built from the decoded request message. That's usually the
+/// message itself, or a `RawRpcRequest` when the handler also needs the bytes it was decoded from.
+pub trait RpcFromRequestMessage<M>: Send + Sized + 'static
+where
+    M: Message,
+{
+    fn rpc_from_request_message(message: M, body: Bytes, content_type: String) -> Self;
+
+    fn message(&self) -> &M;
+}
+
+impl<M> RpcFromRequestMessage<M> for M
+where
+    M: Message + 'static,
+{
+    fn rpc_from_request_message(message: M, _body: Bytes, _content_type: String) -> Self {
+        message
+    }
+
+    fn message(&self) -> &M {
+        self
+    }
+}
+
+/// The decoded request message along with the exact bytes it was decoded from, for verifying
+/// signatures over the body, hashing it for an audit log, or forwarding it untouched from a proxy
+/// handler. Take it in place of the message:
+///
+/// ```ignore
+/// async fn say_hello(request: RawRpcRequest<HelloRequest>) -> HelloResponse {
+///     audit_log.record(sha256(&request.body));
+///     // ...
+/// }
+/// ```
+///
+/// For unary calls `body` is the whole request body. For server streams it's the payload of the
+/// single enveloped request message, without the envelope.
+#[derive(Clone, Debug)]
+pub struct RawRpcRequest<M> {
+    pub message: M,
+    pub body: Bytes,
+    /// The request's `Content-Type` as sent, like `application/json` or
+    /// `application/connect+proto`.
+    pub content_type: String,
+}
+
+impl<M> RpcFromRequestMessage<M> for RawRpcRequest<M>
+where
+    M: Message + 'static,
+{
+    fn rpc_from_request_message(message: M, body: Bytes, content_type: String) -> Self {
+        Self {
+            message,
+            body,
+            content_type,
+        }
+    }
+
+    fn message(&self) -> &M {
+        &self.message
+    }
+}
+
+/// The request messages of a client-streaming call, the last argument of its handler. Messages are
+/// decoded as they arrive, so the handler can start on the first before the client sends the rest.
+/// The stream ends when the client is done sending, or right after the first error (a message that
+/// doesn't decode, or a body that fails to read).
+///
+/// ```ignore
+/// async fn sum(mut numbers: RpcRequestStream<Number>) -> RpcResult<Sum> {
+///     let mut total = 0;
+///     while let Some(number) = numbers.next().await {
+///         total += number?.value;
+///     }
+///     Ok(Sum { total })
+/// }
+/// ```
+pub struct RpcRequestStream<M> {
+    messages: BoxStream<'static, Result<M, RpcError>>,
+}
+
+impl<M> RpcRequestStream<M> {
+    pub fn new<S>(messages: S) -> Self
+    where
+        S: Stream<Item = Result<M, RpcError>> + Send + 'static,
+    {
+        Self {
+            messages: Box::pin(messages),
+        }
+    }
+}
+
+impl<M> Stream for RpcRequestStream<M> {
+    type Item = Result<M, RpcError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.messages.as_mut().poll_next(cx)
+    }
+}
@@ -1,6 +1,14 @@
+use std::sync::Arc;
+
+use axum::{body::Bytes, BoxError};
 use prost::Message;
+use serde::{de::DeserializeOwned, Serialize};
 
-use crate::error::{RpcError, RpcIntoError};
+use crate::{
+    codec::{Codec, JsonCodec, ProtoCodec},
+    error::{RpcError, RpcErrorCode, RpcIntoError},
+    metadata::RpcMetadata,
+};
 
 pub type RpcResult<M> = Result<M, RpcError>;
 
@@ -9,6 +17,27 @@ where
     T: Message,
 {
     fn rpc_into_response(self) -> RpcResult<T>;
+
+    /// The response as it goes on the wire: a message still to be encoded, or (only for
+    /// `EncodedResponse`) one that already is.
+    fn rpc_into_payload(self) -> RpcResult<RpcPayload<T>>
+    where
+        Self: Sized,
+    {
+        self.rpc_into_response().map(RpcPayload::Message)
+    }
+
+    /// The payload along with the metadata to send with it, which only `RpcResponse` sets.
+    fn rpc_into_parts(self) -> RpcResult<RpcResponseParts<T>>
+    where
+        Self: Sized,
+    {
+        self.rpc_into_payload().map(|payload| RpcResponseParts {
+            payload,
+            headers: RpcMetadata::new(),
+            trailers: RpcMetadata::new(),
+        })
+    }
 }
 
 impl<T> RpcIntoResponse<T> for T
@@ -29,3 +58,261 @@ where
         self.map_err(|e| e.rpc_into_error())
     }
 }
+
+/// A response with custom metadata: leading metadata is sent as response headers, trailing
+/// metadata as `trailer-` prefixed headers of a unary response. Binary (`-bin`) values are base64
+/// encoded. It wraps any other response:
+///
+/// ```ignore
+/// async fn say_hello(request: HelloRequest) -> RpcResponse<RpcResult<HelloResponse>> {
+///     let (response, cost) = greet(request).await;
+///     RpcResponse::new(response)
+///         .header("x-served-by", "eu-west-1")
+///         .trailer_bin("x-cost-bin", cost.to_be_bytes().to_vec())
+/// }
+/// ```
+///
+/// A stream's leading metadata is that of its first item, which is awaited before the response
+/// headers are sent, and its trailing metadata that of all its items, sent in the closing
+/// EndStreamResponse. When the wrapped response is an error, its headers and trailers become the
+/// error's metadata, see `RpcError`.
+pub struct RpcResponse<R> {
+    response: R,
+    headers: RpcMetadata,
+    trailers: RpcMetadata,
+}
+
+impl<R> RpcResponse<R> {
+    pub fn new(response: R) -> Self {
+        Self {
+            response,
+            headers: RpcMetadata::new(),
+            trailers: RpcMetadata::new(),
+        }
+    }
+
+    pub fn header(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.headers.append(key, value);
+        self
+    }
+
+    pub fn header_bin(mut self, key: &str, value: impl Into<Bytes>) -> Self {
+        self.headers.append_bin(key, value);
+        self
+    }
+
+    pub fn trailer(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.trailers.append(key, value);
+        self
+    }
+
+    pub fn trailer_bin(mut self, key: &str, value: impl Into<Bytes>) -> Self {
+        self.trailers.append_bin(key, value);
+        self
+    }
+
+    /// Sets the `Cache-Control` header, like `"public, max-age=300"`, for responses to Connect GET
+    /// requests (see `call_unary_get`) that browsers and CDNs may cache.
+    pub fn cache_control(self, value: impl Into<String>) -> Self {
+        self.header("cache-control", value)
+    }
+
+    /// Sets a strong `ETag` for the response, quoting `tag` (a version or content hash, say). A
+    /// Connect GET request naming it in `If-None-Match` gets a `304 Not Modified` instead of the
+    /// body, though the handler still runs: have it answer from something cheap to look up.
+    pub fn etag(self, tag: impl AsRef<str>) -> Self {
+        self.header("etag", format!("\"{}\"", tag.as_ref()))
+    }
+
+    pub fn headers_mut(&mut self) -> &mut RpcMetadata {
+        &mut self.headers
+    }
+
+    pub fn trailers_mut(&mut self) -> &mut RpcMetadata {
+        &mut self.trailers
+    }
+}
+
+impl<T, R> RpcIntoResponse<T> for RpcResponse<R>
+where
+    T: Message,
+    R: RpcIntoResponse<T>,
+{
+    fn rpc_into_response(self) -> RpcResult<T> {
+        self.response.rpc_into_response()
+    }
+
+    fn rpc_into_payload(self) -> RpcResult<RpcPayload<T>> {
+        self.response.rpc_into_payload()
+    }
+
+    fn rpc_into_parts(self) -> RpcResult<RpcResponseParts<T>> {
+        let mut parts = match self.response.rpc_into_parts() {
+            Ok(parts) => parts,
+            Err(mut e) => {
+                // A failed response's metadata goes out with the error instead.
+                e.metadata_mut().extend(self.headers);
+                e.metadata_mut().extend(self.trailers);
+                return Err(e);
+            }
+        };
+        parts.headers.extend(self.headers);
+        parts.trailers.extend(self.trailers);
+        Ok(parts)
+    }
+}
+
+/// A response as it goes on the wire, see `RpcIntoResponse::rpc_into_parts`.
+pub struct RpcResponseParts<T> {
+    pub payload: RpcPayload<T>,
+    /// Leading metadata.
+    pub headers: RpcMetadata,
+    /// Trailing metadata.
+    pub trailers: RpcMetadata,
+}
+
+/// A response message, either as is or already encoded.
+pub enum RpcPayload<T> {
+    Message(T),
+    Encoded(EncodedResponse<T>),
+}
+
+impl<T> RpcPayload<T> {
+    /// The message, decoding it if it's already encoded.
+    pub fn into_message(self) -> RpcResult<T> {
+        match self {
+            RpcPayload::Message(message) => Ok(message),
+            RpcPayload::Encoded(encoded) => encoded.decode(),
+        }
+    }
+}
+
+/// A `T` response that's already encoded, which is sent as is when the client asked for the same
+/// codec, skipping serialization. Meant for hot read paths that serve cached responses:
+///
+/// ```ignore
+/// async fn get_catalog(Extension(cache): Extension<CatalogCache>) -> RpcResult<EncodedResponse<Catalog>> {
+///     // The cache holds `EncodedResponse::json(&catalog)?`, built once when the catalog changes.
+///     Ok(cache.current())
+/// }
+/// ```
+///
+/// Clients that asked for another codec still get a correct response, at the cost of decoding and
+/// re-encoding it. The bytes are checked to be a `T` when they're created by encoding one; bytes
+/// from elsewhere (`from_encoded`) are trusted to be.
+pub struct EncodedResponse<T> {
+    codec: Arc<dyn Codec>,
+    bytes: Bytes,
+    decode: fn(&dyn Codec, Bytes) -> Result<T, BoxError>,
+}
+
+impl<T> Clone for EncodedResponse<T> {
+    fn clone(&self) -> Self {
+        Self {
+            codec: self.codec.clone(),
+            bytes: self.bytes.clone(),
+            decode: self.decode,
+        }
+    }
+}
+
+impl<T> EncodedResponse<T>
+where
+    T: Message + Serialize + DeserializeOwned + Default + 'static,
+{
+    /// Encodes `message` with `codec`.
+    pub fn encode<C>(message: &T, codec: C) -> Result<Self, RpcError>
+    where
+        C: Codec,
+    {
+        let mut buf = vec![];
+        codec.encode(message, &mut buf).map_err(|e| {
+            RpcError::new(
+                RpcErrorCode::Internal,
+                format!("Failed to serialize response: {}", e),
+            )
+        })?;
+        Ok(Self::from_encoded(codec, buf.into()))
+    }
+
+    pub fn json(message: &T) -> Result<Self, RpcError> {
+        Self::encode(message, JsonCodec)
+    }
+
+    pub fn proto(message: &T) -> Self {
+        Self::from_encoded(ProtoCodec, message.encode_to_vec().into())
+    }
+
+    /// Wraps `bytes`, a `T` encoded with `codec`, like a response kept in an external cache.
+    pub fn from_encoded<C>(codec: C, bytes: Bytes) -> Self
+    where
+        C: Codec,
+    {
+        Self {
+            codec: Arc::new(codec),
+            bytes,
+            decode: decode_as::<T>,
+        }
+    }
+}
+
+impl<T> EncodedResponse<T> {
+    /// The name of the codec the response is encoded with, like `json`.
+    pub fn codec_name(&self) -> &'static str {
+        self.codec.name()
+    }
+
+    pub fn bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.bytes
+    }
+
+    pub fn decode(&self) -> RpcResult<T> {
+        (self.decode)(&*self.codec, self.bytes.clone()).map_err(|e| {
+            RpcError::new(
+                RpcErrorCode::Internal,
+                format!("Failed to decode encoded response: {}", e),
+            )
+        })
+    }
+}
+
+fn decode_as<T>(codec: &dyn Codec, bytes: Bytes) -> Result<T, BoxError>
+where
+    T: Message + DeserializeOwned + Default,
+{
+    let mut message = T::default();
+    codec.decode(bytes, &mut message)?;
+    Ok(message)
+}
+
+impl<T> RpcIntoResponse<T> for EncodedResponse<T>
+where
+    T: Message + 'static,
+{
+    fn rpc_into_response(self) -> RpcResult<T> {
+        self.decode()
+    }
+
+    fn rpc_into_payload(self) -> RpcResult<RpcPayload<T>> {
+        Ok(RpcPayload::Encoded(self))
+    }
+}
+
+impl<T, E> RpcIntoResponse<T> for Result<EncodedResponse<T>, E>
+where
+    T: Message + 'static,
+    E: RpcIntoError + Send + Sync + 'static,
+{
+    fn rpc_into_response(self) -> RpcResult<T> {
+        self.map_err(|e| e.rpc_into_error())?.decode()
+    }
+
+    fn rpc_into_payload(self) -> RpcResult<RpcPayload<T>> {
+        self.map(RpcPayload::Encoded)
+            .map_err(|e| e.rpc_into_error())
+    }
+}
@@ -0,0 +1,472 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use async_stream::stream;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use futures::{Stream, StreamExt};
+#[cfg(feature = "hmac")]
+use hmac::{Hmac, Mac};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+#[cfg(feature = "hmac")]
+use sha2::Sha256;
+
+use crate::error::{RpcError, RpcErrorCode};
+
+const PLAIN: u8 = 0;
+#[cfg(feature = "hmac")]
+const SIGNED: u8 = 1;
+
+/// Resume tokens for server streams, so a client that lost its connection can reconnect where it
+/// left off instead of replaying the whole stream. The handler yields each message with its
+/// position (any serde type, like a sequence number or the key of the last row sent), and every so
+/// often a token for that position is put in the message. The client keeps the last token it saw
+/// and sends it back in the request's `resume_token` to reconnect:
+///
+/// ```protobuf
+/// message WatchOrdersRequest {
+///   string customer = 1;
+///   string resume_token = 2;
+/// }
+///
+/// message WatchOrdersResponse {
+///   Order order = 1;
+///   // Set on some messages only, keep the last one seen.
+///   string resume_token = 2;
+/// }
+/// ```
+///
+/// ```ignore
+/// impl ResumeCheckpoint for WatchOrdersResponse {
+///     fn set_resume_token(&mut self, token: String) {
+///         self.resume_token = token;
+///     }
+/// }
+///
+/// async fn watch_orders(
+///     State(tokens): State<ResumeTokens>,
+///     request: WatchOrdersRequest,
+/// ) -> impl Stream<Item = Result<WatchOrdersResponse, RpcError>> {
+///     // Tokens are tied to the stream they came from, a token for another customer is rejected.
+///     let stream_key = format!("orders/{}", request.customer);
+///     let after: Option<u64> = match tokens.decode(&request.resume_token, &stream_key) {
+///         Ok(after) => after,
+///         Err(e) => return futures::stream::once(async { Err(e) }).boxed(),
+///     };
+///     let orders = db.orders_after(&request.customer, after).map(|order| {
+///         let sequence = order.sequence;
+///         Ok((WatchOrdersResponse { order: Some(order), ..Default::default() }, sequence))
+///     });
+///     tokens.checkpoint(stream_key, orders).boxed()
+/// }
+/// ```
+///
+/// By default every message carries a token. Tokens are only encoded, not signed, unless the
+/// `hmac` feature is enabled and a secret is set with `signed`; validate whatever the position
+/// allows access to either way.
+#[derive(Clone)]
+pub struct ResumeTokens {
+    every_messages: usize,
+    every_interval: Option<Duration>,
+    max_age: Option<Duration>,
+    validators: Vec<Arc<ValidateFn>>,
+    #[cfg(feature = "hmac")]
+    secret: Option<Arc<[u8]>>,
+}
+
+type ValidateFn = dyn Fn(&ResumeTokenInfo) -> Result<(), RpcError> + Send + Sync;
+
+/// What a resume token says about where it came from, passed to the `validate` hooks.
+#[derive(Clone, Debug)]
+pub struct ResumeTokenInfo {
+    /// The stream key the token was issued for.
+    pub stream: String,
+    pub issued_at: SystemTime,
+}
+
+/// A server stream message that can carry a resume token, usually in a `resume_token` field.
+pub trait ResumeCheckpoint {
+    fn set_resume_token(&mut self, token: String);
+}
+
+#[derive(Serialize, Deserialize)]
+struct TokenPayload<C> {
+    #[serde(rename = "s")]
+    stream: String,
+    #[serde(rename = "p")]
+    position: C,
+    #[serde(rename = "t")]
+    issued_at: u64,
+}
+
+impl Default for ResumeTokens {
+    fn default() -> Self {
+        Self {
+            every_messages: 1,
+            every_interval: None,
+            max_age: None,
+            validators: Vec::new(),
+            #[cfg(feature = "hmac")]
+            secret: None,
+        }
+    }
+}
+
+impl ResumeTokens {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Signs tokens with HMAC-SHA256, so clients can't forge or edit them. Unsigned tokens are
+    /// rejected from then on.
+    #[cfg(feature = "hmac")]
+    pub fn signed(mut self, secret: impl Into<Vec<u8>>) -> Self {
+        self.secret = Some(secret.into().into());
+        self
+    }
+
+    /// Puts a token in every `n`th message instead of every message.
+    pub fn every_messages(mut self, n: usize) -> Self {
+        self.every_messages = n.max(1);
+        self
+    }
+
+    /// Also puts a token in the next message once `interval` has passed since the last one, so
+    /// slow streams still checkpoint regularly. Combine with a large `every_messages` for a purely
+    /// time based period.
+    pub fn every_interval(mut self, interval: Duration) -> Self {
+        self.every_interval = Some(interval);
+        self
+    }
+
+    /// Rejects tokens older than `max_age` with `FailedPrecondition`, for streams whose history is
+    /// only kept for so long. The client should start over.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Adds a check run on every token that's decoded, after it's been verified to be well formed
+    /// (and signed) and for the right stream. Return an error to reject it.
+    pub fn validate<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&ResumeTokenInfo) -> Result<(), RpcError> + Send + Sync + 'static,
+    {
+        self.validators.push(Arc::new(f));
+        self
+    }
+
+    /// Encodes a token for `position` in the stream identified by `stream_key`.
+    pub fn encode<C>(&self, stream_key: &str, position: &C) -> String
+    where
+        C: Serialize,
+    {
+        let payload = serde_json::to_vec(&TokenPayload {
+            stream: stream_key.to_string(),
+            position,
+            issued_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        })
+        .expect("resume position serializes to JSON");
+
+        #[cfg(feature = "hmac")]
+        if let Some(mac) = self.mac(&payload) {
+            let signature = mac.finalize().into_bytes();
+            return URL_SAFE_NO_PAD.encode([&[SIGNED], payload.as_slice(), &signature].concat());
+        }
+
+        URL_SAFE_NO_PAD.encode([&[PLAIN], payload.as_slice()].concat())
+    }
+
+    /// Decodes the `resume_token` of a request to the stream identified by `stream_key`. An empty
+    /// token (a fresh stream) is `None`. Malformed or forged tokens, and tokens of another stream,
+    /// fail with `InvalidArgument`.
+    pub fn decode<C>(&self, token: &str, stream_key: &str) -> Result<Option<C>, RpcError>
+    where
+        C: DeserializeOwned,
+    {
+        if token.is_empty() {
+            return Ok(None);
+        }
+
+        let invalid =
+            || RpcError::new(RpcErrorCode::InvalidArgument, "Invalid resume_token".into());
+        let token = URL_SAFE_NO_PAD.decode(token).map_err(|_| invalid())?;
+        let payload = self.verify(&token).ok_or_else(invalid)?;
+        let payload: TokenPayload<C> = serde_json::from_slice(payload).map_err(|_| invalid())?;
+
+        if payload.stream != stream_key {
+            return Err(RpcError::new(
+                RpcErrorCode::InvalidArgument,
+                "resume_token is for a different stream".into(),
+            ));
+        }
+
+        let info = ResumeTokenInfo {
+            stream: payload.stream,
+            issued_at: UNIX_EPOCH + Duration::from_secs(payload.issued_at),
+        };
+
+        if let Some(max_age) = self.max_age {
+            let age = SystemTime::now()
+                .duration_since(info.issued_at)
+                .unwrap_or_default();
+            if age > max_age {
+                return Err(RpcError::new(
+                    RpcErrorCode::FailedPrecondition,
+                    "resume_token has expired, start the stream over".into(),
+                ));
+            }
+        }
+
+        for validate in &self.validators {
+            validate(&info)?;
+        }
+
+        Ok(Some(payload.position))
+    }
+
+    /// Turns a stream of messages and their positions into a stream of messages, with a resume
+    /// token put in them as often as configured. Errors are passed through.
+    pub fn checkpoint<S, M, C>(
+        &self,
+        stream_key: impl Into<String>,
+        positions: S,
+    ) -> impl Stream<Item = Result<M, RpcError>>
+    where
+        S: Stream<Item = Result<(M, C), RpcError>>,
+        M: ResumeCheckpoint,
+        C: Serialize,
+    {
+        let tokens = self.clone();
+        let stream_key = stream_key.into();
+
+        stream! {
+            let mut positions = Box::pin(positions);
+            let mut since_token = 0;
+            let mut last_token = Instant::now();
+
+            while let Some(item) = positions.next().await {
+                let (mut message, position) = match item {
+                    Ok(item) => item,
+                    Err(e) => {
+                        yield Err(e);
+                        continue;
+                    }
+                };
+
+                since_token += 1;
+                let interval_passed = tokens
+                    .every_interval
+                    .is_some_and(|interval| last_token.elapsed() >= interval);
+
+                if since_token >= tokens.every_messages || interval_passed {
+                    message.set_resume_token(tokens.encode(&stream_key, &position));
+                    since_token = 0;
+                    last_token = Instant::now();
+                }
+
+                yield Ok(message);
+            }
+        }
+    }
+
+    fn verify<'a>(&self, token: &'a [u8]) -> Option<&'a [u8]> {
+        match token.split_first() {
+            #[cfg(feature = "hmac")]
+            Some((&SIGNED, rest)) if rest.len() >= 32 => {
+                let (payload, signature) = rest.split_at(rest.len() - 32);
+                self.mac(payload)?.verify_slice(signature).ok()?;
+                Some(payload)
+            }
+            #[cfg(feature = "hmac")]
+            Some((&PLAIN, _)) if self.secret.is_some() => None,
+            Some((&PLAIN, payload)) => Some(payload),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "hmac")]
+    fn mac(&self, payload: &[u8]) -> Option<Hmac<Sha256>> {
+        let secret = self.secret.as_ref()?;
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+        mac.update(payload);
+        Some(mac)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq)]
+    struct Message {
+        resume_token: String,
+    }
+
+    impl ResumeCheckpoint for Message {
+        fn set_resume_token(&mut self, token: String) {
+            self.resume_token = token;
+        }
+    }
+
+    fn assert_invalid<C: std::fmt::Debug>(result: Result<Option<C>, RpcError>) {
+        assert_eq!(result.unwrap_err().code, RpcErrorCode::InvalidArgument);
+    }
+
+    /// A plain token for `position`, issued `age` ago.
+    fn plain(stream: &str, position: u64, age: Duration) -> String {
+        let issued_at = SystemTime::now() - age;
+        let payload = serde_json::to_vec(&TokenPayload {
+            stream: stream.to_string(),
+            position,
+            issued_at: issued_at.duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        })
+        .unwrap();
+        URL_SAFE_NO_PAD.encode([&[PLAIN], payload.as_slice()].concat())
+    }
+
+    #[test]
+    fn round_trips_positions() {
+        let tokens = ResumeTokens::new();
+        let token = tokens.encode("orders/acme", &(7u64, "row"));
+        let position: Option<(u64, String)> = tokens.decode(&token, "orders/acme").unwrap();
+        assert_eq!(position, Some((7, "row".to_string())));
+
+        assert_eq!(tokens.decode::<u64>("", "orders/acme").unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_tokens_of_other_streams() {
+        let tokens = ResumeTokens::new();
+        let token = tokens.encode("orders/acme", &7u64);
+        assert_invalid(tokens.decode::<u64>(&token, "orders/other"));
+    }
+
+    #[test]
+    fn rejects_malformed_tokens() {
+        let tokens = ResumeTokens::new();
+        let token = tokens.encode("orders/acme", &7u64);
+        let cases = [
+            "not base64!".to_string(),
+            token[..token.len() - 4].to_string(),
+            URL_SAFE_NO_PAD.encode([9, b'{', b'}']),
+            URL_SAFE_NO_PAD.encode([PLAIN]),
+        ];
+        for token in cases {
+            assert_invalid(tokens.decode::<u64>(&token, "orders/acme"));
+        }
+        assert_invalid(tokens.decode::<String>(&token, "orders/acme"));
+    }
+
+    #[test]
+    fn rejects_expired_tokens() {
+        let tokens = ResumeTokens::new().max_age(Duration::from_secs(60));
+        let fresh = plain("orders/acme", 7, Duration::ZERO);
+        assert_eq!(tokens.decode(&fresh, "orders/acme").unwrap(), Some(7u64));
+
+        let expired = plain("orders/acme", 7, Duration::from_secs(120));
+        let e = tokens.decode::<u64>(&expired, "orders/acme").unwrap_err();
+        assert_eq!(e.code, RpcErrorCode::FailedPrecondition);
+    }
+
+    #[test]
+    fn runs_the_validators() {
+        let tokens = ResumeTokens::new().validate(|info| match info.stream.as_str() {
+            "orders/acme" => Ok(()),
+            _ => Err(RpcError::new(
+                RpcErrorCode::PermissionDenied,
+                "Not yours".into(),
+            )),
+        });
+        let token = tokens.encode("orders/acme", &7u64);
+        assert_eq!(tokens.decode(&token, "orders/acme").unwrap(), Some(7u64));
+
+        let token = tokens.encode("orders/other", &7u64);
+        let e = tokens.decode::<u64>(&token, "orders/other").unwrap_err();
+        assert_eq!(e.code, RpcErrorCode::PermissionDenied);
+    }
+
+    #[cfg(feature = "hmac")]
+    #[test]
+    fn round_trips_signed_tokens() {
+        let tokens = ResumeTokens::new().signed("secret");
+        let token = tokens.encode("orders/acme", &7u64);
+        assert_eq!(tokens.decode(&token, "orders/acme").unwrap(), Some(7u64));
+    }
+
+    #[cfg(feature = "hmac")]
+    #[test]
+    fn rejects_forged_signed_tokens() {
+        let tokens = ResumeTokens::new().signed("secret");
+        let token = URL_SAFE_NO_PAD
+            .decode(tokens.encode("orders/acme", &7u64))
+            .unwrap();
+
+        let mut tampered_signature = token.clone();
+        *tampered_signature.last_mut().unwrap() ^= 1;
+        let mut tampered_payload = token.clone();
+        tampered_payload[1..].swap(0, 1);
+        let cases = [
+            URL_SAFE_NO_PAD.encode(tampered_signature),
+            URL_SAFE_NO_PAD.encode(tampered_payload),
+            URL_SAFE_NO_PAD.encode(&token[..token.len() - 1]),
+            URL_SAFE_NO_PAD.encode(&token[..20]),
+            ResumeTokens::new()
+                .signed("other")
+                .encode("orders/acme", &7u64),
+            // Unsigned tokens aren't accepted once tokens are signed.
+            ResumeTokens::new().encode("orders/acme", &7u64),
+        ];
+        for token in cases {
+            assert_invalid(tokens.decode::<u64>(&token, "orders/acme"));
+        }
+
+        // Nor signed ones without the secret to check them.
+        let token = tokens.encode("orders/acme", &7u64);
+        assert_invalid(ResumeTokens::new().decode::<u64>(&token, "orders/acme"));
+    }
+
+    #[tokio::test]
+    async fn checkpoints_every_nth_message() {
+        let tokens = ResumeTokens::new().every_messages(2);
+        let positions = stream::iter(
+            [
+                Ok((Message::default(), 1u64)),
+                Ok((Message::default(), 2)),
+                Err(RpcError::new(RpcErrorCode::Internal, "failed".into())),
+                Ok((Message::default(), 3)),
+                Ok((Message::default(), 4)),
+            ]
+            .into_iter(),
+        );
+
+        let messages: Vec<_> = tokens.checkpoint("orders/acme", positions).collect().await;
+        let checkpoints: Vec<_> = messages
+            .iter()
+            .map(|message| match message {
+                Ok(message) if message.resume_token.is_empty() => Ok(None),
+                Ok(message) => Ok(tokens
+                    .decode::<u64>(&message.resume_token, "orders/acme")
+                    .unwrap()),
+                Err(e) => Err(e.code.clone()),
+            })
+            .collect();
+        assert_eq!(
+            checkpoints,
+            vec![
+                Ok(None),
+                Ok(Some(2)),
+                Err(RpcErrorCode::Internal),
+                Ok(None),
+                Ok(Some(4)),
+            ]
+        );
+    }
+}
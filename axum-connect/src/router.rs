@@ -1,19 +1,1016 @@
-use axum::Router;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::Arc,
+    task::{Context, Poll},
+};
 
-pub trait RpcRouterExt<S, B>: Sized {
+use axum::{
+    extract::Request,
+    http::{self, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{any, MethodRouter, Route},
+    BoxError, Extension, Router,
+};
+use futures::future::BoxFuture;
+use tower::{util::BoxCloneSyncService, Layer, Service, ServiceExt};
+
+#[cfg(feature = "tonic")]
+use crate::middleware::grpc::is_grpc;
+use crate::{
+    config::RpcServiceConfig,
+    descriptor::{MethodDescriptor, RpcMethodInfo, RpcMethods, ServiceDescriptor},
+    error::{RpcError, RpcErrorCode},
+    handler::{
+        body::any_body,
+        codec::{encode_error_response_for_headers, is_rpc_call},
+    },
+    middleware::grpc::GrpcLayer,
+};
+#[cfg(feature = "tonic")]
+use axum::body::Body;
+
+pub trait RpcRouterExt<S>: Sized {
     fn rpc<F>(self, register: F) -> Self
     where
-        F: FnOnce(Self) -> RpcRouter<S, B>;
+        F: FnOnce(Self) -> Self;
+
+    /// Serve `route` as `method`, at its path, with its `RpcMethodInfo` in the request extensions.
+    /// An `RpcApp` also lists it in its `rpc_methods`. The generated route registration and the
+    /// ready-made services like `Health` go through it; it's public for methods routed by hand.
+    fn rpc_route(self, method: &'static MethodDescriptor, route: MethodRouter<S>) -> Self
+    where
+        S: Clone + Send + Sync + 'static;
+
+    /// Register RPCs like `rpc`, with `layer` wrapping only their routes, for timeouts, auth or
+    /// concurrency limits that apply to some RPCs rather than the whole router:
+    ///
+    /// ```ignore
+    /// let app = Router::new()
+    ///     .rpc(HelloWorldService::say_hello(say_hello))
+    ///     .rpc_with(ConcurrencyLimitLayer::new(8), |router| {
+    ///         router
+    ///             .rpc(HelloWorldService::say_hello_stream(say_hello_stream))
+    ///             .rpc(HelloWorldService::upload(upload))
+    ///     });
+    /// ```
+    ///
+    /// Like with axum's `route_layer`, the layer only sees requests for these routes, and must
+    /// not fail: turn errors (like tower's `Timeout`'s) into responses with `HandleErrorLayer`.
+    fn rpc_with<L, F>(self, layer: L, register: F) -> Self
+    where
+        F: FnOnce(Self) -> Self,
+        L: Layer<Route> + Clone + Send + Sync + 'static,
+        L::Service: Service<Request> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+        S: Clone + Send + Sync + 'static;
+
+    /// Mount every route of `routes` on this router.
+    fn rpc_routes(self, routes: RpcRoutes) -> Self
+    where
+        S: Clone + Send + Sync + 'static;
+
+    /// Mount `services` (a router of `.rpc(...)` calls) with `config` applying to them, and not to
+    /// the rest of this router.
+    fn rpc_with_config(self, config: RpcServiceConfig, services: Self) -> Self
+    where
+        S: Clone + Send + Sync + 'static;
+
+    /// Answer calls of `service`'s methods that have no route of their own (and of methods it
+    /// doesn't have) with an `Unimplemented` error naming the method, as gRPC servers do, rather
+    /// than an empty `404 Not Found`:
+    ///
+    /// ```ignore
+    /// let app = Router::new()
+    ///     .rpc(HelloWorldService::say_hello(say_hello))
+    ///     .rpc_unimplemented(&HelloWorldService::DESCRIPTOR);
+    /// ```
+    ///
+    /// At most once per service. `from_handler` (and so the generated `{Service}Router`) already
+    /// does it.
+    fn rpc_unimplemented(self, service: &'static ServiceDescriptor) -> Self
+    where
+        S: Clone + Send + Sync + 'static;
+
+    /// Send everything but RPC calls to `rest`: requests for other paths, and those for the RPC
+    /// paths that aren't calls (like CORS preflights, or a browser's GET of a method that isn't
+    /// served over GET). A call is a POST in an RPC content type, or a Connect GET. For an app
+    /// serving RPCs next to REST routes and a catch-all static or SPA fallback:
+    ///
+    /// ```ignore
+    /// let rest = Router::new()
+    ///     .route("/api/users", get(users))
+    ///     .fallback_service(ServeDir::new("dist"));
+    ///
+    /// let app = Router::new()
+    ///     .rpc(HelloWorldService::say_hello(say_hello))
+    ///     .rpc_fallback(rest);
+    /// ```
+    ///
+    /// Call it last, on a router of only RPC routes: the routes registered before it are the ones
+    /// that let non-calls through, and it replaces the router's fallback.
+    fn rpc_fallback<T>(self, rest: T) -> Self
+    where
+        T: Service<Request, Error = Infallible> + Clone + Send + Sync + 'static,
+        T::Response: IntoResponse,
+        T::Future: Send + 'static,
+        S: Clone + Send + Sync + 'static;
+
+    /// Register RPCs like `rpc`, under a path prefix, like `/api` or `/v2`:
+    ///
+    /// ```ignore
+    /// let app = Router::new().rpc_nest("/api", |router| {
+    ///     router.rpc(HelloWorldService::say_hello(say_hello))
+    /// });
+    /// ```
+    ///
+    /// serves `SayHello` at `/api/hello.HelloWorldService/SayHello`, as `RpcMethods` lists it.
+    /// The middleware that looks at RPC paths (gRPC, ACLs, method info, ...) goes by their last two
+    /// segments, so it works the same under a prefix. Clients add it to their base URL, like
+    /// `RpcClient::new("https://hello.example.com/api")` or `RpcTestClient::prefix("/api")`.
+    fn rpc_nest<F>(self, prefix: &str, register: F) -> Self
+    where
+        F: FnOnce(Self) -> Self,
+        S: Clone + Send + Sync + 'static;
+
+    /// Register RPCs like `rpc`, once per tenant, under a `/{tenant}` path prefix. Handlers get the
+    /// tenant through the `Tenant` extractor.
+    fn rpc_tenants<F>(self, register: F) -> Self
+    where
+        F: FnOnce(Self) -> Self,
+        S: Clone + Send + Sync + 'static;
+
+    /// Serve a tonic generated gRPC server (`FooServiceServer::new(...)`) next to the Connect
+    /// routes of the same service: gRPC calls (an `application/grpc` content type) under its
+    /// `/package.Service/` path go to tonic, everything else (Connect, gRPC-Web) to the routes.
+    /// Generate it with `axum_connect_codegen_with_generators` so both share one set of prost
+    /// message types.
+    ///
+    /// Call it last, after the service's Connect routes (and `rpc_unimplemented` or
+    /// `rpc_fallback`): like `Router::layer`, it only sees requests for the routes and fallback
+    /// that are already there. Don't also list the service in an `RpcRouterOptions::layer` with
+    /// gRPC on, which would answer its gRPC calls before they get here.
+    #[cfg(feature = "tonic")]
+    fn grpc_service<T>(self, service: T) -> Self
+    where
+        T: tonic::codegen::Service<
+                axum::extract::Request,
+                Response = axum::http::Response<tonic::body::Body>,
+                Error = std::convert::Infallible,
+            > + tonic::server::NamedService
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        T::Future: Send + 'static,
+        S: Clone + Send + Sync + 'static;
 }
 
-impl<S, B> RpcRouterExt<S, B> for Router<S, B> {
+impl<S> RpcRouterExt<S> for Router<S> {
     fn rpc<F>(self, register: F) -> Self
     where
-        F: FnOnce(Self) -> RpcRouter<S, B>,
+        F: FnOnce(Self) -> Self,
     {
         register(self)
-        // unsafe { std::mem::transmute::<RpcRouter<S, B>, Router<S, B>>(register(self)) }
+    }
+
+    fn rpc_route(self, method: &'static MethodDescriptor, route: MethodRouter<S>) -> Self
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        self.route(method.path, route.layer(Extension(RpcMethodInfo(method))))
+    }
+
+    fn rpc_with<L, F>(self, layer: L, register: F) -> Self
+    where
+        F: FnOnce(Self) -> Self,
+        L: Layer<Route> + Clone + Send + Sync + 'static,
+        L::Service: Service<Request> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+        S: Clone + Send + Sync + 'static,
+    {
+        self.merge(register(Router::new()).route_layer(layer))
+    }
+
+    fn rpc_routes(self, routes: RpcRoutes) -> Self
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        routes.routes.iter().fold(self, |router, (path, service)| {
+            router.route_service(path, service.clone())
+        })
+    }
+
+    fn rpc_with_config(self, config: RpcServiceConfig, services: Self) -> Self
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        self.merge(services.layer(Extension(config)))
+    }
+
+    fn rpc_unimplemented(self, service: &'static ServiceDescriptor) -> Self
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        self.route(
+            &format!("/{}/{{*method}}", service.name),
+            any(move |req: Request| unimplemented(service, req)),
+        )
+    }
+
+    fn rpc_fallback<T>(self, rest: T) -> Self
+    where
+        T: Service<Request, Error = Infallible> + Clone + Send + Sync + 'static,
+        T::Response: IntoResponse,
+        T::Future: Send + 'static,
+        S: Clone + Send + Sync + 'static,
+    {
+        self.route_layer(CallsOnlyLayer { rest: rest.clone() })
+            .fallback_service(rest)
+    }
+
+    fn rpc_nest<F>(self, prefix: &str, register: F) -> Self
+    where
+        F: FnOnce(Self) -> Self,
+        S: Clone + Send + Sync + 'static,
+    {
+        self.nest(prefix, register(Router::new()))
+    }
+
+    fn rpc_tenants<F>(self, register: F) -> Self
+    where
+        F: FnOnce(Self) -> Self,
+        S: Clone + Send + Sync + 'static,
+    {
+        self.rpc_nest("/{tenant}", register)
+    }
+
+    #[cfg(feature = "tonic")]
+    fn grpc_service<T>(self, service: T) -> Self
+    where
+        T: tonic::codegen::Service<
+                axum::extract::Request,
+                Response = axum::http::Response<tonic::body::Body>,
+                Error = std::convert::Infallible,
+            > + tonic::server::NamedService
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        T::Future: Send + 'static,
+        S: Clone + Send + Sync + 'static,
+    {
+        self.layer(TonicLayer {
+            prefix: Arc::from(format!("/{}/", T::NAME)),
+            service,
+        })
     }
 }
 
-pub type RpcRouter<S, B> = Router<S, B>;
+/// An axum `Router` that knows the RPC methods registered on it, for listing them in a startup log,
+/// gateway config or a debug endpoint. Build it like a `Router`, with the `RpcRouterExt` methods,
+/// and serve `into_router()`:
+///
+/// ```ignore
+/// let app = RpcApp::new()
+///     .rpc(HelloWorldService::say_hello(say_hello))
+///     .rpc_nest("/api", |router| router.rpc(Health::new().routes()));
+///
+/// for (path, method) in app.rpc_methods() {
+///     tracing::info!(path, kind = ?method.kind, "Serving");
+/// }
+/// axum::serve(listener, app.into_router()).await?;
+/// ```
+///
+/// Use `map_router` for the rest of axum's `Router` API, like non-RPC routes, layers or the state.
+pub struct RpcApp<S = ()> {
+    router: Router<S>,
+    methods: RpcMethods,
+}
+
+impl<S> RpcApp<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Self {
+            router: Router::new(),
+            methods: RpcMethods::default(),
+        }
+    }
+
+    /// The RPC methods registered so far, by path.
+    pub fn rpc_methods(&self) -> &RpcMethods {
+        &self.methods
+    }
+
+    /// Changes the router, keeping the methods. Routes added here aren't listed.
+    pub fn map_router<S2, F>(self, f: F) -> RpcApp<S2>
+    where
+        F: FnOnce(Router<S>) -> Router<S2>,
+    {
+        RpcApp {
+            router: f(self.router),
+            methods: self.methods,
+        }
+    }
+
+    pub fn into_router(self) -> Router<S> {
+        self.router
+    }
+}
+
+impl<S> Clone for RpcApp<S> {
+    fn clone(&self) -> Self {
+        Self {
+            router: self.router.clone(),
+            methods: self.methods.clone(),
+        }
+    }
+}
+
+impl<S> Default for RpcApp<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> From<RpcApp<S>> for Router<S> {
+    fn from(app: RpcApp<S>) -> Self {
+        app.router
+    }
+}
+
+impl<S> RpcRouterExt<S> for RpcApp<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn rpc<F>(self, register: F) -> Self
+    where
+        F: FnOnce(Self) -> Self,
+    {
+        register(self)
+    }
+
+    fn rpc_route(mut self, method: &'static MethodDescriptor, route: MethodRouter<S>) -> Self {
+        self.methods.insert(method.path.to_string(), method);
+        self.map_router(|router| router.rpc_route(method, route))
+    }
+
+    fn rpc_with<L, F>(mut self, layer: L, register: F) -> Self
+    where
+        F: FnOnce(Self) -> Self,
+        L: Layer<Route> + Clone + Send + Sync + 'static,
+        L::Service: Service<Request> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        let services = register(Self::new());
+        self.methods.extend("", &services.methods);
+        self.map_router(|router| router.merge(services.router.route_layer(layer)))
+    }
+
+    fn rpc_routes(mut self, routes: RpcRoutes) -> Self {
+        self.methods.extend("", &routes.methods);
+        self.map_router(|router| router.rpc_routes(routes))
+    }
+
+    fn rpc_with_config(mut self, config: RpcServiceConfig, services: Self) -> Self {
+        self.methods.extend("", &services.methods);
+        self.map_router(|router| router.rpc_with_config(config, services.router))
+    }
+
+    fn rpc_unimplemented(self, service: &'static ServiceDescriptor) -> Self {
+        self.map_router(|router| router.rpc_unimplemented(service))
+    }
+
+    fn rpc_fallback<T>(self, rest: T) -> Self
+    where
+        T: Service<Request, Error = Infallible> + Clone + Send + Sync + 'static,
+        T::Response: IntoResponse,
+        T::Future: Send + 'static,
+    {
+        self.map_router(|router| router.rpc_fallback(rest))
+    }
+
+    fn rpc_nest<F>(mut self, prefix: &str, register: F) -> Self
+    where
+        F: FnOnce(Self) -> Self,
+    {
+        let services = register(Self::new());
+        self.methods.extend(prefix, &services.methods);
+        self.map_router(|router| router.nest(prefix, services.router))
+    }
+
+    fn rpc_tenants<F>(self, register: F) -> Self
+    where
+        F: FnOnce(Self) -> Self,
+    {
+        self.rpc_nest("/{tenant}", register)
+    }
+
+    #[cfg(feature = "tonic")]
+    fn grpc_service<T>(self, service: T) -> Self
+    where
+        T: tonic::codegen::Service<
+                axum::extract::Request,
+                Response = axum::http::Response<tonic::body::Body>,
+                Error = std::convert::Infallible,
+            > + tonic::server::NamedService
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        T::Future: Send + 'static,
+    {
+        self.map_router(|router| router.grpc_service(service))
+    }
+}
+
+/// Lets only RPC calls through to the routes, see `RpcRouterExt::rpc_fallback`.
+#[derive(Clone)]
+struct CallsOnlyLayer<T> {
+    rest: T,
+}
+
+impl<R, T> Layer<R> for CallsOnlyLayer<T>
+where
+    T: Clone,
+{
+    type Service = CallsOnly<R, T>;
+
+    fn layer(&self, inner: R) -> Self::Service {
+        CallsOnly {
+            inner,
+            rest: self.rest.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct CallsOnly<R, T> {
+    inner: R,
+    rest: T,
+}
+
+impl<R, T> Service<Request> for CallsOnly<R, T>
+where
+    R: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    R::Future: Send + 'static,
+    T: Service<Request, Error = Infallible> + Clone + Send + 'static,
+    T::Response: IntoResponse,
+    T::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        if !is_rpc_call(&req) {
+            let rest = self.rest.clone();
+            return Box::pin(async move { Ok(rest.oneshot(req).await?.into_response()) });
+        }
+
+        // The clone might not be ready, keep the one that was polled.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(inner.call(req))
+    }
+}
+
+/// Sends the gRPC calls of a tonic service to it, see `RpcRouterExt::grpc_service`.
+#[cfg(feature = "tonic")]
+#[derive(Clone)]
+struct TonicLayer<T> {
+    prefix: Arc<str>,
+    service: T,
+}
+
+#[cfg(feature = "tonic")]
+impl<R, T> Layer<R> for TonicLayer<T>
+where
+    T: Clone,
+{
+    type Service = Tonic<R, T>;
+
+    fn layer(&self, inner: R) -> Self::Service {
+        Tonic {
+            inner,
+            prefix: self.prefix.clone(),
+            service: self.service.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "tonic")]
+#[derive(Clone)]
+struct Tonic<R, T> {
+    inner: R,
+    prefix: Arc<str>,
+    service: T,
+}
+
+#[cfg(feature = "tonic")]
+impl<R, T> Service<Request> for Tonic<R, T>
+where
+    R: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    R::Future: Send + 'static,
+    T: Service<Request, Response = http::Response<tonic::body::Body>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    T::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        if is_grpc(req.headers()) && req.uri().path().starts_with(&*self.prefix) {
+            let service = self.service.clone();
+            return Box::pin(async move { Ok(service.oneshot(req).await?.map(Body::new)) });
+        }
+
+        // The clone might not be ready, keep the one that was polled.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(inner.call(req))
+    }
+}
+
+/// A call of a method of `service` without a route, framed for the protocol the request speaks.
+async fn unimplemented(service: &'static ServiceDescriptor, req: Request) -> Response {
+    let method = req.uri().path().rsplit('/').next().unwrap_or_default();
+    let message = match service.method(method) {
+        Some(_) => format!("{}.{} is not implemented", service.name, method),
+        None => format!("Unknown method {} of {}", method, service.name),
+    };
+    let e = RpcError::new(RpcErrorCode::Unimplemented, message);
+    encode_error_response_for_headers(&e, req.headers())
+}
+
+/// The protocols RPC routes are served over, like connect-go's handler options, built into the
+/// layer that tells them apart per request by content type. Connect is on by default, gRPC and
+/// gRPC-Web (binary) are off:
+///
+/// ```ignore
+/// let app = Router::new()
+///     .rpc(HelloWorldService::say_hello(say_hello))
+///     .layer(
+///         RpcRouterOptions::new()
+///             .grpc(true)
+///             .grpc_web(true)
+///             .layer([&HelloWorldService::DESCRIPTOR]),
+///     );
+/// ```
+///
+/// Requests over a protocol that's off get a `415 Unsupported Media Type`, with an `Accept-Post`
+/// header listing the content types that are on. Without the layer, routes only speak Connect.
+#[derive(Clone, Copy, Debug)]
+pub struct RpcRouterOptions {
+    pub(crate) connect: bool,
+    pub(crate) grpc: bool,
+    pub(crate) grpc_web: bool,
+}
+
+impl RpcRouterOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn connect(mut self, enabled: bool) -> Self {
+        self.connect = enabled;
+        self
+    }
+
+    pub fn grpc(mut self, enabled: bool) -> Self {
+        self.grpc = enabled;
+        self
+    }
+
+    pub fn grpc_web(mut self, enabled: bool) -> Self {
+        self.grpc_web = enabled;
+        self
+    }
+
+    /// The layer serving the RPC routes of `services` over these protocols. Put it outside of
+    /// any other layers, which then only ever see Connect calls.
+    pub fn layer<I>(self, services: I) -> GrpcLayer
+    where
+        I: IntoIterator<Item = &'static ServiceDescriptor>,
+    {
+        GrpcLayer::with_options(self, services)
+    }
+}
+
+impl Default for RpcRouterOptions {
+    fn default() -> Self {
+        Self {
+            connect: true,
+            grpc: false,
+            grpc_web: false,
+        }
+    }
+}
+
+/// What the generated registration functions used to return, now plain `axum::Router`s. Not to be
+/// confused with `RpcRoutes`, which serves RPCs without an axum `Router`.
+#[deprecated(note = "use `axum::Router`")]
+pub type RpcRouter<S = ()> = Router<S>;
+
+type BoxedRpcService = BoxCloneSyncService<Request, Response, Infallible>;
+
+/// RPC routes by path, as a `tower::Service` of their own, for serving several methods without an
+/// axum `Router`: from a bare hyper server, a lambda runtime, or nested in another framework.
+/// Register the generated `<method>_service`s as their `<METHOD>_DESCRIPTOR`s:
+///
+/// ```ignore
+/// let rpc = RpcRoutes::new()
+///     .rpc(
+///         &HelloWorldService::SAY_HELLO_DESCRIPTOR,
+///         HelloWorldService::say_hello_service(say_hello, state.clone()),
+///     )
+///     .rpc(
+///         &HelloWorldService::SAY_HELLO_STREAM_DESCRIPTOR,
+///         HelloWorldService::say_hello_stream_service(say_hello_stream, state),
+///     );
+/// ```
+///
+/// Requests for any other path get an empty `404 Not Found`, as from axum. To serve it alongside
+/// other routes of an axum app, mount it with `RpcRouterExt::rpc_routes`.
+#[derive(Clone, Default)]
+pub struct RpcRoutes {
+    routes: Arc<HashMap<String, BoxedRpcService>>,
+    methods: RpcMethods,
+}
+
+impl RpcRoutes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve `service` on `path`, like `/hello.HelloWorldService/SayHello`.
+    ///
+    /// # Panics
+    ///
+    /// If `path` already has a route, as axum's `Router::route` does.
+    pub fn route<T>(mut self, path: &str, service: T) -> Self
+    where
+        T: Service<Request, Response = Response, Error = Infallible>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        T::Future: Send + 'static,
+    {
+        let routes = Arc::make_mut(&mut self.routes);
+        if routes.contains_key(path) {
+            panic!("Overlapping RPC route `{}`", path);
+        }
+        routes.insert(path.to_string(), BoxCloneSyncService::new(service));
+        self
+    }
+
+    /// Serve `service` as `method`, at its path, listing it in `rpc_methods`.
+    ///
+    /// # Panics
+    ///
+    /// If the path already has a route, like `route`.
+    pub fn rpc<T>(mut self, method: &'static MethodDescriptor, service: T) -> Self
+    where
+        T: Service<Request, Response = Response, Error = Infallible>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        T::Future: Send + 'static,
+    {
+        self.methods.insert(method.path.to_string(), method);
+        self.route(method.path, service)
+    }
+
+    /// Add the routes of `other`.
+    ///
+    /// # Panics
+    ///
+    /// If `other` has a route on a path this one has a route on, like `route`.
+    pub fn merge(mut self, other: RpcRoutes) -> Self {
+        self.methods.extend("", &other.methods);
+        other.routes.iter().fold(self, |router, (path, service)| {
+            router.route(path, service.clone())
+        })
+    }
+
+    /// The methods registered with `rpc`, by path.
+    pub fn rpc_methods(&self) -> &RpcMethods {
+        &self.methods
+    }
+
+    /// The registered paths, in no particular order.
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.routes.keys().map(String::as_str)
+    }
+}
+
+impl<B> Service<http::Request<B>> for RpcRoutes
+where
+    B: http_body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Readiness is checked per route, once the request says which.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        match self.routes.get(req.uri().path()) {
+            Some(service) => Box::pin(service.clone().oneshot(req.map(any_body))),
+            None => Box::pin(async { Ok(StatusCode::NOT_FOUND.into_response()) }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::header};
+    use http_body_util::BodyExt;
+    use tower::service_fn;
+
+    use super::*;
+    use crate::{
+        handler::RpcService,
+        health::{HealthCheckRequest, HealthCheckResponse, ServingStatus},
+    };
+
+    const CHECK: &str = "/grpc.health.v1.Health/Check";
+    const OTHER: &str = "/test.Other/Call";
+
+    async fn check(_: HealthCheckRequest) -> HealthCheckResponse {
+        HealthCheckResponse::new(ServingStatus::Serving)
+    }
+
+    fn other() -> BoxedRpcService {
+        BoxCloneSyncService::new(service_fn(|_: Request| async {
+            Ok::<_, Infallible>("other".into_response())
+        }))
+    }
+
+    /// POSTs `{}` to `path`, returning the status and body of the response.
+    async fn post<T>(service: T, path: &str) -> (StatusCode, String)
+    where
+        T: Service<Request, Response = Response, Error = Infallible>,
+    {
+        let req = http::Request::post(path)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from("{}"))
+            .unwrap();
+        let res = service.oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn dispatches_by_path() {
+        let routes = RpcRoutes::new()
+            .route(CHECK, RpcService::unary(check, ()))
+            .merge(RpcRoutes::new().route(OTHER, other()));
+        let mut paths: Vec<_> = routes.paths().collect();
+        paths.sort();
+        assert_eq!(paths, vec![CHECK, OTHER]);
+
+        let serving = (StatusCode::OK, r#"{"status":"SERVING"}"#.to_string());
+        assert_eq!(post(routes.clone(), CHECK).await, serving);
+        assert_eq!(
+            post(routes.clone(), OTHER).await,
+            (StatusCode::OK, "other".to_string())
+        );
+        assert_eq!(
+            post(routes.clone(), "/test.Other/Unknown").await,
+            (StatusCode::NOT_FOUND, String::new())
+        );
+
+        let app = Router::new().rpc_routes(routes);
+        assert_eq!(post(app, CHECK).await, serving);
+    }
+
+    #[test]
+    #[should_panic(expected = "Overlapping RPC route `/grpc.health.v1.Health/Check`")]
+    fn panics_on_overlapping_routes() {
+        let _ = RpcRoutes::new()
+            .route(CHECK, RpcService::unary(check, ()))
+            .route(CHECK, other());
+    }
+
+    #[test]
+    #[should_panic(expected = "Overlapping RPC route `/grpc.health.v1.Health/Check`")]
+    fn panics_on_overlapping_merges() {
+        let _ = RpcRoutes::new()
+            .route(CHECK, RpcService::unary(check, ()))
+            .merge(RpcRoutes::new().route(CHECK, other()));
+    }
+
+    #[cfg(feature = "tonic")]
+    mod tonic_interop {
+        use std::{
+            convert::Infallible,
+            marker::PhantomData,
+            task::{Context, Poll},
+        };
+
+        use axum::{
+            body::Body,
+            extract::Request,
+            http::{self, StatusCode},
+            Router,
+        };
+        use bytes::{Buf, BufMut, Bytes};
+        use futures::future::BoxFuture;
+        use http_body_util::BodyExt;
+        use prost::Message;
+        use tonic::{
+            codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder},
+            server::{Grpc, NamedService},
+            Status,
+        };
+        use tower::{Service, ServiceExt};
+
+        use crate::{
+            health::{Health, HealthCheckRequest, HealthCheckResponse, ServingStatus},
+            router::RpcRouterExt,
+        };
+
+        /// `tonic::codec::ProstCodec`, for the prost version the crate is built with.
+        struct TestCodec<E, D>(PhantomData<(E, D)>);
+
+        impl<E, D> Codec for TestCodec<E, D>
+        where
+            E: Message + Send + 'static,
+            D: Message + Default + Send + 'static,
+        {
+            type Encode = E;
+            type Decode = D;
+            type Encoder = Self;
+            type Decoder = Self;
+
+            fn encoder(&mut self) -> Self {
+                Self(PhantomData)
+            }
+
+            fn decoder(&mut self) -> Self {
+                Self(PhantomData)
+            }
+        }
+
+        impl<E: Message, D> Encoder for TestCodec<E, D> {
+            type Item = E;
+            type Error = Status;
+
+            fn encode(&mut self, item: E, dst: &mut EncodeBuf<'_>) -> Result<(), Status> {
+                item.encode(dst)
+                    .map_err(|e| Status::internal(e.to_string()))
+            }
+        }
+
+        impl<E, D: Message + Default> Decoder for TestCodec<E, D> {
+            type Item = D;
+            type Error = Status;
+
+            fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<D>, Status> {
+                D::decode(src)
+                    .map(Some)
+                    .map_err(|e| Status::internal(e.to_string()))
+            }
+        }
+
+        /// What tonic generates for a `grpc.health.v1.Health` server, answering every `Check`
+        /// with `ServiceUnknown` so its responses tell from the Connect handler's.
+        #[derive(Clone)]
+        struct HealthServer;
+
+        impl NamedService for HealthServer {
+            const NAME: &'static str = "grpc.health.v1.Health";
+        }
+
+        impl Service<Request> for HealthServer {
+            type Response = http::Response<tonic::body::Body>;
+            type Error = Infallible;
+            type Future = BoxFuture<'static, Result<Self::Response, Infallible>>;
+
+            fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn call(&mut self, req: Request) -> Self::Future {
+                Box::pin(async move {
+                    if req.uri().path() != "/grpc.health.v1.Health/Check" {
+                        return Ok(Status::unimplemented("").into_http());
+                    }
+                    let check = tower::service_fn(|_: tonic::Request<HealthCheckRequest>| async {
+                        Ok::<_, Status>(tonic::Response::new(HealthCheckResponse::new(
+                            ServingStatus::ServiceUnknown,
+                        )))
+                    });
+                    let mut grpc = Grpc::new(TestCodec::<HealthCheckResponse, HealthCheckRequest>(
+                        PhantomData,
+                    ));
+                    Ok(grpc.unary(check, req).await)
+                })
+            }
+        }
+
+        /// The README's setup: Connect routes of a service, and tonic's server for it.
+        fn app() -> Router {
+            Router::new()
+                .rpc(Health::new().routes())
+                .rpc_unimplemented(&Health::DESCRIPTOR)
+                .grpc_service(HealthServer)
+        }
+
+        fn grpc_request(path: &str) -> Request {
+            let mut body = vec![0];
+            let message = HealthCheckRequest::default().encode_to_vec();
+            body.put_u32(message.len() as u32);
+            body.extend(message);
+            Request::post(path)
+                .header(http::header::CONTENT_TYPE, "application/grpc")
+                .header("te", "trailers")
+                .body(Body::from(body))
+                .unwrap()
+        }
+
+        #[tokio::test]
+        async fn grpc_calls_go_to_tonic() {
+            let res = app()
+                .oneshot(grpc_request("/grpc.health.v1.Health/Check"))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(
+                res.headers()[http::header::CONTENT_TYPE],
+                "application/grpc"
+            );
+
+            let body = res.into_body().collect().await.unwrap();
+            let trailers = body.trailers().cloned().unwrap();
+            assert_eq!(trailers["grpc-status"], "0");
+            let mut frame: Bytes = body.to_bytes();
+            assert_eq!(frame.get_u8(), 0);
+            assert_eq!(frame.get_u32() as usize, frame.len());
+            let response = HealthCheckResponse::decode(frame).unwrap();
+            assert_eq!(response.status(), ServingStatus::ServiceUnknown);
+        }
+
+        #[tokio::test]
+        async fn grpc_calls_of_unrouted_methods_go_to_tonic() {
+            let res = app()
+                .oneshot(grpc_request("/grpc.health.v1.Health/List"))
+                .await
+                .unwrap();
+            // tonic's Unimplemented, trailers-only.
+            assert_eq!(res.headers()["grpc-status"], "12");
+        }
+
+        #[tokio::test]
+        async fn connect_calls_go_to_the_routes() {
+            let req = Request::post("/grpc.health.v1.Health/Check")
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from("{}"))
+                .unwrap();
+            let res = app().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(&body[..], br#"{"status":"SERVING"}"#);
+
+            let req = Request::post("/grpc.health.v1.Health/List")
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from("{}"))
+                .unwrap();
+            let res = app().oneshot(req).await.unwrap();
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["code"], "unimplemented");
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::{future::Future, io, net::SocketAddr, time::Duration};
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request},
+    Router,
+};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::{conn::auto::Builder, graceful::GracefulShutdown},
+};
+use tokio::net::TcpListener;
+use tower::ServiceExt;
+
+/// Serves a `Router` like `axum::serve`, over HTTP/1.1 and HTTP/2 (with TLS terminated in front,
+/// or to plaintext clients with prior knowledge), with the transport settings long-lived RPCs need
+/// that `axum::serve` doesn't expose:
+///
+/// ```ignore
+/// let listener = TcpListener::bind("0.0.0.0:3030").await?;
+/// RpcServer::new()
+///     .http2_keep_alive(Duration::from_secs(30))
+///     .serve(listener, app)
+///     .await;
+/// ```
+///
+/// Handlers can take `ConnectInfo<SocketAddr>` for the client's address.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RpcServer {
+    keep_alive_interval: Option<Duration>,
+    keep_alive_timeout: Option<Duration>,
+}
+
+impl RpcServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends an HTTP/2 PING on each connection every `interval`, so load balancers and proxies
+    /// with idle timeouts don't cut off server streams that go quiet. Pings are answered by the
+    /// client's HTTP/2 stack and never reach the application, unlike messages in the stream. Off
+    /// by default.
+    ///
+    /// HTTP/1.1 has nothing of the kind: for streams over it, raise the proxy's idle timeout, or
+    /// have the handler send messages its clients expect.
+    pub fn http2_keep_alive(mut self, interval: Duration) -> Self {
+        self.keep_alive_interval = Some(interval);
+        self
+    }
+
+    /// How long a ping may go unacknowledged before the connection is closed, 20 seconds by default.
+    pub fn http2_keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.keep_alive_timeout = Some(timeout);
+        self
+    }
+
+    /// Serves `app` on `listener`, forever.
+    pub async fn serve(self, listener: TcpListener, app: Router) {
+        self.serve_with_graceful_shutdown(listener, app, std::future::pending())
+            .await
+    }
+
+    /// Serves `app` on `listener` until `signal` completes, then waits for the open connections to
+    /// finish. To also end streams that would run on, complete `signal` after
+    /// `RpcShutdown::shutdown`.
+    pub async fn serve_with_graceful_shutdown<F>(
+        self,
+        listener: TcpListener,
+        app: Router,
+        signal: F,
+    ) where
+        F: Future<Output = ()>,
+    {
+        let mut builder = Builder::new(TokioExecutor::new());
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .keep_alive_interval(self.keep_alive_interval);
+        if let Some(timeout) = self.keep_alive_timeout {
+            builder.http2().keep_alive_timeout(timeout);
+        }
+
+        let graceful = GracefulShutdown::new();
+        tokio::pin!(signal);
+        loop {
+            let (stream, addr) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        // Like `axum::serve`, back off on errors other than a client going away,
+                        // like running out of file descriptors.
+                        if !is_connection_error(&e) {
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                        }
+                        continue;
+                    }
+                },
+                () = &mut signal => break,
+            };
+
+            let service = hyper::service::service_fn(connection_service(app.clone(), addr));
+            let connection = builder
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .into_owned();
+            let connection = graceful.watch(connection);
+            tokio::spawn(async move {
+                let _ = connection.await;
+            });
+        }
+
+        drop(listener);
+        graceful.shutdown().await;
+    }
+}
+
+/// The service of a connection from `addr`, calling `app` with its address as `ConnectInfo`.
+fn connection_service(
+    app: Router,
+    addr: SocketAddr,
+) -> impl Fn(Request<Incoming>) -> tower::util::Oneshot<Router, Request> + Clone {
+    move |req: Request<Incoming>| {
+        let mut req = req.map(Body::new);
+        req.extensions_mut().insert(ConnectInfo(addr));
+        app.clone().oneshot(req)
+    }
+}
+
+fn is_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::routing::get;
+    use http_body_util::{BodyExt, Empty};
+    use hyper::body::Bytes;
+    use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_http2_with_keep_alive_pings() {
+        // Pings go out while the response is pending, and only reach the client's HTTP/2 stack.
+        let app = Router::new().route(
+            "/",
+            get(|ConnectInfo(addr): ConnectInfo<SocketAddr>| async move {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                addr.ip().to_string()
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local = listener.local_addr().unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = tokio::spawn(
+            RpcServer::new()
+                .http2_keep_alive(Duration::from_millis(50))
+                .http2_keep_alive_timeout(Duration::from_secs(1))
+                .serve_with_graceful_shutdown(listener, app, async {
+                    let _ = stopped.await;
+                }),
+        );
+
+        let stream = tokio::net::TcpStream::connect(local).await.unwrap();
+        let (mut sender, connection) =
+            hyper::client::conn::http2::Builder::new(TokioExecutor::new())
+                .timer(TokioTimer::new())
+                .handshake(TokioIo::new(stream))
+                .await
+                .unwrap();
+        let client = tokio::spawn(connection);
+
+        let req = hyper::Request::get(format!("http://{}/", local))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let res = sender.send_request(req).await.unwrap();
+        assert_eq!(res.version(), hyper::Version::HTTP_2);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"127.0.0.1");
+
+        drop(sender);
+        client.await.unwrap().unwrap();
+        stop.send(()).unwrap();
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,319 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use axum::http::request::Parts;
+
+use crate::{config::RpcServiceConfig, error::RpcErrorCode, interceptor::CallState};
+
+/// Callbacks for the lifecycle of server streams, for gauges of open streams, per-stream counters
+/// or cleanup, without wrapping every handler's stream by hand. Added to the router (or a single
+/// route, with `route_layer`) as an extension:
+///
+/// ```ignore
+/// let hooks = StreamHooks::default()
+///     .on_stream_start(|_| OPEN_STREAMS.inc())
+///     .on_stream_end(|_, _| OPEN_STREAMS.dec());
+///
+/// let app = Router::new()
+///     .rpc(HelloWorldService::say_hello_stream(say_hello_stream))
+///     .layer(Extension(hooks));
+/// ```
+///
+/// `on_stream_end` is called exactly once per started stream: with `None` when it finished
+/// normally, with the error code when the handler yielded an error, and with `Canceled` when the
+/// client went away first.
+#[derive(Clone, Default)]
+pub struct StreamHooks {
+    on_start: Option<Arc<StartFn>>,
+    on_message: Option<Arc<MessageFn>>,
+    on_received: Option<Arc<MessageFn>>,
+    on_end: Option<Arc<EndFn>>,
+}
+
+type StartFn = dyn Fn(&StreamInfo) + Send + Sync;
+type MessageFn = dyn Fn(&StreamInfo, usize) + Send + Sync;
+type EndFn = dyn Fn(&StreamInfo, Option<RpcErrorCode>) + Send + Sync;
+
+/// The stream a hook is called for.
+#[derive(Clone, Debug)]
+pub struct StreamInfo {
+    /// Unique (per process) id of the stream.
+    pub id: u64,
+    /// The RPC path, like `/hello.HelloWorldService/SayHelloStream`.
+    pub path: String,
+    pub started_at: Instant,
+    /// Messages sent so far.
+    pub messages_sent: u64,
+    /// Size of the messages sent so far, as enveloped on the wire.
+    pub bytes_sent: u64,
+    /// Messages received so far (for server streams, the one request message).
+    pub messages_received: u64,
+    pub bytes_received: u64,
+}
+
+impl StreamHooks {
+    pub fn on_stream_start<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&StreamInfo) + Send + Sync + 'static,
+    {
+        self.on_start = Some(Arc::new(f));
+        self
+    }
+
+    /// Called after each message, with its encoded size in bytes.
+    pub fn on_message_sent<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&StreamInfo, usize) + Send + Sync + 'static,
+    {
+        self.on_message = Some(Arc::new(f));
+        self
+    }
+
+    /// Called after each message received from the client, with its enveloped size in bytes. For
+    /// server streams that's the request, before the stream starts.
+    pub fn on_message_received<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&StreamInfo, usize) + Send + Sync + 'static,
+    {
+        self.on_received = Some(Arc::new(f));
+        self
+    }
+
+    pub fn on_stream_end<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&StreamInfo, Option<RpcErrorCode>) + Send + Sync + 'static,
+    {
+        self.on_end = Some(Arc::new(f));
+        self
+    }
+}
+
+/// Drives the hooks for one stream. Dropping it after `start` but before `end` counts as a
+/// cancellation.
+pub(crate) struct StreamLifecycle {
+    hooks: Option<StreamHooks>,
+    /// The call the stream ends, for the interceptors.
+    call: Option<Arc<CallState>>,
+    info: StreamInfo,
+    started: bool,
+    ended: bool,
+}
+
+impl StreamLifecycle {
+    pub fn new(parts: &Parts) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        let hooks = parts.extensions.get::<StreamHooks>().cloned();
+        Self {
+            info: StreamInfo {
+                id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                path: if hooks.is_some() {
+                    parts.uri.path().to_string()
+                } else {
+                    String::new()
+                },
+                started_at: Instant::now(),
+                messages_sent: 0,
+                bytes_sent: 0,
+                messages_received: 0,
+                bytes_received: 0,
+            },
+            hooks,
+            call: parts
+                .extensions
+                .get::<RpcServiceConfig>()
+                .and_then(|config| config.call.clone()),
+            started: false,
+            ended: false,
+        }
+    }
+
+    pub fn start(&mut self) {
+        self.started = true;
+        self.info.started_at = Instant::now();
+        if let Some(call) = &self.call {
+            call.stream_started();
+        }
+        if let Some(f) = self.hooks.as_ref().and_then(|h| h.on_start.as_ref()) {
+            f(&self.info);
+        }
+    }
+
+    pub fn message_received(&mut self, bytes: usize) {
+        self.info.messages_received += 1;
+        self.info.bytes_received += bytes as u64;
+        if let Some(f) = self.hooks.as_ref().and_then(|h| h.on_received.as_ref()) {
+            f(&self.info, bytes);
+        }
+    }
+
+    pub fn message_sent(&mut self, bytes: usize) {
+        self.info.messages_sent += 1;
+        self.info.bytes_sent += bytes as u64;
+        if let Some(f) = self.hooks.as_ref().and_then(|h| h.on_message.as_ref()) {
+            f(&self.info, bytes);
+        }
+    }
+
+    pub fn end(&mut self, code: Option<RpcErrorCode>) {
+        if !self.started || std::mem::replace(&mut self.ended, true) {
+            return;
+        }
+        if let Some(call) = &self.call {
+            call.finish(code.as_ref());
+        }
+        if let Some(f) = self.hooks.as_ref().and_then(|h| h.on_end.as_ref()) {
+            f(&self.info, code);
+        }
+    }
+}
+
+impl Drop for StreamLifecycle {
+    fn drop(&mut self) {
+        self.end(Some(RpcErrorCode::Canceled));
+    }
+}
+
+/// Per-method telemetry for server streams, which request counts and latencies alone say little
+/// about: how many are open, how much they send and receive, and for how long they stay open. Kept
+/// in process memory, read it with `snapshot` to export to your metrics system.
+///
+/// ```ignore
+/// let metrics = StreamMetrics::new();
+/// let app = Router::new()
+///     .rpc(HelloWorldService::say_hello_stream(say_hello_stream))
+///     .layer(Extension(metrics.hooks()));
+///
+/// for (path, stats) in metrics.snapshot() {
+///     OPEN_STREAMS.with_label_values(&[&path]).set(stats.open as i64);
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct StreamMetrics {
+    stats: Arc<Mutex<HashMap<String, StreamStats>>>,
+}
+
+/// The telemetry of one method's streams, since the process started.
+#[derive(Clone, Debug, Default)]
+pub struct StreamStats {
+    /// Streams open right now.
+    pub open: u64,
+    pub started: u64,
+    /// Streams that ended with an error, including cancelled ones.
+    pub failed: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Bytes sent per (ended) stream.
+    pub stream_bytes: Histogram,
+    /// How long (ended) streams were open, in seconds.
+    pub stream_duration: Histogram,
+}
+
+/// A histogram with fixed bucket bounds, like Prometheus': `counts[i]` is the number of
+/// observations of at most `bounds[i]`, and the last count is everything larger.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    pub bounds: &'static [f64],
+    pub counts: Vec<u64>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new(&[])
+    }
+}
+
+const STREAM_BYTES_BOUNDS: &[f64] = &[1e3, 1e4, 1e5, 1e6, 1e7, 1e8, 1e9];
+const STREAM_DURATION_BOUNDS: &[f64] = &[0.1, 1.0, 10.0, 60.0, 300.0, 1800.0, 3600.0];
+
+impl StreamMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hooks that record into these metrics, to add to the router as an extension.
+    pub fn hooks(&self) -> StreamHooks {
+        let (start, sent, received, end) = (self.clone(), self.clone(), self.clone(), self.clone());
+
+        StreamHooks::default()
+            .on_stream_start(move |info| {
+                start.update(info, |stats| {
+                    stats.open += 1;
+                    stats.started += 1;
+                })
+            })
+            .on_message_sent(move |info, bytes| {
+                sent.update(info, |stats| {
+                    stats.messages_sent += 1;
+                    stats.bytes_sent += bytes as u64;
+                })
+            })
+            .on_message_received(move |info, bytes| {
+                received.update(info, |stats| {
+                    stats.messages_received += 1;
+                    stats.bytes_received += bytes as u64;
+                })
+            })
+            .on_stream_end(move |info, code| {
+                end.update(info, |stats| {
+                    stats.open -= 1;
+                    if code.is_some() {
+                        stats.failed += 1;
+                    }
+                    stats.stream_bytes.observe(info.bytes_sent as f64);
+                    stats
+                        .stream_duration
+                        .observe(info.started_at.elapsed().as_secs_f64());
+                })
+            })
+    }
+
+    /// A copy of every method's stats, by RPC path.
+    pub fn snapshot(&self) -> HashMap<String, StreamStats> {
+        self.stats.lock().unwrap().clone()
+    }
+
+    fn update(&self, info: &StreamInfo, f: impl FnOnce(&mut StreamStats)) {
+        let mut stats = self.stats.lock().unwrap();
+        let stats = stats
+            .entry(info.path.clone())
+            .or_insert_with(|| StreamStats {
+                stream_bytes: Histogram::new(STREAM_BYTES_BOUNDS),
+                stream_duration: Histogram::new(STREAM_DURATION_BOUNDS),
+                ..Default::default()
+            });
+        f(stats);
+    }
+}
@@ -0,0 +1,227 @@
+//! OpenTelemetry trace context propagation, with the `opentelemetry` feature: each RPC's span (see
+//! the `tracing` feature) continues the trace of the `traceparent` / `tracestate` (W3C Trace
+//! Context) or `grpc-trace-bin` request headers, and follows the OpenTelemetry RPC semantic
+//! conventions. Spans only reach OpenTelemetry through a `tracing_opentelemetry` layer.
+
+use async_trait::async_trait;
+use axum::http::{self, HeaderMap, HeaderName, HeaderValue};
+use base64::Engine;
+use opentelemetry::{
+    propagation::Injector,
+    trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState},
+    Context,
+};
+use prost::Message;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::{
+    config::RpcServiceConfig,
+    error::{RpcError, RpcErrorCode},
+    metadata::BINARY,
+    parts::RpcFromRequestParts,
+};
+
+/// The trace context of the current RPC, to continue its trace into downstream calls:
+///
+/// ```ignore
+/// async fn checkout(trace: RpcTraceContext, request: CheckoutRequest) -> RpcResult<Order> {
+///     let mut headers = HeaderMap::new();
+///     trace.inject_headers(&mut headers);
+///     let payment = http.post(PAYMENTS_URL).headers(headers).json(&request).send().await?;
+///     // ...
+/// }
+/// ```
+///
+/// It's the context of the RPC's span when that is exported through a `tracing_opentelemetry`
+/// layer, and otherwise the one the request carried, so the trace passes through either way. Pass
+/// `.0` to `opentelemetry` propagators or tracers for anything else.
+#[derive(Clone, Debug, Default)]
+pub struct RpcTraceContext(pub Context);
+
+impl RpcTraceContext {
+    pub fn span_context(&self) -> SpanContext {
+        self.0.span().span_context().clone()
+    }
+
+    /// Adds `traceparent` and `tracestate` (when not empty) to a downstream call, as W3C Trace
+    /// Context. Does nothing without a valid context.
+    pub fn inject(&self, injector: &mut dyn Injector) {
+        let span_context = self.span_context();
+        if !span_context.is_valid() {
+            return;
+        }
+        injector.set(
+            TRACEPARENT,
+            format!(
+                "00-{}-{}-{:02x}",
+                span_context.trace_id(),
+                span_context.span_id(),
+                span_context.trace_flags().to_u8()
+            ),
+        );
+        let trace_state = span_context.trace_state().header();
+        if !trace_state.is_empty() {
+            injector.set(TRACESTATE, trace_state);
+        }
+    }
+
+    pub fn inject_headers(&self, headers: &mut HeaderMap) {
+        self.inject(&mut HeaderInjector(headers));
+    }
+}
+
+#[async_trait]
+impl<M, S> RpcFromRequestParts<M, S> for RpcTraceContext
+where
+    M: Message,
+    S: Send + Sync,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let span_context = parts
+            .extensions
+            .get::<RpcServiceConfig>()
+            .and_then(|config| config.call.as_ref())
+            .map(|call| call.span.context())
+            .filter(|context| context.span().span_context().is_valid());
+        let context = span_context.unwrap_or_else(|| match remote_span_context(&parts.headers) {
+            Some(remote) => Context::new().with_remote_span_context(remote),
+            None => Context::new(),
+        });
+        Ok(Self(context))
+    }
+}
+
+const TRACEPARENT: &str = "traceparent";
+const TRACESTATE: &str = "tracestate";
+const GRPC_TRACE_BIN: &str = "grpc-trace-bin";
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// Makes the caller's span, if the request has a valid trace context, the parent of `span`.
+pub(crate) fn continue_trace(span: &tracing::Span, headers: &HeaderMap) {
+    if let Some(remote) = remote_span_context(headers) {
+        // Fails only without a `tracing_opentelemetry` layer, when there's nothing to export.
+        let _ = span.set_parent(Context::new().with_remote_span_context(remote));
+    }
+}
+
+/// The caller's span, from `traceparent` (and `tracestate`), or else `grpc-trace-bin`.
+fn remote_span_context(headers: &HeaderMap) -> Option<SpanContext> {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    match header(TRACEPARENT) {
+        Some(traceparent) => {
+            let trace_state = header(TRACESTATE)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default();
+            parse_traceparent(traceparent, trace_state)
+        }
+        None => parse_grpc_trace_bin(&BINARY.decode(header(GRPC_TRACE_BIN)?.trim()).ok()?),
+    }
+}
+
+/// A `traceparent` like `00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01`, see:
+/// https://www.w3.org/TR/trace-context/#traceparent-header. Later versions may add fields.
+fn parse_traceparent(traceparent: &str, trace_state: TraceState) -> Option<SpanContext> {
+    let mut fields = traceparent.trim().split('-');
+    let (version, trace_id, span_id, flags) = (
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+    );
+    let is_hex = |field: &str, len| {
+        field.len() == len
+            && field
+                .bytes()
+                .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    if !is_hex(version, 2) || version == "ff" || (version == "00" && fields.next().is_some()) {
+        return None;
+    }
+    if !is_hex(trace_id, 32) || !is_hex(span_id, 16) || !is_hex(flags, 2) {
+        return None;
+    }
+
+    let span_context = SpanContext::new(
+        TraceId::from_hex(trace_id).ok()?,
+        SpanId::from_hex(span_id).ok()?,
+        TraceFlags::new(u8::from_str_radix(flags, 16).ok()?) & TraceFlags::SAMPLED,
+        true,
+        trace_state,
+    );
+    span_context.is_valid().then_some(span_context)
+}
+
+/// The binary trace context of OpenCensus and gRPC, see:
+/// https://github.com/census-instrumentation/opencensus-specs/blob/master/encodings/BinaryEncoding.md.
+/// Version 0: the trace ID (field 0), span ID (1) and options (2), each after its field ID.
+fn parse_grpc_trace_bin(bytes: &[u8]) -> Option<SpanContext> {
+    let [0, 0, rest @ ..] = bytes else {
+        return None;
+    };
+    let (trace_id, rest) = rest.split_first_chunk::<16>()?;
+    let [1, rest @ ..] = rest else {
+        return None;
+    };
+    let (span_id, rest) = rest.split_first_chunk::<8>()?;
+    let flags = match rest {
+        [2, options, ..] => TraceFlags::new(*options) & TraceFlags::SAMPLED,
+        _ => TraceFlags::default(),
+    };
+
+    let span_context = SpanContext::new(
+        TraceId::from_bytes(*trace_id),
+        SpanId::from_bytes(*span_id),
+        flags,
+        true,
+        TraceState::default(),
+    );
+    span_context.is_valid().then_some(span_context)
+}
+
+/// Records how a call ended the way the RPC semantic conventions do: as the
+/// `rpc.connect_rpc.error_code` of a failed Connect call, or the `rpc.grpc.status_code` of a gRPC
+/// one. Only the codes that are the server's fault mark its span as an error, see:
+/// https://opentelemetry.io/docs/specs/semconv/rpc/connect-rpc/.
+pub(crate) fn record_status(span: &tracing::Span, protocol: &str, code: Option<&RpcErrorCode>) {
+    match (protocol, code) {
+        ("connect", Some(code)) => {
+            span.record("rpc.connect_rpc.error_code", code.as_str());
+        }
+        ("connect", None) => {}
+        (_, code) => {
+            span.record("rpc.grpc.status_code", code.map_or(0, RpcErrorCode::as_i32));
+        }
+    }
+    if code.is_some_and(is_server_error) {
+        span.record("otel.status_code", "error");
+    }
+}
+
+fn is_server_error(code: &RpcErrorCode) -> bool {
+    matches!(
+        code,
+        RpcErrorCode::Unknown
+            | RpcErrorCode::DeadlineExceeded
+            | RpcErrorCode::Unimplemented
+            | RpcErrorCode::Internal
+            | RpcErrorCode::Unavailable
+            | RpcErrorCode::DataLoss
+    )
+}
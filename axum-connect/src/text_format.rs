@@ -0,0 +1,70 @@
+use axum::http::request::Parts;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Opt-in debug switch. With this extension on the router, appending `?debug=text` to a unary RPC
+/// URL answers with the request and response messages rendered in (approximate) protobuf text
+/// format, as `text/plain`. Handy for eyeballing binary-only traffic with curl.
+///
+/// ```ignore
+/// let app = Router::new()
+///     .rpc(HelloWorldService::say_hello(say_hello))
+///     .layer(Extension(TextFormatDebug));
+/// ```
+///
+/// Don't enable this in production, it bypasses the negotiated encoding entirely.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TextFormatDebug;
+
+impl TextFormatDebug {
+    pub(crate) fn requested(parts: &Parts) -> bool {
+        parts.extensions.get::<TextFormatDebug>().is_some()
+            && parts
+                .uri
+                .query()
+                .unwrap_or_default()
+                .split('&')
+                .any(|pair| pair == "debug=text")
+    }
+}
+
+/// Renders a message in protobuf text format, for humans. This goes through the same (pbjson) serde
+/// impls as JSON does, so it's an approximation: field names are the JSON names, enums and 64 bit
+/// integers are quoted, and maps are shown as nested blocks.
+pub fn to_text_format<M>(message: &M) -> String
+where
+    M: Serialize,
+{
+    let mut out = String::new();
+    match serde_json::to_value(message) {
+        Ok(Value::Object(fields)) => write_fields(&mut out, &fields, 0),
+        Ok(other) => out.push_str(&other.to_string()),
+        Err(e) => out.push_str(&format!("# failed to render message: {}", e)),
+    }
+    out
+}
+
+fn write_fields(out: &mut String, fields: &Map<String, Value>, depth: usize) {
+    for (name, value) in fields {
+        write_field(out, name, value, depth);
+    }
+}
+
+fn write_field(out: &mut String, name: &str, value: &Value, depth: usize) {
+    let indent = "  ".repeat(depth);
+    match value {
+        Value::Null => {}
+        // Repeated fields are written as one field per element.
+        Value::Array(items) => {
+            for item in items {
+                write_field(out, name, item, depth);
+            }
+        }
+        Value::Object(fields) => {
+            out.push_str(&format!("{}{} {{\n", indent, name));
+            write_fields(out, fields, depth + 1);
+            out.push_str(&format!("{}}}\n", indent));
+        }
+        scalar => out.push_str(&format!("{}{}: {}\n", indent, name, scalar)),
+    }
+}
@@ -0,0 +1,100 @@
+use async_trait::async_trait;
+#[cfg(any(feature = "client", feature = "test-util"))]
+use axum::http::StatusCode;
+#[cfg(any(feature = "client", feature = "test-util"))]
+use base64::Engine;
+use futures::stream::BoxStream;
+use prost::Message;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::prelude::RpcResult;
+#[cfg(any(feature = "client", feature = "test-util"))]
+use crate::{
+    error::{RpcError, RpcErrorCode},
+    metadata::BINARY,
+    middleware::grpc::{code_for_http_status, WireEndStream, WireError},
+};
+
+/// How the generated `{Service}Client`s (see `AxumConnectGenSettings::clients` in
+/// `axum-connect-build`) make their calls, so the same typed client works in memory against a
+/// `Router` in tests (`RpcTestClient`, with the `test-util` feature) and over the network
+/// (`RpcClient`, with the `client` feature).
+///
+/// ```ignore
+/// let client = HelloWorldServiceClient::new(RpcTestClient::new(app));
+/// let res = client.say_hello(HelloRequest { name: Some("Alec".into()) }).await?;
+/// ```
+///
+/// `path` is the RPC's path, like `/hello.HelloWorldService/SayHello`.
+#[async_trait]
+pub trait RpcTransport: Send + Sync {
+    async fn unary<Req, Res>(&self, path: &str, request: Req) -> RpcResult<Res>
+    where
+        Req: Message + Serialize + Send + 'static,
+        Res: Message + DeserializeOwned + Default + Send + 'static;
+
+    /// A unary call to a method that's safe to repeat (with an `idempotency_level` of `IDEMPOTENT`
+    /// or `NO_SIDE_EFFECTS`), which the transport may retry. The generated clients call it for
+    /// those methods; it's `unary` unless overridden.
+    async fn idempotent_unary<Req, Res>(&self, path: &str, request: Req) -> RpcResult<Res>
+    where
+        Req: Message + Serialize + Send + 'static,
+        Res: Message + DeserializeOwned + Default + Send + 'static,
+    {
+        self.unary(path, request).await
+    }
+
+    /// The response messages, ending with the error the stream failed with, if it did.
+    async fn server_stream<Req, Res>(
+        &self,
+        path: &str,
+        request: Req,
+    ) -> BoxStream<'static, RpcResult<Res>>
+    where
+        Req: Message + Serialize + Send + 'static,
+        Res: Message + DeserializeOwned + Default + Send + 'static;
+
+    async fn client_stream<Req, Res>(
+        &self,
+        path: &str,
+        requests: BoxStream<'static, Req>,
+    ) -> RpcResult<Res>
+    where
+        Req: Message + Serialize + Send + 'static,
+        Res: Message + DeserializeOwned + Default + Send + 'static;
+}
+
+/// The error of a failed (non-200) response, from its Connect error body or else its HTTP status.
+#[cfg(any(feature = "client", feature = "test-util"))]
+pub(crate) fn error_response(status: StatusCode, body: &[u8]) -> RpcError {
+    match serde_json::from_slice::<WireError>(body) {
+        Ok(e) => e.into_error(),
+        Err(_) => RpcError::new(code_for_http_status(status), status.to_string()),
+    }
+}
+
+/// The error a stream ended with, from its EndStreamResponse, with that response's metadata.
+#[cfg(any(feature = "client", feature = "test-util"))]
+pub(crate) fn end_stream_error(payload: &[u8]) -> RpcResult<Option<RpcError>> {
+    let end = serde_json::from_slice::<WireEndStream>(payload).map_err(|e| {
+        RpcError::new(
+            RpcErrorCode::Internal,
+            format!("Invalid EndStreamResponse. {}", e),
+        )
+    })?;
+    let Some(e) = end.error else {
+        return Ok(None);
+    };
+
+    let mut e = e.into_error();
+    for (key, values) in end.metadata {
+        for value in values {
+            if !key.ends_with("-bin") {
+                e.metadata_mut().append(&key, value);
+            } else if let Ok(value) = BINARY.decode(&value) {
+                e.metadata_mut().append_bin(&key, value);
+            }
+        }
+    }
+    Ok(Some(e))
+}
@@ -0,0 +1,101 @@
+#![cfg(feature = "macros")]
+
+use std::fmt;
+
+use axum_connect::{
+    error::{RpcError, RpcErrorCode},
+    RpcIntoError,
+};
+
+#[test]
+fn rpc_handler() {
+    let cases = trybuild::TestCases::new();
+    cases.pass("tests/ui/rpc_handler/pass.rs");
+    cases.compile_fail("tests/ui/rpc_handler/not_async.rs");
+    cases.compile_fail("tests/ui/rpc_handler/wrong_request.rs");
+    cases.compile_fail("tests/ui/rpc_handler/wrong_extractor.rs");
+    cases.compile_fail("tests/ui/rpc_handler/stream_for_unary.rs");
+    cases.compile_fail("tests/ui/rpc_handler/unary_for_stream.rs");
+    cases.compile_fail("tests/ui/rpc_handler/stream_request_for_unary.rs");
+}
+
+#[test]
+fn rpc_into_error() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/rpc_into_error/missing_code.rs");
+    cases.compile_fail("tests/ui/rpc_into_error/unknown_code.rs");
+}
+
+#[derive(Debug, RpcIntoError)]
+#[rpc(code = Unavailable)]
+enum ShopError {
+    #[rpc(code = NotFound)]
+    NoSuchItem(String),
+    #[rpc(code = FailedPrecondition, message = "only {left} of {sku} left")]
+    OutOfStock {
+        sku: String,
+        left: u32,
+    },
+    #[rpc(code = InvalidArgument, message = "can't order {0:?}")]
+    BadQuantity(i32),
+    #[rpc(transparent)]
+    Rpc(RpcError),
+    Closed,
+}
+
+impl fmt::Display for ShopError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoSuchItem(sku) => write!(f, "no item {sku}"),
+            _ => f.write_str("shop error"),
+        }
+    }
+}
+
+#[derive(Debug, RpcIntoError)]
+struct ParseError;
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("unparsable")
+    }
+}
+
+#[test]
+fn derived_errors_convert_into_rpc_errors() {
+    let cases = [
+        (
+            ShopError::NoSuchItem("sku-1".into()),
+            RpcErrorCode::NotFound,
+            "no item sku-1",
+        ),
+        (
+            ShopError::OutOfStock {
+                sku: "sku-1".into(),
+                left: 2,
+            },
+            RpcErrorCode::FailedPrecondition,
+            "only 2 of sku-1 left",
+        ),
+        (
+            ShopError::BadQuantity(-1),
+            RpcErrorCode::InvalidArgument,
+            "can't order -1",
+        ),
+        (
+            ShopError::Rpc(RpcError::new(RpcErrorCode::PermissionDenied, "no".into())),
+            RpcErrorCode::PermissionDenied,
+            "no",
+        ),
+        (ShopError::Closed, RpcErrorCode::Unavailable, "shop error"),
+    ];
+    for (error, code, message) in cases {
+        let error = RpcError::from(error);
+        assert_eq!(error.code, code);
+        assert_eq!(error.message, message);
+    }
+
+    let error: RpcError = ParseError.into();
+    assert_eq!(error.code, RpcErrorCode::Internal);
+    assert_eq!(error.message, "unparsable");
+}
@@ -0,0 +1,39 @@
+// `RpcMethod` types for the health service, standing in for generated ones.
+
+use axum_connect::{
+    descriptor::{IdempotencyLevel, MethodDescriptor, MethodKind, RpcMethod},
+    health::{Health, HealthCheckRequest, HealthCheckResponse},
+};
+
+pub struct Check;
+
+impl RpcMethod for Check {
+    type Request = HealthCheckRequest;
+    type Response = HealthCheckResponse;
+    const DESCRIPTOR: &'static MethodDescriptor = &Health::DESCRIPTOR.methods[0];
+}
+
+pub struct Watch;
+
+impl RpcMethod for Watch {
+    type Request = HealthCheckRequest;
+    type Response = HealthCheckResponse;
+    const DESCRIPTOR: &'static MethodDescriptor = &Health::DESCRIPTOR.methods[1];
+}
+
+pub struct CheckAll;
+
+impl RpcMethod for CheckAll {
+    type Request = HealthCheckRequest;
+    type Response = HealthCheckResponse;
+    const DESCRIPTOR: &'static MethodDescriptor = &MethodDescriptor {
+        name: "CheckAll",
+        service: "grpc.health.v1.Health",
+        path: "/grpc.health.v1.Health/CheckAll",
+        input_type: "grpc.health.v1.HealthCheckRequest",
+        output_type: "grpc.health.v1.HealthCheckResponse",
+        kind: MethodKind::ClientStreaming,
+        idempotency: IdempotencyLevel::Unknown,
+        deprecated: false,
+    };
+}
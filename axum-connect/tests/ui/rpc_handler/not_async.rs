@@ -0,0 +1,11 @@
+#[path = "../methods.rs"]
+mod methods;
+
+#[axum_connect::rpc_handler(methods::Check)]
+fn check(
+    _request: axum_connect::health::HealthCheckRequest,
+) -> axum_connect::response::RpcResult<axum_connect::health::HealthCheckResponse> {
+    unimplemented!()
+}
+
+fn main() {}
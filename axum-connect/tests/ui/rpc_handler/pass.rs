@@ -0,0 +1,61 @@
+#[path = "../methods.rs"]
+mod methods;
+
+use axum_connect::{
+    error::RpcError,
+    futures::{stream, Stream, StreamExt},
+    handler::RpcService,
+    health::{HealthCheckRequest, HealthCheckResponse, ServingStatus},
+    metadata::RpcMetadata,
+    parts::RpcDeadline,
+    request::RpcRequestStream,
+    rpc_handler,
+};
+
+#[rpc_handler(methods::Check)]
+async fn check(request: HealthCheckRequest) -> Result<HealthCheckResponse, RpcError> {
+    let _ = request.service;
+    Ok(HealthCheckResponse::new(ServingStatus::Serving))
+}
+
+#[rpc_handler(methods::Check, state = ())]
+async fn check_with_parts(
+    metadata: RpcMetadata,
+    RpcDeadline(deadline): RpcDeadline,
+    request: HealthCheckRequest,
+) -> Result<HealthCheckResponse, RpcError> {
+    let _ = (metadata, deadline, request);
+    Ok(HealthCheckResponse::new(ServingStatus::Serving))
+}
+
+#[rpc_handler(methods::Watch)]
+async fn watch(
+    _request: HealthCheckRequest,
+) -> impl Stream<Item = Result<HealthCheckResponse, RpcError>> {
+    stream::iter([Ok(HealthCheckResponse::new(ServingStatus::Serving))])
+}
+
+#[rpc_handler(methods::CheckAll)]
+async fn check_all(
+    mut requests: RpcRequestStream<HealthCheckRequest>,
+) -> Result<HealthCheckResponse, RpcError> {
+    while let Some(request) = requests.next().await {
+        request?;
+    }
+    Ok(HealthCheckResponse::new(ServingStatus::Serving))
+}
+
+fn main() {
+    // The signatures are left as written, so the handlers can still be called directly.
+    let _ = check(HealthCheckRequest::default());
+    let _ = check_with_parts(
+        RpcMetadata::default(),
+        RpcDeadline(None),
+        HealthCheckRequest::default(),
+    );
+
+    let _ = RpcService::unary(check, ());
+    let _ = RpcService::unary(check_with_parts, ());
+    let _ = RpcService::server_stream(watch, ());
+    let _ = RpcService::client_stream(check_all, ());
+}
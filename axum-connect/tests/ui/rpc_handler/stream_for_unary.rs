@@ -0,0 +1,18 @@
+#[path = "../methods.rs"]
+mod methods;
+
+use axum_connect::{
+    error::RpcError,
+    futures::{stream, Stream},
+    health::{HealthCheckRequest, HealthCheckResponse, ServingStatus},
+    rpc_handler,
+};
+
+#[rpc_handler(methods::Check)]
+async fn check(
+    _request: HealthCheckRequest,
+) -> impl Stream<Item = Result<HealthCheckResponse, RpcError>> {
+    stream::iter([Ok(HealthCheckResponse::new(ServingStatus::Serving))])
+}
+
+fn main() {}
@@ -0,0 +1,18 @@
+#[path = "../methods.rs"]
+mod methods;
+
+use axum_connect::{
+    error::RpcError,
+    health::{HealthCheckRequest, HealthCheckResponse, ServingStatus},
+    request::RpcRequestStream,
+    rpc_handler,
+};
+
+#[rpc_handler(methods::Check)]
+async fn check(
+    _requests: RpcRequestStream<HealthCheckRequest>,
+) -> Result<HealthCheckResponse, RpcError> {
+    Ok(HealthCheckResponse::new(ServingStatus::Serving))
+}
+
+fn main() {}
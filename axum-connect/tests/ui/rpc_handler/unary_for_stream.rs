@@ -0,0 +1,15 @@
+#[path = "../methods.rs"]
+mod methods;
+
+use axum_connect::{
+    error::RpcError,
+    health::{HealthCheckRequest, HealthCheckResponse, ServingStatus},
+    rpc_handler,
+};
+
+#[rpc_handler(methods::Watch)]
+async fn watch(_request: HealthCheckRequest) -> Result<HealthCheckResponse, RpcError> {
+    Ok(HealthCheckResponse::new(ServingStatus::Serving))
+}
+
+fn main() {}
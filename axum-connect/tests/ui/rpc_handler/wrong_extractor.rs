@@ -0,0 +1,22 @@
+#[path = "../methods.rs"]
+mod methods;
+
+use std::rc::Rc;
+
+use axum_connect::{
+    error::RpcError,
+    health::{HealthCheckRequest, HealthCheckResponse, ServingStatus},
+    provide::Provide,
+    rpc_handler,
+};
+
+#[rpc_handler(methods::Check, state = ())]
+async fn check(
+    Provide(counter): Provide<Rc<u32>>,
+    _request: HealthCheckRequest,
+) -> Result<HealthCheckResponse, RpcError> {
+    let _ = counter;
+    Ok(HealthCheckResponse::new(ServingStatus::Serving))
+}
+
+fn main() {}
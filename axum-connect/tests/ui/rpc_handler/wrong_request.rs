@@ -0,0 +1,17 @@
+#[path = "../methods.rs"]
+mod methods;
+
+use axum_connect::{
+    error::RpcError,
+    health::{HealthCheckResponse, ServingStatus},
+    rpc_handler,
+};
+
+#[rpc_handler(methods::Check)]
+async fn check(request: HealthCheckResponse) -> Result<HealthCheckResponse, RpcError> {
+    Ok(request)
+}
+
+fn main() {
+    let _ = check(HealthCheckResponse::new(ServingStatus::Serving));
+}
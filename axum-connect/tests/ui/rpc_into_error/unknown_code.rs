@@ -0,0 +1,17 @@
+use std::fmt;
+
+use axum_connect::RpcIntoError;
+
+#[derive(Debug, RpcIntoError)]
+enum ShopError {
+    #[rpc(code = Missing)]
+    NoSuchItem,
+}
+
+impl fmt::Display for ShopError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("shop error")
+    }
+}
+
+fn main() {}